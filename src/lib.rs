@@ -2,7 +2,14 @@ mod tokenisation;
 mod parsing;
 mod lua;
 
-pub use lua::{get_lua_tokeniser, get_lua_parser};
+pub use lua::{get_lua_tokeniser, get_lua_file_tokeniser, get_lua_parser, read_lua_chunk, print_lua_chunk, LuaDialect};
+pub use lua::{LuaFormatter, LuaPrinter, LuaQuoteStyle, LuaCallParens, LuaLinter, LuaLint, LuaLintRule, resolve_lua_chunk};
+pub use lua::{LuaChange, fold_lua_constants, eliminate_lua_dead_code};
+pub use lua::{LuaValue, LuaNumber, LuaBytes, LuaFunction, LuaTable, LuaReadError};
+pub use tokenisation::{DiagnosticRenderer, Diagnostic, TextEdit};
+pub use tokenisation::{Tokeniser, TokeniserBuilder, TokeniserState, ModeTransition, INITIAL_MODE};
+pub use tokenisation::{Token, TokenData, Location, Trivia, TriviaKind, TokenisationError, TokenisationErrorType};
+pub use parsing::TerminalSymbol;
 
 pub fn test_fn() -> bool {
     true
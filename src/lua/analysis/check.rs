@@ -0,0 +1,91 @@
+/*!
+ * The checks the reference compiler makes that need more than the syntax,
+ * which reading a chunk runs once the whole chunk has been read.
+ */
+
+use crate::lua::LuaDialect;
+use crate::lua::syntax_tree::*;
+use crate::lua::analysis::{LuaScopes, resolve_lua_chunk, check_lua_jumps};
+
+/** Something the reference compiler would reject, even though it reads. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaSemanticError {
+    pub message: String,
+    /** The part of the chunk at fault. */
+    pub span:    LuaSpan
+}
+
+/** Checks a chunk that has been read, giving the first thing that is wrong
+ *  with it. */
+pub fn check_lua_chunk(chunk: &LuaChunk, dialect: LuaDialect) -> Result<(), LuaSemanticError> {
+    check_lua_attribs(chunk)?;
+    check_lua_jumps(chunk, dialect)
+}
+
+/** Checks that nothing assigns to a `<const>` or `<close>` local after its
+ *  declaration. */
+pub fn check_lua_attribs(chunk: &LuaChunk) -> Result<(), LuaSemanticError> {
+    let scopes = resolve_lua_chunk(chunk);
+    let mut checker = LuaAttribChecker {
+        scopes: &scopes,
+        error:  None
+    };
+
+    checker.visit_chunk(chunk);
+
+    match checker.error {
+        Some(error) => Err(error),
+        None        => Ok(())
+    }
+}
+
+struct LuaAttribChecker<'s> {
+    scopes: &'s LuaScopes,
+    error:  Option<LuaSemanticError>
+}
+
+impl<'s> Visitor for LuaAttribChecker<'s> {
+    fn visit_name(&mut self, name: &LuaName) {
+        if self.error.is_some() || !self.scopes.is_write(name.id) {
+            return;
+        }
+
+        let read_only = self.scopes.local_of(name.id)
+            .map(|local| self.scopes.local(local).attrib.is_some())
+            .unwrap_or(false);
+
+        if read_only {
+            self.error = Some(LuaSemanticError {
+                message: format!("attempt to assign to const variable '{}'", name.name),
+                span:    name.span
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lua::{LuaDialect, read_lua_chunk};
+
+    fn error(src: &str) -> String {
+        read_lua_chunk(src, LuaDialect::Lua54, false).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn only_reads_const_and_close() {
+        assert_eq!(error("local x <constant> = 1"), "1: unknown attribute 'constant'");
+        assert_eq!(error("local a <close>, b, c <close> = f()"), "1: multiple to-be-closed variables in local list");
+
+        assert!(read_lua_chunk("local a <const>, b <close>, c <const> = 1, f(), 3", LuaDialect::Lua54, false).is_ok());
+        assert!(read_lua_chunk("local x <const> = 1", LuaDialect::Lua53, false).is_err());
+    }
+
+    #[test]
+    fn finds_assignments_to_read_only_locals() {
+        assert_eq!(error("local x <const> = 1\nx = 2"), "2: attempt to assign to const variable 'x'");
+        assert_eq!(error("local f <close> = nil\nfunction f() end"), "2: attempt to assign to const variable 'f'");
+        assert_eq!(error("local x <const> = 1\nlocal function g()\n    y, x = 1, 2\nend"), "3: attempt to assign to const variable 'x'");
+
+        assert!(read_lua_chunk("local x <const> = {}\nx.y = 1\ndo local x = 2; x = 3 end", LuaDialect::Lua54, false).is_ok());
+    }
+}
@@ -0,0 +1,35 @@
+/*!
+ * Works out where control can go from a statement, for the passes that need
+ * to know which code can run, like the lint for unreachable code and dead
+ * code elimination.
+ */
+
+use crate::lua::syntax_tree::*;
+
+/** Whether a statement always jumps or returns, so that what follows it in
+ *  the same block can't run. */
+pub(crate) fn always_exits(stat: &LuaStat) -> bool {
+    match &stat.kind {
+        LuaStatKind::Break | LuaStatKind::Continue | LuaStatKind::Goto(_) => true,
+        LuaStatKind::Do(block) => block_exits(block),
+        LuaStatKind::If(_, block, else_ifs, Some(else_block)) => {
+            block_exits(block) && else_ifs.iter().all(|(_, block)| block_exits(block)) && block_exits(else_block)
+        },
+        _ => false
+    }
+}
+
+fn block_exits(block: &LuaBlock) -> bool {
+    let mut exits = false;
+
+    for stat in &block.stats {
+        match stat.kind {
+            // Anything after a label can be reached by jumping to it.
+            LuaStatKind::Label(_) => exits = false,
+            _ if always_exits(stat) => exits = true,
+            _ => { }
+        }
+    }
+
+    exits || block.ret_stat.is_some()
+}
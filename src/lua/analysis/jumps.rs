@@ -0,0 +1,360 @@
+/*!
+ * Checks `goto`s, labels and `break`s the way the reference compiler does.
+ *
+ * A `goto` can jump to any label visible where it is, which is one in the
+ * same block or an enclosing one in the same function, whether the label
+ * comes before it or after. Jumping forwards can't take it into the scope
+ * of a local declared in between, though, unless the label is at the end of
+ * its block, where those locals have gone out of scope again. A label at the
+ * end of a `repeat` loop's body isn't, as the condition can still see them.
+ *
+ * The wording of the errors follows the dialect's reference implementation.
+ */
+
+use std::mem;
+
+use crate::lua::LuaDialect;
+use crate::lua::syntax_tree::*;
+use crate::lua::analysis::LuaSemanticError;
+
+/** Checks every jump in a chunk, giving the first one that is wrong at the
+ *  `goto`, label or `break` at fault. */
+pub fn check_lua_jumps(chunk: &LuaChunk, dialect: LuaDialect) -> Result<(), LuaSemanticError> {
+    let mut checker = LuaJumpChecker {
+        dialect,
+        locals:  Vec::new(),
+        blocks:  Vec::new(),
+        error:   None
+    };
+
+    checker.check_function(&[], &chunk.block);
+
+    match checker.error {
+        Some(error) => Err(error),
+        None        => Ok(())
+    }
+}
+
+struct LuaLabelInfo {
+    name: LuaSymbol,
+    line: usize
+}
+
+struct LuaPendingGoto {
+    name:   LuaSymbol,
+    span:   LuaSpan,
+    /** How many locals are in scope at the `goto`, or at the end of the
+     *  innermost block being checked that it is in, if there are fewer. */
+    active: usize
+}
+
+struct LuaBlockFrame {
+    /** How many locals were in scope at the start of the block. */
+    active:  usize,
+    is_loop: bool,
+    labels:  Vec<LuaLabelInfo>,
+    /** `goto`s in the block that jump forwards to a label that hasn't been
+     *  found yet. */
+    gotos:   Vec<LuaPendingGoto>
+}
+
+struct LuaJumpChecker {
+    dialect: LuaDialect,
+    /** The locals in scope in the function being checked, innermost last. */
+    locals:  Vec<LuaSymbol>,
+    /** The blocks being checked in the function being checked, innermost
+     *  last. */
+    blocks:  Vec<LuaBlockFrame>,
+    error:   Option<LuaSemanticError>
+}
+
+impl LuaJumpChecker {
+    fn fail(&mut self, message: String, span: LuaSpan) {
+        if self.error.is_none() {
+            self.error = Some(LuaSemanticError {
+                message,
+                span
+            });
+        }
+    }
+
+    fn check_function(&mut self, params: &[LuaSymbol], block: &LuaBlock) {
+        let locals = mem::replace(&mut self.locals, params.to_vec());
+        let blocks = mem::take(&mut self.blocks);
+
+        self.check_block(block, false, false);
+
+        self.locals = locals;
+        self.blocks = blocks;
+    }
+
+    fn enter_block(&mut self, is_loop: bool) {
+        self.blocks.push(LuaBlockFrame {
+            active:  self.locals.len(),
+            is_loop,
+            labels:  Vec::new(),
+            gotos:   Vec::new()
+        });
+    }
+
+    /** Leaves a block, passing the `goto`s that are still looking for their
+     *  labels out to the block around it, if there is one in the function. */
+    fn leave_block(&mut self) {
+        let frame = self.blocks.pop().unwrap();
+        self.locals.truncate(frame.active);
+
+        let active = frame.active;
+        let mut gotos = frame.gotos.into_iter();
+
+        match self.blocks.last_mut() {
+            Some(outer) => outer.gotos.extend(gotos.map(|goto| LuaPendingGoto {
+                active: goto.active.min(active),
+                ..goto
+            })),
+            None => if let Some(goto) = gotos.next() {
+                let message = match self.dialect {
+                    LuaDialect::LuaJit => format!("undefined label '{}'", goto.name),
+                    _                  => format!("no visible label '{}' for <goto> at line {}", goto.name, goto.span.start.line)
+                };
+
+                self.fail(message, goto.span);
+            }
+        }
+    }
+
+    /** Checks a block, which is the body of a loop if `is_loop`, and of a
+     *  `repeat` loop, whose condition can see its locals, if `repeat`. */
+    fn check_block(&mut self, block: &LuaBlock, is_loop: bool, repeat: bool) {
+        self.enter_block(is_loop);
+
+        for (idx, stat) in block.stats.iter().enumerate() {
+            match &stat.kind {
+                LuaStatKind::Label(label) => {
+                    let last = !repeat && block.ret_stat.is_none() && block.stats[idx + 1..].iter()
+                        .all(|stat| matches!(stat.kind, LuaStatKind::Label(_) | LuaStatKind::Semicolon));
+
+                    self.check_label(label, last);
+                },
+                _ => self.visit_stat(stat)
+            }
+        }
+
+        if let Some(ret_stat) = &block.ret_stat {
+            self.visit_ret_stat(ret_stat);
+        }
+
+        self.leave_block();
+    }
+
+    fn check_goto(&mut self, name: &LuaName) {
+        let visible = self.blocks.iter().any(|frame| frame.labels.iter().any(|label| label.name == name.name));
+
+        // Jumping backwards only ever leaves scopes.
+        if !visible {
+            let active = self.locals.len();
+
+            self.blocks.last_mut().unwrap().gotos.push(LuaPendingGoto {
+                name:   name.name.clone(),
+                span:   name.span,
+                active
+            });
+        }
+    }
+
+    /** Checks a label, which is at the end of its block, as far as the
+     *  locals in scope go, if `last`. */
+    fn check_label(&mut self, label: &LuaLabel, last: bool) {
+        let frames = match self.dialect {
+            // Lua 5.2 and 5.3 only look for repeated labels in the same block.
+            LuaDialect::Lua52 | LuaDialect::Lua53 => &self.blocks[self.blocks.len() - 1..],
+            _                                     => &self.blocks[..]
+        };
+
+        let repeated = frames.iter()
+            .flat_map(|frame| frame.labels.iter())
+            .find(|existing| existing.name == label.name.name)
+            .map(|existing| existing.line);
+
+        if let Some(line) = repeated {
+            let message = match self.dialect {
+                LuaDialect::LuaJit => format!("duplicate label '{}'", label.name.name),
+                _                  => format!("label '{}' already defined on line {}", label.name.name, line)
+            };
+
+            self.fail(message, label.span);
+            return;
+        }
+
+        let frame = self.blocks.last_mut().unwrap();
+        let active = if last { frame.active } else { self.locals.len() };
+
+        frame.labels.push(LuaLabelInfo {
+            name: label.name.name.clone(),
+            line: label.span.start.line
+        });
+
+        let (solved, pending): (Vec<LuaPendingGoto>, _) = mem::take(&mut frame.gotos).into_iter()
+            .partition(|goto| goto.name == label.name.name);
+        frame.gotos = pending;
+
+        for goto in solved {
+            if goto.active < active {
+                let local = &self.locals[goto.active];
+                let message = match self.dialect {
+                    LuaDialect::LuaJit => format!("<goto {}> jumps into the scope of local '{}'", goto.name, local),
+                    _                  => format!("<goto {}> at line {} jumps into the scope of local '{}'",
+                        goto.name, goto.span.start.line, local)
+                };
+
+                self.fail(message, label.span);
+            }
+        }
+    }
+
+    fn check_break(&mut self, span: LuaSpan) {
+        if self.blocks.iter().any(|frame| frame.is_loop) {
+            return;
+        }
+
+        let message = match self.dialect {
+            LuaDialect::Lua54                     => format!("break outside a loop at line {}", span.start.line),
+            LuaDialect::Lua52 | LuaDialect::Lua53 => format!("<break> at line {} not inside a loop", span.start.line),
+            _                                     => String::from("no loop to break")
+        };
+
+        self.fail(message, span);
+    }
+
+    /** Checks the body of a loop, after declaring its variables in a block
+     *  of their own around it. */
+    fn check_loop(&mut self, names: &[LuaName], block: &LuaBlock) {
+        self.enter_block(true);
+        self.locals.extend(names.iter().map(|name| name.name.clone()));
+        self.check_block(block, false, false);
+        self.leave_block();
+    }
+}
+
+impl Visitor for LuaJumpChecker {
+    fn visit_block(&mut self, block: &LuaBlock) {
+        self.check_block(block, false, false);
+    }
+
+    fn visit_stat(&mut self, stat: &LuaStat) {
+        if self.error.is_some() {
+            return;
+        }
+
+        match &stat.kind {
+            LuaStatKind::Goto(name) => self.check_goto(name),
+            LuaStatKind::Break      => self.check_break(stat.span),
+            LuaStatKind::While(condition, block) => {
+                self.visit_exp(condition);
+                self.check_block(block, true, false);
+            },
+            LuaStatKind::Repeat(block, condition) => {
+                self.check_block(block, true, true);
+                self.visit_exp(condition);
+            },
+            LuaStatKind::For(name, initial, limit, step, block) => {
+                self.visit_exp(initial);
+                self.visit_exp(limit);
+
+                if let Some(step) = step {
+                    self.visit_exp(step);
+                }
+
+                self.check_loop(std::slice::from_ref(name), block);
+            },
+            LuaStatKind::ForIn(names, exps, block) => {
+                exps.iter().for_each(|exp| self.visit_exp(exp));
+                self.check_loop(names, block);
+            },
+            LuaStatKind::LocalAttNameList(att_names, exps) => {
+                exps.iter().for_each(|exp| self.visit_exp(exp));
+                self.locals.extend(att_names.iter().map(|att_name| att_name.name.name.clone()));
+            },
+            LuaStatKind::LocalFunction(name, body) => {
+                self.locals.push(name.name.clone());
+                self.visit_func_body(body);
+            },
+            LuaStatKind::Function(func_name, body) => {
+                let mut params: Vec<LuaSymbol> = Vec::new();
+
+                if func_name.method.is_some() {
+                    params.push(LuaSymbol::from("self"));
+                }
+
+                params.extend(body.params.iter().map(|param| param.name.clone()));
+                self.check_function(&params, &body.block);
+            },
+            _ => walk_stat(self, stat)
+        }
+    }
+
+    fn visit_func_body(&mut self, body: &LuaFuncBody) {
+        let params: Vec<LuaSymbol> = body.params.iter().map(|param| param.name.clone()).collect();
+        self.check_function(&params, &body.block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::read_lua_chunk;
+
+    fn check(src: &str, dialect: LuaDialect) -> Result<(), String> {
+        read_lua_chunk(src, dialect, false).map(|_| ()).map_err(|error| error.to_string())
+    }
+
+    fn error(src: &str) -> String {
+        check(src, LuaDialect::Lua54).err().unwrap()
+    }
+
+    #[test]
+    fn finds_gotos_without_labels() {
+        assert_eq!(error("goto nowhere"), "1: no visible label 'nowhere' for <goto> at line 1");
+        assert_eq!(error("do ::inner:: end\ngoto inner"), "2: no visible label 'inner' for <goto> at line 2");
+        assert_eq!(error("::outer::\nlocal f = function() goto outer end"), "2: no visible label 'outer' for <goto> at line 2");
+
+        assert_eq!(check("::top:: local x = 1 goto top", LuaDialect::Lua54), Ok(()));
+        assert_eq!(check("do goto out end ::out::", LuaDialect::Lua54), Ok(()));
+        assert_eq!(check("goto nowhere", LuaDialect::LuaJit).err().unwrap(), "1: undefined label 'nowhere'");
+    }
+
+    #[test]
+    fn finds_repeated_labels() {
+        assert_eq!(error("::a:: ::a::"), "1: label 'a' already defined on line 1");
+        assert_eq!(error("::a::\ndo ::a:: end"), "2: label 'a' already defined on line 1");
+
+        assert_eq!(check("do ::a:: end ::a::", LuaDialect::Lua54), Ok(()));
+        assert_eq!(check("::a:: function f() ::a:: end", LuaDialect::Lua54), Ok(()));
+        assert_eq!(check("::a:: do ::a:: end", LuaDialect::Lua53), Ok(()));
+    }
+
+    #[test]
+    fn finds_jumps_into_the_scope_of_locals() {
+        assert_eq!(error("goto f\nlocal x = 1\n::f::\nprint(x)"), "3: <goto f> at line 1 jumps into the scope of local 'x'");
+        assert_eq!(error("repeat\n  goto f\n  local x\n  ::f::\nuntil x"), "4: <goto f> at line 2 jumps into the scope of local 'x'");
+
+        // Labels at the end of a block are out of the scope of its locals.
+        assert_eq!(check("goto f\nlocal x = 1\n::f:: ;", LuaDialect::Lua54), Ok(()));
+        assert_eq!(check(concat!(
+            "for i = 1, 3 do\n",
+            "    if i == 2 then goto continue end\n",
+            "    local y = i\n",
+            "    ::continue::\n",
+            "end"
+        ), LuaDialect::Lua54), Ok(()));
+    }
+
+    #[test]
+    fn finds_breaks_outside_loops() {
+        assert_eq!(error("break"), "1: break outside a loop at line 1");
+        assert_eq!(error("while true do\n  local f = function() break end\nend"), "2: break outside a loop at line 2");
+        assert_eq!(check("if x then break end", LuaDialect::Lua53).err().unwrap(), "1: <break> at line 1 not inside a loop");
+        assert_eq!(check("break", LuaDialect::Lua51).err().unwrap(), "1: no loop to break");
+
+        assert_eq!(check("for _ in x do do break end end repeat break until x", LuaDialect::Lua54), Ok(()));
+    }
+}
@@ -0,0 +1,593 @@
+/*!
+ * Finds common mistakes in Lua source, the way `phobos lint` does.
+ *
+ * Each kind of mistake is a `LuaLintRule` that can be turned on or off by
+ * itself. A line comment of `-- phobos: ignore` turns every rule off for the
+ * line it ends, or for the next line if it is on a line of its own, and
+ * naming rules after it, as in `-- phobos: ignore unused-local, shadowing`,
+ * only turns those off.
+ *
+ * Locals whose names start with `_` are taken to be unused on purpose, so
+ * they are never reported as unused or as shadowing anything.
+ */
+
+use std::collections::{HashMap, HashSet};
+
+use crate::tokenisation::{Token, TriviaKind};
+use crate::lua::{LuaTerminal, LuaDialect, LuaReadError, LuaNumeral, get_lua_file_tokeniser};
+use crate::lua::syntax_tree::*;
+use crate::lua::analysis::*;
+use crate::lua::analysis::flow::always_exits;
+
+type LintResult<T> = Result<T, LuaReadError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LuaLintRule {
+    UnusedLocal,
+    UnusedParameter,
+    UnusedLoopVariable,
+    /** A local with the same name as one already in scope. */
+    Shadowing,
+    /** A global that is read but never assigned to, and that isn't one of
+     *  the standard library's or an allowed one. */
+    UndefinedGlobal,
+    /** An assignment to a `<const>` or `<close>` local. */
+    ConstAssignment,
+    /** Statements after a `break`, `goto` or `do return end`. */
+    UnreachableCode,
+    /** A key given twice in one table constructor. */
+    DuplicateKey,
+    /** `nil == nil` and `nil ~= nil`. */
+    NilComparison,
+    /** `x = x`, and the same with fields. */
+    SelfAssignment
+}
+
+impl LuaLintRule {
+    pub const ALL: [LuaLintRule; 10] = [
+        LuaLintRule::UnusedLocal,
+        LuaLintRule::UnusedParameter,
+        LuaLintRule::UnusedLoopVariable,
+        LuaLintRule::Shadowing,
+        LuaLintRule::UndefinedGlobal,
+        LuaLintRule::ConstAssignment,
+        LuaLintRule::UnreachableCode,
+        LuaLintRule::DuplicateKey,
+        LuaLintRule::NilComparison,
+        LuaLintRule::SelfAssignment
+    ];
+
+    /** What the rule is called in configuration and ignore comments. */
+    pub fn name(&self) -> &'static str {
+        match self {
+            LuaLintRule::UnusedLocal        => "unused-local",
+            LuaLintRule::UnusedParameter    => "unused-parameter",
+            LuaLintRule::UnusedLoopVariable => "unused-loop-variable",
+            LuaLintRule::Shadowing          => "shadowing",
+            LuaLintRule::UndefinedGlobal    => "undefined-global",
+            LuaLintRule::ConstAssignment    => "const-assignment",
+            LuaLintRule::UnreachableCode    => "unreachable-code",
+            LuaLintRule::DuplicateKey       => "duplicate-key",
+            LuaLintRule::NilComparison      => "nil-comparison",
+            LuaLintRule::SelfAssignment     => "self-assignment"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<LuaLintRule> {
+        LuaLintRule::ALL.iter().copied().find(|rule| rule.name() == name)
+    }
+}
+
+/** A mistake found in some source. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaLint {
+    pub rule:    LuaLintRule,
+    pub message: String,
+    pub span:    LuaSpan
+}
+
+/** Checks Lua source for mistakes with the rules chosen for a project. */
+pub struct LuaLinter {
+    dialect:         LuaDialect,
+    rules:           HashSet<LuaLintRule>,
+    allowed_globals: HashSet<String>
+}
+
+impl LuaLinter {
+    /** A linter with every rule turned on. */
+    pub fn new(dialect: LuaDialect) -> LuaLinter {
+        LuaLinter {
+            dialect,
+            rules:           LuaLintRule::ALL.iter().copied().collect(),
+            allowed_globals: HashSet::new()
+        }
+    }
+
+    pub fn with_rule(mut self,
+        rule:    LuaLintRule,
+        enabled: bool
+    ) -> LuaLinter {
+        if enabled {
+            self.rules.insert(rule);
+        } else {
+            self.rules.remove(&rule);
+        }
+
+        self
+    }
+
+    /** A global that can be read without being assigned to, on top of the
+     *  standard library's, like one a host program provides. */
+    pub fn with_allowed_global(mut self,
+        name: &str
+    ) -> LuaLinter {
+        self.allowed_globals.insert(String::from(name));
+        self
+    }
+
+    /** Checks a whole file, giving what it finds in the order it appears.
+     *  Source that doesn't read gives the error found instead. */
+    pub fn lint(&self, source: &[u8]) -> LintResult<Vec<LuaLint>> {
+        let mut tokeniser = get_lua_file_tokeniser(self.dialect).unwrap();
+        let tokens: Vec<Token<LuaTerminal>> = tokeniser.tokenise(source.to_vec())?.into_iter().collect();
+        let ignored = ignore_comments(&tokens);
+        // Assignments to read-only locals are reported as lints of their own
+        // rather than stopping the whole file from being linted.
+        let chunk = read_checked_lua_tokens(tokens, self.dialect, check_lua_jumps)?;
+
+        let scopes = resolve_lua_chunk(&chunk);
+        let mut walker = LuaLintWalker {
+            lints: Vec::new(),
+            names: HashMap::new()
+        };
+
+        walker.visit_chunk(&chunk);
+
+        let mut lints = walker.lints;
+        self.lint_locals(&scopes, &walker.names, &mut lints);
+        self.lint_globals(&scopes, &walker.names, &mut lints);
+
+        lints.retain(|lint| {
+            self.rules.contains(&lint.rule) && match ignored.get(&lint.span.start.line) {
+                Some(None)        => false,
+                Some(Some(rules)) => !rules.contains(&lint.rule),
+                None              => true
+            }
+        });
+
+        lints.sort_by_key(|a| (a.span.start, a.rule));
+        Ok(lints)
+    }
+
+    fn lint_locals(&self,
+        scopes: &LuaScopes,
+        names:  &HashMap<LuaNodeId, LuaName>,
+        lints:  &mut Vec<LuaLint>
+    ) {
+        for (_, local) in scopes.locals() {
+            let on_purpose = local.name.starts_with('_') || local.kind == LuaLocalKind::SelfParameter;
+
+            // Locals that are closed are there for what closing them does.
+            if local.reads.is_empty() && !on_purpose && local.attrib != Some(LuaAttribKind::Close) {
+                let (rule, description) = match local.kind {
+                    LuaLocalKind::Parameter     => (LuaLintRule::UnusedParameter, "parameter"),
+                    LuaLocalKind::ForVariable   => (LuaLintRule::UnusedLoopVariable, "loop variable"),
+                    LuaLocalKind::LocalFunction => (LuaLintRule::UnusedLocal, "local function"),
+                    _                           => (LuaLintRule::UnusedLocal, "local")
+                };
+
+                lints.push(LuaLint {
+                    rule,
+                    message: format!("unused {} '{}'", description, local.name),
+                    span:    local.span
+                });
+            }
+
+            if let Some(shadowed) = local.shadows {
+                if !on_purpose {
+                    lints.push(LuaLint {
+                        rule:    LuaLintRule::Shadowing,
+                        message: format!("local '{}' shadows the one declared on line {}",
+                            local.name, scopes.local(shadowed).span.start.line),
+                        span:    local.span
+                    });
+                }
+            }
+
+            if local.attrib.is_some() {
+                for write in &local.writes {
+                    lints.push(LuaLint {
+                        rule:    LuaLintRule::ConstAssignment,
+                        message: format!("attempt to assign to const variable '{}'", local.name),
+                        span:    names[write].span
+                    });
+                }
+            }
+        }
+    }
+
+    fn lint_globals(&self,
+        scopes: &LuaScopes,
+        names:  &HashMap<LuaNodeId, LuaName>,
+        lints:  &mut Vec<LuaLint>
+    ) {
+        let globals: Vec<&LuaName> = names.values()
+            .filter(|name| scopes.resolve(name.id) == Some(LuaResolution::Global(None)))
+            .collect();
+
+        let assigned: HashSet<&str> = globals.iter()
+            .filter(|name| scopes.is_write(name.id))
+            .map(|name| &*name.name)
+            .collect();
+
+        for name in globals {
+            let defined = assigned.contains(&*name.name) || self.allowed_globals.contains(&*name.name) ||
+                standard_globals(self.dialect).contains(&&*name.name);
+
+            if !defined {
+                lints.push(LuaLint {
+                    rule:    LuaLintRule::UndefinedGlobal,
+                    message: format!("undefined global '{}'", name.name),
+                    span:    name.span
+                });
+            }
+        }
+    }
+}
+
+/** The globals the standard library of a dialect defines. */
+fn standard_globals(dialect: LuaDialect) -> &'static [&'static str] {
+    match dialect {
+        LuaDialect::Lua51 => &[
+            "_G", "_VERSION", "arg", "assert", "collectgarbage", "coroutine", "debug", "dofile", "error",
+            "gcinfo", "getfenv", "getmetatable", "io", "ipairs", "load", "loadfile", "loadstring", "math",
+            "module", "newproxy", "next", "os", "package", "pairs", "pcall", "print", "rawequal", "rawget",
+            "rawset", "require", "select", "setfenv", "setmetatable", "string", "table", "tonumber",
+            "tostring", "type", "unpack", "xpcall"
+        ],
+        LuaDialect::LuaJit => &[
+            "_G", "_VERSION", "arg", "assert", "bit", "collectgarbage", "coroutine", "debug", "dofile",
+            "error", "gcinfo", "getfenv", "getmetatable", "io", "ipairs", "jit", "load", "loadfile",
+            "loadstring", "math", "module", "newproxy", "next", "os", "package", "pairs", "pcall", "print",
+            "rawequal", "rawget", "rawset", "require", "select", "setfenv", "setmetatable", "string",
+            "table", "tonumber", "tostring", "type", "unpack", "xpcall"
+        ],
+        LuaDialect::Lua52 => &[
+            "_G", "_VERSION", "arg", "assert", "bit32", "collectgarbage", "coroutine", "debug", "dofile",
+            "error", "getmetatable", "io", "ipairs", "load", "loadfile", "math", "next", "os", "package",
+            "pairs", "pcall", "print", "rawequal", "rawget", "rawlen", "rawset", "require", "select",
+            "setmetatable", "string", "table", "tonumber", "tostring", "type", "xpcall"
+        ],
+        LuaDialect::Lua53 => &[
+            "_G", "_VERSION", "arg", "assert", "collectgarbage", "coroutine", "debug", "dofile", "error",
+            "getmetatable", "io", "ipairs", "load", "loadfile", "math", "next", "os", "package", "pairs",
+            "pcall", "print", "rawequal", "rawget", "rawlen", "rawset", "require", "select",
+            "setmetatable", "string", "table", "tonumber", "tostring", "type", "utf8", "xpcall"
+        ],
+        LuaDialect::Lua54 => &[
+            "_G", "_VERSION", "arg", "assert", "collectgarbage", "coroutine", "debug", "dofile", "error",
+            "getmetatable", "io", "ipairs", "load", "loadfile", "math", "next", "os", "package", "pairs",
+            "pcall", "print", "rawequal", "rawget", "rawlen", "rawset", "require", "select",
+            "setmetatable", "string", "table", "tonumber", "tostring", "type", "utf8", "warn", "xpcall"
+        ],
+        LuaDialect::Luau => &[
+            "_G", "_VERSION", "assert", "bit32", "collectgarbage", "coroutine", "debug", "error",
+            "gcinfo", "getfenv", "getmetatable", "ipairs", "load", "math", "newproxy", "next", "os",
+            "pairs", "pcall", "print", "rawequal", "rawget", "rawlen", "rawset", "require", "select",
+            "setfenv", "setmetatable", "string", "table", "tonumber", "tostring", "type", "typeof",
+            "unpack", "utf8", "xpcall"
+        ]
+    }
+}
+
+/** The lines `-- phobos: ignore` comments apply to, with the rules they
+ *  name, or `None` for all of them. */
+fn ignore_comments(tokens: &[Token<LuaTerminal>]) -> HashMap<usize, Option<HashSet<LuaLintRule>>> {
+    let mut ignored = HashMap::new();
+
+    for token in tokens {
+        // Leading trivia starts at the beginning of a line, so comments in it
+        // are on lines of their own and apply to the token's line.
+        let comments = token.leading_trivia.iter().map(|trivia| (trivia, token.location.line))
+            .chain(token.trailing_trivia.iter().map(|trivia| (trivia, token.end_location().line)));
+
+        for (trivia, line) in comments {
+            if trivia.kind != TriviaKind::LineComment {
+                continue;
+            }
+
+            let text = String::from_utf8_lossy(&trivia.text[2..]).into_owned();
+            let rest = match text.trim().strip_prefix("phobos:").map(str::trim) {
+                Some(rest) if rest.starts_with("ignore") => rest["ignore".len()..].trim().to_owned(),
+                _                                       => continue
+            };
+
+            let rules: HashSet<LuaLintRule> = rest.split(|c: char| c == ',' || c.is_whitespace())
+                .filter_map(LuaLintRule::from_name)
+                .collect();
+
+            ignored.insert(line, if rules.is_empty() { None } else { Some(rules) });
+        }
+    }
+
+    ignored
+}
+
+/** A key in a table constructor that can be compared with others before
+ *  running anything. */
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum LuaConstantKey {
+    String(Vec<u8>),
+    Integer(i64),
+    Float(u64),
+    Boolean(bool)
+}
+
+impl LuaConstantKey {
+    fn of(exp: &LuaExp) -> Option<LuaConstantKey> {
+        match &exp.kind {
+            LuaExpKind::String(value)  => Some(LuaConstantKey::String(value.to_vec())),
+            LuaExpKind::Boolean(value) => Some(LuaConstantKey::Boolean(*value)),
+            LuaExpKind::Bracketed(inner) => LuaConstantKey::of(inner),
+            LuaExpKind::Number(LuaNumeral::Integer(value)) => Some(LuaConstantKey::Integer(*value)),
+            // Floats with integer values are the same keys as the integers.
+            LuaExpKind::Number(LuaNumeral::Float(value)) => {
+                if value.fract() == 0.0 && *value >= i64::MIN as f64 && *value < -(i64::MIN as f64) {
+                    Some(LuaConstantKey::Integer(*value as i64))
+                } else if value.is_nan() {
+                    None
+                } else {
+                    Some(LuaConstantKey::Float(value.to_bits()))
+                }
+            },
+            _ => None
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            LuaConstantKey::String(value)  => format!("'{}'", String::from_utf8_lossy(value)),
+            LuaConstantKey::Integer(value) => format!("{}", value),
+            LuaConstantKey::Float(bits)    => format!("{:?}", f64::from_bits(*bits)),
+            LuaConstantKey::Boolean(value) => format!("{}", value)
+        }
+    }
+}
+
+/** Whether two expressions name the same variable or field, without
+ *  anything in them that could give a different answer each time. */
+fn same_place(a: &LuaExp, b: &LuaExp) -> bool {
+    match (&a.kind, &b.kind) {
+        (LuaExpKind::Bracketed(a), _) => same_place(a, b),
+        (_, LuaExpKind::Bracketed(b)) => same_place(a, b),
+        (LuaExpKind::Name(a), LuaExpKind::Name(b)) => a.name == b.name,
+        (LuaExpKind::Field(a, a_name), LuaExpKind::Field(b, b_name)) => a_name.name == b_name.name && same_place(a, b),
+        (LuaExpKind::Index(a, a_key), LuaExpKind::Index(b, b_key)) => {
+            let same_key = match (LuaConstantKey::of(a_key), LuaConstantKey::of(b_key)) {
+                (Some(a_key), Some(b_key)) => a_key == b_key,
+                _                          => same_place(a_key, b_key)
+            };
+
+            same_key && same_place(a, b)
+        },
+        _ => false
+    }
+}
+
+fn is_nil(exp: &LuaExp) -> bool {
+    match &exp.kind {
+        LuaExpKind::Nil              => true,
+        LuaExpKind::Bracketed(inner) => is_nil(inner),
+        _                            => false
+    }
+}
+
+/** Walks a chunk for the rules that only need to look at the tree, noting
+ *  what the rules about locals and globals need along the way. */
+struct LuaLintWalker {
+    lints: Vec<LuaLint>,
+    /** Every name in the chunk. */
+    names: HashMap<LuaNodeId, LuaName>
+}
+
+impl Visitor for LuaLintWalker {
+    fn visit_block(&mut self, block: &LuaBlock) {
+        let mut exited = false;
+        let mut reported = false;
+
+        for stat in &block.stats {
+            match stat.kind {
+                LuaStatKind::Label(_)     => exited = false,
+                LuaStatKind::Semicolon    => { },
+                _ if exited && !reported => {
+                    self.lints.push(LuaLint {
+                        rule:    LuaLintRule::UnreachableCode,
+                        message: String::from("unreachable code"),
+                        span:    stat.span
+                    });
+                    reported = true;
+                },
+                _ => { }
+            }
+
+            exited = exited || always_exits(stat);
+        }
+
+        if let Some(ret_stat) = &block.ret_stat {
+            if exited && !reported {
+                self.lints.push(LuaLint {
+                    rule:    LuaLintRule::UnreachableCode,
+                    message: String::from("unreachable code"),
+                    span:    ret_stat.span
+                });
+            }
+        }
+
+        walk_block(self, block);
+    }
+
+    fn visit_stat(&mut self, stat: &LuaStat) {
+        if let LuaStatKind::VarList(targets, exps) = &stat.kind {
+            for (target, exp) in targets.iter().zip(exps) {
+                if same_place(target, exp) {
+                    self.lints.push(LuaLint {
+                        rule:    LuaLintRule::SelfAssignment,
+                        message: format!("'{}' is assigned to itself", LuaPrinter::new().print_exp(target)),
+                        span:    target.span.to(exp.span)
+                    });
+                }
+            }
+        }
+
+        walk_stat(self, stat);
+    }
+
+    fn visit_exp(&mut self, exp: &LuaExp) {
+        if let LuaExpKind::BinOp(op @ (LuaBinOp::Eq | LuaBinOp::NotEq), left, right) = &exp.kind {
+            if is_nil(left) && is_nil(right) {
+                self.lints.push(LuaLint {
+                    rule:    LuaLintRule::NilComparison,
+                    message: format!("'nil {} nil' is always {}", op.symbol(), *op == LuaBinOp::Eq),
+                    span:    exp.span
+                });
+            }
+        }
+
+        walk_exp(self, exp);
+    }
+
+    fn visit_name(&mut self, name: &LuaName) {
+        self.names.insert(name.id, name.clone());
+    }
+
+    fn visit_table_constructor(&mut self, table: &LuaTableConstructor) {
+        let mut keys: HashMap<LuaConstantKey, LuaSpan> = HashMap::new();
+        let mut position = 0;
+
+        for field in &table.fields {
+            let (key, span) = match &field.kind {
+                LuaFieldKind::Named(name, _) => {
+                    (Some(LuaConstantKey::String(name.name.as_bytes().to_vec())), name.span)
+                },
+                LuaFieldKind::Keyed(key, _) => (LuaConstantKey::of(key), key.span),
+                LuaFieldKind::Positional(value) => {
+                    position += 1;
+                    (Some(LuaConstantKey::Integer(position)), value.span)
+                }
+            };
+
+            if let Some(key) = key {
+                if let Some(first) = keys.get(&key) {
+                    self.lints.push(LuaLint {
+                        rule:    LuaLintRule::DuplicateKey,
+                        message: format!("key {} is already given on line {}", key.describe(), first.start.line),
+                        span
+                    });
+                } else {
+                    keys.insert(key, span);
+                }
+            }
+        }
+
+        walk_table_constructor(self, table);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint(linter: &LuaLinter, src: &str) -> Vec<String> {
+        linter.lint(src.as_bytes()).ok().unwrap().into_iter()
+            .map(|lint| format!("{}: {} [{}]", lint.span.start.line, lint.message, lint.rule.name()))
+            .collect()
+    }
+
+    #[test]
+    fn finds_unused_and_shadowed_locals() {
+        let linter = LuaLinter::new(LuaDialect::Lua54);
+        let src = "\
+local used, unused, _ignored = 1, 2, 3
+local function helper(a, b) return a end
+for i, v in pairs({}) do print(v) end
+local t = {}
+function t:method(x) return self end
+do local used = used end
+local h <close> = nil
+print(used)
+";
+
+        assert_eq!(lint(&linter, src), vec![
+            "1: unused local 'unused' [unused-local]",
+            "2: unused local function 'helper' [unused-local]",
+            "2: unused parameter 'b' [unused-parameter]",
+            "3: unused loop variable 'i' [unused-loop-variable]",
+            "5: unused parameter 'x' [unused-parameter]",
+            "6: unused local 'used' [unused-local]",
+            "6: local 'used' shadows the one declared on line 1 [shadowing]"
+        ]);
+    }
+
+    #[test]
+    fn finds_undefined_globals() {
+        let linter = LuaLinter::new(LuaDialect::Lua54).with_allowed_global("vim");
+        let src = "\
+defined = 1
+print(defined, undefined, vim, string.format)
+function later() return unpack end
+later()
+";
+
+        assert_eq!(lint(&linter, src), vec![
+            "2: undefined global 'undefined' [undefined-global]",
+            "3: undefined global 'unpack' [undefined-global]"
+        ]);
+        assert_eq!(lint(&LuaLinter::new(LuaDialect::Lua51), "return unpack, vim"), vec![
+            "1: undefined global 'vim' [undefined-global]"
+        ]);
+    }
+
+    #[test]
+    fn finds_mistakes_in_statements() {
+        let linter = LuaLinter::new(LuaDialect::Lua54);
+        let src = "\
+local x <const> = 1
+x = 2
+local t = {a = 1, ['a'] = 2, 10, [1] = 20, [2.0] = 3, 30}
+t.a = t.a; t[1] = (t[1])
+print(nil == nil, nil ~= (nil), x == nil)
+for i = 1, 10 do
+    break
+    print(i)
+end
+do return end
+print('never')
+";
+
+        assert_eq!(lint(&linter, src), vec![
+            "2: attempt to assign to const variable 'x' [const-assignment]",
+            "3: key 'a' is already given on line 3 [duplicate-key]",
+            "3: key 1 is already given on line 3 [duplicate-key]",
+            "3: key 2 is already given on line 3 [duplicate-key]",
+            "4: 't.a' is assigned to itself [self-assignment]",
+            "4: 't[1]' is assigned to itself [self-assignment]",
+            "5: 'nil == nil' is always true [nil-comparison]",
+            "5: 'nil ~= nil' is always false [nil-comparison]",
+            "8: unreachable code [unreachable-code]",
+            "11: unreachable code [unreachable-code]"
+        ]);
+    }
+
+    #[test]
+    fn can_be_told_to_ignore_things() {
+        let linter = LuaLinter::new(LuaDialect::Lua54).with_rule(LuaLintRule::UndefinedGlobal, false);
+        let src = "\
+local a = b -- phobos: ignore
+-- phobos: ignore unused-local, shadowing
+local c = d
+local e = f -- phobos: ignore shadowing
+";
+
+        assert_eq!(lint(&linter, src), vec!["4: unused local 'e' [unused-local]"]);
+    }
+}
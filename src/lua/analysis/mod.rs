@@ -0,0 +1,10 @@
+mod scope;
+mod lint;
+mod jumps;
+mod check;
+pub(crate) mod flow;
+
+pub use scope::{LuaScopes, LuaLocalId, LuaLocalKind, LuaResolution, resolve_lua_chunk};
+pub use lint::{LuaLinter, LuaLint, LuaLintRule};
+pub use jumps::check_lua_jumps;
+pub use check::{LuaSemanticError, check_lua_chunk};
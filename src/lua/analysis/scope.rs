@@ -0,0 +1,455 @@
+/*!
+ * Works out what every name in a chunk refers to.
+ *
+ * Each local a chunk declares, including parameters, loop variables and the
+ * implicit `self` of methods, is given a `LuaLocalId`, and every name that is
+ * used as a variable resolves to one of those locals, to an upvalue of an
+ * enclosing function, or to a global, which is a field of `_ENV`. The result
+ * is a table keyed by the `LuaNodeId`s of the names, so that later passes can
+ * look names up without walking scopes themselves.
+ *
+ * The rules are the reference implementation's: a local is in scope from the
+ * statement after its declaration, apart from a `local function`, which can
+ * refer to itself; loop variables are only in scope in the loop's body; and
+ * the condition of `repeat ... until` can see the locals of the body.
+ */
+
+use std::collections::{HashMap, HashSet};
+
+use crate::lua::syntax_tree::*;
+
+/** Identifies a local within the `LuaScopes` that declared it. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LuaLocalId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LuaLocalKind {
+    Local,
+    LocalFunction,
+    Parameter,
+    /** The `self` parameter methods declared with `:` have. */
+    SelfParameter,
+    ForVariable
+}
+
+#[derive(Debug, Clone)]
+pub struct LuaLocal {
+    pub name:           LuaSymbol,
+    pub kind:           LuaLocalKind,
+    /** The attribute a local declared by a `local` statement is given. */
+    pub attrib:         Option<LuaAttribKind>,
+    /** The name that declares the local, which the implicit `self` of a
+     *  method doesn't have. */
+    pub declaration:    Option<LuaNodeId>,
+    /** Where the local is declared, or the method name for `self`. */
+    pub span:           LuaSpan,
+    /** How many functions deep the local is declared, 0 being the chunk. */
+    pub function_depth: usize,
+    /** The local with the same name that was in scope where this one was
+     *  declared, and that it hides. */
+    pub shadows:        Option<LuaLocalId>,
+    /** The names that read the local. Globals read through a local `_ENV`
+     *  count as reading it. */
+    pub reads:          Vec<LuaNodeId>,
+    /** The names that assign to the local, after its declaration. */
+    pub writes:         Vec<LuaNodeId>,
+    /** Whether a function nested in the one the local belongs to uses it. */
+    pub captured:       bool
+}
+
+/** What a name used as a variable refers to. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LuaResolution {
+    /** A local of the function the name is in. */
+    Local(LuaLocalId),
+    /** A local of an enclosing function, with how many functions out it is
+     *  declared. */
+    Upvalue(LuaLocalId, usize),
+    /** A field of the environment, which is the `_ENV` local in scope if
+     *  there is one, and otherwise the chunk's own. */
+    Global(Option<LuaLocalId>),
+    /** `_ENV` itself, when no local of that name is in scope. */
+    Environment
+}
+
+/** The locals of a chunk and what each of its names refers to. */
+#[derive(Debug, Clone)]
+pub struct LuaScopes {
+    locals:       Vec<LuaLocal>,
+    declarations: HashMap<LuaNodeId, LuaLocalId>,
+    references:   HashMap<LuaNodeId, LuaResolution>,
+    writes:       HashSet<LuaNodeId>
+}
+
+impl LuaScopes {
+    pub fn local(&self, id: LuaLocalId) -> &LuaLocal {
+        &self.locals[id.0 as usize]
+    }
+
+    /** Every local, in the order they are declared. */
+    pub fn locals(&self) -> impl Iterator<Item = (LuaLocalId, &LuaLocal)> {
+        self.locals.iter().enumerate().map(|(idx, local)| (LuaLocalId(idx as u32), local))
+    }
+
+    /** The local a name declares, if it declares one. */
+    pub fn declared_by(&self, name: LuaNodeId) -> Option<LuaLocalId> {
+        self.declarations.get(&name).copied()
+    }
+
+    /** What a name that is used as a variable refers to. Names that declare
+     *  locals, and those that aren't variables, like field names and labels,
+     *  don't resolve to anything. */
+    pub fn resolve(&self, name: LuaNodeId) -> Option<LuaResolution> {
+        self.references.get(&name).copied()
+    }
+
+    /** The local a name declares or refers to, if it is one. */
+    pub fn local_of(&self, name: LuaNodeId) -> Option<LuaLocalId> {
+        match self.resolve(name) {
+            Some(LuaResolution::Local(local))      => Some(local),
+            Some(LuaResolution::Upvalue(local, _)) => Some(local),
+            _                                      => self.declared_by(name)
+        }
+    }
+
+    /** Whether a name is assigned to, rather than only read. */
+    pub fn is_write(&self, name: LuaNodeId) -> bool {
+        self.writes.contains(&name)
+    }
+}
+
+/** Resolves every name in a chunk. */
+pub fn resolve_lua_chunk(chunk: &LuaChunk) -> LuaScopes {
+    let mut resolver = LuaResolver {
+        scopes:         LuaScopes {
+            locals:       Vec::new(),
+            declarations: HashMap::new(),
+            references:   HashMap::new(),
+            writes:       HashSet::new()
+        },
+        visible:        Vec::new(),
+        blocks:         Vec::new(),
+        function_depth: 0
+    };
+
+    resolver.visit_chunk(chunk);
+    resolver.scopes
+}
+
+struct LuaResolver {
+    scopes:         LuaScopes,
+    /** The locals in scope, innermost last. */
+    visible:        Vec<LuaLocalId>,
+    /** How many locals were in scope at the start of each block being
+     *  resolved. */
+    blocks:         Vec<usize>,
+    function_depth: usize
+}
+
+impl LuaResolver {
+    fn enter_block(&mut self) {
+        self.blocks.push(self.visible.len());
+    }
+
+    fn exit_block(&mut self) {
+        let count = self.blocks.pop().unwrap();
+        self.visible.truncate(count);
+    }
+
+    fn lookup(&self, name: &str) -> Option<LuaLocalId> {
+        self.visible.iter().rev().copied().find(|id| &*self.scopes.local(*id).name == name)
+    }
+
+    fn add_local(&mut self, name: LuaSymbol, kind: LuaLocalKind, declaration: Option<LuaNodeId>, span: LuaSpan) -> LuaLocalId {
+        let id = LuaLocalId(self.scopes.locals.len() as u32);
+
+        self.scopes.locals.push(LuaLocal {
+            shadows:        self.lookup(&name),
+            name,
+            kind,
+            attrib:         None,
+            declaration,
+            span,
+            function_depth: self.function_depth,
+            reads:          Vec::new(),
+            writes:         Vec::new(),
+            captured:       false
+        });
+
+        if let Some(declaration) = declaration {
+            self.scopes.declarations.insert(declaration, id);
+        }
+
+        self.visible.push(id);
+        id
+    }
+
+    fn declare(&mut self, name: &LuaName, kind: LuaLocalKind) -> LuaLocalId {
+        self.add_local(name.name.clone(), kind, Some(name.id), name.span)
+    }
+
+    /** Resolves a local as it is seen from the current function. */
+    fn resolution_of(&mut self, local: LuaLocalId) -> LuaResolution {
+        let depth = self.scopes.local(local).function_depth;
+
+        if depth == self.function_depth {
+            LuaResolution::Local(local)
+        } else {
+            self.scopes.locals[local.0 as usize].captured = true;
+            LuaResolution::Upvalue(local, self.function_depth - depth)
+        }
+    }
+
+    fn reference(&mut self, name: &LuaName, read: bool, write: bool) {
+        let resolution = match self.lookup(&name.name) {
+            Some(local) => {
+                let resolution = self.resolution_of(local);
+                let local = &mut self.scopes.locals[local.0 as usize];
+
+                if read {
+                    local.reads.push(name.id);
+                }
+
+                if write {
+                    local.writes.push(name.id);
+                }
+
+                resolution
+            },
+            None if &*name.name == "_ENV" => LuaResolution::Environment,
+            None => match self.lookup("_ENV") {
+                Some(env) => {
+                    self.resolution_of(env);
+                    self.scopes.locals[env.0 as usize].reads.push(name.id);
+                    LuaResolution::Global(Some(env))
+                },
+                None => LuaResolution::Global(None)
+            }
+        };
+
+        self.scopes.references.insert(name.id, resolution);
+
+        if write {
+            self.scopes.writes.insert(name.id);
+        }
+    }
+
+    /** Resolves an assignment target, which is written to if it is a name
+     *  and only read from otherwise. */
+    fn assign(&mut self, target: &LuaExp, read: bool) {
+        match &target.kind {
+            LuaExpKind::Name(name) => self.reference(name, read, true),
+            _                      => self.visit_exp(target)
+        }
+    }
+
+    /** Resolves a function's body, which declares `self` before its
+     *  parameters when it is a method. */
+    fn enter_function(&mut self, body: &LuaFuncBody, method: Option<&LuaName>) {
+        self.function_depth += 1;
+        self.enter_block();
+
+        if let Some(method) = method {
+            self.add_local(LuaSymbol::from("self"), LuaLocalKind::SelfParameter, None, method.span);
+        }
+
+        for param in &body.params {
+            self.declare(param, LuaLocalKind::Parameter);
+        }
+
+        walk_block(self, &body.block);
+        self.exit_block();
+        self.function_depth -= 1;
+    }
+}
+
+impl Visitor for LuaResolver {
+    fn visit_block(&mut self, block: &LuaBlock) {
+        self.enter_block();
+        walk_block(self, block);
+        self.exit_block();
+    }
+
+    fn visit_stat(&mut self, stat: &LuaStat) {
+        match &stat.kind {
+            LuaStatKind::VarList(targets, exps) => {
+                targets.iter().for_each(|target| self.assign(target, false));
+                exps.iter().for_each(|exp| self.visit_exp(exp));
+            },
+            LuaStatKind::CompoundAssignment(target, _, value) => {
+                self.assign(target, true);
+                self.visit_exp(value);
+            },
+            LuaStatKind::LocalAttNameList(att_names, exps) => {
+                exps.iter().for_each(|exp| self.visit_exp(exp));
+
+                for att_name in att_names {
+                    let local = self.declare(&att_name.name, LuaLocalKind::Local);
+                    self.scopes.locals[local.0 as usize].attrib = att_name.attrib.as_ref().map(|attrib| attrib.kind);
+                }
+            },
+            LuaStatKind::LocalFunction(name, body) => {
+                self.declare(name, LuaLocalKind::LocalFunction);
+                self.enter_function(body, None);
+            },
+            LuaStatKind::Function(func_name, body) => {
+                // `function f()` assigns to `f`, where `function t.f()` only
+                // reads `t`.
+                let assigns = func_name.path.len() == 1 && func_name.method.is_none();
+                self.reference(&func_name.path[0], !assigns, assigns);
+                self.enter_function(body, func_name.method.as_ref());
+            },
+            LuaStatKind::For(name, initial, limit, step, block) => {
+                self.visit_exp(initial);
+                self.visit_exp(limit);
+
+                if let Some(step) = step {
+                    self.visit_exp(step);
+                }
+
+                self.enter_block();
+                self.declare(name, LuaLocalKind::ForVariable);
+                self.visit_block(block);
+                self.exit_block();
+            },
+            LuaStatKind::ForIn(names, exps, block) => {
+                exps.iter().for_each(|exp| self.visit_exp(exp));
+
+                self.enter_block();
+                names.iter().for_each(|name| { self.declare(name, LuaLocalKind::ForVariable); });
+                self.visit_block(block);
+                self.exit_block();
+            },
+            LuaStatKind::Repeat(block, condition) => {
+                self.enter_block();
+                walk_block(self, block);
+                self.visit_exp(condition);
+                self.exit_block();
+            },
+            LuaStatKind::Label(_) | LuaStatKind::Goto(_) => { },
+            _ => walk_stat(self, stat)
+        }
+    }
+
+    fn visit_exp(&mut self, exp: &LuaExp) {
+        match &exp.kind {
+            LuaExpKind::Name(name) => self.reference(name, true, false),
+            _                      => walk_exp(self, exp)
+        }
+    }
+
+    fn visit_func_body(&mut self, body: &LuaFuncBody) {
+        self.enter_function(body, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::{LuaDialect, read_lua_chunk};
+
+    struct NameCollector {
+        names: Vec<LuaName>
+    }
+
+    impl Visitor for NameCollector {
+        fn visit_name(&mut self, name: &LuaName) {
+            self.names.push(name.clone());
+        }
+    }
+
+    /** Describes what each name in some source is, in the order the names
+     *  are visited. */
+    fn describe(src: &str) -> Vec<String> {
+        let chunk = read_lua_chunk(src, LuaDialect::Lua54, false).ok().unwrap();
+        let scopes = resolve_lua_chunk(&chunk);
+        let mut collector = NameCollector { names: Vec::new() };
+        collector.visit_chunk(&chunk);
+
+        collector.names.iter().map(|name| {
+            let description = match (scopes.declared_by(name.id), scopes.resolve(name.id)) {
+                (Some(local), _) => format!("declares {}", local.0),
+                (_, Some(LuaResolution::Local(local)))             => format!("local {}", local.0),
+                (_, Some(LuaResolution::Upvalue(local, depth)))    => format!("upvalue {} from {} out", local.0, depth),
+                (_, Some(LuaResolution::Global(None)))             => String::from("global"),
+                (_, Some(LuaResolution::Global(Some(local))))      => format!("global in {}", local.0),
+                (_, Some(LuaResolution::Environment))              => String::from("environment"),
+                (None, None)                                       => String::from("-")
+            };
+
+            let write = if scopes.is_write(name.id) { " (write)" } else { "" };
+            format!("{}: {}{}", name.name, description, write)
+        }).collect()
+    }
+
+    #[test]
+    fn tells_locals_upvalues_and_globals_apart() {
+        assert_eq!(describe("local a = b\nlocal function f(p) return function() return a, p, f end end\nc.d = a"), vec![
+            "a: declares 0",
+            "b: global",
+            "f: declares 1",
+            "p: declares 2",
+            "a: upvalue 0 from 2 out",
+            "p: upvalue 2 from 1 out",
+            "f: upvalue 1 from 2 out",
+            "c: global",
+            "d: -",
+            "a: local 0"
+        ]);
+    }
+
+    #[test]
+    fn follows_the_reference_scoping_rules() {
+        // A local's own initialiser sees the local it shadows, where a local
+        // function sees itself.
+        assert_eq!(describe("local x = x\nlocal f = function() return f end"), vec![
+            "x: declares 0",
+            "x: global",
+            "f: declares 1",
+            "f: global"
+        ]);
+
+        assert_eq!(describe("for i = i, 2 do local j = i end\nreturn i"), vec![
+            "i: declares 0",
+            "i: global",
+            "j: declares 1",
+            "i: local 0",
+            "i: global"
+        ]);
+
+        assert_eq!(describe("repeat local done = true until done\nreturn done"), vec![
+            "done: declares 0",
+            "done: local 0",
+            "done: global"
+        ]);
+
+        assert_eq!(describe("local t = {}\nfunction t:m() self = t end\nfunction g() end"), vec![
+            "t: declares 0",
+            "t: local 0",
+            "m: -",
+            "self: local 1 (write)",
+            "t: upvalue 0 from 1 out",
+            "g: global (write)"
+        ]);
+    }
+
+    #[test]
+    fn records_shadowing_uses_and_environments() {
+        let chunk = read_lua_chunk("local a = 1\ndo local a = a + 1; a = a end\nlocal _ENV = {}\nx = 1", LuaDialect::Lua54, false).ok().unwrap();
+        let scopes = resolve_lua_chunk(&chunk);
+        let locals: Vec<&LuaLocal> = scopes.locals().map(|(_, local)| local).collect();
+
+        assert_eq!(locals[0].shadows, None);
+        assert_eq!(locals[1].shadows, Some(LuaLocalId(0)));
+        assert_eq!((locals[0].reads.len(), locals[0].writes.len()), (1, 0));
+        assert_eq!((locals[1].reads.len(), locals[1].writes.len()), (1, 1));
+        assert_eq!(locals[2].reads.len(), 1);
+
+        assert_eq!(describe("x = _ENV\nlocal _ENV = {}\nx = 1"), vec![
+            "x: global (write)",
+            "_ENV: environment",
+            "_ENV: declares 0",
+            "x: global in 0 (write)"
+        ]);
+    }
+}
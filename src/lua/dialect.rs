@@ -0,0 +1,73 @@
+/**
+ * The versions of Lua code can be written for. The tokeniser and parser only
+ * accept the keywords, operators and statements the chosen dialect has.
+ *
+ * Only the parts of LuaJIT and Luau that extend the syntax shared with
+ * reference Lua are covered, so Luau's type annotations, for example, are
+ * not accepted.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum LuaDialect {
+    Lua51,
+    Lua52,
+    Lua53,
+    #[default]
+    Lua54,
+    LuaJit,
+    Luau
+}
+
+impl LuaDialect {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LuaDialect::Lua51  => "Lua 5.1",
+            LuaDialect::Lua52  => "Lua 5.2",
+            LuaDialect::Lua53  => "Lua 5.3",
+            LuaDialect::Lua54  => "Lua 5.4",
+            LuaDialect::LuaJit => "LuaJIT",
+            LuaDialect::Luau   => "Luau"
+        }
+    }
+
+    /** `goto` statements and `::labels::`. */
+    pub fn has_goto(&self) -> bool {
+        !matches!(self, LuaDialect::Lua51 | LuaDialect::Luau)
+    }
+
+    /** The `//` operator. */
+    pub fn has_floor_division(&self) -> bool {
+        matches!(self, LuaDialect::Lua53 | LuaDialect::Lua54 | LuaDialect::Luau)
+    }
+
+    /** The `&`, `|`, `~`, `<<` and `>>` operators. */
+    pub fn has_bitwise_operators(&self) -> bool {
+        matches!(self, LuaDialect::Lua53 | LuaDialect::Lua54)
+    }
+
+    /** Integers as a subtype of numbers alongside floats, rather than every
+     *  number being a float. */
+    pub fn has_integers(&self) -> bool {
+        matches!(self, LuaDialect::Lua53 | LuaDialect::Lua54)
+    }
+
+    /** `<const>` and `<close>` attributes on local variables. */
+    pub fn has_attributes(&self) -> bool {
+        *self == LuaDialect::Lua54
+    }
+
+    /** The `LL`, `ULL` and `i` suffixes on numerals, for 64-bit integer and
+     *  imaginary cdata. */
+    pub fn has_numeral_suffixes(&self) -> bool {
+        *self == LuaDialect::LuaJit
+    }
+
+    /** `continue` statements. */
+    pub fn has_continue(&self) -> bool {
+        *self == LuaDialect::Luau
+    }
+
+    /** Compound assignments such as `x += 1`. */
+    pub fn has_compound_assignment(&self) -> bool {
+        *self == LuaDialect::Luau
+    }
+}
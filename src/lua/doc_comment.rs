@@ -0,0 +1,479 @@
+/*!
+ * Documentation comments, written in the LDoc and EmmyLua style: a run of
+ * line comments starting with `---` directly above a statement.
+ *
+ * ```lua
+ * --- Adds two numbers.
+ * ---@param a number
+ * ---@param b number # the second number
+ * ---@return number sum
+ * local function add(a, b) return a + b end
+ * ```
+ *
+ * Lines starting with `@` are annotations, which are turned into `LuaDocTag`s.
+ * Every other line is free text and becomes part of the description.
+ */
+
+use crate::tokenisation::{Token, Location, Trivia, TriviaKind, newline_length};
+use crate::lua::{LuaTerminal, LuaBytes};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaDocComment {
+    /** The free text lines, joined with newlines. */
+    pub description: String,
+    pub tags:        Vec<LuaDocTag>,
+    /** Where the first line of the comment starts. */
+    pub location:    Location
+}
+
+/** An annotation in a documentation comment. Types are kept as they were
+ *  written, since they have a grammar of their own. */
+#[derive(Debug, Clone, PartialEq)]
+pub enum LuaDocTag {
+    /** `---@param name[?] type [description]` */
+    Param {
+        name:        String,
+        optional:    bool,
+        type_name:   String,
+        description: String
+    },
+    /** `---@return type [name] [description]` */
+    Return {
+        type_name:   String,
+        name:        Option<String>,
+        description: String
+    },
+    /** `---@class Name [: Parent, ...] [description]` */
+    Class {
+        name:        String,
+        parents:     Vec<String>,
+        description: String
+    },
+    /** `---@field [visibility] name[?] type [description]` */
+    Field {
+        name:        String,
+        optional:    bool,
+        type_name:   String,
+        description: String
+    },
+    /** `---@type type [description]` */
+    Type {
+        type_name:   String,
+        description: String
+    },
+    /** Any other annotation, such as `---@deprecated`, with the rest of its
+     *  line. */
+    Other {
+        tag:  String,
+        text: String
+    }
+}
+
+/**
+ * Gets the documentation comment for the statement starting with `token`,
+ * from the token's leading trivia. This is the run of `---` comments
+ * immediately above it; a blank line or any other comment ends the run, so
+ * that commented out code and separators like `------` aren't mistaken for
+ * documentation.
+ */
+pub fn get_doc_comment(token: &Token<LuaTerminal>) -> Option<LuaDocComment> {
+    let mut lines = Vec::new();
+    let mut newlines = 0;
+
+    for trivia in token.leading_trivia.iter().rev() {
+        match trivia.kind {
+            TriviaKind::Whitespace => { },
+            TriviaKind::Newline => {
+                newlines += 1;
+
+                if newlines > 1 {
+                    break;
+                }
+            },
+            TriviaKind::LineComment if is_doc_line(&trivia.text) => {
+                lines.push(trivia);
+                newlines = 0;
+            },
+            _ => break
+        }
+    }
+
+    let location = lines.last()?.location;
+
+    let lines: Vec<String> = lines.iter()
+        .rev()
+        .map(|trivia| String::from_utf8_lossy(&get_comment_text(trivia).unwrap()[1..]).into_owned())
+        .collect();
+
+    Some(parse_doc_comment(&lines, location))
+}
+
+/** Parses the lines of a documentation comment, each without its leading
+ *  `---`. */
+pub fn parse_doc_comment(lines: &[String], location: Location) -> LuaDocComment {
+    let mut description = Vec::new();
+    let mut tags = Vec::new();
+
+    for line in lines {
+        let line = line.trim();
+
+        match line.strip_prefix('@') {
+            Some(annotation) => tags.push(parse_tag(annotation)),
+            None             => description.push(line)
+        }
+    }
+
+    // Blank lines only separate paragraphs, so there's no need to keep them
+    // at either end.
+    while description.last() == Some(&"") {
+        description.pop();
+    }
+
+    let start = description.iter().position(|line| !line.is_empty()).unwrap_or(description.len());
+
+    LuaDocComment {
+        description: description[start..].join("\n"),
+        tags,
+        location
+    }
+}
+
+/**
+ * Gets the text of a comment without its delimiters: the `--` of a line
+ * comment, or the long brackets around a block comment, along with a line
+ * break straight after the opening bracket. Gives `None` for trivia that
+ * isn't a comment.
+ */
+pub fn get_comment_text(trivia: &Trivia) -> Option<LuaBytes> {
+    let text = &trivia.text;
+
+    match trivia.kind {
+        TriviaKind::LineComment => Some(LuaBytes::from(&text[2..])),
+        TriviaKind::BlockComment => {
+            let level = text[3..].iter().take_while(|byte| **byte == b'=').count();
+            let mut start = level + 4;
+            start = start + newline_length(&text[start..]);
+
+            let mut closing = vec![b']'];
+            closing.extend(std::iter::repeat_n(b'=', level));
+            closing.push(b']');
+
+            let end = match text.ends_with(&closing) && text.len() >= start + closing.len() {
+                true  => text.len() - closing.len(),
+                false => text.len()
+            };
+
+            Some(LuaBytes::from(&text[start..end]))
+        },
+        _ => None
+    }
+}
+
+/** Doc lines start with exactly three dashes, so separators made of dashes
+ *  are left out. */
+fn is_doc_line(text: &[u8]) -> bool {
+    text.starts_with(b"---") && text.get(3) != Some(&b'-')
+}
+
+fn parse_tag(annotation: &str) -> LuaDocTag {
+    let (tag, rest) = split_word(annotation);
+
+    match tag {
+        "param" => {
+            let (name, rest) = split_word(rest);
+            let (type_name, rest) = split_type(rest);
+            let (name, optional) = strip_optional(name);
+
+            LuaDocTag::Param {
+                name:        String::from(name),
+                optional,
+                type_name,
+                description: parse_description(rest)
+            }
+        },
+        "return" => {
+            let (type_name, rest) = split_type(rest);
+            let (name, description) = match split_word(rest) {
+                (word, after) if is_name(word) => (Some(String::from(word)), after),
+                _                              => (None, rest)
+            };
+
+            LuaDocTag::Return {
+                type_name,
+                name,
+                description: parse_description(description)
+            }
+        },
+        "class" => {
+            let (name, mut rest) = split_class_name(rest);
+            let mut parents = Vec::new();
+
+            if let Some(after) = rest.strip_prefix(':') {
+                rest = after;
+
+                loop {
+                    let (parent, after) = split_type(rest.trim_start_matches(','));
+                    if !parent.is_empty() {
+                        parents.push(parent);
+                    }
+                    rest = after;
+
+                    if !rest.starts_with(',') {
+                        break;
+                    }
+                }
+            }
+
+            LuaDocTag::Class {
+                name,
+                parents,
+                description: parse_description(rest)
+            }
+        },
+        "field" => {
+            let rest = match split_word(rest) {
+                ("public", after) | ("protected", after) | ("private", after) | ("package", after) => after,
+                _                                                                                   => rest
+            };
+            let (name, rest) = split_type(rest);
+            let (type_name, rest) = split_type(rest);
+            let (name, optional) = strip_optional(&name);
+
+            LuaDocTag::Field {
+                name:        String::from(name),
+                optional,
+                type_name,
+                description: parse_description(rest)
+            }
+        },
+        "type" => {
+            let (type_name, rest) = split_type(rest);
+
+            LuaDocTag::Type {
+                type_name,
+                description: parse_description(rest)
+            }
+        },
+        _ => LuaDocTag::Other {
+            tag:  String::from(tag),
+            text: String::from(rest)
+        }
+    }
+}
+
+/** Splits off the first word, giving the rest with leading whitespace
+ *  removed. */
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+
+    (&text[..end], text[end..].trim_start())
+}
+
+/**
+ * Splits off a type, which ends at whitespace outside of any brackets or
+ * quotes, unless the type is obviously unfinished there. That lets types
+ * like `table<string, number>`, `fun(a: number): string` and
+ * `string | nil` be read whole.
+ */
+fn split_type(text: &str) -> (String, &str) {
+    let text = text.trim_start();
+    let mut depth: usize = 0;
+    let mut quote = None;
+    let mut end = text.len();
+
+    for (idx, c) in text.char_indices() {
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            continue;
+        }
+
+        match c {
+            '"' | '\'' | '`'      => quote = Some(c),
+            '(' | '<' | '[' | '{' => depth += 1,
+            ')' | '>' | ']' | '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0     => {
+                end = idx;
+                break;
+            },
+            c if c.is_whitespace() && depth == 0 => {
+                let before = text[..idx].trim_end();
+                let after  = text[idx..].trim_start();
+
+                let unfinished = before.ends_with('|') || before.ends_with(':');
+                let continues  = after.starts_with('|') || (after.starts_with(':') && before.ends_with(')'));
+
+                if !unfinished && !continues {
+                    end = idx;
+                    break;
+                }
+            },
+            _ => { }
+        }
+    }
+
+    let type_name: String = text[..end].split_whitespace().collect::<Vec<_>>().join(" ");
+    (type_name, text[end..].trim_start())
+}
+
+/** Class names can be preceded by attributes like `(exact)`, and can have
+ *  their parents' colon directly after them. */
+fn split_class_name(text: &str) -> (String, &str) {
+    let mut text = text.trim_start();
+
+    if text.starts_with('(') {
+        if let Some(idx) = text.find(')') {
+            text = text[idx + 1..].trim_start();
+        }
+    }
+
+    let end = text.find(|c: char| c.is_whitespace() || c == ':').unwrap_or(text.len());
+    (String::from(&text[..end]), text[end..].trim_start())
+}
+
+fn strip_optional(name: &str) -> (&str, bool) {
+    match name.strip_suffix('?') {
+        Some(name) => (name, true),
+        None       => (name, false)
+    }
+}
+
+/** Descriptions can be set apart from what comes before them with a `#`. */
+fn parse_description(text: &str) -> String {
+    let text = text.trim();
+    String::from(text.strip_prefix('#').unwrap_or(text).trim_start())
+}
+
+fn is_name(word: &str) -> bool {
+    let mut chars = word.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _                                              => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::{get_lua_tokeniser, LuaDialect};
+
+    fn doc_comments(src: &str) -> Vec<Option<LuaDocComment>> {
+        let mut tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+        let tokens = tokeniser.tokenise(src).ok().unwrap();
+
+        tokens.iter()
+            .filter(|token| token.token_type == LuaTerminal::Local)
+            .map(get_doc_comment)
+            .collect()
+    }
+
+    #[test]
+    fn parses_annotations_and_free_text() {
+        let docs = doc_comments(concat!(
+            "--- Adds two numbers.\n",
+            "---\n",
+            "--- Both must be finite.\n",
+            "---@param a number\n",
+            "---@param b? number # the second number\n",
+            "---@param f fun(x: integer): string | nil callback\n",
+            "---@return number sum the total\n",
+            "---@return table<string, number>\n",
+            "---@deprecated use plus instead\n",
+            "local function add(a, b) return a + b end\n"
+        ));
+
+        let doc = docs[0].clone().unwrap();
+        assert_eq!(doc.description, "Adds two numbers.\n\nBoth must be finite.");
+        assert_eq!(doc.location, Location { line: 1, col: 1 });
+        assert_eq!(doc.tags, vec![
+            LuaDocTag::Param {
+                name: String::from("a"), optional: false,
+                type_name: String::from("number"), description: String::new()
+            },
+            LuaDocTag::Param {
+                name: String::from("b"), optional: true,
+                type_name: String::from("number"), description: String::from("the second number")
+            },
+            LuaDocTag::Param {
+                name: String::from("f"), optional: false,
+                type_name: String::from("fun(x: integer): string | nil"), description: String::from("callback")
+            },
+            LuaDocTag::Return {
+                type_name: String::from("number"), name: Some(String::from("sum")),
+                description: String::from("the total")
+            },
+            LuaDocTag::Return {
+                type_name: String::from("table<string, number>"), name: None,
+                description: String::new()
+            },
+            LuaDocTag::Other { tag: String::from("deprecated"), text: String::from("use plus instead") }
+        ]);
+    }
+
+    #[test]
+    fn parses_classes_fields_and_types() {
+        let docs = doc_comments(concat!(
+            "---@class (exact) Point: Shape, Drawable A point\n",
+            "---@field private x number\n",
+            "---@field label? string # shown on hover\n",
+            "local Point = {}\n",
+            "---@type Point[]\n",
+            "local points = {}\n"
+        ));
+
+        assert_eq!(docs[0].clone().unwrap().tags, vec![
+            LuaDocTag::Class {
+                name: String::from("Point"),
+                parents: vec![String::from("Shape"), String::from("Drawable")],
+                description: String::from("A point")
+            },
+            LuaDocTag::Field {
+                name: String::from("x"), optional: false,
+                type_name: String::from("number"), description: String::new()
+            },
+            LuaDocTag::Field {
+                name: String::from("label"), optional: true,
+                type_name: String::from("string"), description: String::from("shown on hover")
+            }
+        ]);
+        assert_eq!(docs[1].clone().unwrap().tags, vec![
+            LuaDocTag::Type { type_name: String::from("Point[]"), description: String::new() }
+        ]);
+    }
+
+    #[test]
+    fn only_comments_directly_above_are_documentation() {
+        let docs = doc_comments(concat!(
+            "--- Not attached, there's a blank line.\n",
+            "\n",
+            "local a = 1\n",
+            "-- An ordinary comment.\n",
+            "local b = 2\n",
+            "-----------\n",
+            "--- Attached.\n",
+            "  local c = 3 --- Trailing, so not for d.\n",
+            "local d = 4\n"
+        ));
+
+        assert_eq!(docs[0], None);
+        assert_eq!(docs[1], None);
+        assert_eq!(docs[2].as_ref().map(|doc| doc.description.as_str()), Some("Attached."));
+        assert_eq!(docs[3], None);
+    }
+
+    #[test]
+    fn comment_text_leaves_out_delimiters() {
+        let mut tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+        let tokens = tokeniser.tokenise("-- line\n--[==[\nblock]]\n]==]\nx = 1").ok().unwrap();
+
+        let comments: Vec<LuaBytes> = tokens.iter()
+            .flat_map(|token| token.leading_trivia.iter())
+            .filter_map(get_comment_text)
+            .collect();
+
+        assert_eq!(comments, vec![LuaBytes::from(" line"), LuaBytes::from("block]]\n")]);
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt::{Display, Formatter};
+
+use crate::tokenisation::{Token, Location, TokenisationError, TokenisationErrorType, Diagnostic};
+use crate::lua::LuaTerminal;
+
+/** Why some Lua source couldn't be read into a syntax tree. */
+pub enum LuaReadError {
+    /** The source didn't tokenise. */
+    Tokenisation(TokenisationError<LuaTerminal, TokenisationErrorType>),
+    /** The source doesn't follow the grammar. The message says what was
+     *  wrong without saying where, and is reported near the token it was
+     *  found at, as the reference parser does. */
+    Syntax { message: String, near: Box<Token<LuaTerminal>> },
+    /** The source breaks a rule other than its grammar, like a `goto`
+     *  without a label. These aren't reported near a token, but the token
+     *  the part at fault starts with is kept to show where it is. */
+    Semantic { message: String, at: Box<Token<LuaTerminal>> }
+}
+
+impl LuaReadError {
+    pub fn location(&self) -> Location {
+        match self {
+            LuaReadError::Tokenisation(error) => error.partial_token.location,
+            LuaReadError::Syntax { near, .. } => near.location,
+            LuaReadError::Semantic { at, .. } => at.location
+        }
+    }
+
+    /** Describes the error the way the reference Lua implementation does,
+     *  without the line number. */
+    pub fn message(&self) -> String {
+        match self {
+            LuaReadError::Tokenisation(error)      => error.message(),
+            LuaReadError::Syntax { message, near } => match near.token_type {
+                LuaTerminal::EndOfFile => format!("{} near {}", message, near_text(near)),
+                _                      => format!("{} near '{}'", message, near_text(near))
+            },
+            LuaReadError::Semantic { message, .. } => message.clone()
+        }
+    }
+}
+
+/** The source text of the token an error is found at, or `<eof>` at the
+ *  end of the source, as `luaX_token2str` gives it. */
+fn near_text(token: &Token<LuaTerminal>) -> String {
+    match token.token_type {
+        LuaTerminal::EndOfFile => String::from("<eof>"),
+        _                      => String::from_utf8_lossy(&token.text).into_owned()
+    }
+}
+
+impl From<TokenisationError<LuaTerminal, TokenisationErrorType>> for LuaReadError {
+    fn from(error: TokenisationError<LuaTerminal, TokenisationErrorType>) -> LuaReadError {
+        LuaReadError::Tokenisation(error)
+    }
+}
+
+impl Display for LuaReadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}: {}", self.location().line, self.message())
+    }
+}
+
+impl Diagnostic for LuaReadError {
+    fn location(&self) -> Location {
+        LuaReadError::location(self)
+    }
+
+    fn length(&self) -> usize {
+        match self {
+            LuaReadError::Tokenisation(error) => error.length(),
+            LuaReadError::Syntax { near, .. } => near_text(near).chars().count(),
+            LuaReadError::Semantic { at, .. } => near_text(at).chars().count()
+        }
+    }
+
+    fn message(&self) -> String {
+        LuaReadError::message(self)
+    }
+
+    fn start_line(&self) -> Option<(usize, &'static str)> {
+        match self {
+            LuaReadError::Tokenisation(error) => error.start_line(),
+            _                                 => None
+        }
+    }
+}
@@ -0,0 +1,457 @@
+/*!
+ * Formats Lua source, the way `phobos fmt` does.
+ *
+ * The source is read into a syntax tree and printed back out from that, with
+ * the tokens it was read from on hand for the comments and blank lines to
+ * keep. So whatever the source looked like, the output only depends on what
+ * it says and where its comments are, which is what makes formatting the
+ * output again leave it as it is.
+ */
+
+use crate::tokenisation::{Token, Location, newline_length};
+use crate::lua::{LuaTerminal, LuaDialect, LuaReadError, get_lua_file_tokeniser};
+use crate::lua::syntax_tree::*;
+
+type FormatResult<T> = Result<T, LuaReadError>;
+
+/** Formats Lua source with settings to suit a project. */
+pub struct LuaFormatter {
+    dialect:      LuaDialect,
+    indent_width: usize,
+    tabs:         bool,
+    line_width:   usize,
+    quote_style:  LuaQuoteStyle,
+    call_parens:  LuaCallParens
+}
+
+/** Statements that lines to be formatted fall in, along with the lines
+ *  they cover and how deeply they are nested. */
+struct LuaRun {
+    block:      LuaBlock,
+    depth:      usize,
+    first_line: usize,
+    last_line:  usize
+}
+
+impl LuaFormatter {
+    pub fn new(dialect: LuaDialect) -> LuaFormatter {
+        LuaFormatter {
+            dialect,
+            indent_width: 4,
+            tabs:         false,
+            line_width:   100,
+            quote_style:  LuaQuoteStyle::Double,
+            call_parens:  LuaCallParens::Keep
+        }
+    }
+
+    /** How many spaces each level of nesting is indented by, unless it is
+     *  indented with tabs. Four by default. */
+    pub fn with_indent_width(mut self,
+        indent_width: usize
+    ) -> LuaFormatter {
+        self.indent_width = indent_width;
+        self
+    }
+
+    /** Whether to indent with a tab for each level of nesting instead of
+     *  spaces. */
+    pub fn with_tabs(mut self,
+        tabs: bool
+    ) -> LuaFormatter {
+        self.tabs = tabs;
+        self
+    }
+
+    /** How long lines can be before they are broken up, 100 by default. */
+    pub fn with_line_width(mut self,
+        line_width: usize
+    ) -> LuaFormatter {
+        self.line_width = line_width;
+        self
+    }
+
+    /** Which quotes strings are written in, double quotes by default. */
+    pub fn with_quote_style(mut self,
+        quote_style: LuaQuoteStyle
+    ) -> LuaFormatter {
+        self.quote_style = quote_style;
+        self
+    }
+
+    /** Whether calls with one string or table as their argument have
+     *  brackets, left as they are by default. */
+    pub fn with_call_parens(mut self,
+        call_parens: LuaCallParens
+    ) -> LuaFormatter {
+        self.call_parens = call_parens;
+        self
+    }
+
+    fn printer(&self) -> LuaPrinter {
+        let indent = match self.tabs {
+            true  => String::from("\t"),
+            false => " ".repeat(self.indent_width)
+        };
+
+        LuaPrinter::new()
+            .with_indent(&indent)
+            .with_line_width(self.line_width)
+            .with_quote_style(self.quote_style)
+            .with_call_parens(self.call_parens)
+    }
+
+    fn read(&self, source: &[u8]) -> FormatResult<(LuaChunk, Vec<Token<LuaTerminal>>)> {
+        let mut tokeniser = get_lua_file_tokeniser(self.dialect).unwrap();
+        let tokens: Vec<Token<LuaTerminal>> = tokeniser.tokenise(source.to_vec())?.into_iter().collect();
+        let chunk = read_lua_tokens(tokens.clone(), self.dialect)?;
+
+        Ok((chunk, tokens))
+    }
+
+    /** Formats a whole file. Source that doesn't read gives the error
+     *  found instead. */
+    pub fn format(&self, source: &[u8]) -> FormatResult<String> {
+        let (chunk, tokens) = self.read(source)?;
+        Ok(self.printer().format_chunk(&chunk, &tokens))
+    }
+
+    /** Whether a file is formatted already. */
+    pub fn check(&self, source: &[u8]) -> FormatResult<bool> {
+        Ok(self.format(source)?.as_bytes() == source)
+    }
+
+    /**
+     * Formats the statements on the lines from `first_line` to `last_line`,
+     * counting from 1, and leaves the rest of the file as it is.
+     *
+     * The statements are taken from the innermost block that has whole lines
+     * of them covering the range, which means a range inside a function
+     * that is passed as an argument formats the whole statement it is part
+     * of, and a range that covers no statements changes nothing.
+     */
+    pub fn format_range(&self, source: &[u8], first_line: usize, last_line: usize) -> FormatResult<String> {
+        let (chunk, tokens) = self.read(source)?;
+        let text = String::from_utf8_lossy(source).into_owned();
+
+        let run = match find_run(&chunk.block, 0, &tokens, first_line, last_line) {
+            Some(run) => run,
+            None      => return Ok(text)
+        };
+
+        let from = Location { line: run.first_line, col: 1 };
+        let formatted = self.printer().format_block(&run.block, run.depth, &tokens, from);
+
+        let lines = line_starts(text.as_bytes());
+        let start = lines[run.first_line - 1];
+        let end = lines.get(run.last_line).copied().unwrap_or(text.len());
+
+        let mut output = String::from(&text[..start]);
+        output.push_str(&formatted);
+
+        if lines.len() > run.last_line {
+            output.push('\n');
+        }
+
+        output.push_str(&text[end..]);
+        Ok(output)
+    }
+}
+
+/** Where each line of some source starts. */
+fn line_starts(source: &[u8]) -> Vec<usize> {
+    let mut starts = vec![0];
+    let mut idx = 0;
+
+    while idx < source.len() {
+        match newline_length(&source[idx..]) {
+            0      => idx += 1,
+            length => {
+                idx += length;
+                starts.push(idx);
+            }
+        }
+    }
+
+    starts
+}
+
+/** The blocks directly inside a statement. */
+fn child_blocks(stat: &LuaStat) -> Vec<&LuaBlock> {
+    match &stat.kind {
+        LuaStatKind::Do(block)              => vec![block],
+        LuaStatKind::While(_, block)        => vec![block],
+        LuaStatKind::Repeat(block, _)       => vec![block],
+        LuaStatKind::For(_, _, _, _, block) => vec![block],
+        LuaStatKind::ForIn(_, _, block)     => vec![block],
+        LuaStatKind::Function(_, body)      => vec![&body.block],
+        LuaStatKind::LocalFunction(_, body) => vec![&body.block],
+        LuaStatKind::If(_, block, else_ifs, else_block) => {
+            let mut blocks = vec![block];
+            blocks.extend(else_ifs.iter().map(|(_, block)| block));
+            blocks.extend(else_block.iter());
+            blocks
+        },
+        _ => Vec::new()
+    }
+}
+
+/**
+ * Finds the statements of a block, `depth` blocks deep, that the lines from
+ * `first_line` to `last_line` fall in, or in a block inside the one
+ * statement they fall in if there is only one. The statements have to have
+ * lines to themselves, so any statements that share a line with them are
+ * taken too, and if something else shares one there isn't a run to format.
+ */
+fn find_run(
+    block:      &LuaBlock,
+    depth:      usize,
+    tokens:     &[Token<LuaTerminal>],
+    first_line: usize,
+    last_line:  usize
+) -> Option<LuaRun> {
+    let mut spans: Vec<LuaSpan> = block.stats.iter().map(|stat| stat.span).collect();
+    spans.extend(block.ret_stat.iter().map(|ret_stat| ret_stat.span));
+
+    let mut low = spans.iter().position(|span| span.start.line <= last_line && span.end.line >= first_line)?;
+    let mut high = spans.iter().rposition(|span| span.start.line <= last_line && span.end.line >= first_line)?;
+
+    while low > 0 && spans[low - 1].end.line >= spans[low].start.line {
+        low -= 1;
+    }
+
+    while high + 1 < spans.len() && spans[high + 1].start.line <= spans[high].end.line {
+        high += 1;
+    }
+
+    let first = tokens.iter().position(|token| token.location == spans[low].start)?;
+    let last = tokens.iter().position(|token| token.end_location() == spans[high].end)?;
+
+    let clean_before = first == 0 || tokens[first - 1].end_location().line < spans[low].start.line;
+    let clean_after = match tokens.get(last + 1) {
+        Some(token) => token.token_type == LuaTerminal::EndOfFile || token.location.line > spans[high].end.line,
+        None        => true
+    };
+
+    if !clean_before || !clean_after {
+        return None;
+    }
+
+    if low == high && low < block.stats.len() {
+        let stat = &block.stats[low];
+
+        if stat.span.start.line <= first_line && last_line <= stat.span.end.line {
+            for child in child_blocks(stat) {
+                if let Some(run) = find_run(child, depth + 1, tokens, first_line, last_line) {
+                    return Some(run);
+                }
+            }
+        }
+    }
+
+    let stats_end = (high + 1).min(block.stats.len());
+
+    Some(LuaRun {
+        block:      LuaBlock {
+            stats:    block.stats[low..stats_end].to_vec(),
+            ret_stat: if high == block.stats.len() { block.ret_stat.clone() } else { None },
+            span:     LuaSpan::new(spans[low].start, spans[high].end)
+        },
+        depth,
+        first_line: spans[low].start.line,
+        last_line:  spans[high].end.line
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use crate::lua::read_lua_chunk;
+
+    fn corpus() -> Vec<(String, Vec<u8>)> {
+        let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus");
+        let mut files: Vec<(String, Vec<u8>)> = fs::read_dir(directory).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map(|extension| extension == "lua").unwrap_or(false))
+            .map(|path| (path.display().to_string(), fs::read(&path).unwrap()))
+            .collect();
+
+        files.sort();
+        assert!(!files.is_empty());
+        files
+    }
+
+    struct SemicolonRemover;
+
+    impl Folder for SemicolonRemover {
+        fn fold_stats(&mut self, stat: LuaStat) -> Vec<LuaStat> {
+            match stat.kind {
+                LuaStatKind::Semicolon => Vec::new(),
+                _                      => vec![self.fold_stat(stat)]
+            }
+        }
+    }
+
+    /** A chunk printed without its source, which leaves out everything but
+     *  what it says. */
+    fn meaning(source: &[u8]) -> String {
+        let chunk = read_lua_chunk(source, LuaDialect::Lua54, true).ok().unwrap();
+        print_lua_chunk(&SemicolonRemover.fold_chunk(chunk))
+    }
+
+    fn format(formatter: &LuaFormatter, source: &str) -> String {
+        formatter.format(source.as_bytes()).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    #[test]
+    fn formatting_the_corpus_twice_changes_nothing() {
+        let formatters = vec![
+            LuaFormatter::new(LuaDialect::Lua54),
+            LuaFormatter::new(LuaDialect::Lua54).with_tabs(true).with_line_width(40),
+            LuaFormatter::new(LuaDialect::Lua54).with_indent_width(2).with_line_width(60)
+                .with_quote_style(LuaQuoteStyle::Single).with_call_parens(LuaCallParens::Omit),
+            LuaFormatter::new(LuaDialect::Lua54).with_line_width(20)
+                .with_quote_style(LuaQuoteStyle::Keep).with_call_parens(LuaCallParens::Always)
+        ];
+
+        for (name, source) in corpus() {
+            for formatter in &formatters {
+                let formatted = formatter.format(&source).unwrap_or_else(|error| panic!("{}: {}", name, error));
+                let reformatted = formatter.format(formatted.as_bytes()).unwrap_or_else(|error| panic!("{}: {}", name, error));
+
+                assert!(formatted == reformatted, "{} formatted as:\n{}\nand then as:\n{}", name, formatted, reformatted);
+                assert!(formatter.check(formatted.as_bytes()).ok().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn formatting_the_corpus_keeps_what_it_says() {
+        let formatter = LuaFormatter::new(LuaDialect::Lua54).with_line_width(40);
+
+        for (name, source) in corpus() {
+            let formatted = formatter.format(&source).unwrap_or_else(|error| panic!("{}: {}", name, error));
+            assert!(meaning(&source) == meaning(formatted.as_bytes()), "{} formatted as:\n{}", name, formatted);
+        }
+    }
+
+    #[test]
+    fn keeps_comments_and_blank_lines() {
+        let formatter = LuaFormatter::new(LuaDialect::Lua54);
+        let source = "\
+-- leading
+
+
+local a = 1 -- trailing
+local b = { -- after the brace
+  1, -- one
+  --[[ before two ]] 2
+}
+if a then -- after then
+    -- only a comment
+end
+call(a, --[[ inside ]] b); -- after the semicolon
+return a
+-- at the end
+";
+
+        assert_eq!(format(&formatter, source), "\
+-- leading
+
+local a = 1 -- trailing
+local b = { -- after the brace
+    1, -- one
+    --[[ before two ]]
+    2,
+}
+if a then -- after then
+    -- only a comment
+end
+call(
+    a, --[[ inside ]]
+    b
+) -- after the semicolon
+return a
+-- at the end
+");
+    }
+
+    #[test]
+    fn breaks_lines_that_are_too_long() {
+        let formatter = LuaFormatter::new(LuaDialect::Lua54).with_line_width(24);
+
+        assert_eq!(format(&formatter, "local t = {1, 2, 3}\nf(alpha, beta, gamma, delta)\nx = alpha + beta + gamma + delta\n"), "\
+local t = {1, 2, 3}
+f(
+    alpha,
+    beta,
+    gamma,
+    delta
+)
+x = alpha
+    + beta
+    + gamma
+    + delta
+");
+    }
+
+    #[test]
+    fn applies_quote_and_call_styles() {
+        let single = LuaFormatter::new(LuaDialect::Lua54)
+            .with_quote_style(LuaQuoteStyle::Single)
+            .with_call_parens(LuaCallParens::Omit);
+        let always = LuaFormatter::new(LuaDialect::Lua54)
+            .with_call_parens(LuaCallParens::Always);
+
+        assert_eq!(format(&single, "f(\"a\")\nf(\"it's\")\nf({})\nf(\"a\", 1)\n"), "f 'a'\nf \"it's\"\nf {}\nf('a', 1)\n");
+        assert_eq!(format(&always, "f 'a'\nf {}\nx = f[[a]].b\n"), "f(\"a\")\nf({})\nx = f([[a]]).b\n");
+        assert_eq!(format(&always, "x = 0x10 + 1e2\n"), "x = 0x10 + 1e2\n");
+    }
+
+    #[test]
+    fn checks_whether_files_are_formatted() {
+        let formatter = LuaFormatter::new(LuaDialect::Lua54);
+
+        assert!(formatter.check(b"local a = 1\n").ok().unwrap());
+        assert!(!formatter.check(b"local  a=1\n").ok().unwrap());
+        assert!(formatter.check(b"local = 1\n").is_err());
+    }
+
+    #[test]
+    fn formats_only_the_statements_in_range() {
+        let formatter = LuaFormatter::new(LuaDialect::Lua54);
+        let source = "\
+x  =  1
+function f()
+  local a  =  1
+      local b=2 ; local c=3
+  return a+b
+end
+y  =  2
+";
+
+        assert_eq!(formatter.format_range(source.as_bytes(), 4, 4).ok().unwrap(), "\
+x  =  1
+function f()
+  local a  =  1
+    local b = 2
+    local c = 3
+  return a+b
+end
+y  =  2
+");
+        assert_eq!(formatter.format_range(source.as_bytes(), 1, 2).ok().unwrap(), "\
+x = 1
+function f()
+    local a = 1
+    local b = 2
+    local c = 3
+    return a + b
+end
+y  =  2
+");
+        assert_eq!(formatter.format_range(source.as_bytes(), 7, 8).ok().unwrap(), source.replace("y  =  2", "y = 2"));
+    }
+}
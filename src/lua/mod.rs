@@ -2,12 +2,26 @@ mod syntax_tree;
 mod types;
 mod nonterminals;
 mod terminals;
+mod dialect;
+mod numeral;
 mod tokeniser;
+mod doc_comment;
 mod parser;
+mod format;
+mod analysis;
+mod optimise;
+mod error;
 
 pub use syntax_tree::*;
 pub use types::*;
 pub use nonterminals::LuaNonterminal;
 pub use terminals::{LuaTerminal, LuaTokenData};
-pub use tokeniser::get_lua_tokeniser;
+pub use dialect::LuaDialect;
+pub use numeral::LuaNumeral;
+pub use tokeniser::{get_lua_tokeniser, get_lua_file_tokeniser};
 pub use parser::get_lua_parser;
+pub use format::LuaFormatter;
+pub use analysis::*;
+pub use optimise::*;
+pub use doc_comment::{LuaDocComment, get_doc_comment};
+pub use error::LuaReadError;
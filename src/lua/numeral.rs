@@ -0,0 +1,254 @@
+/*!
+ * Conversion of numeral text to numbers, following `luaO_str2num` in the
+ * reference implementation.
+ *
+ * Numerals are first read as integers. Hexadecimal integers wrap around on
+ * overflow, while decimal integers that overflow are read as floats instead.
+ * Anything that isn't an integer is read as a float, which can be decimal or
+ * hexadecimal with a binary exponent. Dialects without an integer subtype
+ * have nothing to wrap around, so they read hexadecimal integers that
+ * overflow as floats too.
+ */
+
+use std::fmt::{Display, Formatter};
+
+use crate::lua::LuaDialect;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LuaNumeral {
+    Integer(i64),
+    Float(f64),
+    /** LuaJIT `LL` numerals. */
+    SignedLong(i64),
+    /** LuaJIT `ULL` numerals. */
+    UnsignedLong(u64),
+    /** LuaJIT `i` numerals. */
+    Imaginary(f64)
+}
+
+impl Display for LuaNumeral {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            LuaNumeral::Integer(value) => write!(f, "{}",   value),
+            LuaNumeral::Float(value)   => write!(f, "{:?}", value),
+            LuaNumeral::SignedLong(value)   => write!(f, "{}LL",   value),
+            LuaNumeral::UnsignedLong(value) => write!(f, "{}ULL",  value),
+            LuaNumeral::Imaginary(value)    => write!(f, "{:?}i",  value)
+        }
+    }
+}
+
+/** Converts a numeral to a number, or gives `None` if it is malformed. */
+pub fn parse_numeral(dialect: LuaDialect, text: &str) -> Option<LuaNumeral> {
+    let integer = match is_hex(text) && !dialect.has_integers() {
+        true  => parse_integer(text).filter(|_| fits_in_integer(&text[2..])),
+        false => parse_integer(text)
+    };
+
+    integer
+        .map(LuaNumeral::Integer)
+        .or_else(|| parse_float(text).map(LuaNumeral::Float))
+}
+
+/** Converts a numeral that may have one of LuaJIT's suffixes, which are
+ *  case insensitive. `LL` and `ULL` only apply to integers. */
+pub fn parse_suffixed_numeral(text: &str) -> Option<LuaNumeral> {
+    let lower = text.to_ascii_lowercase();
+
+    if let Some(digits) = lower.strip_suffix("ull") {
+        parse_unsigned(digits).map(LuaNumeral::UnsignedLong)
+    } else if let Some(digits) = lower.strip_suffix("ll") {
+        parse_unsigned(digits).map(|value| LuaNumeral::SignedLong(value as i64))
+    } else if let Some(digits) = lower.strip_suffix('i') {
+        match parse_numeral(LuaDialect::LuaJit, digits)? {
+            LuaNumeral::Integer(value) => Some(LuaNumeral::Imaginary(value as f64)),
+            LuaNumeral::Float(value)   => Some(LuaNumeral::Imaginary(value)),
+            _                          => None
+        }
+    } else {
+        parse_numeral(LuaDialect::LuaJit, text)
+    }
+}
+
+/** Reads an integer that uses all 64 bits, for the `LL` and `ULL`
+ *  suffixes. */
+fn parse_unsigned(text: &str) -> Option<u64> {
+    if is_hex(text) {
+        parse_integer(text).map(|value| value as u64)
+    } else if !text.is_empty() && text.chars().all(|c| c.is_ascii_digit()) {
+        text.parse::<u64>().ok()
+    } else {
+        None
+    }
+}
+
+fn is_hex(text: &str) -> bool {
+    text.starts_with("0x") || text.starts_with("0X")
+}
+
+fn parse_integer(text: &str) -> Option<i64> {
+    if is_hex(text) {
+        let digits = &text[2..];
+
+        if digits.is_empty() {
+            return None;
+        }
+
+        let mut value: u64 = 0;
+
+        for c in digits.chars() {
+            value = value.wrapping_mul(16).wrapping_add(c.to_digit(16)? as u64);
+        }
+
+        Some(value as i64)
+    } else {
+        if text.is_empty() || !text.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        text.parse::<i64>().ok()
+    }
+}
+
+/** Whether hexadecimal digits give a value an integer can hold without
+ *  wrapping around. */
+fn fits_in_integer(digits: &str) -> bool {
+    digits.chars()
+        .try_fold(0u64, |value, c| value.checked_mul(16)?.checked_add(c.to_digit(16)? as u64))
+        .map(|value| value <= i64::MAX as u64)
+        .unwrap_or(false)
+}
+
+fn parse_float(text: &str) -> Option<f64> {
+    if is_hex(text) {
+        parse_hex_float(&text[2..])
+    } else {
+        parse_decimal_float(text)
+    }
+}
+
+/** Reads `digits [. digits] [(e|E) [+|-] digits]`, with at least one digit
+ *  in the mantissa. */
+fn parse_decimal_float(text: &str) -> Option<f64> {
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(idx) => (&text[..idx], Some(&text[idx + 1..])),
+        None      => (text, None)
+    };
+
+    let mut parts = mantissa.splitn(2, '.');
+    let whole     = parts.next().unwrap_or("");
+    let fraction  = parts.next().unwrap_or("");
+
+    let valid_mantissa = (!whole.is_empty() || !fraction.is_empty()) &&
+        whole.chars().all(|c| c.is_ascii_digit()) &&
+        fraction.chars().all(|c| c.is_ascii_digit());
+
+    if !valid_mantissa || !exponent.map(is_exponent).unwrap_or(true) {
+        return None;
+    }
+
+    text.parse::<f64>().ok()
+}
+
+/** Reads `hexdigits [. hexdigits] [(p|P) [+|-] digits]` after the `0x`, with
+ *  at least one digit in the mantissa. The exponent is a power of two. */
+fn parse_hex_float(text: &str) -> Option<f64> {
+    let (mantissa, exponent) = match text.find(['p', 'P']) {
+        Some(idx) => (&text[..idx], Some(&text[idx + 1..])),
+        None      => (text, None)
+    };
+
+    let mut value       = 0.0;
+    let mut scale: i32  = 0;
+    let mut seen_digit  = false;
+    let mut seen_dot    = false;
+
+    for c in mantissa.chars() {
+        if c == '.' {
+            if seen_dot {
+                return None;
+            }
+            seen_dot = true;
+        } else {
+            value = value * 16.0 + c.to_digit(16)? as f64;
+            seen_digit = true;
+
+            if seen_dot {
+                scale -= 4;
+            }
+        }
+    }
+
+    if !seen_digit {
+        return None;
+    }
+
+    let exponent = match exponent {
+        Some(exponent) if is_exponent(exponent) => exponent.parse::<i32>().unwrap_or(
+            if exponent.starts_with('-') { i32::MIN / 2 } else { i32::MAX / 2 }
+        ),
+        Some(_) => return None,
+        None    => 0
+    };
+
+    Some(value * 2f64.powi(scale.saturating_add(exponent)))
+}
+
+fn is_exponent(text: &str) -> bool {
+    let digits = text.strip_prefix(|c| c == '+' || c == '-').unwrap_or(text);
+
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_integers_and_floats() {
+        assert_eq!(parse_numeral(LuaDialect::Lua54, "3"),          Some(LuaNumeral::Integer(3)));
+        assert_eq!(parse_numeral(LuaDialect::Lua54, "0xFF"),       Some(LuaNumeral::Integer(255)));
+        assert_eq!(parse_numeral(LuaDialect::Lua54, "2.75"),       Some(LuaNumeral::Float(2.75)));
+        assert_eq!(parse_numeral(LuaDialect::Lua54, ".5"),         Some(LuaNumeral::Float(0.5)));
+        assert_eq!(parse_numeral(LuaDialect::Lua54, "3."),         Some(LuaNumeral::Float(3.0)));
+        assert_eq!(parse_numeral(LuaDialect::Lua54, "1e10"),       Some(LuaNumeral::Float(1e10)));
+        assert_eq!(parse_numeral(LuaDialect::Lua54, "2E-3"),       Some(LuaNumeral::Float(2e-3)));
+        assert_eq!(parse_numeral(LuaDialect::Lua54, "0x1p4"),      Some(LuaNumeral::Float(16.0)));
+        assert_eq!(parse_numeral(LuaDialect::Lua54, "0xA.8p-1"),   Some(LuaNumeral::Float(5.25)));
+        assert_eq!(parse_numeral(LuaDialect::Lua54, "0x.1"),       Some(LuaNumeral::Float(0.0625)));
+    }
+
+    #[test]
+    fn wraps_hex_and_widens_decimal_overflow() {
+        assert_eq!(parse_numeral(LuaDialect::Lua54, "0xffffffffffffffff"),  Some(LuaNumeral::Integer(-1)));
+        assert_eq!(parse_numeral(LuaDialect::Lua54, "0x10000000000000001"), Some(LuaNumeral::Integer(1)));
+        assert_eq!(parse_numeral(LuaDialect::Lua54, "9223372036854775807"), Some(LuaNumeral::Integer(i64::MAX)));
+        assert_eq!(parse_numeral(LuaDialect::Lua54, "9223372036854775808"), Some(LuaNumeral::Float(9223372036854775808.0)));
+    }
+
+    #[test]
+    fn reads_hex_overflow_as_floats_without_integers() {
+        assert_eq!(parse_numeral(LuaDialect::Lua51, "0xffffffffffffffffff"), Some(LuaNumeral::Float(4722366482869645213696.0)));
+        assert_eq!(parse_numeral(LuaDialect::Luau,  "0x8000000000000000"),   Some(LuaNumeral::Float(9223372036854775808.0)));
+        assert_eq!(parse_numeral(LuaDialect::Lua52, "0x7fffffffffffffff"),   Some(LuaNumeral::Integer(i64::MAX)));
+        assert_eq!(parse_suffixed_numeral("0xffffffffffffffff"),             Some(LuaNumeral::Float(18446744073709551615.0)));
+    }
+
+    #[test]
+    fn reads_luajit_suffixes() {
+        assert_eq!(parse_suffixed_numeral("1LL"),     Some(LuaNumeral::SignedLong(1)));
+        assert_eq!(parse_suffixed_numeral("0xffULL"), Some(LuaNumeral::UnsignedLong(255)));
+        assert_eq!(parse_suffixed_numeral("18446744073709551615ull"), Some(LuaNumeral::UnsignedLong(u64::MAX)));
+        assert_eq!(parse_suffixed_numeral("2.5i"),    Some(LuaNumeral::Imaginary(2.5)));
+        assert_eq!(parse_suffixed_numeral("12"),      Some(LuaNumeral::Integer(12)));
+        assert_eq!(parse_suffixed_numeral("1.5LL"),   None);
+        assert_eq!(parse_suffixed_numeral("1LLU"),    None);
+    }
+
+    #[test]
+    fn rejects_malformed_numerals() {
+        for text in &["3..2", "0xg", "0x", "1e", "1e+", "0x1p", "12abc", "1.2.3", "0x1.2.3", "3f"] {
+            assert_eq!(parse_numeral(LuaDialect::Lua54, text), None, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,318 @@
+/*!
+ * Removes code that can never run, or whose running makes no difference:
+ * branches of `if`s whose conditions are always false, or that come after
+ * one that is always true, `while` loops whose conditions are always false,
+ * statements after a `return`, `break` or `goto` that no label makes
+ * reachable again, and unused locals whose values can be worked out without
+ * anything else happening.
+ *
+ * Conditions only count as always true or false when they are literals, so
+ * this is best run after folding constants.
+ */
+
+use std::collections::HashSet;
+use std::mem;
+
+use crate::lua::LuaNumeral;
+use crate::lua::syntax_tree::*;
+use crate::lua::analysis::{LuaScopes, LuaResolution, resolve_lua_chunk};
+use crate::lua::analysis::flow::always_exits;
+use crate::lua::optimise::LuaChange;
+
+/** Removes the dead code in a chunk, giving what it removed. Removing some
+ *  code can leave the locals it used unused, so this goes on until there's
+ *  nothing left to remove. */
+pub fn eliminate_lua_dead_code(chunk: &mut LuaChunk) -> Vec<LuaChange> {
+    let mut changes = Vec::new();
+
+    loop {
+        let scopes = resolve_lua_chunk(chunk);
+        let mut eliminator = LuaDeadCodeEliminator {
+            scopes:  &scopes,
+            changes: Vec::new()
+        };
+
+        eliminator.visit_chunk_mut(chunk);
+
+        if eliminator.changes.is_empty() {
+            return changes;
+        }
+
+        changes.extend(eliminator.changes);
+    }
+}
+
+/** Whether a condition is always true or always false, if it's a literal. */
+fn truthiness(exp: &LuaExp) -> Option<bool> {
+    match &exp.kind {
+        LuaExpKind::Nil              => Some(false),
+        LuaExpKind::Boolean(value)   => Some(*value),
+        LuaExpKind::Number(_)        |
+        LuaExpKind::String(_)        => Some(true),
+        LuaExpKind::Bracketed(inner) => truthiness(inner),
+        _                            => None
+    }
+}
+
+fn is_empty(block: &LuaBlock) -> bool {
+    block.stats.is_empty() && block.ret_stat.is_none()
+}
+
+/** Collects the ids of the names in part of a tree. */
+struct LuaNameIds {
+    ids: HashSet<LuaNodeId>
+}
+
+impl Visitor for LuaNameIds {
+    fn visit_name(&mut self, name: &LuaName) {
+        self.ids.insert(name.id);
+    }
+}
+
+struct LuaDeadCodeEliminator<'s> {
+    scopes:  &'s LuaScopes,
+    changes: Vec<LuaChange>
+}
+
+impl<'s> LuaDeadCodeEliminator<'s> {
+    fn change(&mut self, message: &str, span: LuaSpan) {
+        self.changes.push(LuaChange {
+            message: String::from(message),
+            span
+        });
+    }
+
+    /**
+     * Whether working out an expression could do anything besides giving its
+     * value, like calling a function, raising an error or running a
+     * metamethod. This errs on the side of saying it could: every operator
+     * but the logical ones can run a metamethod or raise an error, and so can
+     * indexing. Even reading a global can, through `_ENV`'s metatable.
+     */
+    fn may_have_side_effects(&self, exp: &LuaExp) -> bool {
+        match &exp.kind {
+            LuaExpKind::Nil | LuaExpKind::Boolean(_) | LuaExpKind::Number(_) | LuaExpKind::String(_) => false,
+            LuaExpKind::Varargs | LuaExpKind::FunctionDef(_) => false,
+            LuaExpKind::Bracketed(inner) => self.may_have_side_effects(inner),
+            LuaExpKind::Name(name) => {
+                !matches!(self.scopes.resolve(name.id), Some(LuaResolution::Local(_)) | Some(LuaResolution::Upvalue(_, _)))
+            },
+            LuaExpKind::BinOp(LuaBinOp::And, left, right) |
+            LuaExpKind::BinOp(LuaBinOp::Or, left, right) => {
+                self.may_have_side_effects(left) || self.may_have_side_effects(right)
+            },
+            LuaExpKind::UnOp(LuaUnOp::Not, operand) => self.may_have_side_effects(operand),
+            // Negative numerals.
+            LuaExpKind::UnOp(LuaUnOp::Neg, operand) => !matches!(operand.kind, LuaExpKind::Number(_)),
+            LuaExpKind::TableConstructor(table) => table.fields.iter().any(|field| match &field.kind {
+                LuaFieldKind::Positional(value) => self.may_have_side_effects(value),
+                LuaFieldKind::Named(_, value)   => self.may_have_side_effects(value),
+                // Keys that are nil or NaN raise an error, and only literals
+                // are sure not to be.
+                LuaFieldKind::Keyed(key, value) => match is_literal_key(key) {
+                    true  => self.may_have_side_effects(value),
+                    false => true
+                }
+            }),
+            _ => true
+        }
+    }
+
+    /** Whether a local is never used, apart from in `within`, which is where
+     *  a local function uses itself. Locals that are closed are there for
+     *  what closing them does. */
+    fn is_unused(&self, name: &LuaName, within: &HashSet<LuaNodeId>) -> bool {
+        match self.scopes.declared_by(name.id).map(|local| self.scopes.local(local)) {
+            Some(local) => local.attrib != Some(LuaAttribKind::Close) && local.writes.is_empty() && local.reads.iter().all(|read| within.contains(read)),
+            None        => false
+        }
+    }
+
+    /** Simplifies a statement by itself, giving what it should be replaced
+     *  with, which is nothing if it can go. */
+    fn eliminate_stat(&mut self, stat: LuaStat) -> Option<LuaStat> {
+        let kind = match stat.kind {
+            LuaStatKind::If(condition, block, else_ifs, else_block) => {
+                let mut branches = Vec::new();
+                let mut otherwise = else_block;
+                let mut clauses = vec![(condition, block)];
+                clauses.extend(else_ifs);
+
+                for (condition, block) in clauses {
+                    match truthiness(&condition) {
+                        Some(false) => self.change("removed a branch whose condition is always false", condition.span),
+                        Some(true)  => {
+                            self.change("kept only the branch whose condition is always true", condition.span);
+                            otherwise = Some(block);
+                            break;
+                        },
+                        None => branches.push((condition, block))
+                    }
+                }
+
+                if branches.is_empty() {
+                    match otherwise {
+                        Some(block) if !is_empty(&block) => LuaStatKind::Do(block),
+                        _                                => return None
+                    }
+                } else {
+                    let (condition, block) = branches.remove(0);
+                    LuaStatKind::If(condition, block, branches, otherwise)
+                }
+            },
+            LuaStatKind::While(condition, _) if truthiness(&condition) == Some(false) => {
+                self.change("removed a loop whose condition is always false", stat.span);
+                return None;
+            },
+            LuaStatKind::LocalFunction(name, body) => {
+                let mut within = LuaNameIds { ids: HashSet::new() };
+                within.visit_func_body(&body);
+
+                if self.is_unused(&name, &within.ids) {
+                    self.change(&format!("removed unused local function '{}'", name.name), stat.span);
+                    return None;
+                }
+
+                LuaStatKind::LocalFunction(name, body)
+            },
+            LuaStatKind::LocalAttNameList(att_names, exps) => {
+                let unused = att_names.iter().all(|att_name| self.is_unused(&att_name.name, &HashSet::new()))
+                    && !exps.iter().any(|exp| self.may_have_side_effects(exp));
+
+                if unused {
+                    let names: Vec<String> = att_names.iter().map(|att_name| format!("'{}'", att_name.name.name)).collect();
+                    let message = match names.len() {
+                        1 => format!("removed unused local {}", names[0]),
+                        _ => format!("removed unused locals {}", names.join(", "))
+                    };
+
+                    self.change(&message, stat.span);
+                    return None;
+                }
+
+                LuaStatKind::LocalAttNameList(att_names, exps)
+            },
+            kind => kind
+        };
+
+        Some(LuaStat { kind, ..stat })
+    }
+}
+
+/** Whether a table key is a literal that can be a key. */
+fn is_literal_key(exp: &LuaExp) -> bool {
+    match &exp.kind {
+        LuaExpKind::Boolean(_) | LuaExpKind::String(_)   => true,
+        LuaExpKind::Number(LuaNumeral::Float(value))     => !value.is_nan(),
+        LuaExpKind::Number(_)                            => true,
+        LuaExpKind::Bracketed(inner)                     => is_literal_key(inner),
+        _                                                => false
+    }
+}
+
+impl<'s> VisitorMut for LuaDeadCodeEliminator<'s> {
+    fn visit_block_mut(&mut self, block: &mut LuaBlock) {
+        walk_block_mut(self, block);
+
+        let mut exited = false;
+        let mut unreachable: Option<LuaSpan> = None;
+
+        for stat in mem::take(&mut block.stats) {
+            match stat.kind {
+                // Anything after a label can be reached by jumping to it.
+                LuaStatKind::Label(_) => exited = false,
+                _ if exited => {
+                    unreachable = Some(unreachable.map(|span| span.to(stat.span)).unwrap_or(stat.span));
+                    continue;
+                },
+                _ => { }
+            }
+
+            if let Some(span) = unreachable.take() {
+                self.change("removed unreachable code", span);
+            }
+
+            if let Some(stat) = self.eliminate_stat(stat) {
+                exited = always_exits(&stat);
+                block.stats.push(stat);
+            }
+        }
+
+        if exited {
+            if let Some(ret_stat) = block.ret_stat.take() {
+                unreachable = Some(unreachable.map(|span| span.to(ret_stat.span)).unwrap_or(ret_stat.span));
+            }
+        }
+
+        if let Some(span) = unreachable {
+            self.change("removed unreachable code", span);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::{LuaDialect, read_lua_chunk, fold_lua_constants};
+
+    fn eliminate(src: &str) -> String {
+        let mut chunk = read_lua_chunk(src, LuaDialect::Lua54, false).ok().unwrap();
+        fold_lua_constants(&mut chunk, LuaDialect::Lua54);
+        eliminate_lua_dead_code(&mut chunk);
+        print_lua_chunk(&chunk)
+    }
+
+    #[test]
+    fn removes_branches_that_never_run() {
+        assert_eq!(eliminate("if false then a() end\nif nil then b() else c() end\nwhile 1 > 2 do d() end"), "do\n    c()\nend\n");
+        assert_eq!(eliminate("if x then a() elseif false then b() elseif true then c() elseif y then d() end"),
+            "if x then\n    a()\nelse\n    c()\nend\n");
+        assert_eq!(eliminate("local debug <const> = false\nif debug then print('debug') end\nif not debug then f() end"),
+            "do\n    f()\nend\n");
+    }
+
+    #[test]
+    fn removes_unreachable_code() {
+        assert_eq!(eliminate("for i = 1, 2 do\n    f(i)\n    break\n    g(i)\nend"), "for i = 1, 2 do\n    f(i)\n    break\nend\n");
+        assert_eq!(eliminate("goto skip\nf()\n::skip::\ng()\ndo return end\nh()\nreturn 1"),
+            "goto skip\n::skip::\ng()\ndo\n    return\nend\n");
+    }
+
+    #[test]
+    fn removes_unused_locals_without_side_effects() {
+        let src = "\
+local function helper() return helper end
+local function used() return 1 end
+local function caller() return used() end
+local t, n = {1, x = 2, [true] = (used)}, -1
+local kept = g.x
+local also_kept = h
+local closed <close> = nil
+print(used, ...)
+";
+
+        assert_eq!(eliminate(src), "\
+local function used()
+    return 1
+end
+local kept = g.x
+local also_kept = h
+local closed <close> = nil
+print(used, ...)
+");
+    }
+
+    #[test]
+    fn reports_what_it_removed() {
+        let mut chunk = read_lua_chunk("if false then f() end\nlocal function g() end\nbreak_it()\ndo return end\nh()\ni()", LuaDialect::Lua54, false).ok().unwrap();
+        let changes: Vec<String> = eliminate_lua_dead_code(&mut chunk).iter()
+            .map(|change| format!("{}-{}: {}", change.span.start.line, change.span.end.line, change.message))
+            .collect();
+
+        assert_eq!(changes, vec![
+            "1-1: removed a branch whose condition is always false",
+            "2-2: removed unused local function 'g'",
+            "5-6: removed unreachable code"
+        ]);
+    }
+}
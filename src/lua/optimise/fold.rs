@@ -0,0 +1,589 @@
+/*!
+ * Folds operators whose operands are literals into the value they give,
+ * following what Lua 5.4 does when it runs them. Integers wrap around,
+ * `/` and `^` always give floats, and mixing integers and floats gives
+ * floats. Operations that would raise an error, like dividing by zero or
+ * comparing a number to a string, are left to raise it when the code runs,
+ * as are those that strings only support through their metatable, like
+ * arithmetic on numeric strings. Ordering strings depends on the locale, so
+ * that isn't folded either.
+ *
+ * Dialects without an integer subtype do all their arithmetic in floats.
+ *
+ * Along the way, `<const>` locals whose values are literals are replaced by
+ * them, and `not not x` is simplified to `x` where only whether it is true
+ * matters, as in the condition of an `if`.
+ */
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::mem;
+
+use crate::lua::{LuaDialect, LuaNumeral, LuaBytes};
+use crate::lua::syntax_tree::*;
+use crate::lua::analysis::{LuaScopes, LuaLocalId, LuaResolution, resolve_lua_chunk};
+
+/** Something an optimisation changed, to see what it did. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaChange {
+    pub message: String,
+    /** Where the code that was changed was. */
+    pub span:    LuaSpan
+}
+
+/** Folds the constants in a chunk, giving what it changed in the order it
+ *  changed it. */
+pub fn fold_lua_constants(chunk: &mut LuaChunk, dialect: LuaDialect) -> Vec<LuaChange> {
+    let scopes = resolve_lua_chunk(chunk);
+    let mut folder = LuaConstantFolder {
+        scopes:    &scopes,
+        integers:  dialect == LuaDialect::Lua53 || dialect == LuaDialect::Lua54,
+        constants: HashMap::new(),
+        changes:   Vec::new()
+    };
+
+    // The folder takes the chunk by value, so it is moved out and left
+    // empty until the folded chunk replaces it.
+    let taken = LuaChunk {
+        block: LuaBlock {
+            stats:    mem::take(&mut chunk.block.stats),
+            ret_stat: chunk.block.ret_stat.take(),
+            span:     chunk.block.span
+        },
+        span: chunk.span
+    };
+
+    *chunk = folder.fold_chunk(taken);
+    folder.changes
+}
+
+/** A value that has a literal. */
+#[derive(Debug, Clone, PartialEq)]
+enum LuaConstant {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(LuaBytes)
+}
+
+impl LuaConstant {
+    fn is_truthy(&self) -> bool {
+        match self {
+            LuaConstant::Nil            => false,
+            LuaConstant::Boolean(value) => *value,
+            _                           => true
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            LuaConstant::Integer(value) => *value == 0,
+            LuaConstant::Float(value)   => *value == 0.0,
+            _                           => false
+        }
+    }
+
+    fn to_float(&self) -> Option<f64> {
+        match self {
+            LuaConstant::Integer(value) => Some(*value as f64),
+            LuaConstant::Float(value)   => Some(*value),
+            _                           => None
+        }
+    }
+
+    /** The integer a number is, if it is one without rounding, as bitwise
+     *  operators need. */
+    fn to_integer(&self) -> Option<i64> {
+        match self {
+            LuaConstant::Integer(value) => Some(*value),
+            LuaConstant::Float(value) if value.floor() == *value && *value >= -TWO_TO_63 && *value < TWO_TO_63 => {
+                Some(*value as i64)
+            },
+            _ => None
+        }
+    }
+}
+
+const TWO_TO_63: f64 = 9223372036854775808.0;
+
+/** Compares an integer to a float without rounding either. */
+fn compare_integer_float(integer: i64, float: f64) -> Option<Ordering> {
+    if float.is_nan() {
+        None
+    } else if float >= TWO_TO_63 {
+        Some(Ordering::Less)
+    } else if float < -TWO_TO_63 {
+        Some(Ordering::Greater)
+    } else {
+        let truncated = float.trunc();
+
+        match integer.cmp(&(truncated as i64)) {
+            Ordering::Equal => 0f64.partial_cmp(&(float - truncated)),
+            ordering        => Some(ordering)
+        }
+    }
+}
+
+fn compare_numbers(a: &LuaConstant, b: &LuaConstant) -> Option<Ordering> {
+    match (a, b) {
+        (LuaConstant::Integer(a), LuaConstant::Integer(b)) => Some(a.cmp(b)),
+        (LuaConstant::Float(a), LuaConstant::Float(b))     => a.partial_cmp(b),
+        (LuaConstant::Integer(a), LuaConstant::Float(b))   => compare_integer_float(*a, *b),
+        (LuaConstant::Float(a), LuaConstant::Integer(b))   => compare_integer_float(*b, *a).map(Ordering::reverse),
+        _                                                  => None
+    }
+}
+
+/** Integer floor division, which wraps around for the smallest integer
+ *  divided by -1. */
+fn floor_divide(a: i64, b: i64) -> i64 {
+    if b == -1 {
+        return a.wrapping_neg();
+    }
+
+    let quotient = a / b;
+
+    if (a ^ b) < 0 && a % b != 0 { quotient - 1 } else { quotient }
+}
+
+/** Integer modulo, which has the sign of the divisor. */
+fn modulo(a: i64, b: i64) -> i64 {
+    if b == -1 {
+        return 0;
+    }
+
+    let remainder = a % b;
+
+    if remainder != 0 && (remainder ^ b) < 0 { remainder + b } else { remainder }
+}
+
+fn float_modulo(a: f64, b: f64) -> f64 {
+    let remainder = a % b;
+
+    if (remainder > 0.0 && b < 0.0) || (remainder < 0.0 && b > 0.0) {
+        remainder + b
+    } else {
+        remainder
+    }
+}
+
+/** Shifts left, or right for negative shifts, filling with zeroes. */
+fn shift_left(value: i64, shift: i64) -> i64 {
+    if shift <= -64 || shift >= 64 {
+        0
+    } else if shift >= 0 {
+        ((value as u64) << shift) as i64
+    } else {
+        ((value as u64) >> -shift) as i64
+    }
+}
+
+/** Formats a float the way `tostring` does, which is `%.14g`, with `.0` on
+ *  the end of floats that would otherwise look like integers in dialects
+ *  that have them. */
+fn float_to_string(value: f64, integers: bool) -> Option<String> {
+    if value.is_nan() {
+        // Whether this has a sign depends on the platform.
+        return None;
+    } else if value.is_infinite() {
+        return Some(String::from(if value > 0.0 { "inf" } else { "-inf" }));
+    }
+
+    let trim = |text: String| match text.contains('.') {
+        true  => String::from(text.trim_end_matches('0').trim_end_matches('.')),
+        false => text
+    };
+
+    let scientific = format!("{:.13e}", value);
+    let (mantissa, exponent) = scientific.split_at(scientific.find('e').unwrap());
+    let exponent: i32 = exponent[1..].parse().unwrap();
+
+    let text = if !(-4..14).contains(&exponent) {
+        format!("{}e{}{:02}", trim(String::from(mantissa)), if exponent < 0 { '-' } else { '+' }, exponent.abs())
+    } else {
+        trim(format!("{:.*}", (13 - exponent) as usize, value))
+    };
+
+    match integers && text.chars().all(|c| c == '-' || c.is_ascii_digit()) {
+        true  => Some(format!("{}.0", text)),
+        false => Some(text)
+    }
+}
+
+fn unbracketed(exp: &LuaExp) -> &LuaExp {
+    match &exp.kind {
+        LuaExpKind::Bracketed(inner) => unbracketed(inner),
+        _                            => exp
+    }
+}
+
+/** What `not not x` is simplified to, if `exp` is one. */
+fn double_negated(exp: &LuaExp) -> Option<&LuaExp> {
+    match &exp.kind {
+        LuaExpKind::UnOp(LuaUnOp::Not, operand) => match &unbracketed(operand).kind {
+            LuaExpKind::UnOp(LuaUnOp::Not, inner) => Some(inner),
+            _                                      => None
+        },
+        _ => None
+    }
+}
+
+struct LuaConstantFolder<'s> {
+    scopes:    &'s LuaScopes,
+    integers:  bool,
+    /** The values of the `<const>` locals that are literals. */
+    constants: HashMap<LuaLocalId, LuaConstant>,
+    changes:   Vec<LuaChange>
+}
+
+impl<'s> LuaConstantFolder<'s> {
+    fn change(&mut self, message: String, span: LuaSpan) {
+        self.changes.push(LuaChange {
+            message,
+            span
+        });
+    }
+
+    /** The value of an expression, if it is a literal. Negated numerals count,
+     *  since that is how negative numbers are written. */
+    fn value_of(&self, exp: &LuaExp) -> Option<LuaConstant> {
+        let value = match &exp.kind {
+            LuaExpKind::Nil                                 => LuaConstant::Nil,
+            LuaExpKind::Boolean(value)                      => LuaConstant::Boolean(*value),
+            LuaExpKind::Number(LuaNumeral::Integer(value))  => LuaConstant::Integer(*value),
+            LuaExpKind::Number(LuaNumeral::Float(value))    => LuaConstant::Float(*value),
+            LuaExpKind::String(value)                       => LuaConstant::String(value.clone()),
+            LuaExpKind::Bracketed(inner)                    => return self.value_of(inner),
+            LuaExpKind::UnOp(LuaUnOp::Neg, operand) => match &operand.kind {
+                LuaExpKind::Number(_) => return self.value_of(operand).and_then(|value| self.negate(value)),
+                _                     => return None
+            },
+            _ => return None
+        };
+
+        match value {
+            LuaConstant::Integer(value) if !self.integers => Some(LuaConstant::Float(value as f64)),
+            value                                         => Some(value)
+        }
+    }
+
+    fn literal(&self, value: LuaConstant) -> LuaExpKind {
+        match value {
+            LuaConstant::Nil            => LuaExpKind::Nil,
+            LuaConstant::Boolean(value) => LuaExpKind::Boolean(value),
+            LuaConstant::Integer(value) => LuaExpKind::Number(LuaNumeral::Integer(value)),
+            // Without integers, whole numbers are still written as them.
+            LuaConstant::Float(value) if !self.integers && value.floor() == value && value.abs() < 9007199254740992.0 && value != 0.0 => {
+                LuaExpKind::Number(LuaNumeral::Integer(value as i64))
+            },
+            LuaConstant::Float(value)   => LuaExpKind::Number(LuaNumeral::Float(value)),
+            LuaConstant::String(value)  => LuaExpKind::String(value)
+        }
+    }
+
+    fn negate(&self, value: LuaConstant) -> Option<LuaConstant> {
+        match value {
+            LuaConstant::Integer(value) => Some(LuaConstant::Integer(value.wrapping_neg())),
+            LuaConstant::Float(value)   => Some(LuaConstant::Float(-value)),
+            _                           => None
+        }
+    }
+
+    fn to_bytes(&self, value: &LuaConstant) -> Option<Vec<u8>> {
+        match value {
+            LuaConstant::String(value)  => Some(value.to_vec()),
+            LuaConstant::Integer(value) => Some(value.to_string().into_bytes()),
+            LuaConstant::Float(value)   => float_to_string(*value, self.integers).map(String::into_bytes),
+            _                           => None
+        }
+    }
+
+    fn arithmetic(&self, op: LuaBinOp, a: LuaConstant, b: LuaConstant) -> Option<LuaConstant> {
+        a.to_float()?;
+        b.to_float()?;
+
+        match op {
+            LuaBinOp::Div | LuaBinOp::FloorDiv | LuaBinOp::Mod if b.is_zero() => return None,
+            LuaBinOp::BitwiseOr | LuaBinOp::BitwiseXor | LuaBinOp::BitwiseAnd | LuaBinOp::LeftShift | LuaBinOp::RightShift => {
+                let (a, b) = (a.to_integer()?, b.to_integer()?);
+
+                return Some(LuaConstant::Integer(match op {
+                    LuaBinOp::BitwiseOr  => a | b,
+                    LuaBinOp::BitwiseXor => a ^ b,
+                    LuaBinOp::BitwiseAnd => a & b,
+                    LuaBinOp::LeftShift  => shift_left(a, b),
+                    _                    => shift_left(a, b.wrapping_neg())
+                }));
+            },
+            _ => { }
+        }
+
+        if let (LuaConstant::Integer(a), LuaConstant::Integer(b)) = (&a, &b) {
+            let (a, b) = (*a, *b);
+
+            match op {
+                LuaBinOp::Add      => return Some(LuaConstant::Integer(a.wrapping_add(b))),
+                LuaBinOp::Sub      => return Some(LuaConstant::Integer(a.wrapping_sub(b))),
+                LuaBinOp::Mul      => return Some(LuaConstant::Integer(a.wrapping_mul(b))),
+                LuaBinOp::FloorDiv => return Some(LuaConstant::Integer(floor_divide(a, b))),
+                LuaBinOp::Mod      => return Some(LuaConstant::Integer(modulo(a, b))),
+                _                  => { }
+            }
+        }
+
+        let (a, b) = (a.to_float()?, b.to_float()?);
+        let result = match op {
+            LuaBinOp::Add      => a + b,
+            LuaBinOp::Sub      => a - b,
+            LuaBinOp::Mul      => a * b,
+            LuaBinOp::Div      => a / b,
+            LuaBinOp::FloorDiv => (a / b).floor(),
+            LuaBinOp::Mod      => float_modulo(a, b),
+            LuaBinOp::Pow      => a.powf(b),
+            _                  => return None
+        };
+
+        // There is no literal for NaN.
+        if result.is_nan() { None } else { Some(LuaConstant::Float(result)) }
+    }
+
+    fn fold_bin_op(&self, op: LuaBinOp, left: &LuaExp, right: &LuaExp) -> Option<LuaExpKind> {
+        let a = self.value_of(left);
+
+        match op {
+            // Only the left operand needs to be known to know which operand
+            // these give.
+            LuaBinOp::And | LuaBinOp::Or => {
+                let a = a?;
+
+                return Some(match a.is_truthy() == (op == LuaBinOp::Or) {
+                    true  => self.literal(a),
+                    false => match &right.kind {
+                        // These give the first of their values, which they
+                        // wouldn't if they weren't the operand any more.
+                        LuaExpKind::FunctionCall(_) | LuaExpKind::Varargs => LuaExpKind::Bracketed(Box::new(right.clone())),
+                        kind                                              => kind.clone()
+                    }
+                });
+            },
+            _ => { }
+        }
+
+        let (a, b) = (a?, self.value_of(right)?);
+
+        let result = match op {
+            LuaBinOp::Eq | LuaBinOp::NotEq => {
+                let equal = match (&a, &b) {
+                    (LuaConstant::String(a), LuaConstant::String(b)) => a == b,
+                    (LuaConstant::Nil, LuaConstant::Nil)             => true,
+                    (LuaConstant::Boolean(a), LuaConstant::Boolean(b)) => a == b,
+                    _ => compare_numbers(&a, &b) == Some(Ordering::Equal)
+                };
+
+                LuaConstant::Boolean(equal == (op == LuaBinOp::Eq))
+            },
+            LuaBinOp::Less | LuaBinOp::Greater | LuaBinOp::LessEq | LuaBinOp::GreaterEq => {
+                if a.to_float().is_none() || b.to_float().is_none() {
+                    return None;
+                }
+
+                let ordering = compare_numbers(&a, &b);
+
+                LuaConstant::Boolean(match op {
+                    LuaBinOp::Less    => ordering == Some(Ordering::Less),
+                    LuaBinOp::Greater => ordering == Some(Ordering::Greater),
+                    LuaBinOp::LessEq  => ordering == Some(Ordering::Less) || ordering == Some(Ordering::Equal),
+                    _                 => ordering == Some(Ordering::Greater) || ordering == Some(Ordering::Equal)
+                })
+            },
+            LuaBinOp::Concat => {
+                let mut bytes = self.to_bytes(&a)?;
+                bytes.extend(self.to_bytes(&b)?);
+                LuaConstant::String(LuaBytes::from(bytes))
+            },
+            op => self.arithmetic(op, a, b)?
+        };
+
+        Some(self.literal(result))
+    }
+
+    fn fold_un_op(&self, op: LuaUnOp, operand: &LuaExp) -> Option<LuaExpKind> {
+        let value = self.value_of(operand)?;
+
+        let result = match op {
+            LuaUnOp::Not => LuaConstant::Boolean(!value.is_truthy()),
+            // Negative numerals are already as folded as they get.
+            LuaUnOp::Neg => match &operand.kind {
+                LuaExpKind::Number(_) => return None,
+                _                     => self.negate(value)?
+            },
+            LuaUnOp::Len => match value {
+                LuaConstant::String(value) => LuaConstant::Integer(value.len() as i64),
+                _                          => return None
+            },
+            LuaUnOp::BitwiseNot => LuaConstant::Integer(!value.to_integer()?)
+        };
+
+        match result {
+            LuaConstant::Float(value) if value.is_nan() => None,
+            result                                      => Some(self.literal(result))
+        }
+    }
+
+    /** Simplifies an expression where only whether it is true matters. */
+    fn simplify_condition(&mut self, exp: &mut LuaExp) {
+        if let Some(inner) = double_negated(exp).cloned() {
+            let printer = LuaPrinter::new();
+            let message = format!("simplified '{}' to '{}'", printer.print_exp(exp), printer.print_exp(&inner));
+            self.change(message, exp.span);
+
+            *exp = inner;
+            self.simplify_condition(exp);
+            return;
+        }
+
+        match &mut exp.kind {
+            LuaExpKind::UnOp(LuaUnOp::Not, operand) => self.simplify_condition(operand),
+            // Which operand these give is only down to whether they're true.
+            LuaExpKind::BinOp(LuaBinOp::And, left, right) |
+            LuaExpKind::BinOp(LuaBinOp::Or, left, right) => {
+                self.simplify_condition(left);
+                self.simplify_condition(right);
+            },
+            LuaExpKind::Bracketed(inner) => self.simplify_condition(inner),
+            _ => { }
+        }
+    }
+}
+
+impl<'s> Folder for LuaConstantFolder<'s> {
+    fn fold_stat(&mut self, stat: LuaStat) -> LuaStat {
+        let mut stat = fold_stat(self, stat);
+
+        match &mut stat.kind {
+            LuaStatKind::LocalAttNameList(att_names, exps) => {
+                for (att_name, exp) in att_names.iter().zip(exps.iter()) {
+                    let constant = att_name.attrib.as_ref().map(|attrib| attrib.kind) == Some(LuaAttribKind::Const);
+
+                    if let (true, Some(value), Some(local)) = (constant, self.value_of(exp), self.scopes.declared_by(att_name.name.id)) {
+                        self.constants.insert(local, value);
+                    }
+                }
+            },
+            LuaStatKind::If(condition, _, else_ifs, _) => {
+                self.simplify_condition(condition);
+                else_ifs.iter_mut().for_each(|(condition, _)| self.simplify_condition(condition));
+            },
+            LuaStatKind::While(condition, _) | LuaStatKind::Repeat(_, condition) => self.simplify_condition(condition),
+            _ => { }
+        }
+
+        stat
+    }
+
+    fn fold_exp(&mut self, exp: LuaExp) -> LuaExp {
+        let exp = fold_exp(self, exp);
+
+        let folded = match &exp.kind {
+            LuaExpKind::Name(name) => {
+                let value = match self.scopes.resolve(name.id) {
+                    Some(LuaResolution::Local(local)) | Some(LuaResolution::Upvalue(local, _)) => self.constants.get(&local).cloned(),
+                    _ => None
+                };
+
+                let literal = match value {
+                    Some(value) => LuaExp { kind: self.literal(value), span: exp.span },
+                    None        => return exp
+                };
+
+                let message = format!("replaced '{}' with its value {}", name.name, LuaPrinter::new().print_exp(&literal));
+                self.change(message, exp.span);
+                return literal;
+            },
+            LuaExpKind::BinOp(op, left, right) => self.fold_bin_op(*op, left, right),
+            LuaExpKind::UnOp(op, operand)      => self.fold_un_op(*op, operand),
+            _                                  => None
+        };
+
+        match folded {
+            Some(kind) => {
+                let folded = LuaExp { kind, span: exp.span };
+                let printer = LuaPrinter::new();
+                let message = format!("folded '{}' to '{}'", printer.print_exp(&exp), printer.print_exp(&folded));
+                self.change(message, exp.span);
+                folded
+            },
+            None => exp
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::read_lua_chunk;
+
+    fn fold_in(src: &str, dialect: LuaDialect) -> String {
+        let mut chunk = read_lua_chunk(src, dialect, false).ok().unwrap();
+        fold_lua_constants(&mut chunk, dialect);
+        print_lua_chunk(&chunk)
+    }
+
+    fn fold(src: &str) -> String {
+        fold_in(src, LuaDialect::Lua54)
+    }
+
+    #[test]
+    fn folds_arithmetic_like_lua_5_4() {
+        assert_eq!(fold("return 1 + 2 * 3, 7 // 2, 7 / 2, 2 ^ 10, -7 // 2, -7 % 3, 7 % -3, 5.5 % -2, 1 + 0.5"),
+            "return 7, 3, 3.5, 1024.0, -4, 2, -2, -0.5, 1.5\n");
+        assert_eq!(fold("return 9223372036854775807 + 1, -(-9223372036854775807 - 1) // -1"),
+            "return 0x8000000000000000, 0x8000000000000000\n");
+        assert_eq!(fold("return 1 << 63, 1 << 64, -1 >> 1, 3 & 1.0, ~0, 5 ~ 3"),
+            "return 0x8000000000000000, 0, 9223372036854775807, 1, -1, 6\n");
+
+        // These raise errors, or involve strings' metatable.
+        assert_eq!(fold("return 1 / 0, 1 // 0, 1 % 0.0, 1 | 1.5, 1 < '2', '10' + 1, -'1'"),
+            "return 1 / 0, 1 // 0, 1 % 0.0, 1 | 1.5, 1 < \"2\", \"10\" + 1, -\"1\"\n");
+
+        assert_eq!(fold_in("return 7 / 2, 2 ^ 2, 1 .. 2, 3 * 0.5", LuaDialect::Lua51), "return 3.5, 4, \"12\", 1.5\n");
+    }
+
+    #[test]
+    fn folds_comparisons_concatenation_and_logic() {
+        assert_eq!(fold("return 1 == 1.0, 2 ~= 2, 1 < 2.5, 3 >= 3, 'a' == 'a', nil == false, 'a' < 'b'"),
+            "return true, false, true, true, true, false, \"a\" < \"b\"\n");
+        assert_eq!(fold("return 'a' .. 1 .. 2.0, 0.1 .. '', 1e100 .. '', #'abc'"),
+            "return \"a12.0\", \"0.1\", \"1e+100\", 3\n");
+        assert_eq!(fold("return nil or f(), false and g(), 1 or h(), 1 and ..., not nil, not 0"),
+            "return (f()), false, 1, (...), true, false\n");
+    }
+
+    #[test]
+    fn propagates_constant_locals() {
+        let src = "local n <const> = 10\nlocal s <const>, t = 'x', {}\nlocal m = 2\nreturn n * m, s .. n, function() return n end";
+
+        assert_eq!(fold(src), "local n <const> = 10\nlocal s <const>, t = \"x\", {}\nlocal m = 2\nreturn 10 * m, \"x10\", function()\n    return 10\nend\n");
+    }
+
+    #[test]
+    fn simplifies_double_negation_in_conditions() {
+        assert_eq!(fold("if not not x then elseif not (not y) and not not not z then end\nlocal a = not not x"),
+            "if x then elseif y and not z then end\nlocal a = not not x\n");
+    }
+
+    #[test]
+    fn reports_what_it_changed() {
+        let mut chunk = read_lua_chunk("local a <const> = 2\nreturn a + 1 .. 'x'", LuaDialect::Lua54, false).ok().unwrap();
+        let changes: Vec<String> = fold_lua_constants(&mut chunk, LuaDialect::Lua54).iter()
+            .map(|change| format!("{}: {}", change.span.start.line, change.message))
+            .collect();
+
+        assert_eq!(changes, vec![
+            "2: replaced 'a' with its value 2",
+            "2: folded '2 + 1' to '3'",
+            "2: folded '3 .. \"x\"' to '\"3x\"'"
+        ]);
+    }
+}
@@ -0,0 +1,5 @@
+mod fold;
+mod dead_code;
+
+pub use fold::{LuaChange, fold_lua_constants};
+pub use dead_code::eliminate_lua_dead_code;
@@ -1,4 +1,4 @@
-use crate::lua::{LuaTerminal, LuaNonterminal};
+use crate::lua::{LuaTerminal, LuaNonterminal, LuaDialect};
 use crate::parsing::*;
 
 macro_rules! t {
@@ -25,13 +25,15 @@ macro_rules! maybe {
     ($e:expr) => { SymbolSequence::maybe($e) };
 }
 
-pub fn get_lua_parser() {
+/** Builds the parser for the given dialect of Lua, leaving out the
+ *  statements and operators it doesn't have. */
+pub fn get_lua_parser(dialect: LuaDialect) {
     let mut productions: Vec<Production<LuaTerminal, LuaNonterminal>> = Vec::new();
 
     productions.push(Production::builder()
         .producing(LuaNonterminal::Chunk)
         .from(n!(Block))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -40,104 +42,142 @@ pub fn get_lua_parser() {
         .from(seq!(
             many!(n!(Stat)),
             maybe!(n!(RetStat))))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
-    productions.push(Production::builder()
-        .producing(LuaNonterminal::Stat)
-        .from(one_of!(
-            t!(Semicolon),
-            seq!(n!(VarList), t!(Equals), n!(ExpList)),
-            n!(FunctionCall),
-            n!(Label),
-            t!(Break),
-            seq!(t!(Goto), t!(Identifier)),
-            seq!(t!(Do), n!(Block), t!(End)),
-            seq!(t!(While), n!(Exp), t!(Do), n!(Block), t!(End)),
-            seq!(t!(Repeat), n!(Block), t!(Until), n!(Exp)),
-            seq!(
-                t!(If),
+    let mut statements = vec![
+        t!(Semicolon),
+        seq!(n!(VarList), t!(Equals), n!(ExpList)),
+        n!(FunctionCall),
+        t!(Break),
+        seq!(t!(Do), n!(Block), t!(End)),
+        seq!(t!(While), n!(Exp), t!(Do), n!(Block), t!(End)),
+        seq!(t!(Repeat), n!(Block), t!(Until), n!(Exp)),
+        seq!(
+            t!(If),
+            n!(Exp),
+            t!(Then),
+            n!(Block),
+            many!(seq!(
+                t!(Elseif),
                 n!(Exp),
                 t!(Then),
-                n!(Block),
-                many!(seq!(
-                    t!(Elseif),
-                    n!(Exp),
-                    t!(Then),
-                    n!(Block))),
-                maybe!(seq!(
-                    t!(Else),
-                    n!(Block))),
-                t!(End)),
-            seq!(
-                t!(For),
-                t!(Identifier),
-                t!(Equals),
-                n!(Exp),
-                t!(Comma),
-                n!(Exp),
-                maybe!(seq!(
-                    t!(Comma),
-                    n!(Exp))),
-                t!(Do),
-                n!(Block),
-                t!(End)),
-            seq!(t!(For), n!(NameList), t!(In), n!(ExpList), t!(Do), n!(Block), t!(End)),
-            seq!(t!(Function), n!(FuncName), n!(FuncBody)),
-            seq!(t!(Local), t!(Function), t!(Identifier), n!(FuncBody)),
-            seq!(
-                t!(Local),
-                n!(AttNameList),
-                maybe!(seq!(
-                    t!(Equals),
-                    n!(ExpList))))))
-        .with_handler(&reduce_production)
-        .build()
-        .unwrap());
-    
-    productions.push(Production::builder()
-        .producing(LuaNonterminal::AttNameList)
-        .from(seq!(
+                n!(Block))),
+            maybe!(seq!(
+                t!(Else),
+                n!(Block))),
+            t!(End)),
+        seq!(
+            t!(For),
             t!(Identifier),
-            n!(Attrib),
-            many!(seq!(
+            t!(Equals),
+            n!(Exp),
+            t!(Comma),
+            n!(Exp),
+            maybe!(seq!(
                 t!(Comma),
-                t!(Identifier),
-                n!(Attrib)))))
-        .with_handler(&reduce_production)
-        .build()
-        .unwrap());
-    
+                n!(Exp))),
+            t!(Do),
+            n!(Block),
+            t!(End)),
+        seq!(t!(For), n!(NameList), t!(In), n!(ExpList), t!(Do), n!(Block), t!(End)),
+        seq!(t!(Function), n!(FuncName), n!(FuncBody)),
+        seq!(t!(Local), t!(Function), t!(Identifier), n!(FuncBody))];
+
+    if dialect.has_attributes() {
+        statements.push(seq!(
+            t!(Local),
+            n!(AttNameList),
+            maybe!(seq!(
+                t!(Equals),
+                n!(ExpList)))));
+    } else {
+        statements.push(seq!(
+            t!(Local),
+            n!(NameList),
+            maybe!(seq!(
+                t!(Equals),
+                n!(ExpList)))));
+    }
+
+    if dialect.has_goto() {
+        statements.push(n!(Label));
+        statements.push(seq!(t!(Goto), t!(Identifier)));
+    }
+
+    if dialect.has_continue() {
+        statements.push(t!(Continue));
+    }
+
+    if dialect.has_compound_assignment() {
+        statements.push(seq!(
+            n!(Var),
+            one_of!(
+                t!(PlusEquals),
+                t!(MinusEquals),
+                t!(MultiplyEquals),
+                t!(DivideEquals),
+                t!(FloorDivideEquals),
+                t!(PowerEquals),
+                t!(ModuloEquals),
+                t!(ConcatEquals)),
+            n!(Exp)));
+    }
+
     productions.push(Production::builder()
-        .producing(LuaNonterminal::Attrib)
-        .from(maybe!(seq!(
-            t!(LessThan),
-            t!(Identifier),
-            t!(GreaterThan))))
-        .with_handler(&reduce_production)
+        .producing(LuaNonterminal::Stat)
+        .from(SymbolSequence::Alternatives(statements))
+        .with_handler(reduce_production)
         .build()
         .unwrap());
-    
+
+    if dialect.has_attributes() {
+        productions.push(Production::builder()
+            .producing(LuaNonterminal::AttNameList)
+            .from(seq!(
+                t!(Identifier),
+                n!(Attrib),
+                many!(seq!(
+                    t!(Comma),
+                    t!(Identifier),
+                    n!(Attrib)))))
+            .with_handler(reduce_production)
+            .build()
+            .unwrap());
+
+        productions.push(Production::builder()
+            .producing(LuaNonterminal::Attrib)
+            .from(maybe!(seq!(
+                t!(LessThan),
+                t!(Identifier),
+                t!(GreaterThan))))
+            .with_handler(reduce_production)
+            .build()
+            .unwrap());
+    }
+
     productions.push(Production::builder()
         .producing(LuaNonterminal::RetStat)
         .from(seq!(
             t!(Return),
             maybe!(n!(ExpList)),
             maybe!(t!(Semicolon))))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
-    productions.push(Production::builder()
-        .producing(LuaNonterminal::Label)
-        .from(seq!(
-            t!(DoubleColon),
-            t!(Identifier),
-            t!(DoubleColon)))
-        .with_handler(&reduce_production)
-        .build()
-        .unwrap());
+    if dialect.has_goto() {
+        productions.push(Production::builder()
+            .producing(LuaNonterminal::Label)
+            .from(seq!(
+                t!(DoubleColon),
+                t!(Identifier),
+                t!(DoubleColon)))
+            .with_handler(reduce_production)
+            .build()
+            .unwrap());
+    }
 
     productions.push(Production::builder()
         .producing(LuaNonterminal::FuncName)
@@ -149,7 +189,7 @@ pub fn get_lua_parser() {
             maybe!(seq!(
                 t!(Colon),
                 t!(Identifier)))))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -160,7 +200,7 @@ pub fn get_lua_parser() {
             many!(seq!(
                 t!(Comma),
                 n!(Var)))))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -178,7 +218,7 @@ pub fn get_lua_parser() {
                 t!(Dot),
                 t!(Identifier)
             )))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -189,7 +229,7 @@ pub fn get_lua_parser() {
             many!(seq!(
                 t!(Comma),
                 t!(Identifier)))))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -200,7 +240,7 @@ pub fn get_lua_parser() {
             many!(seq!(
                 t!(Comma),
                 n!(Exp)))))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -212,7 +252,7 @@ pub fn get_lua_parser() {
                 t!(Or),
                 n!(Exp2)),
             n!(Exp2)))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -224,7 +264,7 @@ pub fn get_lua_parser() {
                 t!(And),
                 n!(Exp3)),
             n!(Exp3)))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -256,61 +296,72 @@ pub fn get_lua_parser() {
                 t!(DoubleEquals),
                 n!(Exp4)),
             n!(Exp4)))
-        .with_handler(&reduce_production)
-        .build()
-        .unwrap());
-
-    productions.push(Production::builder()
-        .producing(LuaNonterminal::Exp4)
-        .from(one_of!(
-            seq!(
-                n!(Exp4),
-                t!(BitwiseOr),
-                n!(Exp5)),
-            n!(Exp5)))
-        .with_handler(&reduce_production)
-        .build()
-        .unwrap());
-
-    productions.push(Production::builder()
-        .producing(LuaNonterminal::Exp5)
-        .from(one_of!(
-            seq!(
-                n!(Exp5),
-                t!(BitwiseNeg),
-                n!(Exp6)),
-            n!(Exp6)))
-        .with_handler(&reduce_production)
-        .build()
-        .unwrap());
-
-    productions.push(Production::builder()
-        .producing(LuaNonterminal::Exp6)
-        .from(one_of!(
-            seq!(
-                n!(Exp6),
-                t!(BitwiseAnd),
-                n!(Exp7)),
-            n!(Exp7)))
-        .with_handler(&reduce_production)
-        .build()
-        .unwrap());
-
-    productions.push(Production::builder()
-        .producing(LuaNonterminal::Exp7)
-        .from(one_of!(
-            seq!(
-                n!(Exp7),
-                t!(LeftShift),
-                n!(Exp8)),
-            seq!(
-                n!(Exp7),
-                t!(RightShift),
-                n!(Exp8)),
-            n!(Exp8)))
-        .with_handler(&reduce_production)
-        .build()
-        .unwrap());
+        .with_handler(reduce_production)
+        .build()
+        .unwrap());
+
+    if dialect.has_bitwise_operators() {
+        productions.push(Production::builder()
+            .producing(LuaNonterminal::Exp4)
+            .from(one_of!(
+                seq!(
+                    n!(Exp4),
+                    t!(BitwiseOr),
+                    n!(Exp5)),
+                n!(Exp5)))
+            .with_handler(reduce_production)
+            .build()
+            .unwrap());
+
+        productions.push(Production::builder()
+            .producing(LuaNonterminal::Exp5)
+            .from(one_of!(
+                seq!(
+                    n!(Exp5),
+                    t!(BitwiseNeg),
+                    n!(Exp6)),
+                n!(Exp6)))
+            .with_handler(reduce_production)
+            .build()
+            .unwrap());
+
+        productions.push(Production::builder()
+            .producing(LuaNonterminal::Exp6)
+            .from(one_of!(
+                seq!(
+                    n!(Exp6),
+                    t!(BitwiseAnd),
+                    n!(Exp7)),
+                n!(Exp7)))
+            .with_handler(reduce_production)
+            .build()
+            .unwrap());
+
+        productions.push(Production::builder()
+            .producing(LuaNonterminal::Exp7)
+            .from(one_of!(
+                seq!(
+                    n!(Exp7),
+                    t!(LeftShift),
+                    n!(Exp8)),
+                seq!(
+                    n!(Exp7),
+                    t!(RightShift),
+                    n!(Exp8)),
+                n!(Exp8)))
+            .with_handler(reduce_production)
+            .build()
+            .unwrap());
+    } else {
+        // Without bitwise operators, comparisons apply directly to
+        // concatenations.
+        productions.push(Production::builder()
+            .producing(LuaNonterminal::Exp4)
+            .from(n!(Exp8))
+            .with_handler(reduce_production)
+            .build()
+            .unwrap());
+    }
 
     productions.push(Production::builder()
         .producing(LuaNonterminal::Exp8)
@@ -320,7 +371,7 @@ pub fn get_lua_parser() {
                 t!(Concat),
                 n!(Exp8)),
             n!(Exp9)))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -336,51 +387,61 @@ pub fn get_lua_parser() {
                 t!(Minus),
                 n!(Exp10)),
             n!(Exp10)))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
+    let mut multiplicative_operators = vec![
+        seq!(
+            n!(Exp10),
+            t!(Multiply),
+            n!(Exp11)),
+        seq!(
+            n!(Exp10),
+            t!(Divide),
+            n!(Exp11)),
+        seq!(
+            n!(Exp10),
+            t!(Modulo),
+            n!(Exp11)),
+        n!(Exp11)];
+
+    if dialect.has_floor_division() {
+        multiplicative_operators.push(seq!(
+            n!(Exp10),
+            t!(FloorDivide),
+            n!(Exp11)));
+    }
+
     productions.push(Production::builder()
         .producing(LuaNonterminal::Exp10)
-        .from(one_of!(
-            seq!(
-                n!(Exp10),
-                t!(Multiply),
-                n!(Exp11)),
-            seq!(
-                n!(Exp10),
-                t!(Divide),
-                n!(Exp11)),
-            seq!(
-                n!(Exp10),
-                t!(FloorDivide),
-                n!(Exp11)),
-            seq!(
-                n!(Exp10),
-                t!(Modulo),
-                n!(Exp11)),
-            n!(Exp11)))
-        .with_handler(&reduce_production)
+        .from(SymbolSequence::Alternatives(multiplicative_operators))
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
+    let mut unary_operators = vec![
+        seq!(
+            t!(Not),
+            n!(Exp12)),
+        seq!(
+            t!(Length),
+            n!(Exp12)),
+        seq!(
+            t!(Minus),
+            n!(Exp12)),
+        n!(Exp12)];
+
+    if dialect.has_bitwise_operators() {
+        unary_operators.push(seq!(
+            t!(BitwiseNeg),
+            n!(Exp12)));
+    }
+
     productions.push(Production::builder()
         .producing(LuaNonterminal::Exp11)
-        .from(one_of!(
-            seq!(
-                t!(Not),
-                n!(Exp12)),
-            seq!(
-                t!(Length),
-                n!(Exp12)),
-            seq!(
-                t!(Minus),
-                n!(Exp12)),
-            seq!(
-                t!(BitwiseNeg),
-                n!(Exp12)),
-            n!(Exp12)))
-        .with_handler(&reduce_production)
+        .from(SymbolSequence::Alternatives(unary_operators))
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -392,7 +453,7 @@ pub fn get_lua_parser() {
                 t!(Power),
                 n!(Exp12)),
             n!(Exp13)))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -408,7 +469,7 @@ pub fn get_lua_parser() {
             n!(FunctionDef),
             n!(PrefixExp),
             n!(TableConstructor)))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -421,7 +482,7 @@ pub fn get_lua_parser() {
                 t!(LeftParenthesis),
                 n!(Exp),
                 t!(RightParenthesis))))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -436,7 +497,7 @@ pub fn get_lua_parser() {
                 t!(Colon),
                 t!(Identifier),
                 n!(Args))))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -449,7 +510,7 @@ pub fn get_lua_parser() {
                 t!(RightParenthesis)),
             n!(TableConstructor),
             t!(StringLiteral)))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -458,7 +519,7 @@ pub fn get_lua_parser() {
         .from(seq!(
             t!(Function),
             n!(FuncBody)))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -470,7 +531,7 @@ pub fn get_lua_parser() {
             t!(RightParenthesis),
             n!(Block),
             t!(End)))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -483,7 +544,7 @@ pub fn get_lua_parser() {
                     t!(Comma),
                     t!(Varargs)))),
             t!(Varargs)))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -493,7 +554,7 @@ pub fn get_lua_parser() {
             t!(LeftBrace),
             maybe!(n!(FieldList)),
             t!(RightBrace)))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -505,7 +566,7 @@ pub fn get_lua_parser() {
                 n!(FieldSep),
                 n!(Field))),
             maybe!(n!(FieldSep))))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -523,7 +584,7 @@ pub fn get_lua_parser() {
                 t!(Equals),
                 n!(Exp)),
             n!(Exp)))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -532,7 +593,7 @@ pub fn get_lua_parser() {
         .from(one_of!(
             t!(Comma),
             t!(Semicolon)))
-        .with_handler(&reduce_production)
+        .with_handler(reduce_production)
         .build()
         .unwrap());
 
@@ -540,13 +601,12 @@ pub fn get_lua_parser() {
     //     println!("{}", p);
     // }
 
-    let parser_builder = ParserBuilder::new()
+    ParserBuilder::new()
         .with_productions(&mut productions)
         .with_start_symbol(LuaNonterminal::Chunk)
         .build();
-    
 }
 
-fn reduce_production(symbols: Vec<Symbol<LuaTerminal, LuaNonterminal>>) -> LuaNonterminal {
+fn reduce_production(_symbols: Vec<Symbol<LuaTerminal, LuaNonterminal>>) -> LuaNonterminal {
     LuaNonterminal::Exp
 }
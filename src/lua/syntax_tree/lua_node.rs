@@ -1,212 +1,321 @@
+use crate::lua::{LuaNumeral, LuaBytes, LuaDocComment};
+use crate::lua::syntax_tree::{LuaSpan, LuaSymbol};
+
+/** What the LR parser's reductions will build, once it builds syntax trees
+ *  rather than only its parsing tables. */
+#[allow(dead_code, clippy::large_enum_variant)]
 pub enum LuaNode {
     Chunk(LuaChunk),
     Block(LuaBlock),
     Stat(LuaStat),
-    AttNameList(LuaAttNameList),
+    AttNameList(Vec<LuaAttName>),
     Attrib(LuaAttrib),
     RetStat(LuaRetStat),
     Label(LuaLabel),
     FuncName(LuaFuncName),
-    VarList(LuaVarList),
-    Var(LuaVar),
-    NameList(LuaNameList),
-    ExpList(LuaExpList),
+    VarList(Vec<LuaExp>),
+    Name(LuaName),
+    NameList(Vec<LuaName>),
+    ExpList(Vec<LuaExp>),
     Exp(LuaExp),
-    Exp2(LuaExp2),
-    Exp3(LuaExp3),
-    Exp4(LuaExp4),
-    Exp5(LuaExp5),
-    Exp6(LuaExp6),
-    Exp7(LuaExp7),
-    Exp8(LuaExp8),
-    Exp9(LuaExp9),
-    Exp10(LuaExp10),
-    Exp11(LuaExp11),
-    Exp12(LuaExp12),
-    Exp13(LuaExp13),
-    PrefixExp(LuaPrefixExp),
     FunctionCall(LuaFunctionCall),
     Args(LuaArgs),
-    FunctionDef(LuaFunctionDef),
     FuncBody(LuaFuncBody),
-    ParList(LuaParList),
     TableConstructor(LuaTableConstructor),
-    FieldList(LuaFieldList),
-    Field(LuaField),
-    FieldSep(LuaFieldSep)
+    FieldList(Vec<LuaField>),
+    Field(LuaField)
 }
 
-pub struct LuaChunk {}
+/** Identifies a name in a tree, so that analyses can record what they find
+ *  out about it in a table of their own. Ids are unique within one parse. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LuaNodeId(pub u32);
 
-pub struct LuaBlock {}
-
-pub struct LuaName {}
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaChunk {
+    pub block: LuaBlock,
+    pub span:  LuaSpan
+}
 
-pub struct LuaNumber {}
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaBlock {
+    pub stats:    Vec<LuaStat>,
+    pub ret_stat: Option<LuaRetStat>,
+    pub span:     LuaSpan
+}
 
-pub struct LuaString {}
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaName {
+    pub id:   LuaNodeId,
+    pub name: LuaSymbol,
+    pub span: LuaSpan
+}
 
-pub struct LuaBool {}
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaStat {
+    pub kind: LuaStatKind,
+    pub span: LuaSpan,
+    /** The documentation comment directly above the statement. */
+    pub doc:  Option<LuaDocComment>
+}
 
-pub enum LuaStat {
+#[derive(Debug, Clone, PartialEq)]
+pub enum LuaStatKind {
     Semicolon,
-    VarList(LuaVarList, LuaExpList),
+    /** Assigns to each of the targets, which are all names, indexes or
+     *  fields. */
+    VarList(Vec<LuaExp>, Vec<LuaExp>),
+    /** Luau's `x += 1` and friends. */
+    CompoundAssignment(LuaExp, LuaBinOp, LuaExp),
     FunctionCall(LuaFunctionCall),
     Label(LuaLabel),
     Break,
+    /** Luau's `continue`. */
+    Continue,
     Goto(LuaName),
     Do(LuaBlock),
     While(LuaExp, LuaBlock),
     Repeat(LuaBlock, LuaExp),
     If(LuaExp, LuaBlock, Vec<(LuaExp, LuaBlock)>, Option<LuaBlock>),
-    For(LuaName, LuaExp, LuaExp, Option<LuaExp>, LuaBlock),
-    ForIn(LuaNameList, LuaExpList, LuaBlock),
+    For(LuaName, LuaExp, LuaExp, Option<Box<LuaExp>>, LuaBlock),
+    ForIn(Vec<LuaName>, Vec<LuaExp>, LuaBlock),
     Function(LuaFuncName, LuaFuncBody),
-    LocalFunction(LuaFuncName, LuaFuncBody),
-    LocalAttNameList(LuaAttNameList, Option<LuaExpList>)
+    LocalFunction(LuaName, LuaFuncBody),
+    LocalAttNameList(Vec<LuaAttName>, Vec<LuaExp>)
 }
 
-pub struct LuaAttNameList {}
-
-pub struct LuaAttrib {}
-
-pub struct LuaRetStat {}
-
-pub struct LuaLabel {}
-
-pub struct LuaFuncName {}
-
-pub struct LuaVarList {}
-
-pub enum LuaVar {
-    Name(LuaName),
-    Index(LuaPrefixExp, LuaExp),
-    Field(LuaPrefixExp, LuaName)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaAttName {
+    pub name:   LuaName,
+    pub attrib: Option<LuaAttrib>,
+    pub span:   LuaSpan
 }
 
-pub struct LuaNameList {}
-
-pub struct LuaExpList {}
-
-pub enum LuaExp {
-    OrOp(Box<LuaExp>, LuaExp2),
-    Other(LuaExp2)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaAttrib {
+    pub kind: LuaAttribKind,
+    pub span: LuaSpan
 }
 
-pub enum LuaExp2 {
-    AndOp(Box<LuaExp2>, LuaExp3),
-    Other(LuaExp3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LuaAttribKind {
+    /** `<const>`, which can't be assigned to after its declaration. */
+    Const,
+    /** `<close>`, which is closed when it goes out of scope, and also can't
+     *  be assigned to. */
+    Close
 }
 
-pub enum LuaExp3 {
-    LessOp(Box<LuaExp3>, LuaExp4),
-    GreaterOp(Box<LuaExp3>, LuaExp4),
-    LessEqOp(Box<LuaExp3>, LuaExp4),
-    GreaterEqOp(Box<LuaExp3>, LuaExp4),
-    NotEqOp(Box<LuaExp3>, LuaExp4),
-    EqOp(Box<LuaExp3>, LuaExp4),
-    Other(LuaExp4)
+impl LuaAttribKind {
+    pub fn from_name(name: &str) -> Option<LuaAttribKind> {
+        match name {
+            "const" => Some(LuaAttribKind::Const),
+            "close" => Some(LuaAttribKind::Close),
+            _       => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LuaAttribKind::Const => "const",
+            LuaAttribKind::Close => "close"
+        }
+    }
 }
 
-pub enum LuaExp4 {
-    BinOrOp(Box<LuaExp4>, LuaExp5),
-    Other(LuaExp5)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaRetStat {
+    pub exps: Vec<LuaExp>,
+    pub span: LuaSpan
 }
 
-pub enum LuaExp5 {
-    BinNotOp(Box<LuaExp5>, LuaExp6),
-    Other(LuaExp6)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaLabel {
+    pub name: LuaName,
+    pub span: LuaSpan
 }
 
-pub enum LuaExp6 {
-    BinAndOp(Box<LuaExp6>, LuaExp7),
-    Other(LuaExp7)
+/** `a.b.c` or `a.b:c` in a function statement. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaFuncName {
+    pub path:   Vec<LuaName>,
+    pub method: Option<LuaName>,
+    pub span:   LuaSpan
 }
 
-pub enum LuaExp7 {
-    LeftShiftOp(Box<LuaExp7>, LuaExp8),
-    RightShoftOp(Box<LuaExp7>, LuaExp8),
-    Other(LuaExp8)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaExp {
+    pub kind: LuaExpKind,
+    pub span: LuaSpan
 }
 
-pub enum LuaExp8 {
-    ConcatOp(LuaExp9, Box<LuaExp8>), // Right associative
-    Other(LuaExp9)
+#[derive(Debug, Clone, PartialEq)]
+pub enum LuaExpKind {
+    Nil,
+    Boolean(bool),
+    Number(LuaNumeral),
+    String(LuaBytes),
+    Varargs,
+    FunctionDef(LuaFuncBody),
+    TableConstructor(LuaTableConstructor),
+    BinOp(LuaBinOp, Box<LuaExp>, Box<LuaExp>),
+    UnOp(LuaUnOp, Box<LuaExp>),
+    /** An expression in parentheses, which is kept since it truncates
+     *  multiple results to one. */
+    Bracketed(Box<LuaExp>),
+    Name(LuaName),
+    Index(Box<LuaExp>, Box<LuaExp>),
+    Field(Box<LuaExp>, LuaName),
+    FunctionCall(Box<LuaFunctionCall>)
 }
 
-pub enum LuaExp9 {
-    AddOp(Box<LuaExp9>, LuaExp10),
-    MinOp(Box<LuaExp9>, LuaExp10),
-    Other(LuaExp10)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LuaBinOp {
+    Or,
+    And,
+    Less,
+    Greater,
+    LessEq,
+    GreaterEq,
+    NotEq,
+    Eq,
+    BitwiseOr,
+    BitwiseXor,
+    BitwiseAnd,
+    LeftShift,
+    RightShift,
+    Concat,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    FloorDiv,
+    Mod,
+    Pow
 }
 
-pub enum LuaExp10 {
-    MulOp(Box<LuaExp10>, LuaExp11),
-    DivOp(Box<LuaExp10>, LuaExp11),
-    FloorDivOp(Box<LuaExp10>, LuaExp11),
-    ModOp(Box<LuaExp10>, LuaExp11),
-    Other(LuaExp11)
+impl LuaBinOp {
+    /** The operator's left and right binding power, as in the reference
+     *  parser. Operators bind tighter the higher these are, and the right
+     *  associative ones bind less tightly on their right. */
+    pub fn priority(&self) -> (u8, u8) {
+        match self {
+            LuaBinOp::Or         => (1, 1),
+            LuaBinOp::And        => (2, 2),
+            LuaBinOp::Less       | LuaBinOp::Greater |
+            LuaBinOp::LessEq     | LuaBinOp::GreaterEq |
+            LuaBinOp::NotEq      | LuaBinOp::Eq        => (3, 3),
+            LuaBinOp::BitwiseOr  => (4, 4),
+            LuaBinOp::BitwiseXor => (5, 5),
+            LuaBinOp::BitwiseAnd => (6, 6),
+            LuaBinOp::LeftShift  | LuaBinOp::RightShift => (7, 7),
+            LuaBinOp::Concat     => (9, 8),
+            LuaBinOp::Add        | LuaBinOp::Sub => (10, 10),
+            LuaBinOp::Mul        | LuaBinOp::Div |
+            LuaBinOp::FloorDiv   | LuaBinOp::Mod => (11, 11),
+            LuaBinOp::Pow        => (14, 13)
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            LuaBinOp::Or         => "or",
+            LuaBinOp::And        => "and",
+            LuaBinOp::Less       => "<",
+            LuaBinOp::Greater    => ">",
+            LuaBinOp::LessEq     => "<=",
+            LuaBinOp::GreaterEq  => ">=",
+            LuaBinOp::NotEq      => "~=",
+            LuaBinOp::Eq         => "==",
+            LuaBinOp::BitwiseOr  => "|",
+            LuaBinOp::BitwiseXor => "~",
+            LuaBinOp::BitwiseAnd => "&",
+            LuaBinOp::LeftShift  => "<<",
+            LuaBinOp::RightShift => ">>",
+            LuaBinOp::Concat     => "..",
+            LuaBinOp::Add        => "+",
+            LuaBinOp::Sub        => "-",
+            LuaBinOp::Mul        => "*",
+            LuaBinOp::Div        => "/",
+            LuaBinOp::FloorDiv   => "//",
+            LuaBinOp::Mod        => "%",
+            LuaBinOp::Pow        => "^"
+        }
+    }
 }
 
-pub enum LuaExp11 {
-    NotOp(LuaExp12),
-    LenOp(LuaExp12),
-    NegOp(LuaExp12),
-    BinNotOp(LuaExp12),
-    Other(LuaExp12)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LuaUnOp {
+    Not,
+    Len,
+    Neg,
+    BitwiseNot
 }
 
-pub enum LuaExp12 {
-    Power(LuaExp13, Box<LuaExp12>), // Right associative
-    Other(LuaExp13)
+impl LuaUnOp {
+    /** Unary operators bind tighter than every binary operator but `^`. */
+    pub const PRIORITY: u8 = 12;
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            LuaUnOp::Not        => "not",
+            LuaUnOp::Len        => "#",
+            LuaUnOp::Neg        => "-",
+            LuaUnOp::BitwiseNot => "~"
+        }
+    }
 }
 
-pub enum LuaExp13 {
-    Nil,
-    Boolean(bool),
-    NumberExp(f64),
-    StringExp(String),
-    Varargs,
-    FunctionDef(LuaFunctionDef),
-    PrefixExp(LuaPrefixExp),
-    TableConstructor(LuaTableConstructor)
+/** `f(args)`, or `o:m(args)` when there is a method name. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaFunctionCall {
+    pub callee: LuaExp,
+    pub method: Option<LuaName>,
+    pub args:   LuaArgs,
+    pub span:   LuaSpan
 }
 
-pub enum LuaPrefixExp {
-    Var(Box<LuaVar>),
-    FunctionCall(Box<LuaFunctionCall>),
-    BracketedExp(Box<LuaExp>)
+/** The arguments of a call, from its opening bracket, brace or quote. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaArgs {
+    pub kind: LuaArgsKind,
+    pub span: LuaSpan
 }
 
-pub enum LuaFunctionCall {
-    Function(LuaPrefixExp, LuaArgs),
-    Method(LuaPrefixExp, LuaName, LuaArgs)
-}
-
-pub enum LuaArgs {
-    ExpList(LuaExpList),
+#[derive(Debug, Clone, PartialEq)]
+pub enum LuaArgsKind {
+    ExpList(Vec<LuaExp>),
     TableConstructor(LuaTableConstructor),
-    LiteralString(LuaString)
+    LiteralString(LuaBytes)
 }
 
-pub struct LuaFunctionDef {}
-
-pub struct LuaFuncBody {}
-
-pub enum LuaParList {
-    JustNames(LuaNameList),
-    NamesAndVarargs(LuaNameList),
-    JustVarargs
+/** The parameters and body of a function. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaFuncBody {
+    pub params:  Vec<LuaName>,
+    pub varargs: bool,
+    pub block:   LuaBlock,
+    pub span:    LuaSpan
 }
 
-pub struct LuaTableConstructor {}
-
-pub struct LuaFieldList {}
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaTableConstructor {
+    pub fields: Vec<LuaField>,
+    pub span:   LuaSpan
+}
 
-pub enum LuaField {
-    FieldDefined(LuaExp, LuaExp),
-    NameDefined(LuaName, LuaExp),
-    ExpDefined(LuaExp)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaField {
+    pub kind: LuaFieldKind,
+    pub span: LuaSpan
 }
 
-pub struct LuaFieldSep {}
+#[derive(Debug, Clone, PartialEq)]
+pub enum LuaFieldKind {
+    /** `[k] = v` */
+    Keyed(LuaExp, LuaExp),
+    /** `k = v` */
+    Named(LuaName, LuaExp),
+    /** `v` */
+    Positional(LuaExp)
+}
@@ -1,3 +1,13 @@
 mod lua_node;
+mod span;
+mod symbol;
+mod reader;
+mod printer;
+mod visitor;
 
 pub use lua_node::*;
+pub use span::LuaSpan;
+pub use symbol::{LuaSymbol, LuaInterner};
+pub use reader::{read_lua_chunk, read_lua_tokens, read_checked_lua_tokens};
+pub use printer::{LuaPrinter, LuaQuoteStyle, LuaCallParens, print_lua_chunk};
+pub use visitor::*;
@@ -0,0 +1,1909 @@
+/*!
+ * Turns syntax trees back into Lua source.
+ *
+ * The output reads back into the same tree, apart from spans and ids. Only
+ * the parentheses that precedence needs are added, using the same binding
+ * powers the reader uses, so `a * (b + c)` keeps its parentheses while
+ * `(a * b) + c` is read back as the `Bracketed` node it is. Floats are
+ * printed with as many digits as it takes to read back exactly, and strings
+ * use long brackets when they are text spread over several lines.
+ *
+ * Given the tokens a tree was read from, the printer formats it instead:
+ * comments and blank lines between statements are kept, as is the text of
+ * every literal, and with a line width set, calls, tables and chains of
+ * operators that don't fit on a line are broken over several. Comments that
+ * sit somewhere in the middle of a statement are moved to the line above it.
+ */
+
+use std::collections::HashMap;
+
+use crate::tokenisation::{Token, Trivia, TriviaKind, Location};
+use crate::lua::{LuaNumeral, LuaTerminal};
+use crate::lua::syntax_tree::*;
+
+/** Which quotes strings are written in. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LuaQuoteStyle {
+    /** Double quotes, unless the string has more of them than single
+     *  quotes. */
+    Double,
+    /** Single quotes, unless the string has more of them than double
+     *  quotes. */
+    Single,
+    /** Whatever the source used, or double quotes without any source. */
+    Keep
+}
+
+/** Whether calls with one string or table as their argument have brackets
+ *  around it. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LuaCallParens {
+    Keep,
+    /** `f("x")` and `f({})` */
+    Always,
+    /** `f "x"` and `f {}` */
+    Omit
+}
+
+/** Prints syntax trees as Lua source, one statement per line. */
+pub struct LuaPrinter {
+    indent:      String,
+    line_width:  Option<usize>,
+    quote_style: LuaQuoteStyle,
+    call_parens: LuaCallParens
+}
+
+impl LuaPrinter {
+    pub fn new() -> LuaPrinter {
+        LuaPrinter {
+            indent:      String::from("    "),
+            line_width:  None,
+            quote_style: LuaQuoteStyle::Keep,
+            call_parens: LuaCallParens::Keep
+        }
+    }
+
+    /** What each level of nesting is indented by, four spaces by default. */
+    pub fn with_indent(mut self,
+        indent: &str
+    ) -> LuaPrinter {
+        self.indent = String::from(indent);
+        self
+    }
+
+    /** How long lines can be before calls, tables and operators are broken
+     *  over several. Nothing is broken up by default. Tabs count as four
+     *  columns. */
+    pub fn with_line_width(mut self,
+        line_width: usize
+    ) -> LuaPrinter {
+        self.line_width = Some(line_width);
+        self
+    }
+
+    pub fn with_quote_style(mut self,
+        quote_style: LuaQuoteStyle
+    ) -> LuaPrinter {
+        self.quote_style = quote_style;
+        self
+    }
+
+    pub fn with_call_parens(mut self,
+        call_parens: LuaCallParens
+    ) -> LuaPrinter {
+        self.call_parens = call_parens;
+        self
+    }
+
+    pub fn print_chunk(&self, chunk: &LuaChunk) -> String {
+        let mut writer = LuaWriter::new(self, None, 0);
+        writer.write_stats(&chunk.block, None);
+        writer.finish()
+    }
+
+    pub fn print_block(&self, block: &LuaBlock) -> String {
+        let mut writer = LuaWriter::new(self, None, 0);
+        writer.write_stats(block, None);
+        writer.output
+    }
+
+    pub fn print_stat(&self, stat: &LuaStat) -> String {
+        let mut writer = LuaWriter::new(self, None, 0);
+        writer.write_stat(stat);
+        writer.output
+    }
+
+    pub fn print_exp(&self, exp: &LuaExp) -> String {
+        let mut writer = LuaWriter::new(self, None, 0);
+        writer.write_exp(exp);
+        writer.output
+    }
+
+    /** Prints a chunk read from `tokens`, keeping its comments, the blank
+     *  lines between its statements and the text of its literals. */
+    pub fn format_chunk(&self, chunk: &LuaChunk, tokens: &[Token<LuaTerminal>]) -> String {
+        let eof = tokens.len().checked_sub(1);
+        let mut writer = LuaWriter::new(self, Some(LuaSource::new(tokens, Location { line: 1, col: 1 })), 0);
+        writer.write_stats(&chunk.block, eof);
+        writer.finish()
+    }
+
+    /**
+     * Formats the statements of a block from a chunk read from `tokens`, as
+     * they appear `depth` blocks deep, starting with the indentation of the
+     * first line and without a line break at the end. Comments before
+     * `from` are left out, since they aren't part of what is being
+     * formatted.
+     */
+    pub fn format_block(&self,
+        block:  &LuaBlock,
+        depth:  usize,
+        tokens: &[Token<LuaTerminal>],
+        from:   Location
+    ) -> String {
+        let mut writer = LuaWriter::new(self, Some(LuaSource::new(tokens, from)), depth);
+        writer.write_stats(block, None);
+        writer.output
+    }
+}
+
+impl Default for LuaPrinter {
+    fn default() -> LuaPrinter {
+        LuaPrinter::new()
+    }
+}
+
+/** Prints a chunk with the default settings. */
+pub fn print_lua_chunk(chunk: &LuaChunk) -> String {
+    LuaPrinter::new().print_chunk(chunk)
+}
+
+/**
+ * Gives source for a numeral that reads back as the same value. Floats are
+ * printed with the shortest digits that do so, and always look like floats.
+ * Values with no literal of their own, like negative numbers, infinities and
+ * NaN, are printed as expressions that evaluate to them.
+ */
+pub fn print_lua_numeral(numeral: &LuaNumeral) -> String {
+    match numeral {
+        // There is no decimal literal for the smallest integer, but
+        // hexadecimal integers wrap around.
+        LuaNumeral::Integer(value) if *value == i64::MIN => String::from("0x8000000000000000"),
+        LuaNumeral::Float(value) | LuaNumeral::Imaginary(value) if !value.is_finite() => {
+            let text = if value.is_nan() {
+                "(0/0)"
+            } else if *value > 0.0 {
+                "1e9999"
+            } else {
+                "-1e9999"
+            };
+
+            match numeral {
+                LuaNumeral::Imaginary(_) => format!("{}i", text),
+                _                        => String::from(text)
+            }
+        },
+        numeral => numeral.to_string()
+    }
+}
+
+/**
+ * Gives a string literal for `value`, using long brackets for text over
+ * several lines and quotes otherwise. Double quotes are preferred, unless
+ * the string has more of them than single quotes.
+ */
+pub fn print_lua_string(value: &[u8]) -> String {
+    print_string_preferring(value, b'"')
+}
+
+fn print_string_preferring(value: &[u8], quote: u8) -> String {
+    if can_use_long_brackets(value) {
+        return long_bracket_string(value);
+    }
+
+    let other = if quote == b'"' { b'\'' } else { b'"' };
+    let preferred = value.iter().filter(|byte| **byte == quote).count();
+    let others = value.iter().filter(|byte| **byte == other).count();
+
+    quote_lua_string(value, if preferred > others { other } else { quote })
+}
+
+/**
+ * Puts the source text of a quoted string in the other kind of quotes,
+ * unescaping the quotes it used to need to escape. Strings that would need
+ * more escapes in the other quotes, and long strings, are left as they are.
+ */
+fn requote(text: &str, quote: char) -> String {
+    let old_quote = match text.chars().next() {
+        Some(old_quote) if old_quote != quote && (old_quote == '"' || old_quote == '\'') => old_quote,
+        _ => return String::from(text)
+    };
+
+    let content = &text[1..text.len() - 1];
+    let mut output = String::new();
+    let mut chars = content.chars();
+
+    output.push(quote);
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped) if escaped == old_quote => output.push(escaped),
+                Some(escaped) => {
+                    output.push(c);
+                    output.push(escaped);
+                },
+                None => output.push(c)
+            },
+            c if c == quote => return String::from(text),
+            c => output.push(c)
+        }
+    }
+
+    output.push(quote);
+    output
+}
+
+/**
+ * Gives a literal for `value` in the given quotes, escaping whatever can't
+ * appear in it as is. Bytes that aren't part of valid UTF-8 are written as
+ * decimal escapes, which every version of Lua understands.
+ */
+pub fn quote_lua_string(value: &[u8], quote: u8) -> String {
+    let mut output = String::new();
+    output.push(quote as char);
+
+    let mut remaining = value;
+
+    while !remaining.is_empty() {
+        let (valid, invalid) = match std::str::from_utf8(remaining) {
+            Ok(text) => (text, 0),
+            Err(error) => {
+                let valid = std::str::from_utf8(&remaining[..error.valid_up_to()]).unwrap();
+                let invalid = error.error_len().unwrap_or(remaining.len() - error.valid_up_to());
+                (valid, invalid)
+            }
+        };
+
+        for c in valid.chars() {
+            match c {
+                '\\'   => output.push_str("\\\\"),
+                '\n'   => output.push_str("\\n"),
+                '\r'   => output.push_str("\\r"),
+                '\t'   => output.push_str("\\t"),
+                '\x07' => output.push_str("\\a"),
+                '\x08' => output.push_str("\\b"),
+                '\x0b' => output.push_str("\\v"),
+                '\x0c' => output.push_str("\\f"),
+                c if c == quote as char => {
+                    output.push('\\');
+                    output.push(c);
+                },
+                // Three digits, so a digit after it isn't taken as part of it.
+                c if c.is_ascii_control() => output.push_str(&format!("\\{:03}", c as u8)),
+                c => output.push(c)
+            }
+        }
+
+        let rest = &remaining[valid.len()..];
+
+        for byte in &rest[..invalid] {
+            output.push_str(&format!("\\{:03}", byte));
+        }
+
+        remaining = &rest[invalid..];
+    }
+
+    output.push(quote as char);
+    output
+}
+
+/** Long brackets can't escape anything, and turn `\r` into `\n`, so they're
+ *  only used for text with line breaks and nothing unprintable. */
+fn can_use_long_brackets(value: &[u8]) -> bool {
+    value.contains(&b'\n') &&
+        std::str::from_utf8(value).is_ok() &&
+        value.iter().all(|byte| *byte == b'\n' || *byte == b'\t' || (*byte >= 0x20 && *byte != 0x7f))
+}
+
+/** Uses the lowest level that doesn't close early. A line break right after
+ *  the opening bracket is skipped, so one is added when the text starts
+ *  with a line break of its own. */
+fn long_bracket_string(value: &[u8]) -> String {
+    let text = std::str::from_utf8(value).unwrap();
+    let mut level = 0;
+
+    loop {
+        let equals = "=".repeat(level);
+        let close = format!("]{}]", equals);
+
+        if !format!("{}]{}", text, equals).contains(&close) {
+            let newline = if text.starts_with('\n') { "\n" } else { "" };
+            return format!("[{}[{}{}{}", equals, newline, text, close);
+        }
+
+        level += 1;
+    }
+}
+
+/** Whether printing `exp` would start with an opening parenthesis. */
+fn starts_with_bracket(exp: &LuaExp) -> bool {
+    match &exp.kind {
+        LuaExpKind::Name(_)            => false,
+        LuaExpKind::Index(prefix, _)   => starts_with_bracket(prefix),
+        LuaExpKind::Field(prefix, _)   => starts_with_bracket(prefix),
+        LuaExpKind::FunctionCall(call) => starts_with_bracket(&call.callee),
+        // Anything else is bracketed, either already or to use it as a prefix.
+        _                              => true
+    }
+}
+
+fn is_prefix_exp(exp: &LuaExp) -> bool {
+    matches!(exp.kind,
+        LuaExpKind::Name(_)         | LuaExpKind::Index(_, _) | LuaExpKind::Field(_, _) |
+        LuaExpKind::FunctionCall(_) | LuaExpKind::Bracketed(_)
+    )
+}
+
+/** Whether a statement ends with an expression, which a following
+ *  statement starting with `(` would be read as calling. */
+fn ends_with_exp(stat: &LuaStat) -> bool {
+    match &stat.kind {
+        LuaStatKind::VarList(_, _)            | LuaStatKind::CompoundAssignment(_, _, _) |
+        LuaStatKind::FunctionCall(_)          | LuaStatKind::Repeat(_, _) => true,
+        LuaStatKind::LocalAttNameList(_, exps) => !exps.is_empty(),
+        _ => false
+    }
+}
+
+fn starts_stat_with_bracket(stat: &LuaStat) -> bool {
+    match &stat.kind {
+        LuaStatKind::VarList(targets, _)          => starts_with_bracket(&targets[0]),
+        LuaStatKind::CompoundAssignment(target, _, _) => starts_with_bracket(target),
+        LuaStatKind::FunctionCall(call)           => starts_with_bracket(&call.callee),
+        _ => false
+    }
+}
+
+/** Whether `exp` is printed starting with a unary operator, so that it
+ *  binds like one. */
+fn is_unary(exp: &LuaExp) -> bool {
+    match &exp.kind {
+        LuaExpKind::UnOp(_, _) => true,
+        LuaExpKind::Number(LuaNumeral::Integer(value)) => *value < 0 && *value != i64::MIN,
+        LuaExpKind::Number(LuaNumeral::Float(value))   |
+        LuaExpKind::Number(LuaNumeral::Imaginary(value)) => value.is_sign_negative() && !value.is_nan(),
+        LuaExpKind::Number(LuaNumeral::SignedLong(value)) => *value < 0,
+        _ => false
+    }
+}
+
+fn starts_with_minus(exp: &LuaExp) -> bool {
+    match &exp.kind {
+        LuaExpKind::UnOp(op, _) => *op == LuaUnOp::Neg,
+        _                       => is_unary(exp)
+    }
+}
+
+fn is_comment(trivia: &Trivia) -> bool {
+    matches!(trivia.kind, TriviaKind::LineComment | TriviaKind::BlockComment | TriviaKind::Shebang)
+}
+
+fn comment_text(trivia: &Trivia) -> String {
+    let text = String::from_utf8_lossy(&trivia.text);
+
+    match trivia.kind {
+        TriviaKind::BlockComment => text.into_owned(),
+        _                        => String::from(text.trim_end())
+    }
+}
+
+/** The comments in a token's leading trivia, each with whether there is a
+ *  blank line before it, and whether there is one before the token. */
+fn leading_comments(leading_trivia: &[Trivia]) -> (Vec<(bool, &Trivia)>, bool) {
+    let mut comments = Vec::new();
+    let mut newlines = 0;
+
+    // Every comment but the first ends its own line with one of the line
+    // breaks, where the line before the first was ended by the token before.
+    for trivia in leading_trivia {
+        if trivia.kind == TriviaKind::Newline {
+            newlines += 1;
+        } else if is_comment(trivia) {
+            comments.push((newlines > comments.len().min(1), trivia));
+            newlines = 0;
+        }
+    }
+
+    let blank = newlines > comments.len().min(1);
+    (comments, blank)
+}
+
+const LEADING:  usize = 0;
+const TRAILING: usize = 1;
+
+/** The tokens a tree was read from, and which of their comments have been
+ *  written so far. */
+struct LuaSource<'t> {
+    tokens:  &'t [Token<LuaTerminal>],
+    starts:  HashMap<Location, usize>,
+    ends:    HashMap<Location, usize>,
+    /** Whether the comments before and after each token have been written. */
+    written: Vec<[bool; 2]>,
+    /** What has been marked written, so a layout that is tried out and
+     *  thrown away can be undone. */
+    log:     Vec<(usize, usize)>,
+    /** Comments before this aren't written. */
+    from:    Location
+}
+
+impl<'t> LuaSource<'t> {
+    fn new(tokens: &'t [Token<LuaTerminal>], from: Location) -> LuaSource<'t> {
+        LuaSource {
+            tokens,
+            starts:  tokens.iter().enumerate().map(|(idx, token)| (token.location, idx)).collect(),
+            ends:    tokens.iter().enumerate().map(|(idx, token)| (token.end_location(), idx)).collect(),
+            written: vec![[false; 2]; tokens.len()],
+            log:     Vec::new(),
+            from
+        }
+    }
+
+    /** The first token at or after `location`. */
+    fn after(&self, location: Location) -> usize {
+        let mut low  = 0;
+        let mut high = self.tokens.len();
+
+        while low < high {
+            let mid = (low + high) / 2;
+
+            if self.tokens[mid].location < location {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+
+        low
+    }
+
+    fn trivia(&self, idx: usize, side: usize) -> &'t [Trivia] {
+        match side {
+            LEADING => &self.tokens[idx].leading_trivia,
+            _       => &self.tokens[idx].trailing_trivia
+        }
+    }
+
+    fn has_comments(&self, idx: usize, side: usize) -> bool {
+        !self.written[idx][side] &&
+            self.trivia(idx, side).iter().any(|trivia| is_comment(trivia) && trivia.location >= self.from)
+    }
+
+    /** Marks one side of a token written, giving its comments unless they
+     *  were already written. */
+    fn take(&mut self, idx: usize, side: usize) -> &'t [Trivia] {
+        if self.written[idx][side] {
+            return &[];
+        }
+
+        self.written[idx][side] = true;
+        self.log.push((idx, side));
+        self.trivia(idx, side)
+    }
+}
+
+struct LuaWriterState {
+    output_len:   usize,
+    log_len:      usize,
+    depth:        usize,
+    at_start:     bool,
+    line_comment: bool
+}
+
+struct LuaWriter<'p> {
+    printer:      &'p LuaPrinter,
+    source:       Option<LuaSource<'p>>,
+    output:       String,
+    depth:        usize,
+    /** Whether the first line is still to be started. */
+    at_start:     bool,
+    /** Whether the current line ends with a line comment, so nothing else
+     *  can go on it. */
+    line_comment: bool,
+    /** Whether to keep everything on one line, while trying that out. */
+    flat:         bool
+}
+
+/** Something in a list that might be broken over several lines. */
+enum LuaListItem<'a> {
+    Exp(&'a LuaExp),
+    Field(&'a LuaField)
+}
+
+impl<'p> LuaWriter<'p> {
+    fn new(printer: &'p LuaPrinter, source: Option<LuaSource<'p>>, depth: usize) -> LuaWriter<'p> {
+        LuaWriter {
+            printer,
+            source,
+            output:       printer.indent.repeat(depth),
+            depth,
+            at_start:     true,
+            line_comment: false,
+            flat:         false
+        }
+    }
+
+    fn finish(mut self) -> String {
+        if !self.output.is_empty() {
+            self.output.push('\n');
+        }
+
+        self.output
+    }
+
+    fn write(&mut self, text: &str) {
+        self.output.push_str(text);
+        self.at_start = false;
+    }
+
+    fn write_line(&mut self) {
+        self.output.push('\n');
+
+        for _ in 0..self.depth {
+            self.output.push_str(&self.printer.indent);
+        }
+
+        self.line_comment = false;
+    }
+
+    /** Starts a new line, unless nothing has been written yet. */
+    fn begin_line(&mut self, blank: bool) {
+        if self.at_start {
+            self.at_start = false;
+            return;
+        }
+
+        if blank {
+            self.output.push('\n');
+        }
+
+        self.write_line();
+    }
+
+    fn state(&self) -> LuaWriterState {
+        LuaWriterState {
+            output_len:   self.output.len(),
+            log_len:      self.source.as_ref().map(|source| source.log.len()).unwrap_or(0),
+            depth:        self.depth,
+            at_start:     self.at_start,
+            line_comment: self.line_comment
+        }
+    }
+
+    fn restore(&mut self, state: LuaWriterState) {
+        self.output.truncate(state.output_len);
+        self.depth        = state.depth;
+        self.at_start     = state.at_start;
+        self.line_comment = state.line_comment;
+
+        if let Some(source) = &mut self.source {
+            while source.log.len() > state.log_len {
+                let (idx, side) = source.log.pop().unwrap();
+                source.written[idx][side] = false;
+            }
+        }
+    }
+
+    /** Whether the line that was being written at `start` fits within the
+     *  line width, up to where it ends. */
+    fn fits_from(&self, start: usize) -> bool {
+        let line_width = match self.printer.line_width {
+            Some(line_width) => line_width,
+            None             => return true
+        };
+
+        let line_start = self.output[..start].rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+        let line_end   = self.output[start..].find('\n').map(|idx| start + idx).unwrap_or(self.output.len());
+
+        let width: usize = self.output[line_start..line_end]
+            .chars()
+            .map(|c| if c == '\t' { 4 } else { 1 })
+            .sum();
+
+        width <= line_width
+    }
+
+    /** Whether a layout should be tried out on one line before breaking it
+     *  up. */
+    fn can_break(&self) -> bool {
+        !self.flat && self.printer.line_width.is_some()
+    }
+
+    /** Writes something on one line, keeping it if the line still fits and
+     *  undoing it otherwise. */
+    fn try_flat<F>(&mut self, write: F) -> bool where F: FnOnce(&mut Self) {
+        let state = self.state();
+        let start = state.output_len;
+
+        self.flat = true;
+        write(self);
+        self.flat = false;
+
+        if self.fits_from(start) {
+            true
+        } else {
+            self.restore(state);
+            false
+        }
+    }
+
+    fn token_starting(&self, location: Location) -> Option<usize> {
+        self.source.as_ref().and_then(|source| source.starts.get(&location).copied())
+    }
+
+    fn token_ending(&self, location: Location) -> Option<usize> {
+        self.source.as_ref().and_then(|source| source.ends.get(&location).copied())
+    }
+
+    fn has_comments(&self, idx: Option<usize>, side: usize) -> bool {
+        match (&self.source, idx) {
+            (Some(source), Some(idx)) => source.has_comments(idx, side),
+            _                         => false
+        }
+    }
+
+    fn take_trivia(&mut self, idx: Option<usize>, side: usize) -> &'p [Trivia] {
+        match (&mut self.source, idx) {
+            (Some(source), Some(idx)) => source.take(idx, side),
+            _                         => &[]
+        }
+    }
+
+    fn from(&self) -> Location {
+        self.source.as_ref().map(|source| source.from).unwrap_or(Location { line: 1, col: 1 })
+    }
+
+    /**
+     * Writes the comments on the lines before a token, each on a line of its
+     * own, and gives whether there was a blank line before the token. Blank
+     * lines are kept, apart from the ones at the start of a block, as
+     * `first` says it is.
+     */
+    fn write_leading_comments(&mut self, idx: Option<usize>, first: &mut bool) -> bool {
+        let from = self.from();
+        let trivia = self.take_trivia(idx, LEADING);
+
+        if trivia.first().map(|trivia| trivia.kind == TriviaKind::ByteOrderMark && trivia.location >= from) == Some(true) {
+            self.output.push('\u{feff}');
+        }
+
+        let (comments, blank) = leading_comments(trivia);
+
+        for (blank, comment) in comments {
+            if comment.location < from {
+                continue;
+            }
+
+            self.begin_line(blank && !*first);
+            self.write(&comment_text(comment));
+            self.line_comment = comment.kind != TriviaKind::BlockComment;
+            *first = false;
+        }
+
+        blank
+    }
+
+    /** Writes the comments after a token on the same line. */
+    fn write_trailing_comments(&mut self, idx: Option<usize>) {
+        let from = self.from();
+
+        for comment in self.take_trivia(idx, TRAILING) {
+            if is_comment(comment) && comment.location >= from {
+                self.write(" ");
+                self.write(&comment_text(comment));
+                self.line_comment = comment.kind != TriviaKind::BlockComment;
+            }
+        }
+    }
+
+    /** Moves any comments between two tokens that haven't been written yet
+     *  to the lines before `at`, which is where a statement starts. */
+    fn hoist_comments(&mut self, first: Option<usize>, last: Option<usize>, at: usize) {
+        let (first, last) = match (first, last) {
+            (Some(first), Some(last)) => (first, last),
+            _                         => return
+        };
+
+        let mut hoisted = String::new();
+
+        for idx in first..=last {
+            for side in &[LEADING, TRAILING] {
+                if !self.has_comments(Some(idx), *side) {
+                    continue;
+                }
+
+                let from = self.from();
+
+                for comment in self.take_trivia(Some(idx), *side) {
+                    if is_comment(comment) && comment.location >= from {
+                        hoisted.push_str(&comment_text(comment));
+                        hoisted.push('\n');
+                        hoisted.push_str(&self.printer.indent.repeat(self.depth));
+                    }
+                }
+            }
+        }
+
+        self.output.insert_str(at, &hoisted);
+    }
+
+    /** Writes `;`, on a new line if the current one ends in a comment. */
+    fn write_semicolon(&mut self) {
+        if self.line_comment {
+            self.begin_line(false);
+        }
+
+        self.write(";");
+    }
+
+    /**
+     * Writes the statements of a block on lines of their own, without a
+     * line break before the first, followed by the comments before the
+     * token that closes the block.
+     */
+    fn write_stats(&mut self, block: &LuaBlock, closer: Option<usize>) {
+        let mut first = true;
+        let mut previous: Option<&LuaStat> = None;
+
+        for stat in &block.stats {
+            let first_token = self.token_starting(stat.span.start);
+
+            // Formatting leaves semicolons out, apart from their comments,
+            // and puts back the ones that are needed.
+            if stat.kind == LuaStatKind::Semicolon && self.source.is_some() {
+                self.write_leading_comments(first_token, &mut first);
+                self.write_trailing_comments(first_token);
+                continue;
+            }
+
+            // Semicolons go at the end of the statement before them.
+            if stat.kind == LuaStatKind::Semicolon && previous.is_some() &&
+                !self.line_comment && !self.has_comments(first_token, LEADING) {
+                self.write(";");
+                self.write_trailing_comments(first_token);
+                previous = Some(stat);
+                continue;
+            }
+
+            if let Some(previous) = previous {
+                if ends_with_exp(previous) && starts_stat_with_bracket(stat) {
+                    self.write_semicolon();
+                }
+            }
+
+            let blank = self.write_leading_comments(first_token, &mut first);
+            self.begin_line(blank && !first);
+            first = false;
+
+            let start = self.output.len();
+            self.write_stat(stat);
+
+            let last_token = self.token_ending(stat.span.end);
+            self.write_trailing_comments(last_token);
+            self.hoist_comments(first_token, last_token, start);
+
+            previous = Some(stat);
+        }
+
+        if let Some(ret_stat) = &block.ret_stat {
+            let first_token = self.token_starting(ret_stat.span.start);
+            let blank = self.write_leading_comments(first_token, &mut first);
+            self.begin_line(blank && !first);
+            first = false;
+
+            let start = self.output.len();
+            self.write("return");
+
+            if !ret_stat.exps.is_empty() {
+                self.write(" ");
+                self.write_exp_list(&ret_stat.exps);
+            }
+
+            let last_token = self.token_ending(ret_stat.span.end);
+            self.write_trailing_comments(last_token);
+            self.hoist_comments(first_token, last_token, start);
+        }
+
+        self.write_leading_comments(closer, &mut first);
+    }
+
+    /**
+     * Writes an indented block and then the keyword that closes it, on the
+     * same line when the block is empty. Comments after the token that
+     * opens the block go on its line.
+     */
+    fn write_block(&mut self, block: &LuaBlock, closer_text: &str) {
+        let (opener, closer) = match &self.source {
+            Some(source) => {
+                let first = source.after(block.span.start);
+                (first.checked_sub(1), Some(source.after(block.span.end)))
+            },
+            None => (None, None)
+        };
+
+        self.write_trailing_comments(opener);
+
+        let flat = self.flat;
+        self.flat = false;
+
+        // Formatting leaves out semicolons without comments, so a block of
+        // only those is as good as empty.
+        let empty = block.ret_stat.is_none() && !self.has_comments(closer, LEADING) &&
+            block.stats.iter().all(|stat| {
+                let token = self.token_starting(stat.span.start);
+
+                stat.kind == LuaStatKind::Semicolon && self.source.is_some() &&
+                    !self.has_comments(token, LEADING) && !self.has_comments(token, TRAILING)
+            });
+
+        if empty && !self.line_comment {
+            self.write(" ");
+        } else if empty {
+            self.write_line();
+        } else {
+            self.depth += 1;
+            self.write_line();
+            self.at_start = true;
+            self.write_stats(block, closer);
+            self.at_start = false;
+            self.depth -= 1;
+            self.write_line();
+        }
+
+        self.flat = flat;
+        self.write(closer_text);
+    }
+
+    fn write_stat(&mut self, stat: &LuaStat) {
+        match &stat.kind {
+            LuaStatKind::Semicolon => self.write(";"),
+            LuaStatKind::VarList(targets, exps) => {
+                self.write_exp_list(targets);
+                self.write(" = ");
+                self.write_exp_list(exps);
+            },
+            LuaStatKind::CompoundAssignment(target, op, value) => {
+                self.write_exp(target);
+                self.write(&format!(" {}= ", op.symbol()));
+                self.write_exp(value);
+            },
+            LuaStatKind::FunctionCall(call) => self.write_function_call(call),
+            LuaStatKind::Label(label) => self.write(&format!("::{}::", label.name.name)),
+            LuaStatKind::Break        => self.write("break"),
+            LuaStatKind::Continue     => self.write("continue"),
+            LuaStatKind::Goto(name)   => self.write(&format!("goto {}", name.name)),
+            LuaStatKind::Do(block) => {
+                self.write("do");
+                self.write_block(block, "end");
+            },
+            LuaStatKind::While(condition, block) => {
+                self.write("while ");
+                self.write_exp(condition);
+                self.write(" do");
+                self.write_block(block, "end");
+            },
+            LuaStatKind::Repeat(block, condition) => {
+                self.write("repeat");
+                self.write_block(block, "until ");
+                self.write_exp(condition);
+            },
+            LuaStatKind::If(condition, block, else_ifs, else_block) => {
+                self.write("if ");
+                self.write_exp(condition);
+                self.write(" then");
+
+                let mut current = block;
+
+                for (condition, next_block) in else_ifs {
+                    self.write_block(current, "elseif ");
+                    self.write_exp(condition);
+                    self.write(" then");
+                    current = next_block;
+                }
+
+                match else_block {
+                    Some(else_block) => {
+                        self.write_block(current, "else");
+                        self.write_block(else_block, "end");
+                    },
+                    None => self.write_block(current, "end")
+                }
+            },
+            LuaStatKind::For(name, initial, limit, step, block) => {
+                self.write(&format!("for {} = ", name.name));
+                self.write_exp(initial);
+                self.write(", ");
+                self.write_exp(limit);
+
+                if let Some(step) = step {
+                    self.write(", ");
+                    self.write_exp(step);
+                }
+
+                self.write(" do");
+                self.write_block(block, "end");
+            },
+            LuaStatKind::ForIn(names, exps, block) => {
+                self.write("for ");
+                self.write_name_list(names);
+                self.write(" in ");
+                self.write_exp_list(exps);
+                self.write(" do");
+                self.write_block(block, "end");
+            },
+            LuaStatKind::Function(func_name, body) => {
+                self.write("function ");
+                self.write_name_list_with(&func_name.path, ".");
+
+                if let Some(method) = &func_name.method {
+                    self.write(&format!(":{}", method.name));
+                }
+
+                self.write_func_body(body);
+            },
+            LuaStatKind::LocalFunction(name, body) => {
+                self.write(&format!("local function {}", name.name));
+                self.write_func_body(body);
+            },
+            LuaStatKind::LocalAttNameList(att_names, exps) => {
+                self.write("local ");
+
+                for (idx, att_name) in att_names.iter().enumerate() {
+                    if idx > 0 {
+                        self.write(", ");
+                    }
+
+                    self.write(&att_name.name.name);
+
+                    if let Some(attrib) = &att_name.attrib {
+                        self.write(&format!(" <{}>", attrib.kind.name()));
+                    }
+                }
+
+                if !exps.is_empty() {
+                    self.write(" = ");
+                    self.write_exp_list(exps);
+                }
+            }
+        }
+    }
+
+    fn write_name_list(&mut self, names: &[LuaName]) {
+        self.write_name_list_with(names, ", ");
+    }
+
+    fn write_name_list_with(&mut self, names: &[LuaName], separator: &str) {
+        for (idx, name) in names.iter().enumerate() {
+            if idx > 0 {
+                self.write(separator);
+            }
+
+            self.write(&name.name);
+        }
+    }
+
+    fn write_exp_list(&mut self, exps: &[LuaExp]) {
+        for (idx, exp) in exps.iter().enumerate() {
+            if idx > 0 {
+                self.write(", ");
+            }
+
+            self.write_exp(exp);
+        }
+    }
+
+    fn write_exp(&mut self, exp: &LuaExp) {
+        self.write_sub_exp(exp, 0, 0);
+    }
+
+    /**
+     * Writes an expression that the reader will read with `read_sub_exp`
+     * given `limit`, and that is followed by a binary operator with left
+     * binding power `next`, or 0 if there isn't one.
+     *
+     * The reader carries on into an operator when its left binding power is
+     * more than the limit, and reads what follows with the operator's right
+     * binding power as the new limit. So an operator can go without
+     * parentheses when it binds more tightly than `limit` on its left, and
+     * no less tightly than `next` on its right.
+     */
+    fn write_sub_exp(&mut self, exp: &LuaExp, limit: u8, next: u8) {
+        match &exp.kind {
+            LuaExpKind::BinOp(op, left, right) => {
+                let (left_priority, right_priority) = op.priority();
+
+                if left_priority > limit && next <= right_priority {
+                    let written = match self.can_break() {
+                        true  => self.try_flat(|writer| writer.write_bin_op(*op, left, right, limit, next)),
+                        false => {
+                            self.write_bin_op(*op, left, right, limit, next);
+                            true
+                        }
+                    };
+
+                    if !written {
+                        self.write_broken_bin_op(exp, limit, next);
+                    }
+                } else {
+                    self.write("(");
+                    self.write_bin_op(*op, left, right, 0, 0);
+                    self.write(")");
+                }
+            },
+            // The operand is read up to the first operator that binds less
+            // tightly than a unary operator, which is all of them but `^`.
+            _ if is_unary(exp) && next > LuaUnOp::PRIORITY => {
+                self.write("(");
+                self.write_sub_exp(exp, 0, 0);
+                self.write(")");
+            },
+            LuaExpKind::UnOp(op, operand) => {
+                self.write(op.symbol());
+
+                match op {
+                    LuaUnOp::Not => self.write(" "),
+                    // So that `- -x` doesn't become a comment.
+                    LuaUnOp::Neg if starts_with_minus(operand) => self.write(" "),
+                    _ => { }
+                }
+
+                self.write_sub_exp(operand, LuaUnOp::PRIORITY, next);
+            },
+            LuaExpKind::Nil            => self.write("nil"),
+            LuaExpKind::Boolean(true)  => self.write("true"),
+            LuaExpKind::Boolean(false) => self.write("false"),
+            LuaExpKind::Number(numeral) => {
+                let text = self.literal_text(exp.span, LuaTerminal::NumberLiteral)
+                    .unwrap_or_else(|| print_lua_numeral(numeral));
+                self.write(&text);
+            },
+            LuaExpKind::String(value) => self.write_string(value, exp.span),
+            LuaExpKind::Varargs => self.write("..."),
+            LuaExpKind::FunctionDef(body) => {
+                self.write("function");
+                self.write_func_body(body);
+            },
+            LuaExpKind::TableConstructor(table) => self.write_table_constructor(table),
+            LuaExpKind::Bracketed(inner) => {
+                self.write("(");
+                self.write_exp(inner);
+                self.write(")");
+            },
+            LuaExpKind::Name(name) => self.write(&name.name),
+            LuaExpKind::Index(prefix, key) => {
+                self.write_prefix_exp(prefix);
+                self.write_bracketed_key(key);
+            },
+            LuaExpKind::Field(prefix, name) => {
+                self.write_prefix_exp(prefix);
+                self.write(&format!(".{}", name.name));
+            },
+            LuaExpKind::FunctionCall(call) => self.write_function_call(call)
+        }
+    }
+
+    fn write_bin_op(&mut self, op: LuaBinOp, left: &LuaExp, right: &LuaExp, limit: u8, next: u8) {
+        let (left_priority, right_priority) = op.priority();
+
+        self.write_sub_exp(left, limit, left_priority);
+        self.write(&format!(" {} ", op.symbol()));
+        self.write_sub_exp(right, right_priority, next);
+    }
+
+    /**
+     * Writes a chain of operators that bind equally tightly with each one
+     * starting a new, further indented line. The chain runs down the left
+     * of the tree for left associative operators, and down the right for
+     * the others.
+     */
+    fn write_broken_bin_op(&mut self, exp: &LuaExp, limit: u8, next: u8) {
+        let mut operands = Vec::new();
+        let mut ops = Vec::new();
+        let mut current = exp;
+
+        let priority = match &exp.kind {
+            LuaExpKind::BinOp(op, _, _) => op.priority(),
+            _                           => return
+        };
+        let (left_priority, right_priority) = priority;
+
+        while let LuaExpKind::BinOp(op, left, right) = &current.kind {
+            if op.priority() != priority {
+                break;
+            }
+
+            if left_priority <= right_priority {
+                operands.push(right.as_ref());
+                ops.push(*op);
+                current = left;
+            } else {
+                operands.push(left.as_ref());
+                ops.push(*op);
+                current = right;
+            }
+        }
+
+        operands.push(current);
+
+        if left_priority <= right_priority {
+            operands.reverse();
+            ops.reverse();
+        }
+
+        let count = operands.len();
+
+        for (idx, operand) in operands.into_iter().enumerate() {
+            let operand_limit = if idx == 0 { limit } else { right_priority };
+            let operand_next  = if idx == count - 1 { next } else { left_priority };
+
+            if idx > 0 {
+                self.depth += 1;
+                self.write_line();
+                self.write(ops[idx - 1].symbol());
+                self.write(" ");
+                self.write_sub_exp(operand, operand_limit, operand_next);
+                self.depth -= 1;
+            } else {
+                self.write_sub_exp(operand, operand_limit, operand_next);
+            }
+        }
+    }
+
+    /** The source text of a literal, if it still covers the whole of the
+     *  node it was read as. */
+    fn literal_text(&self, span: LuaSpan, token_type: LuaTerminal) -> Option<String> {
+        let source = self.source.as_ref()?;
+        let token = &source.tokens[*source.starts.get(&span.start)?];
+
+        if token.token_type != token_type || token.end_location() != span.end {
+            return None;
+        }
+
+        String::from_utf8(token.text.clone()).ok()
+    }
+
+    fn write_string(&mut self, value: &[u8], span: LuaSpan) {
+        let text = match (self.literal_text(span, LuaTerminal::StringLiteral), self.printer.quote_style) {
+            (Some(text), LuaQuoteStyle::Keep)   => text,
+            (Some(text), LuaQuoteStyle::Double) => requote(&text, '"'),
+            (Some(text), LuaQuoteStyle::Single) => requote(&text, '\''),
+            (None, LuaQuoteStyle::Single)       => print_string_preferring(value, b'\''),
+            (None, _)                           => print_lua_string(value)
+        };
+
+        self.write(&text);
+    }
+
+    /** Writes an expression that something is indexed or called on, which
+     *  has to be bracketed unless it is a variable or a call already. */
+    fn write_prefix_exp(&mut self, exp: &LuaExp) {
+        if let LuaExpKind::FunctionCall(call) = &exp.kind {
+            self.write_function_call_with(call, false);
+        } else if is_prefix_exp(exp) {
+            self.write_exp(exp);
+        } else {
+            self.write("(");
+            self.write_exp(exp);
+            self.write(")");
+        }
+    }
+
+    /** Writes `[key]`, with spaces inside if the key starts with a long
+     *  bracket, so that the two don't run together. */
+    fn write_bracketed_key(&mut self, key: &LuaExp) {
+        self.write("[");
+
+        let start = self.output.len();
+        self.write_exp(key);
+
+        if self.output[start..].starts_with('[') {
+            self.output.insert(start, ' ');
+            self.write(" ]");
+        } else {
+            self.write("]");
+        }
+    }
+
+    fn write_function_call(&mut self, call: &LuaFunctionCall) {
+        self.write_function_call_with(call, true);
+    }
+
+    /**
+     * Writes a call, with a space before a string or table argument without
+     * brackets unless the call is indexed or called itself, where `f"x".y`
+     * reads better than `f "x".y`. Chains of method calls that don't fit on
+     * a line have each call after the first on a line of its own.
+     */
+    fn write_function_call_with(&mut self, call: &LuaFunctionCall, spaced: bool) {
+        if self.can_break() && method_chain(call).len() > 2 {
+            if !self.try_flat(|writer| writer.write_single_call(call, spaced)) {
+                self.write_broken_chain(call, spaced);
+            }
+        } else {
+            self.write_single_call(call, spaced);
+        }
+    }
+
+    fn write_single_call(&mut self, call: &LuaFunctionCall, spaced: bool) {
+        self.write_prefix_exp(&call.callee);
+
+        if let Some(method) = &call.method {
+            self.write(&format!(":{}", method.name));
+        }
+
+        self.write_args(&call.args, call.span, spaced);
+    }
+
+    fn write_broken_chain(&mut self, call: &LuaFunctionCall, spaced: bool) {
+        let chain = method_chain(call);
+        let count = chain.len();
+
+        self.write_prefix_exp(&chain[count - 1].callee);
+        self.depth += 1;
+
+        for (idx, link) in chain.into_iter().rev().enumerate() {
+            if idx > 0 {
+                self.write_line();
+            }
+
+            self.write(&format!(":{}", link.method.as_ref().unwrap().name));
+            self.write_args(&link.args, link.span, spaced && idx + 1 == count);
+        }
+
+        self.depth -= 1;
+    }
+
+    fn write_args(&mut self, args: &LuaArgs, span: LuaSpan, spaced: bool) {
+        let parens = self.printer.call_parens;
+        let space = if spaced { " " } else { "" };
+
+        match &args.kind {
+            LuaArgsKind::ExpList(exps) => match exps.as_slice() {
+                [arg] if parens == LuaCallParens::Omit && is_literal_arg(arg) => {
+                    self.write(space);
+                    self.write_exp(arg);
+                },
+                _ => {
+                    let closer = self.token_ending(span.end);
+                    let items = exps.iter().map(LuaListItem::Exp).collect();
+                    self.write_list("(", ")", items, closer, false);
+                }
+            },
+            // Bracketed like any other list of arguments, so that the result
+            // is laid out the same when it is formatted again.
+            LuaArgsKind::TableConstructor(table) if parens == LuaCallParens::Always => {
+                let arg = LuaExp { kind: LuaExpKind::TableConstructor(table.clone()), span: table.span };
+                self.write_list("(", ")", vec![LuaListItem::Exp(&arg)], None, false);
+            },
+            LuaArgsKind::TableConstructor(table) => {
+                self.write(space);
+                self.write_table_constructor(table);
+            },
+            LuaArgsKind::LiteralString(value) => {
+                let string_span = self.token_ending(span.end)
+                    .map(|idx| {
+                        let token = &self.source.as_ref().unwrap().tokens[idx];
+                        LuaSpan::new(token.location, token.end_location())
+                    })
+                    .unwrap_or(span);
+
+                if parens == LuaCallParens::Always {
+                    let arg = LuaExp { kind: LuaExpKind::String(value.clone()), span: string_span };
+                    self.write_list("(", ")", vec![LuaListItem::Exp(&arg)], None, false);
+                } else {
+                    self.write(space);
+                    self.write_string(value, string_span);
+                }
+            }
+        }
+    }
+
+    fn write_func_body(&mut self, body: &LuaFuncBody) {
+        self.write("(");
+        self.write_name_list(&body.params);
+
+        if body.varargs {
+            self.write(if body.params.is_empty() { "..." } else { ", ..." });
+        }
+
+        self.write(")");
+        self.write_block(&body.block, "end");
+    }
+
+    fn write_table_constructor(&mut self, table: &LuaTableConstructor) {
+        let closer = self.token_ending(table.span.end);
+        let items = table.fields.iter().map(LuaListItem::Field).collect();
+        self.write_list("{", "}", items, closer, true);
+    }
+
+    /** The first and last tokens of an item in a list. */
+    fn item_tokens(&self, item: &LuaListItem) -> (Option<usize>, Option<usize>) {
+        let (start, end) = match item {
+            LuaListItem::Exp(exp)     => (self.token_starting(exp.span.start), exp.span.end),
+            LuaListItem::Field(field) => (self.token_starting(field.span.start), field.span.end)
+        };
+
+        (start, self.token_ending(end))
+    }
+
+    /** The `,` or `;` after an item, if there is one. */
+    fn separator_after(&self, last: Option<usize>) -> Option<usize> {
+        let source = self.source.as_ref()?;
+        let idx = last? + 1;
+
+        match source.tokens.get(idx)?.token_type {
+            LuaTerminal::Comma | LuaTerminal::Semicolon => Some(idx),
+            _                                           => None
+        }
+    }
+
+    /**
+     * Writes the items of a list between brackets, on one line if it fits
+     * and otherwise with each item on a line of its own. Lists with comments
+     * between their items are always broken up, to keep the comments where
+     * they are.
+     */
+    fn write_list(&mut self, open: &str, close: &str, items: Vec<LuaListItem>, closer: Option<usize>, trailing_separator: bool) {
+        let opener = match items.first() {
+            Some(item) => self.item_tokens(item).0,
+            None       => closer
+        }.and_then(|idx| idx.checked_sub(1));
+
+        let has_comments = self.has_comments(opener, TRAILING) || self.has_comments(closer, LEADING) ||
+            items.iter().any(|item| {
+                let (first, last) = self.item_tokens(item);
+
+                self.has_comments(first, LEADING) || self.has_comments(last, TRAILING) ||
+                    self.has_comments(self.separator_after(last), TRAILING)
+            });
+
+        let written = if has_comments || items.is_empty() && self.line_comment {
+            false
+        } else if self.can_break() && !items.is_empty() {
+            self.try_flat(|writer| writer.write_flat_list(open, close, &items))
+        } else {
+            self.write_flat_list(open, close, &items);
+            true
+        };
+
+        if written {
+            return;
+        }
+
+        self.write(open);
+        self.write_trailing_comments(opener);
+        self.depth += 1;
+
+        let mut first = true;
+
+        for (idx, item) in items.iter().enumerate() {
+            let (first_token, last_token) = self.item_tokens(item);
+
+            self.write_leading_comments(first_token, &mut first);
+            self.begin_line(false);
+            self.write_list_item(item);
+
+            if idx + 1 < items.len() || trailing_separator {
+                self.write(",");
+            }
+
+            self.write_trailing_comments(last_token);
+            self.write_trailing_comments(self.separator_after(last_token));
+        }
+
+        self.write_leading_comments(closer, &mut first);
+        self.depth -= 1;
+        self.begin_line(false);
+        self.write(close);
+    }
+
+    fn write_flat_list(&mut self, open: &str, close: &str, items: &[LuaListItem]) {
+        self.write(open);
+
+        for (idx, item) in items.iter().enumerate() {
+            if idx > 0 {
+                self.write(", ");
+            }
+
+            self.write_list_item(item);
+        }
+
+        self.write(close);
+    }
+
+    fn write_list_item(&mut self, item: &LuaListItem) {
+        match item {
+            LuaListItem::Exp(exp) => self.write_exp(exp),
+            LuaListItem::Field(field) => match &field.kind {
+                LuaFieldKind::Keyed(key, value) => {
+                    self.write_bracketed_key(key);
+                    self.write(" = ");
+                    self.write_exp(value);
+                },
+                LuaFieldKind::Named(name, value) => {
+                    self.write(&format!("{} = ", name.name));
+                    self.write_exp(value);
+                },
+                LuaFieldKind::Positional(value) => self.write_exp(value)
+            }
+        }
+    }
+}
+
+/** The method calls a call is the end of a chain of, last first. */
+fn method_chain(call: &LuaFunctionCall) -> Vec<&LuaFunctionCall> {
+    let mut chain = Vec::new();
+    let mut current = call;
+
+    while current.method.is_some() {
+        chain.push(current);
+
+        match &current.callee.kind {
+            LuaExpKind::FunctionCall(callee) => current = callee,
+            _                                => break
+        }
+    }
+
+    chain
+}
+
+/** Whether a call's only argument can go without brackets. */
+fn is_literal_arg(exp: &LuaExp) -> bool {
+    matches!(exp.kind, LuaExpKind::String(_) | LuaExpKind::TableConstructor(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use crate::tokenisation::Location;
+    use crate::lua::{LuaDialect, LuaBytes};
+
+    fn read(src: &str) -> LuaChunk {
+        read_lua_chunk(src, LuaDialect::Lua54, false).ok().unwrap()
+    }
+
+    fn reprint(src: &str) -> String {
+        print_lua_chunk(&read(src))
+    }
+
+    /** Clears what printing can't keep, so trees can be compared. The
+     *  printer adds semicolons where statements would otherwise run
+     *  together, so those are dropped too. */
+    struct Normaliser;
+
+    const NOWHERE: LuaSpan = LuaSpan {
+        start: Location { line: 1, col: 1 },
+        end:   Location { line: 1, col: 1 }
+    };
+
+    impl VisitorMut for Normaliser {
+        fn visit_chunk_mut(&mut self, chunk: &mut LuaChunk) {
+            chunk.span = NOWHERE;
+            walk_chunk_mut(self, chunk);
+        }
+
+        fn visit_block_mut(&mut self, block: &mut LuaBlock) {
+            block.span = NOWHERE;
+            block.stats.retain(|stat| stat.kind != LuaStatKind::Semicolon);
+            walk_block_mut(self, block);
+        }
+
+        fn visit_stat_mut(&mut self, stat: &mut LuaStat) {
+            stat.span = NOWHERE;
+            stat.doc = None;
+            walk_stat_mut(self, stat);
+        }
+
+        fn visit_ret_stat_mut(&mut self, ret_stat: &mut LuaRetStat) {
+            ret_stat.span = NOWHERE;
+            walk_ret_stat_mut(self, ret_stat);
+        }
+
+        fn visit_att_name_mut(&mut self, att_name: &mut LuaAttName) {
+            att_name.span = NOWHERE;
+
+            if let Some(attrib) = &mut att_name.attrib {
+                attrib.span = NOWHERE;
+            }
+
+            walk_att_name_mut(self, att_name);
+        }
+
+        fn visit_label_mut(&mut self, label: &mut LuaLabel) {
+            label.span = NOWHERE;
+            self.visit_name_mut(&mut label.name);
+        }
+
+        fn visit_func_name_mut(&mut self, func_name: &mut LuaFuncName) {
+            func_name.span = NOWHERE;
+            walk_func_name_mut(self, func_name);
+        }
+
+        fn visit_exp_mut(&mut self, exp: &mut LuaExp) {
+            exp.span = NOWHERE;
+            walk_exp_mut(self, exp);
+        }
+
+        fn visit_name_mut(&mut self, name: &mut LuaName) {
+            name.span = NOWHERE;
+            name.id = LuaNodeId(0);
+        }
+
+        fn visit_function_call_mut(&mut self, call: &mut LuaFunctionCall) {
+            call.span = NOWHERE;
+            walk_function_call_mut(self, call);
+        }
+
+        fn visit_func_body_mut(&mut self, body: &mut LuaFuncBody) {
+            body.span = NOWHERE;
+            walk_func_body_mut(self, body);
+        }
+
+        fn visit_table_constructor_mut(&mut self, table: &mut LuaTableConstructor) {
+            table.span = NOWHERE;
+            walk_table_constructor_mut(self, table);
+        }
+
+        fn visit_args_mut(&mut self, args: &mut LuaArgs) {
+            args.span = NOWHERE;
+            walk_args_mut(self, args);
+        }
+
+        fn visit_field_mut(&mut self, field: &mut LuaField) {
+            field.span = NOWHERE;
+            walk_field_mut(self, field);
+        }
+    }
+
+    /** Takes out every `Bracketed` node, leaving what it held. */
+    struct BracketRemover;
+
+    impl VisitorMut for BracketRemover {
+        fn visit_exp_mut(&mut self, exp: &mut LuaExp) {
+            while let LuaExpKind::Bracketed(inner) = &exp.kind {
+                *exp = (**inner).clone();
+            }
+
+            walk_exp_mut(self, exp);
+        }
+    }
+
+    fn remove_brackets(mut chunk: LuaChunk) -> LuaChunk {
+        BracketRemover.visit_chunk_mut(&mut chunk);
+        chunk
+    }
+
+    fn normalise(mut chunk: LuaChunk) -> LuaChunk {
+        Normaliser.visit_chunk_mut(&mut chunk);
+        chunk
+    }
+
+    fn read_printed(chunk: &LuaChunk) -> LuaChunk {
+        let source = print_lua_chunk(chunk);
+
+        read_lua_chunk(source.as_str(), LuaDialect::Lua54, false)
+            .unwrap_or_else(|error| panic!("{}\nin:\n{}", error.message(), source))
+    }
+
+    /** Prints a tree and reads it back, then checks that doing that again
+     *  gives the same tree. Trees built by hand can be missing the
+     *  `Bracketed` nodes that reading would give them, so the first trip
+     *  only gets them into the shape the reader gives. */
+    fn assert_round_trips(chunk: LuaChunk) {
+        let parsed = normalise(read_printed(&chunk));
+        let reparsed = normalise(read_printed(&parsed));
+
+        assert!(parsed == reparsed, "printed as:\n{}\nread back as:\n{}", print_lua_chunk(&parsed), print_lua_chunk(&reparsed));
+    }
+
+    #[test]
+    fn adds_only_the_parentheses_precedence_needs() {
+        let cases = vec![
+            ("x = a + b * c",       "x = a + b * c"),
+            ("x = (a + b) * c",     "x = (a + b) * c"),
+            ("x = a .. b .. c",     "x = a .. b .. c"),
+            ("x = 2 ^ 3 ^ 2",       "x = 2 ^ 3 ^ 2"),
+            ("x = -a ^ b",          "x = -a ^ b"),
+            ("x = - - a",           "x = - -a"),
+            ("x = not a == b",      "x = not a == b"),
+            ("x = ('a'):rep(3)",    "x = (\"a\"):rep(3)"),
+            ("x = t[ [[a\nb]] ]",   "x = t[ [[a\nb]] ]")
+        ];
+
+        for (src, expected) in cases {
+            assert_eq!(reprint(src), format!("{}\n", expected));
+        }
+    }
+
+    #[test]
+    fn brackets_trees_that_were_built_by_hand() {
+        let exp = |kind| LuaExp { kind, span: NOWHERE };
+        let name = |text: &str| exp(LuaExpKind::Name(LuaName { id: LuaNodeId(0), name: LuaSymbol::from(text), span: NOWHERE }));
+        let binop = |op, left, right| exp(LuaExpKind::BinOp(op, Box::new(left), Box::new(right)));
+        let number = |value| exp(LuaExpKind::Number(value));
+
+        let printer = LuaPrinter::new();
+        let sub = binop(LuaBinOp::Sub, name("a"), binop(LuaBinOp::Sub, name("b"), name("c")));
+        let pow = binop(LuaBinOp::Pow, number(LuaNumeral::Integer(-2)), number(LuaNumeral::Float(-0.5)));
+        let neg = exp(LuaExpKind::UnOp(LuaUnOp::Neg, Box::new(number(LuaNumeral::Integer(-1)))));
+
+        assert_eq!(printer.print_exp(&sub), "a - (b - c)");
+        assert_eq!(printer.print_exp(&pow), "(-2) ^ -0.5");
+        assert_eq!(printer.print_exp(&neg), "- -1");
+        assert_eq!(printer.print_exp(&number(LuaNumeral::Integer(i64::MIN))), "0x8000000000000000");
+        assert_eq!(printer.print_exp(&number(LuaNumeral::Float(f64::NAN))), "(0/0)");
+    }
+
+    #[test]
+    fn quotes_strings_so_they_read_back() {
+        assert_eq!(print_lua_string(b"plain"),           "\"plain\"");
+        assert_eq!(print_lua_string(b"say \"hi\""),      "'say \"hi\"'");
+        assert_eq!(print_lua_string(b"\x00\x01" as &[u8]), "\"\\000\\001\"");
+        assert_eq!(print_lua_string(b"\xff1"),           "\"\\2551\"");
+        assert_eq!(print_lua_string(b"one\ntwo"),        "[[one\ntwo]]");
+        assert_eq!(print_lua_string(b"\na]]"),           "[=[\n\na]]]=]");
+        assert_eq!(print_lua_string(b"a\r\nb"),          "\"a\\r\\nb\"");
+        assert_eq!(print_lua_string(b"x]=\ny"),          "[[x]=\ny]]");
+        assert_eq!(print_lua_string(b"x]\n"),            "[[x]\n]]");
+        assert_eq!(print_lua_string(b"\nx]"),            "[=[\n\nx]]=]");
+    }
+
+    #[test]
+    fn prints_every_statement() {
+        let src = "\
+local a <const>, b = 1, 2.5
+local function f(x, ...)
+    return ...
+end
+function t.a.b:c() end
+while a do
+    break
+end
+repeat
+    local x = 1
+until x
+for i = 1, 10, 2 do
+    goto done
+end
+::done::
+for k, v in pairs(t) do
+    print(k, v)
+end
+if a then
+    f()
+elseif b then
+    g()
+else
+    h()
+end
+do end
+s = f {1, x = 2, [3] = 4}
+s = f \"x\"
+f();
+(g)()
+return
+";
+        assert_eq!(reprint(src), src);
+        assert_round_trips(read(src));
+    }
+
+    fn arb_name() -> impl Strategy<Value = LuaName> {
+        prop::sample::select(vec!["a", "b", "x", "_y", "foo", "end_", "Nil"])
+            .prop_map(|name| LuaName { id: LuaNodeId(0), name: LuaSymbol::from(name), span: NOWHERE })
+    }
+
+    fn arb_string() -> impl Strategy<Value = LuaBytes> {
+        let bytes = prop_oneof![
+            any::<u8>(),
+            prop::sample::select(b"ab\n\r\t]=[\"'\\0".to_vec())
+        ];
+
+        prop::collection::vec(bytes, 0..12).prop_map(LuaBytes::from)
+    }
+
+    fn arb_numeral() -> impl Strategy<Value = LuaNumeral> {
+        use prop::num::f64::{POSITIVE, ZERO, NORMAL, SUBNORMAL};
+
+        prop_oneof![
+            (0..=i64::MAX).prop_map(LuaNumeral::Integer),
+            (POSITIVE | ZERO | NORMAL | SUBNORMAL).prop_map(LuaNumeral::Float)
+        ]
+    }
+
+    fn exp(kind: LuaExpKind) -> LuaExp {
+        LuaExp { kind, span: NOWHERE }
+    }
+
+    fn arb_exp(depth: u32) -> BoxedStrategy<LuaExp> {
+        let leaf = prop_oneof![
+            Just(LuaExpKind::Nil),
+            any::<bool>().prop_map(LuaExpKind::Boolean),
+            arb_numeral().prop_map(LuaExpKind::Number),
+            arb_string().prop_map(LuaExpKind::String),
+            Just(LuaExpKind::Varargs),
+            arb_name().prop_map(LuaExpKind::Name)
+        ].prop_map(exp);
+
+        if depth == 0 {
+            return leaf.boxed();
+        }
+
+        let sub = arb_exp(depth - 1);
+        let binop = prop::sample::select(vec![
+            LuaBinOp::Or, LuaBinOp::And, LuaBinOp::Less, LuaBinOp::GreaterEq, LuaBinOp::Eq,
+            LuaBinOp::NotEq, LuaBinOp::BitwiseOr, LuaBinOp::BitwiseXor, LuaBinOp::BitwiseAnd,
+            LuaBinOp::LeftShift, LuaBinOp::Concat, LuaBinOp::Add, LuaBinOp::Sub, LuaBinOp::Mul,
+            LuaBinOp::Div, LuaBinOp::FloorDiv, LuaBinOp::Mod, LuaBinOp::Pow
+        ]);
+        let unop = prop::sample::select(vec![LuaUnOp::Not, LuaUnOp::Len, LuaUnOp::Neg, LuaUnOp::BitwiseNot]);
+
+        prop_oneof![
+            2 => leaf,
+            2 => (binop, sub.clone(), sub.clone())
+                .prop_map(|(op, left, right)| exp(LuaExpKind::BinOp(op, Box::new(left), Box::new(right)))),
+            1 => (unop, sub.clone()).prop_map(|(op, operand)| exp(LuaExpKind::UnOp(op, Box::new(operand)))),
+            1 => sub.clone().prop_map(|inner| exp(LuaExpKind::Bracketed(Box::new(inner)))),
+            1 => arb_prefix_exp(depth),
+            1 => arb_table_constructor(depth - 1).prop_map(|table| exp(LuaExpKind::TableConstructor(table))),
+            1 => arb_func_body(depth - 1).prop_map(|body| exp(LuaExpKind::FunctionDef(body)))
+        ].boxed()
+    }
+
+    /** Expressions that can be indexed or called as they are. */
+    fn arb_prefix_exp(depth: u32) -> BoxedStrategy<LuaExp> {
+        let name = arb_name().prop_map(|name| exp(LuaExpKind::Name(name)));
+
+        if depth == 0 {
+            return name.boxed();
+        }
+
+        let prefix = arb_prefix_exp(depth - 1);
+        let sub = arb_exp(depth - 1);
+
+        prop_oneof![
+            name,
+            sub.clone().prop_map(|inner| exp(LuaExpKind::Bracketed(Box::new(inner)))),
+            (prefix.clone(), sub).prop_map(|(prefix, key)| exp(LuaExpKind::Index(Box::new(prefix), Box::new(key)))),
+            (prefix, arb_name()).prop_map(|(prefix, name)| exp(LuaExpKind::Field(Box::new(prefix), name))),
+            arb_function_call(depth).prop_map(|call| exp(LuaExpKind::FunctionCall(Box::new(call))))
+        ].boxed()
+    }
+
+    fn arb_function_call(depth: u32) -> BoxedStrategy<LuaFunctionCall> {
+        let args = prop_oneof![
+            prop::collection::vec(arb_exp(depth.saturating_sub(1)), 0..3).prop_map(LuaArgsKind::ExpList),
+            arb_table_constructor(depth.saturating_sub(1)).prop_map(LuaArgsKind::TableConstructor),
+            arb_string().prop_map(LuaArgsKind::LiteralString)
+        ].prop_map(|kind| LuaArgs { kind, span: NOWHERE });
+
+        (arb_prefix_exp(depth.saturating_sub(1)), prop::option::of(arb_name()), args)
+            .prop_map(|(callee, method, args)| LuaFunctionCall { callee, method, args, span: NOWHERE })
+            .boxed()
+    }
+
+    fn arb_table_constructor(depth: u32) -> BoxedStrategy<LuaTableConstructor> {
+        let sub = arb_exp(depth);
+        let field = prop_oneof![
+            (sub.clone(), sub.clone()).prop_map(|(key, value)| LuaFieldKind::Keyed(key, value)),
+            (arb_name(), sub.clone()).prop_map(|(name, value)| LuaFieldKind::Named(name, value)),
+            sub.prop_map(LuaFieldKind::Positional)
+        ].prop_map(|kind| LuaField { kind, span: NOWHERE });
+
+        prop::collection::vec(field, 0..3)
+            .prop_map(|fields| LuaTableConstructor { fields, span: NOWHERE })
+            .boxed()
+    }
+
+    /** Every generated function takes `...`, so that `...` can appear
+     *  anywhere. */
+    fn arb_func_body(depth: u32) -> BoxedStrategy<LuaFuncBody> {
+        (prop::collection::vec(arb_name(), 0..3), arb_block(depth))
+            .prop_map(|(params, block)| LuaFuncBody { params, varargs: true, block, span: NOWHERE })
+            .boxed()
+    }
+
+    fn stat(kind: LuaStatKind) -> LuaStat {
+        LuaStat { kind, span: NOWHERE, doc: None }
+    }
+
+    /** Statements that later passes over the tree can't object to, so no
+     *  jumps, attributes or semicolons. */
+    fn arb_stat(depth: u32) -> BoxedStrategy<LuaStat> {
+        let sub = arb_exp(depth);
+        let exps = prop::collection::vec(sub.clone(), 1..3);
+        let target = prop_oneof![
+            arb_name().prop_map(|name| exp(LuaExpKind::Name(name))),
+            arb_prefix_exp(depth).prop_filter("assignable", |target| {
+                matches!(target.kind, LuaExpKind::Name(_) | LuaExpKind::Index(_, _) | LuaExpKind::Field(_, _))
+            })
+        ];
+
+        let simple = prop_oneof![
+            (prop::collection::vec(target, 1..3), exps.clone())
+                .prop_map(|(targets, exps)| stat(LuaStatKind::VarList(targets, exps))),
+            arb_function_call(depth).prop_map(|call| stat(LuaStatKind::FunctionCall(call))),
+            (prop::collection::vec(arb_name(), 1..3), prop::collection::vec(sub.clone(), 0..3))
+                .prop_map(|(names, exps)| {
+                    let att_names = names.into_iter()
+                        .map(|name| LuaAttName { name, attrib: None, span: NOWHERE })
+                        .collect();
+                    stat(LuaStatKind::LocalAttNameList(att_names, exps))
+                })
+        ];
+
+        if depth == 0 {
+            return simple.boxed();
+        }
+
+        let block = arb_block(depth - 1);
+        let body = arb_func_body(depth - 1);
+        let func_name = (prop::collection::vec(arb_name(), 1..3), prop::option::of(arb_name()))
+            .prop_map(|(path, method)| LuaFuncName { path, method, span: NOWHERE });
+
+        prop_oneof![
+            3 => simple,
+            1 => block.clone().prop_map(|block| stat(LuaStatKind::Do(block))),
+            1 => (sub.clone(), block.clone()).prop_map(|(condition, block)| stat(LuaStatKind::While(condition, block))),
+            1 => (block.clone(), sub.clone()).prop_map(|(block, condition)| stat(LuaStatKind::Repeat(block, condition))),
+            1 => (sub.clone(), block.clone(), prop::collection::vec((sub.clone(), block.clone()), 0..2), prop::option::of(block.clone()))
+                .prop_map(|(condition, block, else_ifs, else_block)| stat(LuaStatKind::If(condition, block, else_ifs, else_block))),
+            1 => (arb_name(), sub.clone(), sub.clone(), prop::option::of(sub.clone().prop_map(Box::new)), block.clone())
+                .prop_map(|(name, initial, limit, step, block)| stat(LuaStatKind::For(name, initial, limit, step, block))),
+            1 => (prop::collection::vec(arb_name(), 1..3), exps, block)
+                .prop_map(|(names, exps, block)| stat(LuaStatKind::ForIn(names, exps, block))),
+            1 => (func_name, body.clone()).prop_map(|(func_name, body)| stat(LuaStatKind::Function(func_name, body))),
+            1 => (arb_name(), body).prop_map(|(name, body)| stat(LuaStatKind::LocalFunction(name, body)))
+        ].boxed()
+    }
+
+    fn arb_block(depth: u32) -> BoxedStrategy<LuaBlock> {
+        let ret_stat = prop::option::of(prop::collection::vec(arb_exp(depth), 0..3))
+            .prop_map(|exps| exps.map(|exps| LuaRetStat { exps, span: NOWHERE }));
+
+        (prop::collection::vec(arb_stat(depth), 0..4), ret_stat)
+            .prop_map(|(stats, ret_stat)| LuaBlock { stats, ret_stat, span: NOWHERE })
+            .boxed()
+    }
+
+    proptest! {
+        #[test]
+        fn printed_expressions_read_back_the_same(exp in arb_exp(4)) {
+            let ret_stat = LuaRetStat { exps: vec![exp], span: NOWHERE };
+            let block = LuaBlock { stats: vec![], ret_stat: Some(ret_stat), span: NOWHERE };
+            let chunk = LuaChunk { block, span: NOWHERE };
+
+            // Whatever brackets were added, the operators must still group
+            // the same way.
+            let reread = remove_brackets(normalise(read_printed(&chunk)));
+            prop_assert_eq!(reread, remove_brackets(normalise(chunk.clone())));
+
+            assert_round_trips(chunk);
+        }
+
+        #[test]
+        fn printed_chunks_read_back_the_same(block in arb_block(2)) {
+            assert_round_trips(LuaChunk { block, span: NOWHERE });
+        }
+
+        #[test]
+        fn printed_numerals_read_back_exactly(numeral in arb_numeral()) {
+            let printed = read(&format!("return {}", print_lua_numeral(&numeral)));
+
+            match &printed.block.ret_stat.as_ref().unwrap().exps[0].kind {
+                LuaExpKind::Number(reread) => prop_assert_eq!(*reread, numeral),
+                other => panic!("read back as {:?}", other)
+            }
+        }
+    }
+}
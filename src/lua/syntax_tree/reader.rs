@@ -0,0 +1,1047 @@
+/*!
+ * Reads Lua source into a syntax tree.
+ *
+ * This is a recursive descent parser over the token stream that follows
+ * `lparser.c` in the reference implementation, including the wording of its
+ * error messages. Operator precedence is handled the same way too, with the
+ * binding powers in `LuaBinOp::priority` standing in for the
+ * `LuaNonterminal::Exp` to `Exp13` levels of the LR grammar that
+ * `get_lua_parser` builds.
+ */
+
+use crate::tokenisation::{Token, Location};
+use crate::lua::{LuaTerminal, LuaTokenData, LuaDialect, LuaReadError, LuaSemanticError, get_lua_tokeniser, get_lua_file_tokeniser, get_doc_comment, check_lua_chunk};
+use crate::lua::syntax_tree::*;
+
+type ReadResult<T> = Result<T, LuaReadError>;
+
+/**
+ * Reads a whole chunk of Lua into a syntax tree. With `prologue`, a byte
+ * order mark and a first line starting with `#` are skipped the way
+ * `luaL_loadfile` skips them, as files should be read; without it the source
+ * is taken exactly as it is, the way `load` takes a string.
+ *
+ * The first error found, either
+ * while tokenising or parsing, is returned instead; syntax errors are
+ * `LuaReadError::Syntax` and are reported near the offending token, as the
+ * reference parser does. What the reference compiler rejects once it has
+ * read something, like jumps into the scope of a local, or assignments to
+ * `<const>` locals, is `LuaReadError::Semantic` at the part at fault.
+ */
+pub fn read_lua_chunk<S>(source: S, dialect: LuaDialect, prologue: bool) -> ReadResult<LuaChunk> where S: Into<Vec<u8>> {
+    let mut tokeniser = match prologue {
+        true  => get_lua_file_tokeniser(dialect),
+        false => get_lua_tokeniser(dialect)
+    }.unwrap();
+    let tokens = tokeniser.tokenise(source)?;
+
+    read_lua_tokens(tokens.into_iter().collect(), dialect)
+}
+
+/** Reads a whole Lua file that has already been tokenised, ending with the
+ *  end of file token. */
+pub fn read_lua_tokens(tokens: Vec<Token<LuaTerminal>>, dialect: LuaDialect) -> ReadResult<LuaChunk> {
+    read_checked_lua_tokens(tokens, dialect, check_lua_chunk)
+}
+
+/** Reads tokens the way `read_lua_tokens` does, but with `check` in place
+ *  of the reference compiler's checks, for callers that would rather report
+ *  some of what they find themselves. */
+pub fn read_checked_lua_tokens<F>(
+    tokens:  Vec<Token<LuaTerminal>>,
+    dialect: LuaDialect,
+    check:   F
+) -> ReadResult<LuaChunk> where F: FnOnce(&LuaChunk, LuaDialect) -> Result<(), LuaSemanticError> {
+    let mut reader = LuaReader::new(tokens, dialect);
+    let chunk = reader.read_chunk()?;
+
+    match check(&chunk, dialect) {
+        Ok(())     => Ok(chunk),
+        Err(error) => Err(reader.semantic_error(error.message, error.span))
+    }
+}
+
+struct LuaReader {
+    tokens:        Vec<Token<LuaTerminal>>,
+    position:      usize,
+    dialect:       LuaDialect,
+    interner:      LuaInterner,
+    next_id:       u32,
+    /** Where the most recently consumed token ended. */
+    last_end:      Location,
+    /** Whether each function being read, innermost last, takes `...`. */
+    vararg_scopes: Vec<bool>
+}
+
+impl LuaReader {
+    fn new(tokens: Vec<Token<LuaTerminal>>, dialect: LuaDialect) -> LuaReader {
+        LuaReader {
+            tokens,
+            position:      0,
+            dialect,
+            interner:      LuaInterner::new(),
+            next_id:       0,
+            last_end:      Location { line: 1, col: 1 },
+            vararg_scopes: vec![true]
+        }
+    }
+
+    fn read_chunk(&mut self) -> ReadResult<LuaChunk> {
+        let start = self.current().location;
+        let block = self.read_block()?;
+        self.expect(LuaTerminal::EndOfFile, "<eof>")?;
+
+        Ok(LuaChunk {
+            block,
+            span:  LuaSpan::new(start, self.last_end.max(start))
+        })
+    }
+
+    fn current(&self) -> &Token<LuaTerminal> {
+        &self.tokens[self.position]
+    }
+
+    fn current_type(&self) -> LuaTerminal {
+        self.current().token_type
+    }
+
+    fn lookahead_type(&self) -> LuaTerminal {
+        self.tokens
+            .get(self.position + 1)
+            .map(|token| token.token_type)
+            .unwrap_or(LuaTerminal::EndOfFile)
+    }
+
+    fn line(&self) -> usize {
+        self.current().location.line
+    }
+
+    /** Moves past the current token. The end of file token is never moved
+     *  past, so there is always a current token. */
+    fn advance(&mut self) -> Token<LuaTerminal> {
+        let token = self.tokens[self.position].clone();
+
+        if token.token_type != LuaTerminal::EndOfFile {
+            self.last_end = token.end_location();
+            self.position += 1;
+        }
+
+        token
+    }
+
+    fn accept(&mut self, token_type: LuaTerminal) -> bool {
+        if self.current_type() == token_type {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token_type: LuaTerminal, text: &str) -> ReadResult<Token<LuaTerminal>> {
+        if self.current_type() == token_type {
+            Ok(self.advance())
+        } else {
+            Err(self.error(format!("{} expected", quote_token(text))))
+        }
+    }
+
+    /** Expects the token closing a construct that was opened on `line`,
+     *  mentioning where it was opened if that was on another line. */
+    fn expect_match(&mut self,
+        token_type: LuaTerminal,
+        text:       &str,
+        opened_by:  &str,
+        line:       usize
+    ) -> ReadResult<Token<LuaTerminal>> {
+        if self.current_type() == token_type || line == self.line() {
+            self.expect(token_type, text)
+        } else {
+            Err(self.error(format!("{} expected (to close {} at line {})", quote_token(text), quote_token(opened_by), line)))
+        }
+    }
+
+    /** An error at the current token. Like the reference parser, it is
+     *  reported near the token's source text. */
+    fn error(&self, message: String) -> LuaReadError {
+        let mut near = self.current().clone();
+        near.leading_trivia.clear();
+        near.trailing_trivia.clear();
+
+        LuaReadError::Syntax { message, near: Box::new(near) }
+    }
+
+    /** An error that isn't about the syntax, at the token a span starts
+     *  with. */
+    fn semantic_error(&self, message: String, span: LuaSpan) -> LuaReadError {
+        let mut at = self.tokens.iter()
+            .find(|token| token.location == span.start)
+            .unwrap_or(self.current())
+            .clone();
+        at.leading_trivia.clear();
+        at.trailing_trivia.clear();
+
+        LuaReadError::Semantic { message, at: Box::new(at) }
+    }
+
+    fn span_from(&self, start: Location) -> LuaSpan {
+        LuaSpan::new(start, self.last_end.max(start))
+    }
+
+    fn read_name(&mut self) -> ReadResult<LuaName> {
+        let token = match self.current_type() {
+            LuaTerminal::Identifier => self.advance(),
+            _                       => return Err(self.error(String::from("<name> expected")))
+        };
+
+        let name = match &token.token_data {
+            Some(LuaTokenData::Identifier(name)) => self.interner.intern(name),
+            _                                    => self.interner.intern(&String::from_utf8_lossy(&token.text))
+        };
+
+        let id = LuaNodeId(self.next_id);
+        self.next_id += 1;
+
+        Ok(LuaName {
+            id,
+            name,
+            span: LuaSpan::new(token.location, token.end_location())
+        })
+    }
+
+    /** Whether the current token ends a block. */
+    fn block_follows(&self, with_until: bool) -> bool {
+        match self.current_type() {
+            LuaTerminal::Else | LuaTerminal::Elseif |
+            LuaTerminal::End  | LuaTerminal::EndOfFile => true,
+            LuaTerminal::Until                         => with_until,
+            _                                          => false
+        }
+    }
+
+    fn read_block(&mut self) -> ReadResult<LuaBlock> {
+        let start = self.current().location;
+        let mut stats = Vec::new();
+        let mut ret_stat = None;
+
+        while !self.block_follows(true) {
+            if self.current_type() == LuaTerminal::Return {
+                ret_stat = Some(self.read_ret_stat()?);
+                break;
+            }
+
+            stats.push(self.read_stat()?);
+        }
+
+        Ok(LuaBlock {
+            stats,
+            ret_stat,
+            span:     self.span_from(start)
+        })
+    }
+
+    fn read_ret_stat(&mut self) -> ReadResult<LuaRetStat> {
+        let start = self.advance().location;
+
+        let exps = match self.block_follows(true) || self.current_type() == LuaTerminal::Semicolon {
+            true  => Vec::new(),
+            false => self.read_exp_list()?
+        };
+        self.accept(LuaTerminal::Semicolon);
+
+        Ok(LuaRetStat {
+            exps,
+            span: self.span_from(start)
+        })
+    }
+
+    fn read_stat(&mut self) -> ReadResult<LuaStat> {
+        let start = self.current().location;
+        let line  = self.line();
+        let doc   = get_doc_comment(self.current());
+
+        let kind = match self.current_type() {
+            LuaTerminal::Semicolon => {
+                self.advance();
+                LuaStatKind::Semicolon
+            },
+            LuaTerminal::If => self.read_if_stat(line)?,
+            LuaTerminal::While => {
+                self.advance();
+                let condition = self.read_exp()?;
+                self.expect(LuaTerminal::Do, "do")?;
+                let block = self.read_block()?;
+                self.expect_match(LuaTerminal::End, "end", "while", line)?;
+
+                LuaStatKind::While(condition, block)
+            },
+            LuaTerminal::Do => {
+                self.advance();
+                let block = self.read_block()?;
+                self.expect_match(LuaTerminal::End, "end", "do", line)?;
+
+                LuaStatKind::Do(block)
+            },
+            LuaTerminal::For => self.read_for_stat(line)?,
+            LuaTerminal::Repeat => {
+                self.advance();
+                let block = self.read_block()?;
+                self.expect_match(LuaTerminal::Until, "until", "repeat", line)?;
+                let condition = self.read_exp()?;
+
+                LuaStatKind::Repeat(block, condition)
+            },
+            LuaTerminal::Function => {
+                self.advance();
+                let name = self.read_func_name()?;
+                let body = self.read_func_body(line)?;
+
+                LuaStatKind::Function(name, body)
+            },
+            LuaTerminal::Local => {
+                self.advance();
+
+                if self.accept(LuaTerminal::Function) {
+                    let name = self.read_name()?;
+                    let body = self.read_func_body(line)?;
+
+                    LuaStatKind::LocalFunction(name, body)
+                } else {
+                    self.read_local_stat()?
+                }
+            },
+            LuaTerminal::DoubleColon => {
+                self.advance();
+                let name = self.read_name()?;
+                self.expect(LuaTerminal::DoubleColon, "::")?;
+
+                LuaStatKind::Label(LuaLabel {
+                    name,
+                    span: self.span_from(start)
+                })
+            },
+            LuaTerminal::Break => {
+                self.advance();
+                LuaStatKind::Break
+            },
+            LuaTerminal::Goto => {
+                self.advance();
+                LuaStatKind::Goto(self.read_name()?)
+            },
+            _ => self.read_exp_stat()?
+        };
+
+        Ok(LuaStat {
+            kind,
+            span: self.span_from(start),
+            doc
+        })
+    }
+
+    fn read_if_stat(&mut self, line: usize) -> ReadResult<LuaStatKind> {
+        self.advance();
+        let condition = self.read_exp()?;
+        self.expect(LuaTerminal::Then, "then")?;
+        let block = self.read_block()?;
+
+        let mut else_ifs = Vec::new();
+        let mut else_block = None;
+
+        while self.accept(LuaTerminal::Elseif) {
+            let condition = self.read_exp()?;
+            self.expect(LuaTerminal::Then, "then")?;
+            else_ifs.push((condition, self.read_block()?));
+        }
+
+        if self.accept(LuaTerminal::Else) {
+            else_block = Some(self.read_block()?);
+        }
+
+        self.expect_match(LuaTerminal::End, "end", "if", line)?;
+        Ok(LuaStatKind::If(condition, block, else_ifs, else_block))
+    }
+
+    fn read_for_stat(&mut self, line: usize) -> ReadResult<LuaStatKind> {
+        self.advance();
+        let name = self.read_name()?;
+
+        match self.current_type() {
+            LuaTerminal::Equals => {
+                self.advance();
+                let initial = self.read_exp()?;
+                self.expect(LuaTerminal::Comma, ",")?;
+                let limit = self.read_exp()?;
+
+                let step = match self.accept(LuaTerminal::Comma) {
+                    true  => Some(Box::new(self.read_exp()?)),
+                    false => None
+                };
+
+                let block = self.read_for_body(line)?;
+                Ok(LuaStatKind::For(name, initial, limit, step, block))
+            },
+            LuaTerminal::Comma | LuaTerminal::In => {
+                let mut names = vec![name];
+
+                while self.accept(LuaTerminal::Comma) {
+                    names.push(self.read_name()?);
+                }
+
+                self.expect(LuaTerminal::In, "in")?;
+                let exps = self.read_exp_list()?;
+
+                let block = self.read_for_body(line)?;
+                Ok(LuaStatKind::ForIn(names, exps, block))
+            },
+            _ => Err(self.error(String::from("'=' or 'in' expected")))
+        }
+    }
+
+    fn read_for_body(&mut self, line: usize) -> ReadResult<LuaBlock> {
+        self.expect(LuaTerminal::Do, "do")?;
+        let block = self.read_block()?;
+        self.expect_match(LuaTerminal::End, "end", "for", line)?;
+
+        Ok(block)
+    }
+
+    fn read_local_stat(&mut self) -> ReadResult<LuaStatKind> {
+        let mut att_names = Vec::new();
+        let mut closed = false;
+
+        loop {
+            let name = self.read_name()?;
+            let start = name.span.start;
+
+            let attrib = match self.dialect.has_attributes() && self.current_type() == LuaTerminal::LessThan {
+                true => {
+                    let attrib_start = self.advance().location;
+                    let attrib_name = self.read_name()?;
+                    self.expect(LuaTerminal::GreaterThan, ">")?;
+
+                    let kind = match LuaAttribKind::from_name(&attrib_name.name) {
+                        Some(kind) => kind,
+                        None       => return Err(self.semantic_error(
+                            format!("unknown attribute '{}'", attrib_name.name), attrib_name.span))
+                    };
+
+                    if kind == LuaAttribKind::Close && closed {
+                        return Err(self.semantic_error(
+                            String::from("multiple to-be-closed variables in local list"), attrib_name.span));
+                    }
+
+                    closed = closed || kind == LuaAttribKind::Close;
+
+                    Some(LuaAttrib {
+                        kind,
+                        span: self.span_from(attrib_start)
+                    })
+                },
+                false => None
+            };
+
+            att_names.push(LuaAttName {
+                name,
+                attrib,
+                span:   self.span_from(start)
+            });
+
+            if !self.accept(LuaTerminal::Comma) {
+                break;
+            }
+        }
+
+        let exps = match self.accept(LuaTerminal::Equals) {
+            true  => self.read_exp_list()?,
+            false => Vec::new()
+        };
+
+        Ok(LuaStatKind::LocalAttNameList(att_names, exps))
+    }
+
+    fn read_exp_stat(&mut self) -> ReadResult<LuaStatKind> {
+        let exp = self.read_suffixed_exp()?;
+
+        if self.current_type() == LuaTerminal::Equals || self.current_type() == LuaTerminal::Comma {
+            let mut targets = vec![self.check_assignable(exp)?];
+
+            while self.accept(LuaTerminal::Comma) {
+                let target = self.read_suffixed_exp()?;
+                targets.push(self.check_assignable(target)?);
+            }
+
+            self.expect(LuaTerminal::Equals, "=")?;
+            let exps = self.read_exp_list()?;
+
+            return Ok(LuaStatKind::VarList(targets, exps));
+        }
+
+        if let Some(op) = compound_operator(self.current_type()) {
+            let target = self.check_assignable(exp)?;
+            self.advance();
+            let value = self.read_exp()?;
+
+            return Ok(LuaStatKind::CompoundAssignment(target, op, value));
+        }
+
+        match exp.kind {
+            LuaExpKind::FunctionCall(call) => Ok(LuaStatKind::FunctionCall(*call)),
+            // Luau's `continue` isn't reserved, so it is only a statement
+            // where a name couldn't be one on its own.
+            LuaExpKind::Name(name) if self.dialect.has_continue() && name.name.as_str() == "continue" => {
+                Ok(LuaStatKind::Continue)
+            },
+            _ => Err(self.error(String::from("syntax error")))
+        }
+    }
+
+    fn check_assignable(&self, exp: LuaExp) -> ReadResult<LuaExp> {
+        match exp.kind {
+            LuaExpKind::Name(_) | LuaExpKind::Index(_, _) | LuaExpKind::Field(_, _) => Ok(exp),
+            _ => Err(self.error(String::from("syntax error")))
+        }
+    }
+
+    fn read_func_name(&mut self) -> ReadResult<LuaFuncName> {
+        let start = self.current().location;
+        let mut path = vec![self.read_name()?];
+        let mut method = None;
+
+        while self.accept(LuaTerminal::Dot) {
+            path.push(self.read_name()?);
+        }
+
+        if self.accept(LuaTerminal::Colon) {
+            method = Some(self.read_name()?);
+        }
+
+        Ok(LuaFuncName {
+            path,
+            method,
+            span:   self.span_from(start)
+        })
+    }
+
+    /** Reads a function's parameters and body. `line` is where the
+     *  `function` keyword was. */
+    fn read_func_body(&mut self, line: usize) -> ReadResult<LuaFuncBody> {
+        let start = self.current().location;
+        self.expect(LuaTerminal::LeftParenthesis, "(")?;
+
+        let mut params = Vec::new();
+        let mut varargs = false;
+
+        if self.current_type() != LuaTerminal::RightParenthesis {
+            loop {
+                match self.current_type() {
+                    LuaTerminal::Identifier => params.push(self.read_name()?),
+                    LuaTerminal::Varargs    => {
+                        self.advance();
+                        varargs = true;
+                        break;
+                    },
+                    _ => return Err(self.error(String::from("<name> expected")))
+                }
+
+                if !self.accept(LuaTerminal::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.expect(LuaTerminal::RightParenthesis, ")")?;
+
+        self.vararg_scopes.push(varargs);
+        let block = self.read_block();
+        self.vararg_scopes.pop();
+        let block = block?;
+
+        self.expect_match(LuaTerminal::End, "end", "function", line)?;
+
+        Ok(LuaFuncBody {
+            params,
+            varargs,
+            block,
+            span:    self.span_from(start)
+        })
+    }
+
+    fn read_exp_list(&mut self) -> ReadResult<Vec<LuaExp>> {
+        let mut exps = vec![self.read_exp()?];
+
+        while self.accept(LuaTerminal::Comma) {
+            exps.push(self.read_exp()?);
+        }
+
+        Ok(exps)
+    }
+
+    fn read_exp(&mut self) -> ReadResult<LuaExp> {
+        self.read_sub_exp(0)
+    }
+
+    /** Reads an expression whose binary operators all bind more tightly
+     *  than `limit`. */
+    fn read_sub_exp(&mut self, limit: u8) -> ReadResult<LuaExp> {
+        let start = self.current().location;
+
+        let mut left = match unary_operator(self.current_type()) {
+            Some(op) => {
+                self.advance();
+                let operand = self.read_sub_exp(LuaUnOp::PRIORITY)?;
+
+                LuaExp {
+                    kind: LuaExpKind::UnOp(op, Box::new(operand)),
+                    span: self.span_from(start)
+                }
+            },
+            None => self.read_simple_exp()?
+        };
+
+        loop {
+            let op = match binary_operator(self.current_type()) {
+                Some(op) if op.priority().0 > limit => op,
+                _                                   => break
+            };
+
+            self.advance();
+            let right = self.read_sub_exp(op.priority().1)?;
+
+            left = LuaExp {
+                span: left.span.to(right.span),
+                kind: LuaExpKind::BinOp(op, Box::new(left), Box::new(right))
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn read_simple_exp(&mut self) -> ReadResult<LuaExp> {
+        let start = self.current().location;
+        let line  = self.line();
+
+        let kind = match self.current_type() {
+            LuaTerminal::NumberLiteral => match self.advance().token_data {
+                Some(LuaTokenData::NumberLiteral(value)) => LuaExpKind::Number(value),
+                _                                        => unreachable!()
+            },
+            LuaTerminal::StringLiteral => match self.advance().token_data {
+                Some(LuaTokenData::StringLiteral(value)) => LuaExpKind::String(value),
+                _                                        => unreachable!()
+            },
+            LuaTerminal::Nil => {
+                self.advance();
+                LuaExpKind::Nil
+            },
+            LuaTerminal::True => {
+                self.advance();
+                LuaExpKind::Boolean(true)
+            },
+            LuaTerminal::False => {
+                self.advance();
+                LuaExpKind::Boolean(false)
+            },
+            LuaTerminal::Varargs => {
+                if !self.vararg_scopes.last().cloned().unwrap_or(false) {
+                    return Err(self.error(String::from("cannot use '...' outside a vararg function")));
+                }
+
+                self.advance();
+                LuaExpKind::Varargs
+            },
+            LuaTerminal::LeftBrace => LuaExpKind::TableConstructor(self.read_table_constructor()?),
+            LuaTerminal::Function => {
+                self.advance();
+                LuaExpKind::FunctionDef(self.read_func_body(line)?)
+            },
+            _ => return self.read_suffixed_exp()
+        };
+
+        Ok(LuaExp {
+            kind,
+            span: self.span_from(start)
+        })
+    }
+
+    fn read_primary_exp(&mut self) -> ReadResult<LuaExp> {
+        let start = self.current().location;
+
+        let kind = match self.current_type() {
+            LuaTerminal::Identifier => LuaExpKind::Name(self.read_name()?),
+            LuaTerminal::LeftParenthesis => {
+                let line = self.line();
+                self.advance();
+                let exp = self.read_exp()?;
+                self.expect_match(LuaTerminal::RightParenthesis, ")", "(", line)?;
+
+                LuaExpKind::Bracketed(Box::new(exp))
+            },
+            _ => return Err(self.error(String::from("unexpected symbol")))
+        };
+
+        Ok(LuaExp {
+            kind,
+            span: self.span_from(start)
+        })
+    }
+
+    /** Reads a primary expression followed by any number of fields, indexes
+     *  and calls. */
+    fn read_suffixed_exp(&mut self) -> ReadResult<LuaExp> {
+        let start = self.current().location;
+        let mut exp = self.read_primary_exp()?;
+
+        loop {
+            let kind = match self.current_type() {
+                LuaTerminal::Dot => {
+                    self.advance();
+                    LuaExpKind::Field(Box::new(exp), self.read_name()?)
+                },
+                LuaTerminal::LeftBracket => {
+                    self.advance();
+                    let key = self.read_exp()?;
+                    self.expect(LuaTerminal::RightBracket, "]")?;
+
+                    LuaExpKind::Index(Box::new(exp), Box::new(key))
+                },
+                LuaTerminal::Colon => {
+                    self.advance();
+                    let method = self.read_name()?;
+                    let args = self.read_args()?;
+
+                    LuaExpKind::FunctionCall(Box::new(LuaFunctionCall {
+                        callee: exp,
+                        method: Some(method),
+                        args,
+                        span:   self.span_from(start)
+                    }))
+                },
+                LuaTerminal::LeftParenthesis | LuaTerminal::StringLiteral | LuaTerminal::LeftBrace => {
+                    let args = self.read_args()?;
+
+                    LuaExpKind::FunctionCall(Box::new(LuaFunctionCall {
+                        callee: exp,
+                        method: None,
+                        args,
+                        span:   self.span_from(start)
+                    }))
+                },
+                _ => return Ok(exp)
+            };
+
+            exp = LuaExp {
+                kind,
+                span: self.span_from(start)
+            };
+        }
+    }
+
+    fn read_args(&mut self) -> ReadResult<LuaArgs> {
+        let start = self.current().location;
+
+        let kind = match self.current_type() {
+            LuaTerminal::StringLiteral => match self.advance().token_data {
+                Some(LuaTokenData::StringLiteral(value)) => LuaArgsKind::LiteralString(value),
+                _                                        => unreachable!()
+            },
+            LuaTerminal::LeftBrace => LuaArgsKind::TableConstructor(self.read_table_constructor()?),
+            LuaTerminal::LeftParenthesis => {
+                let line = self.line();
+                self.advance();
+
+                let exps = match self.current_type() {
+                    LuaTerminal::RightParenthesis => Vec::new(),
+                    _                             => self.read_exp_list()?
+                };
+
+                self.expect_match(LuaTerminal::RightParenthesis, ")", "(", line)?;
+                LuaArgsKind::ExpList(exps)
+            },
+            _ => return Err(self.error(String::from("function arguments expected")))
+        };
+
+        Ok(LuaArgs {
+            kind,
+            span: self.span_from(start)
+        })
+    }
+
+    fn read_table_constructor(&mut self) -> ReadResult<LuaTableConstructor> {
+        let start = self.current().location;
+        let line  = self.line();
+        self.expect(LuaTerminal::LeftBrace, "{")?;
+
+        let mut fields = Vec::new();
+
+        while self.current_type() != LuaTerminal::RightBrace {
+            fields.push(self.read_field()?);
+
+            if !self.accept(LuaTerminal::Comma) && !self.accept(LuaTerminal::Semicolon) {
+                break;
+            }
+        }
+
+        self.expect_match(LuaTerminal::RightBrace, "}", "{", line)?;
+
+        Ok(LuaTableConstructor {
+            fields,
+            span:   self.span_from(start)
+        })
+    }
+
+    fn read_field(&mut self) -> ReadResult<LuaField> {
+        let start = self.current().location;
+
+        let kind = match self.current_type() {
+            LuaTerminal::Identifier if self.lookahead_type() == LuaTerminal::Equals => {
+                let name = self.read_name()?;
+                self.advance();
+
+                LuaFieldKind::Named(name, self.read_exp()?)
+            },
+            LuaTerminal::LeftBracket => {
+                self.advance();
+                let key = self.read_exp()?;
+                self.expect(LuaTerminal::RightBracket, "]")?;
+                self.expect(LuaTerminal::Equals, "=")?;
+
+                LuaFieldKind::Keyed(key, self.read_exp()?)
+            },
+            _ => LuaFieldKind::Positional(self.read_exp()?)
+        };
+
+        Ok(LuaField {
+            kind,
+            span: self.span_from(start)
+        })
+    }
+}
+
+/** Quotes a token the way `luaX_token2str` does, which leaves out the
+ *  quotes for placeholders like `<eof>`. */
+fn quote_token(text: &str) -> String {
+    match text.starts_with('<') && text.len() > 1 {
+        true  => String::from(text),
+        false => format!("'{}'", text)
+    }
+}
+
+fn unary_operator(token_type: LuaTerminal) -> Option<LuaUnOp> {
+    match token_type {
+        LuaTerminal::Not        => Some(LuaUnOp::Not),
+        LuaTerminal::Length     => Some(LuaUnOp::Len),
+        LuaTerminal::Minus      => Some(LuaUnOp::Neg),
+        LuaTerminal::BitwiseNeg => Some(LuaUnOp::BitwiseNot),
+        _                       => None
+    }
+}
+
+fn binary_operator(token_type: LuaTerminal) -> Option<LuaBinOp> {
+    match token_type {
+        LuaTerminal::Or           => Some(LuaBinOp::Or),
+        LuaTerminal::And          => Some(LuaBinOp::And),
+        LuaTerminal::LessThan     => Some(LuaBinOp::Less),
+        LuaTerminal::GreaterThan  => Some(LuaBinOp::Greater),
+        LuaTerminal::LessEq       => Some(LuaBinOp::LessEq),
+        LuaTerminal::GreaterEq    => Some(LuaBinOp::GreaterEq),
+        LuaTerminal::NotEq        => Some(LuaBinOp::NotEq),
+        LuaTerminal::DoubleEquals => Some(LuaBinOp::Eq),
+        LuaTerminal::BitwiseOr    => Some(LuaBinOp::BitwiseOr),
+        LuaTerminal::BitwiseNeg   => Some(LuaBinOp::BitwiseXor),
+        LuaTerminal::BitwiseAnd   => Some(LuaBinOp::BitwiseAnd),
+        LuaTerminal::LeftShift    => Some(LuaBinOp::LeftShift),
+        LuaTerminal::RightShift   => Some(LuaBinOp::RightShift),
+        LuaTerminal::Concat       => Some(LuaBinOp::Concat),
+        LuaTerminal::Plus         => Some(LuaBinOp::Add),
+        LuaTerminal::Minus        => Some(LuaBinOp::Sub),
+        LuaTerminal::Multiply     => Some(LuaBinOp::Mul),
+        LuaTerminal::Divide       => Some(LuaBinOp::Div),
+        LuaTerminal::FloorDivide  => Some(LuaBinOp::FloorDiv),
+        LuaTerminal::Modulo       => Some(LuaBinOp::Mod),
+        LuaTerminal::Power        => Some(LuaBinOp::Pow),
+        _                         => None
+    }
+}
+
+fn compound_operator(token_type: LuaTerminal) -> Option<LuaBinOp> {
+    match token_type {
+        LuaTerminal::PlusEquals        => Some(LuaBinOp::Add),
+        LuaTerminal::MinusEquals       => Some(LuaBinOp::Sub),
+        LuaTerminal::MultiplyEquals    => Some(LuaBinOp::Mul),
+        LuaTerminal::DivideEquals      => Some(LuaBinOp::Div),
+        LuaTerminal::FloorDivideEquals => Some(LuaBinOp::FloorDiv),
+        LuaTerminal::PowerEquals       => Some(LuaBinOp::Pow),
+        LuaTerminal::ModuloEquals      => Some(LuaBinOp::Mod),
+        LuaTerminal::ConcatEquals      => Some(LuaBinOp::Concat),
+        _                              => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::{LuaNumeral, LuaBytes};
+
+    fn read(src: &str) -> LuaChunk {
+        read_lua_chunk(src, LuaDialect::Lua54, false).ok().unwrap()
+    }
+
+    fn read_exp(src: &str) -> LuaExp {
+        let chunk = read(&format!("return {}", src));
+        chunk.block.ret_stat.unwrap().exps.remove(0)
+    }
+
+    fn error_message(src: &str) -> String {
+        read_lua_chunk(src, LuaDialect::Lua54, false).err().unwrap().to_string()
+    }
+
+    /** Writes an expression with every operator fully bracketed. */
+    fn bracketed(exp: &LuaExp) -> String {
+        match &exp.kind {
+            LuaExpKind::BinOp(op, left, right) => format!("({} {} {})", bracketed(left), op.symbol(), bracketed(right)),
+            LuaExpKind::UnOp(op, operand)      => format!("({}{})", op.symbol(), bracketed(operand)),
+            LuaExpKind::Name(name)             => name.name.to_string(),
+            LuaExpKind::Number(value)          => value.to_string(),
+            _                                  => String::from("?")
+        }
+    }
+
+    #[test]
+    fn operators_follow_reference_precedence() {
+        assert_eq!(bracketed(&read_exp("1 + 2 * 3 - 4")),      "((1 + (2 * 3)) - 4)");
+        assert_eq!(bracketed(&read_exp("a .. b .. c")),        "(a .. (b .. c))");
+        assert_eq!(bracketed(&read_exp("-x ^ 2")),             "(-(x ^ 2))");
+        assert_eq!(bracketed(&read_exp("2 ^ -3 ^ 2")),         "(2 ^ (-(3 ^ 2)))");
+        assert_eq!(bracketed(&read_exp("not a == b or c")),    "(((nota) == b) or c)");
+        assert_eq!(bracketed(&read_exp("a | b ~ c & d << 1")), "(a | (b ~ (c & (d << 1))))");
+        assert_eq!(bracketed(&read_exp("1 < 2 and ~x // 3")),  "((1 < 2) and ((~x) // 3))");
+    }
+
+    #[test]
+    fn statements_hold_their_children() {
+        let chunk = read(concat!(
+            "--- Greets someone.\n",
+            "local function greet(name, ...)\n",
+            "  print(\"hi \" .. name, ...)\n",
+            "end\n",
+            "for i = 1, 10, 2 do t[i] = {i, x = 1, [i] = i} end\n",
+            "local a <const>, b = 5\n",
+            "obj:method 'text'\n"
+        ));
+
+        let stats = &chunk.block.stats;
+        assert_eq!(stats.len(), 4);
+
+        match &stats[0].kind {
+            LuaStatKind::LocalFunction(name, body) => {
+                assert_eq!(name.name.as_str(), "greet");
+                assert_eq!(body.params.len(), 1);
+                assert!(body.varargs);
+                assert_eq!(body.block.stats.len(), 1);
+            },
+            _ => panic!("expected a local function")
+        }
+        assert_eq!(stats[0].doc.as_ref().unwrap().description, "Greets someone.");
+        assert_eq!(stats[0].span, LuaSpan::new(Location { line: 2, col: 1 }, Location { line: 4, col: 4 }));
+
+        match &stats[1].kind {
+            LuaStatKind::For(_, _, _, Some(step), block) => {
+                assert_eq!(step.kind, LuaExpKind::Number(LuaNumeral::Integer(2)));
+
+                match &block.stats[0].kind {
+                    LuaStatKind::VarList(targets, exps) => {
+                        assert!(matches!(targets[0].kind, LuaExpKind::Index(_, _)));
+
+                        match &exps[0].kind {
+                            LuaExpKind::TableConstructor(table) => {
+                                assert!(matches!(table.fields[0].kind, LuaFieldKind::Positional(_)));
+                                assert!(matches!(table.fields[1].kind, LuaFieldKind::Named(_, _)));
+                                assert!(matches!(table.fields[2].kind, LuaFieldKind::Keyed(_, _)));
+                                assert_eq!(table.fields[2].span, LuaSpan::new(Location { line: 5, col: 39 }, Location { line: 5, col: 46 }));
+                            },
+                            _ => panic!("expected a table constructor")
+                        }
+                    },
+                    _ => panic!("expected an assignment")
+                }
+            },
+            _ => panic!("expected a numeric for")
+        }
+
+        match &stats[2].kind {
+            LuaStatKind::LocalAttNameList(names, exps) => {
+                assert_eq!(names[0].attrib.as_ref().unwrap().kind, LuaAttribKind::Const);
+                assert!(names[1].attrib.is_none());
+                assert_eq!(exps.len(), 1);
+            },
+            _ => panic!("expected a local declaration")
+        }
+
+        match &stats[3].kind {
+            LuaStatKind::FunctionCall(call) => {
+                assert_eq!(call.method.as_ref().unwrap().name.as_str(), "method");
+                assert_eq!(call.args.kind, LuaArgsKind::LiteralString(LuaBytes::from("text")));
+                assert_eq!(call.args.span, LuaSpan::new(Location { line: 7, col: 12 }, Location { line: 7, col: 18 }));
+            },
+            _ => panic!("expected a method call")
+        }
+    }
+
+    #[test]
+    fn continue_is_only_a_statement_on_its_own() {
+        let chunk = read_lua_chunk("local continue = 1\nwhile true do continue = continue + 1; continue end", LuaDialect::Luau, false).ok().unwrap();
+
+        assert!(matches!(chunk.block.stats[0].kind, LuaStatKind::LocalAttNameList(_, _)));
+
+        match &chunk.block.stats[1].kind {
+            LuaStatKind::While(_, block) => {
+                assert!(matches!(block.stats[0].kind, LuaStatKind::VarList(_, _)));
+                assert_eq!(block.stats[2].kind, LuaStatKind::Continue);
+            },
+            _ => panic!("expected a while loop")
+        }
+
+        assert_eq!(error_message("while true do continue end"), "1: syntax error near 'end'");
+    }
+
+    #[test]
+    fn only_files_have_a_prologue() {
+        let src = "#!/usr/bin/env lua\nprint(1)\n";
+
+        assert_eq!(read_lua_chunk(src, LuaDialect::Lua54, true).ok().unwrap().block.stats.len(), 1);
+        assert_eq!(error_message(src), "1: unexpected symbol near '!'");
+    }
+
+    #[test]
+    fn names_are_interned_and_numbered() {
+        let chunk = read("x = x + x");
+
+        match &chunk.block.stats[0].kind {
+            LuaStatKind::VarList(targets, exps) => match (&targets[0].kind, &exps[0].kind) {
+                (LuaExpKind::Name(target), LuaExpKind::BinOp(_, left, _)) => match &left.kind {
+                    LuaExpKind::Name(operand) => {
+                        assert_eq!(target.name, operand.name);
+                        assert_ne!(target.id, operand.id);
+                    },
+                    _ => panic!("expected a name")
+                },
+                _ => panic!("expected names")
+            },
+            _ => panic!("expected an assignment")
+        }
+    }
+
+    #[test]
+    fn reports_errors_like_the_reference_parser() {
+        assert_eq!(error_message("x"),                        "1: syntax error near <eof>");
+        assert_eq!(error_message("x = = 1"),                  "1: unexpected symbol near '='");
+        assert_eq!(error_message("f() = 1"),                  "1: syntax error near '='");
+        assert_eq!(error_message("if x then\n\ny = 1\n"),     "4: 'end' expected (to close 'if' at line 1) near <eof>");
+        assert_eq!(error_message("while true y = 1 end"),     "1: 'do' expected near 'y'");
+        assert_eq!(error_message("for x do end"),             "1: '=' or 'in' expected near 'do'");
+        assert_eq!(error_message("local function f() return ... end"), "1: cannot use '...' outside a vararg function near '...'");
+        assert_eq!(error_message("return 1\nx = 2"),          "2: <eof> expected near 'x'");
+        assert_eq!(error_message("local 1 = 2"),              "1: <name> expected near '1'");
+        assert_eq!(error_message("x = \"abc\ny = 1"),         "1: unfinished string near '\"abc'");
+    }
+}
@@ -0,0 +1,35 @@
+use std::fmt::{Display, Formatter};
+
+use crate::tokenisation::Location;
+
+/** The part of the source a node was read from, from the start of its first
+ *  token up to just past the end of its last. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LuaSpan {
+    pub start: Location,
+    pub end:   Location
+}
+
+impl LuaSpan {
+    pub fn new(start: Location, end: Location) -> LuaSpan {
+        LuaSpan {
+            start,
+            end
+        }
+    }
+
+    /** The smallest span covering both `self` and `other`. */
+    pub fn to(&self, other: LuaSpan) -> LuaSpan {
+        LuaSpan::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    pub fn contains(&self, location: Location) -> bool {
+        self.start <= location && location < self.end
+    }
+}
+
+impl Display for LuaSpan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}:{}-{}:{}", self.start.line, self.start.col, self.end.line, self.end.col)
+    }
+}
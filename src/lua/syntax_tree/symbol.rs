@@ -0,0 +1,66 @@
+use std::collections::HashSet;
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::Deref;
+use std::rc::Rc;
+
+/**
+ * An interned name. Every occurrence of a name read by the same interner
+ * shares one allocation, so names are cheap to clone and keep around in
+ * the tables analyses build.
+ */
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LuaSymbol(Rc<str>);
+
+impl LuaSymbol {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for LuaSymbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for LuaSymbol {
+    fn from(name: &str) -> LuaSymbol {
+        LuaSymbol(Rc::from(name))
+    }
+}
+
+impl Display for LuaSymbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Debug for LuaSymbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+pub struct LuaInterner {
+    symbols: HashSet<Rc<str>>
+}
+
+impl LuaInterner {
+    pub fn new() -> LuaInterner {
+        LuaInterner {
+            symbols: HashSet::new()
+        }
+    }
+
+    pub fn intern(&mut self, name: &str) -> LuaSymbol {
+        if let Some(symbol) = self.symbols.get(name) {
+            return LuaSymbol(symbol.clone());
+        }
+
+        let symbol: Rc<str> = Rc::from(name);
+        self.symbols.insert(symbol.clone());
+        LuaSymbol(symbol)
+    }
+}
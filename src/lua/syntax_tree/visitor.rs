@@ -0,0 +1,737 @@
+/*!
+ * Traversal of syntax trees.
+ *
+ * `Visitor` and `VisitorMut` have a method for each kind of node, which by
+ * default walks the node's children in source order using the matching
+ * `walk_*` function. Implementations override the methods for the nodes they
+ * care about, calling the `walk_*` function themselves if they still want to
+ * carry on into the children.
+ *
+ * `Folder` rebuilds a tree from the bottom up, taking each node by value and
+ * giving back its replacement.
+ */
+
+use crate::lua::syntax_tree::*;
+
+pub trait Visitor {
+    fn visit_chunk(&mut self, chunk: &LuaChunk) {
+        walk_chunk(self, chunk);
+    }
+
+    fn visit_block(&mut self, block: &LuaBlock) {
+        walk_block(self, block);
+    }
+
+    fn visit_stat(&mut self, stat: &LuaStat) {
+        walk_stat(self, stat);
+    }
+
+    fn visit_ret_stat(&mut self, ret_stat: &LuaRetStat) {
+        walk_ret_stat(self, ret_stat);
+    }
+
+    fn visit_att_name(&mut self, att_name: &LuaAttName) {
+        walk_att_name(self, att_name);
+    }
+
+    fn visit_label(&mut self, label: &LuaLabel) {
+        self.visit_name(&label.name);
+    }
+
+    fn visit_func_name(&mut self, func_name: &LuaFuncName) {
+        walk_func_name(self, func_name);
+    }
+
+    fn visit_exp(&mut self, exp: &LuaExp) {
+        walk_exp(self, exp);
+    }
+
+    fn visit_name(&mut self, _name: &LuaName) { }
+
+    fn visit_function_call(&mut self, call: &LuaFunctionCall) {
+        walk_function_call(self, call);
+    }
+
+    fn visit_args(&mut self, args: &LuaArgs) {
+        walk_args(self, args);
+    }
+
+    fn visit_func_body(&mut self, body: &LuaFuncBody) {
+        walk_func_body(self, body);
+    }
+
+    fn visit_table_constructor(&mut self, table: &LuaTableConstructor) {
+        walk_table_constructor(self, table);
+    }
+
+    fn visit_field(&mut self, field: &LuaField) {
+        walk_field(self, field);
+    }
+}
+
+pub fn walk_chunk<V>(visitor: &mut V, chunk: &LuaChunk) where V: Visitor + ?Sized {
+    visitor.visit_block(&chunk.block);
+}
+
+pub fn walk_block<V>(visitor: &mut V, block: &LuaBlock) where V: Visitor + ?Sized {
+    for stat in &block.stats {
+        visitor.visit_stat(stat);
+    }
+
+    if let Some(ret_stat) = &block.ret_stat {
+        visitor.visit_ret_stat(ret_stat);
+    }
+}
+
+pub fn walk_stat<V>(visitor: &mut V, stat: &LuaStat) where V: Visitor + ?Sized {
+    match &stat.kind {
+        LuaStatKind::Semicolon | LuaStatKind::Break | LuaStatKind::Continue => { },
+        LuaStatKind::VarList(targets, exps) => {
+            targets.iter().for_each(|target| visitor.visit_exp(target));
+            exps.iter().for_each(|exp| visitor.visit_exp(exp));
+        },
+        LuaStatKind::CompoundAssignment(target, _, value) => {
+            visitor.visit_exp(target);
+            visitor.visit_exp(value);
+        },
+        LuaStatKind::FunctionCall(call) => visitor.visit_function_call(call),
+        LuaStatKind::Label(label)       => visitor.visit_label(label),
+        LuaStatKind::Goto(name)         => visitor.visit_name(name),
+        LuaStatKind::Do(block)          => visitor.visit_block(block),
+        LuaStatKind::While(condition, block) => {
+            visitor.visit_exp(condition);
+            visitor.visit_block(block);
+        },
+        LuaStatKind::Repeat(block, condition) => {
+            visitor.visit_block(block);
+            visitor.visit_exp(condition);
+        },
+        LuaStatKind::If(condition, block, else_ifs, else_block) => {
+            visitor.visit_exp(condition);
+            visitor.visit_block(block);
+
+            for (condition, block) in else_ifs {
+                visitor.visit_exp(condition);
+                visitor.visit_block(block);
+            }
+
+            if let Some(block) = else_block {
+                visitor.visit_block(block);
+            }
+        },
+        LuaStatKind::For(name, initial, limit, step, block) => {
+            visitor.visit_name(name);
+            visitor.visit_exp(initial);
+            visitor.visit_exp(limit);
+
+            if let Some(step) = step {
+                visitor.visit_exp(step);
+            }
+
+            visitor.visit_block(block);
+        },
+        LuaStatKind::ForIn(names, exps, block) => {
+            names.iter().for_each(|name| visitor.visit_name(name));
+            exps.iter().for_each(|exp| visitor.visit_exp(exp));
+            visitor.visit_block(block);
+        },
+        LuaStatKind::Function(func_name, body) => {
+            visitor.visit_func_name(func_name);
+            visitor.visit_func_body(body);
+        },
+        LuaStatKind::LocalFunction(name, body) => {
+            visitor.visit_name(name);
+            visitor.visit_func_body(body);
+        },
+        LuaStatKind::LocalAttNameList(att_names, exps) => {
+            att_names.iter().for_each(|att_name| visitor.visit_att_name(att_name));
+            exps.iter().for_each(|exp| visitor.visit_exp(exp));
+        }
+    }
+}
+
+pub fn walk_ret_stat<V>(visitor: &mut V, ret_stat: &LuaRetStat) where V: Visitor + ?Sized {
+    ret_stat.exps.iter().for_each(|exp| visitor.visit_exp(exp));
+}
+
+pub fn walk_att_name<V>(visitor: &mut V, att_name: &LuaAttName) where V: Visitor + ?Sized {
+    visitor.visit_name(&att_name.name);
+}
+
+pub fn walk_func_name<V>(visitor: &mut V, func_name: &LuaFuncName) where V: Visitor + ?Sized {
+    func_name.path.iter().for_each(|name| visitor.visit_name(name));
+
+    if let Some(method) = &func_name.method {
+        visitor.visit_name(method);
+    }
+}
+
+pub fn walk_exp<V>(visitor: &mut V, exp: &LuaExp) where V: Visitor + ?Sized {
+    match &exp.kind {
+        LuaExpKind::Nil | LuaExpKind::Boolean(_) | LuaExpKind::Number(_) |
+        LuaExpKind::String(_) | LuaExpKind::Varargs => { },
+        LuaExpKind::FunctionDef(body)       => visitor.visit_func_body(body),
+        LuaExpKind::TableConstructor(table) => visitor.visit_table_constructor(table),
+        LuaExpKind::BinOp(_, left, right) => {
+            visitor.visit_exp(left);
+            visitor.visit_exp(right);
+        },
+        LuaExpKind::UnOp(_, operand)  => visitor.visit_exp(operand),
+        LuaExpKind::Bracketed(inner)  => visitor.visit_exp(inner),
+        LuaExpKind::Name(name)        => visitor.visit_name(name),
+        LuaExpKind::Index(prefix, key) => {
+            visitor.visit_exp(prefix);
+            visitor.visit_exp(key);
+        },
+        LuaExpKind::Field(prefix, name) => {
+            visitor.visit_exp(prefix);
+            visitor.visit_name(name);
+        },
+        LuaExpKind::FunctionCall(call) => visitor.visit_function_call(call)
+    }
+}
+
+pub fn walk_function_call<V>(visitor: &mut V, call: &LuaFunctionCall) where V: Visitor + ?Sized {
+    visitor.visit_exp(&call.callee);
+
+    if let Some(method) = &call.method {
+        visitor.visit_name(method);
+    }
+
+    visitor.visit_args(&call.args);
+}
+
+pub fn walk_args<V>(visitor: &mut V, args: &LuaArgs) where V: Visitor + ?Sized {
+    match &args.kind {
+        LuaArgsKind::ExpList(exps)           => exps.iter().for_each(|exp| visitor.visit_exp(exp)),
+        LuaArgsKind::TableConstructor(table) => visitor.visit_table_constructor(table),
+        LuaArgsKind::LiteralString(_)        => { }
+    }
+}
+
+pub fn walk_func_body<V>(visitor: &mut V, body: &LuaFuncBody) where V: Visitor + ?Sized {
+    body.params.iter().for_each(|param| visitor.visit_name(param));
+    visitor.visit_block(&body.block);
+}
+
+pub fn walk_table_constructor<V>(visitor: &mut V, table: &LuaTableConstructor) where V: Visitor + ?Sized {
+    table.fields.iter().for_each(|field| visitor.visit_field(field));
+}
+
+pub fn walk_field<V>(visitor: &mut V, field: &LuaField) where V: Visitor + ?Sized {
+    match &field.kind {
+        LuaFieldKind::Keyed(key, value) => {
+            visitor.visit_exp(key);
+            visitor.visit_exp(value);
+        },
+        LuaFieldKind::Named(name, value) => {
+            visitor.visit_name(name);
+            visitor.visit_exp(value);
+        },
+        LuaFieldKind::Positional(value) => visitor.visit_exp(value)
+    }
+}
+
+/** Like `Visitor`, but able to change nodes in place. */
+pub trait VisitorMut {
+    fn visit_chunk_mut(&mut self, chunk: &mut LuaChunk) {
+        walk_chunk_mut(self, chunk);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut LuaBlock) {
+        walk_block_mut(self, block);
+    }
+
+    fn visit_stat_mut(&mut self, stat: &mut LuaStat) {
+        walk_stat_mut(self, stat);
+    }
+
+    fn visit_ret_stat_mut(&mut self, ret_stat: &mut LuaRetStat) {
+        walk_ret_stat_mut(self, ret_stat);
+    }
+
+    fn visit_att_name_mut(&mut self, att_name: &mut LuaAttName) {
+        walk_att_name_mut(self, att_name);
+    }
+
+    fn visit_label_mut(&mut self, label: &mut LuaLabel) {
+        self.visit_name_mut(&mut label.name);
+    }
+
+    fn visit_func_name_mut(&mut self, func_name: &mut LuaFuncName) {
+        walk_func_name_mut(self, func_name);
+    }
+
+    fn visit_exp_mut(&mut self, exp: &mut LuaExp) {
+        walk_exp_mut(self, exp);
+    }
+
+    fn visit_name_mut(&mut self, _name: &mut LuaName) { }
+
+    fn visit_function_call_mut(&mut self, call: &mut LuaFunctionCall) {
+        walk_function_call_mut(self, call);
+    }
+
+    fn visit_args_mut(&mut self, args: &mut LuaArgs) {
+        walk_args_mut(self, args);
+    }
+
+    fn visit_func_body_mut(&mut self, body: &mut LuaFuncBody) {
+        walk_func_body_mut(self, body);
+    }
+
+    fn visit_table_constructor_mut(&mut self, table: &mut LuaTableConstructor) {
+        walk_table_constructor_mut(self, table);
+    }
+
+    fn visit_field_mut(&mut self, field: &mut LuaField) {
+        walk_field_mut(self, field);
+    }
+}
+
+pub fn walk_chunk_mut<V>(visitor: &mut V, chunk: &mut LuaChunk) where V: VisitorMut + ?Sized {
+    visitor.visit_block_mut(&mut chunk.block);
+}
+
+pub fn walk_block_mut<V>(visitor: &mut V, block: &mut LuaBlock) where V: VisitorMut + ?Sized {
+    for stat in &mut block.stats {
+        visitor.visit_stat_mut(stat);
+    }
+
+    if let Some(ret_stat) = &mut block.ret_stat {
+        visitor.visit_ret_stat_mut(ret_stat);
+    }
+}
+
+pub fn walk_stat_mut<V>(visitor: &mut V, stat: &mut LuaStat) where V: VisitorMut + ?Sized {
+    match &mut stat.kind {
+        LuaStatKind::Semicolon | LuaStatKind::Break | LuaStatKind::Continue => { },
+        LuaStatKind::VarList(targets, exps) => {
+            targets.iter_mut().for_each(|target| visitor.visit_exp_mut(target));
+            exps.iter_mut().for_each(|exp| visitor.visit_exp_mut(exp));
+        },
+        LuaStatKind::CompoundAssignment(target, _, value) => {
+            visitor.visit_exp_mut(target);
+            visitor.visit_exp_mut(value);
+        },
+        LuaStatKind::FunctionCall(call) => visitor.visit_function_call_mut(call),
+        LuaStatKind::Label(label)       => visitor.visit_label_mut(label),
+        LuaStatKind::Goto(name)         => visitor.visit_name_mut(name),
+        LuaStatKind::Do(block)          => visitor.visit_block_mut(block),
+        LuaStatKind::While(condition, block) => {
+            visitor.visit_exp_mut(condition);
+            visitor.visit_block_mut(block);
+        },
+        LuaStatKind::Repeat(block, condition) => {
+            visitor.visit_block_mut(block);
+            visitor.visit_exp_mut(condition);
+        },
+        LuaStatKind::If(condition, block, else_ifs, else_block) => {
+            visitor.visit_exp_mut(condition);
+            visitor.visit_block_mut(block);
+
+            for (condition, block) in else_ifs {
+                visitor.visit_exp_mut(condition);
+                visitor.visit_block_mut(block);
+            }
+
+            if let Some(block) = else_block {
+                visitor.visit_block_mut(block);
+            }
+        },
+        LuaStatKind::For(name, initial, limit, step, block) => {
+            visitor.visit_name_mut(name);
+            visitor.visit_exp_mut(initial);
+            visitor.visit_exp_mut(limit);
+
+            if let Some(step) = step {
+                visitor.visit_exp_mut(step);
+            }
+
+            visitor.visit_block_mut(block);
+        },
+        LuaStatKind::ForIn(names, exps, block) => {
+            names.iter_mut().for_each(|name| visitor.visit_name_mut(name));
+            exps.iter_mut().for_each(|exp| visitor.visit_exp_mut(exp));
+            visitor.visit_block_mut(block);
+        },
+        LuaStatKind::Function(func_name, body) => {
+            visitor.visit_func_name_mut(func_name);
+            visitor.visit_func_body_mut(body);
+        },
+        LuaStatKind::LocalFunction(name, body) => {
+            visitor.visit_name_mut(name);
+            visitor.visit_func_body_mut(body);
+        },
+        LuaStatKind::LocalAttNameList(att_names, exps) => {
+            att_names.iter_mut().for_each(|att_name| visitor.visit_att_name_mut(att_name));
+            exps.iter_mut().for_each(|exp| visitor.visit_exp_mut(exp));
+        }
+    }
+}
+
+pub fn walk_ret_stat_mut<V>(visitor: &mut V, ret_stat: &mut LuaRetStat) where V: VisitorMut + ?Sized {
+    ret_stat.exps.iter_mut().for_each(|exp| visitor.visit_exp_mut(exp));
+}
+
+pub fn walk_att_name_mut<V>(visitor: &mut V, att_name: &mut LuaAttName) where V: VisitorMut + ?Sized {
+    visitor.visit_name_mut(&mut att_name.name);
+}
+
+pub fn walk_func_name_mut<V>(visitor: &mut V, func_name: &mut LuaFuncName) where V: VisitorMut + ?Sized {
+    func_name.path.iter_mut().for_each(|name| visitor.visit_name_mut(name));
+
+    if let Some(method) = &mut func_name.method {
+        visitor.visit_name_mut(method);
+    }
+}
+
+pub fn walk_exp_mut<V>(visitor: &mut V, exp: &mut LuaExp) where V: VisitorMut + ?Sized {
+    match &mut exp.kind {
+        LuaExpKind::Nil | LuaExpKind::Boolean(_) | LuaExpKind::Number(_) |
+        LuaExpKind::String(_) | LuaExpKind::Varargs => { },
+        LuaExpKind::FunctionDef(body)       => visitor.visit_func_body_mut(body),
+        LuaExpKind::TableConstructor(table) => visitor.visit_table_constructor_mut(table),
+        LuaExpKind::BinOp(_, left, right) => {
+            visitor.visit_exp_mut(left);
+            visitor.visit_exp_mut(right);
+        },
+        LuaExpKind::UnOp(_, operand)  => visitor.visit_exp_mut(operand),
+        LuaExpKind::Bracketed(inner)  => visitor.visit_exp_mut(inner),
+        LuaExpKind::Name(name)        => visitor.visit_name_mut(name),
+        LuaExpKind::Index(prefix, key) => {
+            visitor.visit_exp_mut(prefix);
+            visitor.visit_exp_mut(key);
+        },
+        LuaExpKind::Field(prefix, name) => {
+            visitor.visit_exp_mut(prefix);
+            visitor.visit_name_mut(name);
+        },
+        LuaExpKind::FunctionCall(call) => visitor.visit_function_call_mut(call)
+    }
+}
+
+pub fn walk_function_call_mut<V>(visitor: &mut V, call: &mut LuaFunctionCall) where V: VisitorMut + ?Sized {
+    visitor.visit_exp_mut(&mut call.callee);
+
+    if let Some(method) = &mut call.method {
+        visitor.visit_name_mut(method);
+    }
+
+    visitor.visit_args_mut(&mut call.args);
+}
+
+pub fn walk_args_mut<V>(visitor: &mut V, args: &mut LuaArgs) where V: VisitorMut + ?Sized {
+    match &mut args.kind {
+        LuaArgsKind::ExpList(exps)           => exps.iter_mut().for_each(|exp| visitor.visit_exp_mut(exp)),
+        LuaArgsKind::TableConstructor(table) => visitor.visit_table_constructor_mut(table),
+        LuaArgsKind::LiteralString(_)        => { }
+    }
+}
+
+pub fn walk_func_body_mut<V>(visitor: &mut V, body: &mut LuaFuncBody) where V: VisitorMut + ?Sized {
+    body.params.iter_mut().for_each(|param| visitor.visit_name_mut(param));
+    visitor.visit_block_mut(&mut body.block);
+}
+
+pub fn walk_table_constructor_mut<V>(visitor: &mut V, table: &mut LuaTableConstructor) where V: VisitorMut + ?Sized {
+    table.fields.iter_mut().for_each(|field| visitor.visit_field_mut(field));
+}
+
+pub fn walk_field_mut<V>(visitor: &mut V, field: &mut LuaField) where V: VisitorMut + ?Sized {
+    match &mut field.kind {
+        LuaFieldKind::Keyed(key, value) => {
+            visitor.visit_exp_mut(key);
+            visitor.visit_exp_mut(value);
+        },
+        LuaFieldKind::Named(name, value) => {
+            visitor.visit_name_mut(name);
+            visitor.visit_exp_mut(value);
+        },
+        LuaFieldKind::Positional(value) => visitor.visit_exp_mut(value)
+    }
+}
+
+/**
+ * Rewrites a tree, taking each node by value and giving back what should
+ * replace it. The default methods fold a node's children and rebuild the
+ * node around them, so a folder only has to handle the nodes it changes.
+ *
+ * Blocks fold their statements with `fold_stats`, which can give back any
+ * number of statements for each one, so statements can be removed or split
+ * up as well as replaced.
+ */
+pub trait Folder {
+    fn fold_chunk(&mut self, chunk: LuaChunk) -> LuaChunk {
+        LuaChunk {
+            block: self.fold_block(chunk.block),
+            span:  chunk.span
+        }
+    }
+
+    fn fold_block(&mut self, block: LuaBlock) -> LuaBlock {
+        fold_block(self, block)
+    }
+
+    fn fold_stats(&mut self, stat: LuaStat) -> Vec<LuaStat> {
+        vec![self.fold_stat(stat)]
+    }
+
+    fn fold_stat(&mut self, stat: LuaStat) -> LuaStat {
+        fold_stat(self, stat)
+    }
+
+    fn fold_ret_stat(&mut self, ret_stat: LuaRetStat) -> LuaRetStat {
+        LuaRetStat {
+            exps: self.fold_exps(ret_stat.exps),
+            span: ret_stat.span
+        }
+    }
+
+    fn fold_exps(&mut self, exps: Vec<LuaExp>) -> Vec<LuaExp> {
+        exps.into_iter().map(|exp| self.fold_exp(exp)).collect()
+    }
+
+    fn fold_exp(&mut self, exp: LuaExp) -> LuaExp {
+        fold_exp(self, exp)
+    }
+
+    fn fold_function_call(&mut self, call: LuaFunctionCall) -> LuaFunctionCall {
+        LuaFunctionCall {
+            callee: self.fold_exp(call.callee),
+            method: call.method,
+            args:   self.fold_args(call.args),
+            span:   call.span
+        }
+    }
+
+    fn fold_args(&mut self, args: LuaArgs) -> LuaArgs {
+        let kind = match args.kind {
+            LuaArgsKind::ExpList(exps)           => LuaArgsKind::ExpList(self.fold_exps(exps)),
+            LuaArgsKind::TableConstructor(table) => LuaArgsKind::TableConstructor(self.fold_table_constructor(table)),
+            LuaArgsKind::LiteralString(value)    => LuaArgsKind::LiteralString(value)
+        };
+
+        LuaArgs {
+            kind,
+            span: args.span
+        }
+    }
+
+    fn fold_func_body(&mut self, body: LuaFuncBody) -> LuaFuncBody {
+        LuaFuncBody {
+            params:  body.params,
+            varargs: body.varargs,
+            block:   self.fold_block(body.block),
+            span:    body.span
+        }
+    }
+
+    fn fold_table_constructor(&mut self, table: LuaTableConstructor) -> LuaTableConstructor {
+        LuaTableConstructor {
+            fields: table.fields.into_iter().map(|field| self.fold_field(field)).collect(),
+            span:   table.span
+        }
+    }
+
+    fn fold_field(&mut self, field: LuaField) -> LuaField {
+        let kind = match field.kind {
+            LuaFieldKind::Keyed(key, value)  => LuaFieldKind::Keyed(self.fold_exp(key), self.fold_exp(value)),
+            LuaFieldKind::Named(name, value) => LuaFieldKind::Named(name, self.fold_exp(value)),
+            LuaFieldKind::Positional(value)  => LuaFieldKind::Positional(self.fold_exp(value))
+        };
+
+        LuaField {
+            kind,
+            span: field.span
+        }
+    }
+}
+
+pub fn fold_block<F>(folder: &mut F, block: LuaBlock) -> LuaBlock where F: Folder + ?Sized {
+    LuaBlock {
+        stats:    block.stats.into_iter().flat_map(|stat| folder.fold_stats(stat)).collect(),
+        ret_stat: block.ret_stat.map(|ret_stat| folder.fold_ret_stat(ret_stat)),
+        span:     block.span
+    }
+}
+
+pub fn fold_stat<F>(folder: &mut F, stat: LuaStat) -> LuaStat where F: Folder + ?Sized {
+    let kind = match stat.kind {
+        LuaStatKind::VarList(targets, exps) => {
+            LuaStatKind::VarList(folder.fold_exps(targets), folder.fold_exps(exps))
+        },
+        LuaStatKind::CompoundAssignment(target, op, value) => {
+            LuaStatKind::CompoundAssignment(folder.fold_exp(target), op, folder.fold_exp(value))
+        },
+        LuaStatKind::FunctionCall(call) => LuaStatKind::FunctionCall(folder.fold_function_call(call)),
+        LuaStatKind::Do(block)          => LuaStatKind::Do(folder.fold_block(block)),
+        LuaStatKind::While(condition, block) => {
+            LuaStatKind::While(folder.fold_exp(condition), folder.fold_block(block))
+        },
+        LuaStatKind::Repeat(block, condition) => {
+            LuaStatKind::Repeat(folder.fold_block(block), folder.fold_exp(condition))
+        },
+        LuaStatKind::If(condition, block, else_ifs, else_block) => {
+            let condition = folder.fold_exp(condition);
+            let block = folder.fold_block(block);
+            let else_ifs = else_ifs.into_iter()
+                .map(|(condition, block)| (folder.fold_exp(condition), folder.fold_block(block)))
+                .collect();
+            let else_block = else_block.map(|block| folder.fold_block(block));
+
+            LuaStatKind::If(condition, block, else_ifs, else_block)
+        },
+        LuaStatKind::For(name, initial, limit, step, block) => {
+            let initial = folder.fold_exp(initial);
+            let limit = folder.fold_exp(limit);
+            let step = step.map(|step| Box::new(folder.fold_exp(*step)));
+
+            LuaStatKind::For(name, initial, limit, step, folder.fold_block(block))
+        },
+        LuaStatKind::ForIn(names, exps, block) => {
+            let exps = folder.fold_exps(exps);
+            LuaStatKind::ForIn(names, exps, folder.fold_block(block))
+        },
+        LuaStatKind::Function(func_name, body) => {
+            LuaStatKind::Function(func_name, folder.fold_func_body(body))
+        },
+        LuaStatKind::LocalFunction(name, body) => {
+            LuaStatKind::LocalFunction(name, folder.fold_func_body(body))
+        },
+        LuaStatKind::LocalAttNameList(att_names, exps) => {
+            LuaStatKind::LocalAttNameList(att_names, folder.fold_exps(exps))
+        },
+        kind @ LuaStatKind::Semicolon | kind @ LuaStatKind::Break | kind @ LuaStatKind::Continue |
+        kind @ LuaStatKind::Label(_)  | kind @ LuaStatKind::Goto(_) => kind
+    };
+
+    LuaStat {
+        kind,
+        span: stat.span,
+        doc:  stat.doc
+    }
+}
+
+pub fn fold_exp<F>(folder: &mut F, exp: LuaExp) -> LuaExp where F: Folder + ?Sized {
+    let kind = match exp.kind {
+        LuaExpKind::FunctionDef(body)       => LuaExpKind::FunctionDef(folder.fold_func_body(body)),
+        LuaExpKind::TableConstructor(table) => LuaExpKind::TableConstructor(folder.fold_table_constructor(table)),
+        LuaExpKind::BinOp(op, left, right) => {
+            let left = folder.fold_exp(*left);
+            LuaExpKind::BinOp(op, Box::new(left), Box::new(folder.fold_exp(*right)))
+        },
+        LuaExpKind::UnOp(op, operand) => LuaExpKind::UnOp(op, Box::new(folder.fold_exp(*operand))),
+        LuaExpKind::Bracketed(inner)  => LuaExpKind::Bracketed(Box::new(folder.fold_exp(*inner))),
+        LuaExpKind::Index(prefix, key) => {
+            let prefix = folder.fold_exp(*prefix);
+            LuaExpKind::Index(Box::new(prefix), Box::new(folder.fold_exp(*key)))
+        },
+        LuaExpKind::Field(prefix, name) => LuaExpKind::Field(Box::new(folder.fold_exp(*prefix)), name),
+        LuaExpKind::FunctionCall(call)  => LuaExpKind::FunctionCall(Box::new(folder.fold_function_call(*call))),
+        kind => kind
+    };
+
+    LuaExp {
+        kind,
+        span: exp.span
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::{LuaDialect, LuaNumeral};
+
+    fn read(src: &str) -> LuaChunk {
+        read_lua_chunk(src, LuaDialect::Lua54, false).ok().unwrap()
+    }
+
+    struct NameCollector {
+        names: Vec<String>
+    }
+
+    impl Visitor for NameCollector {
+        fn visit_name(&mut self, name: &LuaName) {
+            self.names.push(name.name.to_string());
+        }
+
+        // Doesn't look inside functions.
+        fn visit_func_body(&mut self, _body: &LuaFuncBody) { }
+    }
+
+    #[test]
+    fn visitors_walk_children_in_source_order() {
+        let chunk = read("local a <const> = b.c[d](e, {f = g})\nlocal function h(i) return j end\nreturn k");
+        let mut collector = NameCollector { names: Vec::new() };
+        collector.visit_chunk(&chunk);
+
+        assert_eq!(collector.names, vec!["a", "b", "c", "d", "e", "f", "g", "h", "k"]);
+    }
+
+    struct Renamer;
+
+    impl VisitorMut for Renamer {
+        fn visit_name_mut(&mut self, name: &mut LuaName) {
+            name.name = LuaSymbol::from(name.name.to_uppercase().as_str());
+        }
+    }
+
+    #[test]
+    fn mutable_visitors_change_nodes_in_place() {
+        let mut chunk = read("x = y");
+        Renamer.visit_chunk_mut(&mut chunk);
+
+        let mut collector = NameCollector { names: Vec::new() };
+        collector.visit_chunk(&chunk);
+        assert_eq!(collector.names, vec!["X", "Y"]);
+    }
+
+    /** Replaces every numeral with zero, and drops every `break`. */
+    struct Zeroer;
+
+    impl Folder for Zeroer {
+        fn fold_stats(&mut self, stat: LuaStat) -> Vec<LuaStat> {
+            match stat.kind {
+                LuaStatKind::Break => Vec::new(),
+                _                  => vec![self.fold_stat(stat)]
+            }
+        }
+
+        fn fold_exp(&mut self, exp: LuaExp) -> LuaExp {
+            match exp.kind {
+                LuaExpKind::Number(_) => LuaExp { kind: LuaExpKind::Number(LuaNumeral::Integer(0)), span: exp.span },
+                _                     => fold_exp(self, exp)
+            }
+        }
+    }
+
+    #[test]
+    fn folders_rebuild_the_tree() {
+        let chunk = Zeroer.fold_chunk(read("while true do x = f(1 + 2) break end"));
+
+        match &chunk.block.stats[0].kind {
+            LuaStatKind::While(_, block) => {
+                assert_eq!(block.stats.len(), 1);
+
+                match &block.stats[0].kind {
+                    LuaStatKind::VarList(_, exps) => match &exps[0].kind {
+                        LuaExpKind::FunctionCall(call) => match &call.args.kind {
+                            LuaArgsKind::ExpList(args) => match &args[0].kind {
+                                LuaExpKind::BinOp(_, left, right) => {
+                                    assert_eq!(left.kind,  LuaExpKind::Number(LuaNumeral::Integer(0)));
+                                    assert_eq!(right.kind, LuaExpKind::Number(LuaNumeral::Integer(0)));
+                                },
+                                _ => panic!("expected an addition")
+                            },
+                            _ => panic!("expected arguments")
+                        },
+                        _ => panic!("expected a call")
+                    },
+                    _ => panic!("expected an assignment")
+                }
+            },
+            _ => panic!("expected a loop")
+        }
+    }
+}
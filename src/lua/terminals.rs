@@ -4,12 +4,14 @@ use enum_iterator::IntoEnumIterator;
 
 use crate::tokenisation::TokenData;
 use crate::parsing::TerminalSymbol;
+use crate::lua::{LuaNumeral, LuaBytes};
 
+#[derive(Debug, Clone)]
 pub enum LuaTokenData {
     Error(String),
     Identifier(String),
-    NumberLiteral(f64),
-    StringLiteral(String)
+    NumberLiteral(LuaNumeral),
+    StringLiteral(LuaBytes)
 }
 
 impl TokenData for LuaTokenData {
@@ -51,6 +53,7 @@ pub enum LuaTerminal {
     Or,
     Not,
     Goto,
+    Continue,
     Equals,
     DoubleEquals,
     Dot,
@@ -83,6 +86,14 @@ pub enum LuaTerminal {
     GreaterThan,
     GreaterEq,
     NotEq,
+    PlusEquals,
+    MinusEquals,
+    MultiplyEquals,
+    DivideEquals,
+    FloorDivideEquals,
+    PowerEquals,
+    ModuloEquals,
+    ConcatEquals,
     Length,
     Comment,
     EndOfFile,
@@ -119,6 +130,7 @@ impl TerminalSymbol for LuaTerminal {
             LuaTerminal::Or               => "or",
             LuaTerminal::Not              => "not",
             LuaTerminal::Goto             => "goto",
+            LuaTerminal::Continue         => "continue",
             LuaTerminal::Equals           => "`=`",
             LuaTerminal::DoubleEquals     => "`==`",
             LuaTerminal::Dot              => "`.`",
@@ -151,6 +163,14 @@ impl TerminalSymbol for LuaTerminal {
             LuaTerminal::GreaterThan      => "`>`",
             LuaTerminal::GreaterEq        => "`>=`",
             LuaTerminal::NotEq            => "`~=`",
+            LuaTerminal::PlusEquals       => "`+=`",
+            LuaTerminal::MinusEquals      => "`-=`",
+            LuaTerminal::MultiplyEquals   => "`*=`",
+            LuaTerminal::DivideEquals     => "`/=`",
+            LuaTerminal::FloorDivideEquals => "`//=`",
+            LuaTerminal::PowerEquals      => "`^=`",
+            LuaTerminal::ModuloEquals     => "`%=`",
+            LuaTerminal::ConcatEquals     => "`..=`",
             LuaTerminal::Length           => "`#`",
             LuaTerminal::Comment          => "Comment",
             LuaTerminal::EndOfFile        => "<eof>",
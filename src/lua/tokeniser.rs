@@ -1,47 +1,109 @@
-use regex::Regex;
-use lazy_static::lazy_static;
-
-use crate::tokenisation::{Tokeniser, TokeniserState, Token, Location, TokeniserBuilder, TokenisationError, TokenisationErrorType};
-use crate::lua::{LuaTerminal, LuaTokenData};
-
-lazy_static!{
-    pub static ref MULTILINE_START_REGEX:  Regex = Regex::new(r"^\[=*\[").unwrap();
-    pub static ref MULTILINE_FINISH_REGEX: Regex = Regex::new(r"\]=*\]").unwrap();
-}
-
-pub fn get_lua_tokeniser<'t>() -> Option<Tokeniser<'t, LuaTerminal, TokenisationErrorType>> {
-    TokeniserBuilder::<LuaTerminal, TokenisationErrorType>::new()
-        .with_static_token(Regex::new(r"^end\b").unwrap(),      LuaTerminal::End)
-        .with_static_token(Regex::new(r"^do\b").unwrap(),       LuaTerminal::Do)
-        .with_static_token(Regex::new(r"^while\b").unwrap(),    LuaTerminal::While)
-        .with_static_token(Regex::new(r"^repeat\b").unwrap(),   LuaTerminal::Repeat)
-        .with_static_token(Regex::new(r"^until\b").unwrap(),    LuaTerminal::Until)
-        .with_static_token(Regex::new(r"^if\b").unwrap(),       LuaTerminal::If)
-        .with_static_token(Regex::new(r"^in\b").unwrap(),       LuaTerminal::In)
-        .with_static_token(Regex::new(r"^then\b").unwrap(),     LuaTerminal::Then)
-        .with_static_token(Regex::new(r"^elseif\b").unwrap(),   LuaTerminal::Elseif)
-        .with_static_token(Regex::new(r"^else\b").unwrap(),     LuaTerminal::Else)
-        .with_static_token(Regex::new(r"^for\b").unwrap(),      LuaTerminal::For)
-        .with_static_token(Regex::new(r"^function\b").unwrap(), LuaTerminal::Function)
-        .with_static_token(Regex::new(r"^local\b").unwrap(),    LuaTerminal::Local)
-        .with_static_token(Regex::new(r"^return\b").unwrap(),   LuaTerminal::Return)
-        .with_static_token(Regex::new(r"^break\b").unwrap(),    LuaTerminal::Break)
-        .with_static_token(Regex::new(r"^true\b").unwrap(),     LuaTerminal::True)
-        .with_static_token(Regex::new(r"^false\b").unwrap(),    LuaTerminal::False)
-        .with_static_token(Regex::new(r"^nil\b").unwrap(),      LuaTerminal::Nil)
-        .with_static_token(Regex::new(r"^and\b").unwrap(),      LuaTerminal::And)
-        .with_static_token(Regex::new(r"^or\b").unwrap(),       LuaTerminal::Or)
-        .with_static_token(Regex::new(r"^not\b").unwrap(),      LuaTerminal::Not)
-        .with_static_token(Regex::new(r"^goto\b").unwrap(),     LuaTerminal::Goto)
+use regex::bytes::Regex;
+
+use crate::tokenisation::{Tokeniser, TokeniserState, Token, Location, TokeniserBuilder, TokenisationError, TokenisationErrorType, TriviaKind, newline_length};
+use crate::lua::{LuaTerminal, LuaTokenData, LuaBytes, LuaDialect};
+use crate::lua::numeral::{parse_numeral, parse_suffixed_numeral};
+
+/**
+ * Creates a tokeniser for the given dialect of Lua. Keywords and operators
+ * the dialect doesn't have are left out, so they are read as identifiers or
+ * reported as unexpected symbols just as that version of Lua would.
+ *
+ * This tokenises chunks the way `load` does, so the source is taken exactly
+ * as it is. Use `get_lua_file_tokeniser` for Lua files.
+ */
+pub fn get_lua_tokeniser(dialect: LuaDialect) -> Option<Tokeniser<LuaTerminal, TokenisationErrorType>> {
+    build_lua_tokeniser(dialect, false)
+}
+
+/**
+ * Creates a tokeniser for Lua files. Like `luaL_loadfile`, it skips a UTF-8
+ * byte order mark and then a first line starting with `#`, so executable
+ * scripts with a shebang line can be loaded. Both are kept as trivia of the
+ * first token.
+ */
+pub fn get_lua_file_tokeniser(dialect: LuaDialect) -> Option<Tokeniser<LuaTerminal, TokenisationErrorType>> {
+    build_lua_tokeniser(dialect, true)
+}
+
+/** The reserved words of reference Lua since 5.2. */
+const KEYWORDS: [(&str, LuaTerminal); 22] = [
+    ("and",      LuaTerminal::And),
+    ("break",    LuaTerminal::Break),
+    ("do",       LuaTerminal::Do),
+    ("else",     LuaTerminal::Else),
+    ("elseif",   LuaTerminal::Elseif),
+    ("end",      LuaTerminal::End),
+    ("false",    LuaTerminal::False),
+    ("for",      LuaTerminal::For),
+    ("function", LuaTerminal::Function),
+    ("goto",     LuaTerminal::Goto),
+    ("if",       LuaTerminal::If),
+    ("in",       LuaTerminal::In),
+    ("local",    LuaTerminal::Local),
+    ("nil",      LuaTerminal::Nil),
+    ("not",      LuaTerminal::Not),
+    ("or",       LuaTerminal::Or),
+    ("repeat",   LuaTerminal::Repeat),
+    ("return",   LuaTerminal::Return),
+    ("then",     LuaTerminal::Then),
+    ("true",     LuaTerminal::True),
+    ("until",    LuaTerminal::Until),
+    ("while",    LuaTerminal::While)
+];
+
+fn build_lua_tokeniser(
+    dialect:       LuaDialect,
+    skip_prologue: bool
+) -> Option<Tokeniser<LuaTerminal, TokenisationErrorType>> {
+    let mut builder = TokeniserBuilder::<LuaTerminal, TokenisationErrorType>::new();
+
+    if skip_prologue {
+        builder = builder
+            .with_prologue_trivia(Regex::new(r"^(?-u:\xEF\xBB\xBF)").unwrap(), TriviaKind::ByteOrderMark)
+            .with_prologue_trivia(Regex::new(r"^#(?-u:[^\n])*").unwrap(),      TriviaKind::Shebang);
+    }
+
+    builder = builder
         .with_dynamic_token(
-            Regex::new(r"^[a-zA-Z_]\w*").unwrap(),
-            &parse_identifier
-        )
-        .with_static_token(Regex::new(r"^\.{3}").unwrap(),      LuaTerminal::Varargs)
+            Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*").unwrap(),
+            parse_identifier
+        );
+
+    for (word, keyword) in KEYWORDS.iter() {
+        builder = builder.with_keyword(word, *keyword);
+    }
+
+    if !dialect.has_goto() {
+        builder = builder.without_keyword("goto");
+    }
+
+    builder = builder
+        .with_static_token(Regex::new(r"^\.{3}").unwrap(),      LuaTerminal::Varargs);
+
+    if dialect.has_compound_assignment() {
+        builder = builder
+            .with_static_token(Regex::new(r"^\.{2}=").unwrap(),  LuaTerminal::ConcatEquals)
+            .with_static_token(Regex::new(r"^//=").unwrap(),     LuaTerminal::FloorDivideEquals)
+            .with_static_token(Regex::new(r"^\+=").unwrap(),     LuaTerminal::PlusEquals)
+            .with_static_token(Regex::new(r"^-=").unwrap(),      LuaTerminal::MinusEquals)
+            .with_static_token(Regex::new(r"^\*=").unwrap(),     LuaTerminal::MultiplyEquals)
+            .with_static_token(Regex::new(r"^/=").unwrap(),      LuaTerminal::DivideEquals)
+            .with_static_token(Regex::new(r"^\^=").unwrap(),     LuaTerminal::PowerEquals)
+            .with_static_token(Regex::new(r"^%=").unwrap(),      LuaTerminal::ModuloEquals);
+    }
+
+    builder = builder
         .with_static_token(Regex::new(r"^\.{2}").unwrap(),      LuaTerminal::Concat)
         .with_static_token(Regex::new(r"^==").unwrap(),         LuaTerminal::DoubleEquals)
-        .with_static_token(Regex::new(r"^=").unwrap(),          LuaTerminal::Equals)
-        .with_static_token(Regex::new(r"^::").unwrap(),         LuaTerminal::DoubleColon)
+        .with_static_token(Regex::new(r"^=").unwrap(),          LuaTerminal::Equals);
+
+    if dialect.has_goto() {
+        builder = builder
+            .with_static_token(Regex::new(r"^::").unwrap(),     LuaTerminal::DoubleColon);
+    }
+
+    builder = builder
         .with_static_token(Regex::new(r"^:").unwrap(),          LuaTerminal::Colon)
         .with_static_token(Regex::new(r"^,").unwrap(),          LuaTerminal::Comma)
         .with_static_token(Regex::new(r"^\]").unwrap(),         LuaTerminal::RightBracket)
@@ -49,15 +111,26 @@ pub fn get_lua_tokeniser<'t>() -> Option<Tokeniser<'t, LuaTerminal, Tokenisation
         .with_static_token(Regex::new(r"^\)").unwrap(),         LuaTerminal::RightParenthesis)
         .with_static_token(Regex::new(r"^\{").unwrap(),         LuaTerminal::LeftBrace)
         .with_static_token(Regex::new(r"^\}").unwrap(),         LuaTerminal::RightBrace)
-        .with_static_token(Regex::new(r"^<<").unwrap(),         LuaTerminal::LeftShift)
-        .with_static_token(Regex::new(r"^>>").unwrap(),         LuaTerminal::RightShift)
-        .with_static_token(Regex::new(r"^&").unwrap(),          LuaTerminal::BitwiseAnd)
-        .with_static_token(Regex::new(r"^\|").unwrap(),         LuaTerminal::BitwiseOr)
-        .with_static_token(Regex::new(r"^~").unwrap(),          LuaTerminal::BitwiseNeg)
+        .with_static_token(Regex::new(r"^~=").unwrap(),         LuaTerminal::NotEq);
+
+    if dialect.has_bitwise_operators() {
+        builder = builder
+            .with_static_token(Regex::new(r"^<<").unwrap(),     LuaTerminal::LeftShift)
+            .with_static_token(Regex::new(r"^>>").unwrap(),     LuaTerminal::RightShift)
+            .with_static_token(Regex::new(r"^&").unwrap(),      LuaTerminal::BitwiseAnd)
+            .with_static_token(Regex::new(r"^\|").unwrap(),     LuaTerminal::BitwiseOr)
+            .with_static_token(Regex::new(r"^~").unwrap(),      LuaTerminal::BitwiseNeg);
+    }
+
+    if dialect.has_floor_division() {
+        builder = builder
+            .with_static_token(Regex::new(r"^//").unwrap(),     LuaTerminal::FloorDivide);
+    }
+
+    builder = builder
         .with_static_token(Regex::new(r"^;").unwrap(),          LuaTerminal::Semicolon)
         .with_static_token(Regex::new(r"^\+").unwrap(),         LuaTerminal::Plus)
         .with_static_token(Regex::new(r"^\*").unwrap(),         LuaTerminal::Multiply)
-        .with_static_token(Regex::new(r"^//").unwrap(),         LuaTerminal::FloorDivide)
         .with_static_token(Regex::new(r"^/").unwrap(),          LuaTerminal::Divide)
         .with_static_token(Regex::new(r"^\^").unwrap(),         LuaTerminal::Power)
         .with_static_token(Regex::new(r"^%").unwrap(),          LuaTerminal::Modulo)
@@ -65,65 +138,285 @@ pub fn get_lua_tokeniser<'t>() -> Option<Tokeniser<'t, LuaTerminal, Tokenisation
         .with_static_token(Regex::new(r"^<").unwrap(),          LuaTerminal::LessThan)
         .with_static_token(Regex::new(r"^>=").unwrap(),         LuaTerminal::GreaterEq)
         .with_static_token(Regex::new(r"^>").unwrap(),          LuaTerminal::GreaterThan)
-        .with_static_token(Regex::new(r"^~=").unwrap(),         LuaTerminal::NotEq)
         .with_static_token(Regex::new(r"^#").unwrap(),          LuaTerminal::Length)
-        .with_dynamic_token(
-            Regex::new("^\"((\")|.)+?[^\\\\]\"").unwrap(),
-            &parse_string
-        )
-        .with_dynamic_token(
-            Regex::new(r"^'(\\'|.)+?[^\\]'").unwrap(),
-            &parse_string
+        .with_complicated_token(
+            Regex::new(r#"^["']"#).unwrap(),
+            parse_string
         )
         .with_complicated_token(
             Regex::new(r"^\[=*\[").unwrap(),
-            &parse_multiline_string
+            parse_multiline_string
+        )
+        .with_dynamic_token(
+            Regex::new(r"^\[=+").unwrap(),
+            get_invalid_long_string_delimiter_error
         )
         .with_complicated_token(
             Regex::new(r"^-{2}\[=*\[").unwrap(),
-            &parse_multiline_comment
+            parse_multiline_comment
         )
-        .with_static_token(Regex::new(r"^-{2}.*").unwrap(),     LuaTerminal::Comment)
+        .with_trivia(TriviaKind::BlockComment)
+        .with_static_token(Regex::new(r"^-{2}(?-u:[^\r\n])*").unwrap(), LuaTerminal::Comment)
+        .with_trivia(TriviaKind::LineComment)
         .with_static_token(Regex::new(r"^-").unwrap(),          LuaTerminal::Minus)
-        .with_static_token(Regex::new(r"\[").unwrap(),          LuaTerminal::LeftBracket)
+        .with_static_token(Regex::new(r"^\[").unwrap(),         LuaTerminal::LeftBracket);
+
+    // LuaJIT's lexer reads every identifier character into a numeral, since
+    // any of them could be part of a suffix.
+    let numeral_matcher = match dialect.has_numeral_suffixes() {
+        true  => r"^(0[xX]([pP][+-]?|[0-9a-zA-Z_.])*|\.?[0-9]([eE][+-]?|[0-9a-zA-Z_.])*)",
+        false => r"^(0[xX]([pP][+-]?|[0-9a-fA-F.])*|\.?[0-9]([eE][+-]?|[0-9a-fA-F.])*)[a-zA-Z_]?"
+    };
+
+    builder
         .with_dynamic_token(
-            Regex::new(r"^\d+").unwrap(),
-            &parse_number
+            Regex::new(numeral_matcher).unwrap(),
+            move |value, location| parse_number(dialect, value, location)
         )
         .with_static_token(Regex::new(r"^\.{1}").unwrap(),      LuaTerminal::Dot)
-        .with_error_handler('"',  &handle_unfinished_str)
-        .with_error_handler('\'', &handle_unfinished_str)
-        .with_eof_handler(&get_eof_token)
-        .with_unexpected_symbol_handler(&get_unexpected_symbol_error)
+        .with_eof_handler(get_eof_token)
+        .with_unexpected_symbol_handler(get_unexpected_symbol_error)
         .build()
 }
 
-fn parse_number(value: String, location: Location) -> Result<Token<LuaTerminal>, TokenisationError<LuaTerminal, TokenisationErrorType>> {
-    Ok(Token {
-        token_type: LuaTerminal::NumberLiteral,
-        token_data: Some(LuaTokenData::NumberLiteral(1.0)),
-        location:   location
-    })
+/**
+ * Like the reference lexer, the numeral regex matches greedily - any run of
+ * hex digits, dots and signed exponents, plus a letter touching the end - so
+ * that text like `3..2` or `0xg` is reported as a malformed number rather
+ * than being split into several tokens.
+ */
+fn parse_number(
+    dialect:  LuaDialect,
+    value:    Vec<u8>,
+    location: Location
+) -> Result<Token<LuaTerminal>, TokenisationError<LuaTerminal, TokenisationErrorType>> {
+    let value = String::from_utf8_lossy(&value).into_owned();
+
+    let numeral = match dialect.has_numeral_suffixes() {
+        true  => parse_suffixed_numeral(&value),
+        false => parse_numeral(dialect, &value)
+    };
+
+    match numeral {
+        Some(numeral) => Ok(Token::new(
+            LuaTerminal::NumberLiteral,
+            Some(LuaTokenData::NumberLiteral(numeral)),
+            location
+        )),
+        None => Err(TokenisationError {
+            partial_token: Box::new(Token::new(
+                LuaTerminal::Error,
+                Some(LuaTokenData::Error(value)),
+                location
+            )),
+            error_type: TokenisationErrorType::MalformedNumber
+        })
+    }
 }
 
-fn parse_string(value: String, location: Location) -> Result<Token<LuaTerminal>, TokenisationError<LuaTerminal, TokenisationErrorType>> {
-    let len = value.len() - 1;
-    Ok(Token {
-        token_type: LuaTerminal::StringLiteral,
-        token_data: Some(LuaTokenData::StringLiteral(String::from(&value[1..len]))),
-        location:   location
-    })
+/**
+ * Reads a quoted string, decoding its escape sequences the way the reference
+ * lexer does. Errors are reported near the text of the string up to the
+ * offending character, as the reference lexer does, but the partial token is
+ * placed at the escape sequence itself.
+ */
+fn parse_string(
+    state:    &mut TokeniserState<LuaTerminal, TokenisationErrorType>,
+    location: Location
+) -> Result<Token<LuaTerminal>, TokenisationError<LuaTerminal, TokenisationErrorType>> {
+    let bytes     = state.remaining();
+    let delimiter = bytes[0];
+    let mut value = Vec::new();
+    let mut idx   = 1;
+
+    loop {
+        match bytes.get(idx) {
+            None => {
+                state.consume_chars(idx);
+                return Err(TokenisationError {
+                    partial_token: Box::new(get_unfinished_token(state.location)),
+                    error_type:    TokenisationErrorType::UnfinishedString
+                });
+            },
+            Some(b'\n') | Some(b'\r') => {
+                state.consume_chars(idx);
+                return Err(TokenisationError {
+                    partial_token: Box::new(Token::new(
+                        LuaTerminal::Error,
+                        Some(LuaTokenData::Error(String::from_utf8_lossy(&bytes[..idx]).into_owned())),
+                        location
+                    )),
+                    error_type: TokenisationErrorType::UnfinishedString
+                });
+            },
+            Some(b'\\') => match read_escape(&bytes[idx..]) {
+                Ok((decoded, length)) => {
+                    value.extend(decoded);
+                    idx += length;
+                },
+                Err((error_type, length)) => {
+                    // Take the whole of the offending character, so it isn't
+                    // mangled when the message is shown.
+                    let mut end = (idx + length).min(bytes.len());
+                    while end < bytes.len() && bytes[end] & 0xc0 == 0x80 {
+                        end += 1;
+                    }
+
+                    state.consume_chars(idx);
+                    let mut partial_token = Token::new(
+                        LuaTerminal::Error,
+                        Some(LuaTokenData::Error(String::from_utf8_lossy(&bytes[..end]).into_owned())),
+                        state.location
+                    );
+                    partial_token.text = bytes[idx..end].to_vec();
+
+                    // Carry on to the end of the string so that tokenisation
+                    // can recover after it.
+                    state.consume_chars(skip_string(&bytes[idx..], delimiter));
+                    return Err(TokenisationError { partial_token: Box::new(partial_token), error_type });
+                }
+            },
+            Some(byte) if *byte == delimiter => {
+                state.consume_chars(idx + 1);
+                return Ok(Token::new(
+                    LuaTerminal::StringLiteral,
+                    Some(LuaTokenData::StringLiteral(LuaBytes::from(value))),
+                    location
+                ));
+            },
+            Some(byte) => {
+                value.push(*byte);
+                idx += 1;
+            }
+        }
+    }
+}
+
+/**
+ * Decodes the escape sequence at the start of `bytes`, which begins with a
+ * backslash. Gives the bytes it stands for and its length, or the error and
+ * the length up to and including the offending character.
+ */
+fn read_escape(bytes: &[u8]) -> Result<(Vec<u8>, usize), (TokenisationErrorType, usize)> {
+    let simple = |byte: u8| Ok((vec![byte], 2));
+
+    match bytes.get(1) {
+        // Left for the string to report as unfinished.
+        None        => Ok((vec![], 1)),
+        Some(b'a')  => simple(0x07),
+        Some(b'b')  => simple(0x08),
+        Some(b'f')  => simple(0x0c),
+        Some(b'n')  => simple(b'\n'),
+        Some(b'r')  => simple(b'\r'),
+        Some(b't')  => simple(b'\t'),
+        Some(b'v')  => simple(0x0b),
+        Some(b'\\') | Some(b'"') | Some(b'\'') => simple(bytes[1]),
+        Some(b'\n') | Some(b'\r') => Ok((vec![b'\n'], 1 + newline_length(&bytes[1..]))),
+        Some(b'x') => {
+            let high = hex_digit_at(bytes, 2)?;
+            let low  = hex_digit_at(bytes, 3)?;
+            Ok((vec![(high * 16 + low) as u8], 4))
+        },
+        Some(b'u') => {
+            if bytes.get(2) != Some(&b'{') {
+                return Err((TokenisationErrorType::MissingUtf8EscapeBrace { opening: true }, 3));
+            }
+
+            let mut value = hex_digit_at(bytes, 3)?;
+            let mut idx   = 4;
+
+            while let Ok(digit) = hex_digit_at(bytes, idx) {
+                if value > (0x7FFFFFFF >> 4) {
+                    return Err((TokenisationErrorType::Utf8ValueTooLarge, idx + 1));
+                }
+                value = value * 16 + digit;
+                idx += 1;
+            }
+
+            if bytes.get(idx) != Some(&b'}') {
+                return Err((TokenisationErrorType::MissingUtf8EscapeBrace { opening: false }, idx + 1));
+            }
+
+            Ok((encode_utf8(value), idx + 1))
+        },
+        Some(b'z') => {
+            let spaces = bytes[2..].iter()
+                .take_while(|byte| b" \t\n\r\x0b\x0c".contains(byte))
+                .count();
+            Ok((vec![], 2 + spaces))
+        },
+        Some(byte) if byte.is_ascii_digit() => {
+            let digits = bytes[1..].iter()
+                .take(3)
+                .take_while(|byte| byte.is_ascii_digit())
+                .fold(Vec::new(), |mut digits, digit| { digits.push(digit - b'0'); digits });
+            let value = digits.iter().fold(0u32, |value, digit| value * 10 + *digit as u32);
+
+            if value > 255 {
+                return Err((TokenisationErrorType::DecimalEscapeTooLarge, digits.len() + 2));
+            }
+
+            Ok((vec![value as u8], digits.len() + 1))
+        },
+        Some(_) => Err((TokenisationErrorType::InvalidEscapeSequence, 2))
+    }
+}
+
+fn hex_digit_at(bytes: &[u8], idx: usize) -> Result<u32, (TokenisationErrorType, usize)> {
+    bytes.get(idx)
+        .and_then(|byte| (*byte as char).to_digit(16))
+        .ok_or((TokenisationErrorType::HexadecimalDigitExpected, idx + 1))
+}
+
+/** Encodes a `\u{...}` value the way the reference implementation does,
+ *  which allows values up to 2^31 using sequences of up to six bytes. */
+fn encode_utf8(value: u32) -> Vec<u8> {
+    if value < 0x80 {
+        return vec![value as u8];
+    }
+
+    let mut encoded   = Vec::new();
+    let mut remaining = value;
+    let mut first_max = 0x3f;
+
+    while remaining > first_max {
+        encoded.push((0x80 | (remaining & 0x3f)) as u8);
+        remaining >>= 6;
+        first_max >>= 1;
+    }
+
+    encoded.push(((!first_max << 1) | remaining) as u8);
+    encoded.reverse();
+    encoded
+}
+
+/** The length of the rest of a string starting at `bytes`, up to and
+ *  including its closing delimiter, or up to the end of the line if it is
+ *  unfinished. */
+fn skip_string(bytes: &[u8], delimiter: u8) -> usize {
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'\n' | b'\r'                => return idx,
+            b'\\'                       => idx += 2,
+            byte if byte == delimiter   => return idx + 1,
+            _                           => idx += 1
+        }
+    }
+
+    bytes.len()
 }
 
 fn parse_identifier(
-    value:    String,
+    value:    Vec<u8>,
     location: Location
 ) -> Result<Token<LuaTerminal>, TokenisationError<LuaTerminal, TokenisationErrorType>> {
-    Ok(Token {
-        token_type: LuaTerminal::Identifier,
-        token_data: Some(LuaTokenData::Identifier(value)),
-        location:   location
-    })
+    Ok(Token::new(
+        LuaTerminal::Identifier,
+        Some(LuaTokenData::Identifier(String::from_utf8_lossy(&value).into_owned())),
+        location
+    ))
 }
 
 fn parse_multiline_string(
@@ -132,16 +425,16 @@ fn parse_multiline_string(
 ) -> Result<Token<LuaTerminal>, TokenisationError<LuaTerminal, TokenisationErrorType>> {
     parse_multiline(state)
         .map(|parsed_str| {
-            Token {
-                token_type: LuaTerminal::StringLiteral,
-                token_data: Some(LuaTokenData::StringLiteral(parsed_str)),
-                location:   location
-            }
+            Token::new(
+                LuaTerminal::StringLiteral,
+                Some(LuaTokenData::StringLiteral(parsed_str)),
+                location
+            )
         })
         .ok_or(
             TokenisationError {
-                partial_token: get_eof_token(location),
-                error_type:    TokenisationErrorType::UnfinishedLongString
+                partial_token: Box::new(get_unfinished_token(state.location)),
+                error_type:    TokenisationErrorType::UnfinishedLongString { start_line: location.line }
             }
         )
 }
@@ -154,87 +447,527 @@ fn parse_multiline_comment(
 
     parse_multiline(state)
         .map(|_| {
-            Token {
-                token_type: LuaTerminal::Comment,
-                token_data: None,
-                location:   location
-            }
+            Token::new(
+                LuaTerminal::Comment,
+                None,
+                location
+            )
         })
         .ok_or(
             TokenisationError {
-                partial_token: get_eof_token(location),
-                error_type:    TokenisationErrorType::UnfinishedLongComment
+                partial_token: Box::new(get_unfinished_token(state.location)),
+                error_type:    TokenisationErrorType::UnfinishedLongComment { start_line: location.line }
             }
         )
 }
 
+/** The partial token for a string or comment the source ends in the middle
+ *  of. It has no data, so the error is reported near `<eof>`. */
+fn get_unfinished_token(location: Location) -> Token<LuaTerminal> {
+    Token::new(
+        LuaTerminal::Error,
+        None,
+        location
+    )
+}
+
 fn get_eof_token(location: Location) -> Token<LuaTerminal> {
-    Token {
-        token_type: LuaTerminal::EndOfFile,
-        token_data: None,
-        location:   location
-    }
+    Token::new(
+        LuaTerminal::EndOfFile,
+        None,
+        location
+    )
 }
 
+/**
+ * Reads a long bracket, starting at its opening `[`, the way the reference
+ * lexer does. It closes at the first `]` followed by the same number of `=`
+ * and another `]`, a line break straight after the opening bracket is
+ * skipped, and every line break inside it becomes a single `\n`. Gives
+ * `None` if it is never closed, in which case the rest of the file belongs
+ * to it.
+ */
 fn parse_multiline(
     tokeniser_state: &mut TokeniserState<LuaTerminal, TokenisationErrorType>
-) -> Option<String> {
-    let mut line = tokeniser_state.line_buffer.as_ref().expect("Empty Line Buffer").clone();
-    let start_mat = MULTILINE_START_REGEX.find(&line).expect("Failed to match the start of multiline regex");
-    let depth = start_mat.end() - start_mat.start();
-    let mut cols_to_undo = line.len();
-
-    let end_mat = loop {
-        let end = MULTILINE_FINISH_REGEX.find_iter(&line)
-            .filter(|mat| (*mat).end() - (*mat).start() == depth)
-            .into_iter()
-            .next();
-
-        if end.is_some() {
-            break end;
-        } else {
-            if !tokeniser_state.has_next_line() {
-                break None;
-            } else {
-                cols_to_undo = line.len();
-                tokeniser_state.pop_line();
-                line = tokeniser_state.line_buffer.as_ref().expect("Empty Line Buffer").clone();
+) -> Option<LuaBytes> {
+    let bytes = tokeniser_state.remaining();
+    let level = bytes[1..].iter().take_while(|byte| **byte == b'=').count();
+
+    let mut value = Vec::new();
+    let mut idx   = level + 2;
+    idx = idx + newline_length(&bytes[idx..]);
+
+    loop {
+        match bytes.get(idx) {
+            None => {
+                tokeniser_state.consume_chars(idx);
+                return None;
+            },
+            Some(b']') if closes_long_bracket(&bytes[idx..], level) => {
+                tokeniser_state.consume_chars(idx + level + 2);
+                return Some(LuaBytes::from(value));
+            },
+            Some(b'\n') | Some(b'\r') => {
+                value.push(b'\n');
+                idx = idx + newline_length(&bytes[idx..]);
+            },
+            Some(byte) => {
+                value.push(*byte);
+                idx += 1;
             }
         }
-    };
+    }
+}
 
-    end_mat.map(|mat| {
-        let value = String::from(&line[depth..mat.start()]);
-        tokeniser_state.consume_chars(mat.end());
-        tokeniser_state.location.col = mat.end() - cols_to_undo + 1;
-        value
-    })
+/** Whether `bytes` starts with a closing long bracket of the given level. */
+fn closes_long_bracket(bytes: &[u8], level: usize) -> bool {
+    bytes.len() >= level + 2 &&
+        bytes[0] == b']' &&
+        bytes[1..level + 1].iter().all(|byte| *byte == b'=') &&
+        bytes[level + 1] == b']'
 }
 
-fn handle_unfinished_str(
-    location: Location,
-    line:     String
-) -> TokenisationError<LuaTerminal, TokenisationErrorType> {
-    TokenisationError {
-        partial_token: Token {
-            token_type: LuaTerminal::StringLiteral,
-            token_data: Some(LuaTokenData::Error(line)),
-            location:   location
-        },
-        error_type: TokenisationErrorType::UnfinishedString
-    }
+fn get_invalid_long_string_delimiter_error(
+    value:    Vec<u8>,
+    location: Location
+) -> Result<Token<LuaTerminal>, TokenisationError<LuaTerminal, TokenisationErrorType>> {
+    Err(TokenisationError {
+        partial_token: Box::new(Token::new(
+            LuaTerminal::Error,
+            Some(LuaTokenData::Error(String::from_utf8_lossy(&value).into_owned())),
+            location
+        )),
+        error_type: TokenisationErrorType::InvalidLongStringDelimiter
+    })
 }
 
+/** Symbols that aren't valid UTF-8 are shown by their byte value, as the
+ *  reference lexer does for unprintable characters. */
 fn get_unexpected_symbol_error(
     location: Location,
-    symbol:   char
+    symbol:   &[u8]
 ) -> TokenisationError<LuaTerminal, TokenisationErrorType> {
+    let symbol = match std::str::from_utf8(symbol) {
+        Ok(symbol) => String::from(symbol),
+        Err(_)     => format!("<\\{}>", symbol[0])
+    };
+
     TokenisationError {
-        partial_token: Token {
-            token_type: LuaTerminal::Error,
-            token_data: Some(LuaTokenData::Error(symbol.to_string())),
-            location:   location
-        },
+        partial_token: Box::new(Token::new(
+            LuaTerminal::Error,
+            Some(LuaTokenData::Error(symbol)),
+            location
+        )),
         error_type:    TokenisationErrorType::UnexpectedSymbol
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::tokenisation::TextEdit;
+    use crate::lua::LuaNumeral;
+
+    #[test]
+    fn recovers_from_every_lexical_error() {
+        let mut tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+        let src = String::from("local x = @ $\ny = 'abc\nz = 1");
+
+        let (tokens, errors) = tokeniser.tokenise_with_recovery(src);
+
+        let error_types: Vec<_> = errors.iter().map(|e| format!("{:?}", e.error_type)).collect();
+        assert_eq!(error_types, vec!["UnexpectedSymbol", "UnexpectedSymbol", "UnfinishedString"]);
+
+        let token_types: Vec<_> = tokens.iter().map(|t| t.token_type).collect();
+        assert_eq!(token_types, vec![
+            LuaTerminal::Local, LuaTerminal::Identifier, LuaTerminal::Equals,
+            LuaTerminal::Error, LuaTerminal::Error,
+            LuaTerminal::Identifier, LuaTerminal::Equals, LuaTerminal::Error,
+            LuaTerminal::Identifier, LuaTerminal::Equals, LuaTerminal::NumberLiteral,
+            LuaTerminal::EndOfFile
+        ]);
+        assert_eq!(tokens[8].location, Location { line: 3, col: 1 });
+    }
+
+    fn recover_at_eof(src: &str) -> (Vec<LuaTerminal>, Vec<String>) {
+        let mut tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+        let (tokens, errors) = tokeniser.tokenise_with_recovery(src);
+
+        (tokens.iter().map(|t| t.token_type).collect(), errors.iter().map(|e| e.message()).collect())
+    }
+
+    #[test]
+    fn unfinished_strings_at_eof_are_error_tokens() {
+        let (token_types, messages) = recover_at_eof("x = 'abc");
+
+        assert_eq!(token_types, vec![
+            LuaTerminal::Identifier, LuaTerminal::Equals, LuaTerminal::Error, LuaTerminal::EndOfFile
+        ]);
+        assert_eq!(messages, vec!["unfinished string near <eof>"]);
+    }
+
+    #[test]
+    fn unfinished_long_strings_at_eof_are_error_tokens() {
+        let (token_types, messages) = recover_at_eof("x = [[abc");
+
+        assert_eq!(token_types, vec![
+            LuaTerminal::Identifier, LuaTerminal::Equals, LuaTerminal::Error, LuaTerminal::EndOfFile
+        ]);
+        assert_eq!(messages, vec!["unfinished long string (starting at line 1) near <eof>"]);
+    }
+
+    #[test]
+    fn unfinished_long_comments_at_eof_are_error_tokens() {
+        let (token_types, messages) = recover_at_eof("x = 1 --[[abc");
+
+        assert_eq!(token_types, vec![
+            LuaTerminal::Identifier, LuaTerminal::Equals, LuaTerminal::NumberLiteral, LuaTerminal::Error, LuaTerminal::EndOfFile
+        ]);
+        assert_eq!(messages, vec!["unfinished long comment (starting at line 1) near <eof>"]);
+    }
+
+    #[test]
+    fn reads_numerals_like_the_reference_lexer() {
+        let mut tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+        let src = String::from("x = .5 + 0x10 .. 3..2 0xg 1e-3");
+
+        let (tokens, errors) = tokeniser.tokenise_with_recovery(src);
+
+        let texts: Vec<_> = tokens.iter().map(|t| String::from_utf8_lossy(&t.text)).collect();
+        assert_eq!(texts, vec!["x", "=", ".5", "+", "0x10", "..", "3..2", "0xg", "1e-3", ""]);
+
+        let messages: Vec<_> = errors.iter().map(|e| e.message()).collect();
+        assert_eq!(messages, vec!["malformed number near '3..2'", "malformed number near '0xg'"]);
+
+        match (&tokens[2].token_data, &tokens[4].token_data) {
+            (Some(LuaTokenData::NumberLiteral(half)), Some(LuaTokenData::NumberLiteral(sixteen))) => {
+                assert_eq!(*half,    LuaNumeral::Float(0.5));
+                assert_eq!(*sixteen, LuaNumeral::Integer(16));
+            },
+            _ => panic!("expected numerals")
+        }
+    }
+
+    fn string_values(src: &str) -> Vec<LuaBytes> {
+        let mut tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+
+        tokeniser.tokenise(String::from(src)).ok().unwrap()
+            .iter()
+            .filter_map(|token| match &token.token_data {
+                Some(LuaTokenData::StringLiteral(value)) => Some(value.clone()),
+                _ => None
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_string_escapes() {
+        let values = string_values(concat!(
+            r#"a = "a\nb" .. "\65\066\0673" .. '\x41\u{48}\u{20AC}' .. "\\" .. "" .. '\'"'"#, "\n",
+            r#"b = "one\z"#, "\n",
+            r#"      two\"#, "\r\n",
+            r#"three""#
+        ));
+
+        let expected: Vec<_> = vec!["a\nb", "ABC3", "AH\u{20AC}", "\\", "", "'\"", "onetwo\nthree"]
+            .into_iter()
+            .map(LuaBytes::from)
+            .collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn accepts_source_that_is_not_utf8() {
+        let mut tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+        let src = b"s = '\xff\\255' .. [[\xfe]] -- \xfd\n\x80".to_vec();
+
+        let (tokens, errors) = tokeniser.tokenise_with_recovery(src.clone());
+
+        let values: Vec<_> = tokens.iter()
+            .filter_map(|token| match &token.token_data {
+                Some(LuaTokenData::StringLiteral(value)) => Some(value.as_bytes().to_vec()),
+                _ => None
+            })
+            .collect();
+        assert_eq!(values, vec![vec![0xff, 0xff], vec![0xfe]]);
+
+        let round_trip: Vec<u8> = tokens.iter().flat_map(|t| t.full_text()).collect();
+        assert_eq!(round_trip, src);
+        assert_eq!(errors[0].message(), "unexpected symbol near '<\\128>'");
+    }
+
+    /** Tokenises `src`, giving the value of its first string literal or the
+     *  message of its first error. */
+    fn first_long_string(src: &str) -> Result<Vec<u8>, String> {
+        let mut tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+        let (tokens, errors) = tokeniser.tokenise_with_recovery(src);
+
+        if let Some(error) = errors.first() {
+            return Err(error.to_string());
+        }
+
+        tokens.iter()
+            .find_map(|token| match &token.token_data {
+                Some(LuaTokenData::StringLiteral(value)) => Some(value.as_bytes().to_vec()),
+                _ => None
+            })
+            .ok_or(String::from("no string"))
+    }
+
+    #[test]
+    fn long_brackets_match_the_reference_lexer() {
+        let corpus: Vec<(&str, Result<&str, &str>)> = vec![
+            ("[[]]",                        Ok("")),
+            ("[[abc]]",                     Ok("abc")),
+            ("[[\nabc]]",                   Ok("abc")),
+            ("[[\r\nabc]]",                 Ok("abc")),
+            ("[[\n\rabc]]",                 Ok("abc")),
+            ("[[\rabc]]",                   Ok("abc")),
+            ("[[\n\nabc]]",                 Ok("\nabc")),
+            ("[[\r\n\r\nabc]]",             Ok("\nabc")),
+            ("[[ \nabc]]",                  Ok(" \nabc")),
+            ("[[a\r\nb\rc\n\rd\ne]]",       Ok("a\nb\nc\nd\ne")),
+            ("[[a\n\n\rb]]",                Ok("a\n\nb")),
+            ("[==[a]]b]=]c]==]",            Ok("a]]b]=]c")),
+            ("[=[a]==]=]",                  Ok("a]==")),
+            ("[=[]]]=]",                    Ok("]]")),
+            ("[[a]]]",                      Ok("a")),
+            ("[[[[nested]]",                Ok("[[nested")),
+            ("x = [[a]] .. [=[b]=]",        Ok("a")),
+            ("[==[ abc ]=]",                Err("1: unfinished long string (starting at line 1) near <eof>")),
+            ("\n\n[[\nabc",                 Err("4: unfinished long string (starting at line 3) near <eof>")),
+            ("--[==[\n]]\n",                Err("3: unfinished long comment (starting at line 1) near <eof>")),
+            ("[=",                          Err("1: invalid long string delimiter near '[='")),
+            ("x = [==x",                    Err("1: invalid long string delimiter near '[=='")),
+            ("--[==[ ]] ]==] [[c]]",        Ok("c")),
+            ("--[=x\n[[d]]",                Ok("d")),
+            ("--[[]] [[e]]",                Ok("e"))
+        ];
+
+        for (src, expected) in corpus {
+            let expected = expected.map(|value| value.as_bytes().to_vec()).map_err(String::from);
+            assert_eq!(first_long_string(src), expected, "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn long_brackets_end_where_they_close() {
+        let mut tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+
+        for src in &["[[\na\nb]] x", "[[\r\na\r\nb]] x", "--[==[\na]]\nb]==] x"] {
+            let tokens = tokeniser.tokenise(*src).ok().unwrap();
+            let x = &tokens[tokens.len() - 2];
+
+            assert_eq!(x.text, b"x");
+            assert_eq!(x.location.line, 3, "{:?}", src);
+            assert_eq!(x.location.col, src.len() - src.rfind('\n').unwrap() - 1, "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn reports_invalid_escapes_where_they_are() {
+        let mut tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+        let src = String::from("x = \"ab\\q\" .. '\\xg' .. \"\\256\"\ny = \"\\u{110000000}\" .. '\\u41' .. \"\\u{41\"\nz = 1");
+
+        let (tokens, errors) = tokeniser.tokenise_with_recovery(src);
+
+        let messages: Vec<_> = errors.iter().map(|e| e.message()).collect();
+        assert_eq!(messages, vec![
+            "invalid escape sequence near '\"ab\\q'",
+            "hexadecimal digit expected near ''\\xg'",
+            "decimal escape too large near '\"\\256\"'",
+            "UTF-8 value too large near '\"\\u{110000000'",
+            "missing '{' in \\u{xxxx} near ''\\u4'",
+            "missing '}' in \\u{xxxx} near '\"\\u{41\"'"
+        ]);
+
+        assert_eq!(errors[0].partial_token.location, Location { line: 1, col: 8 });
+        assert_eq!(errors[0].partial_token.text, b"\\q");
+        assert_eq!(errors[3].partial_token.location, Location { line: 2, col: 6 });
+
+        let token_types: Vec<_> = tokens.iter().rev().take(4).map(|t| t.token_type).collect();
+        assert_eq!(token_types, vec![
+            LuaTerminal::EndOfFile, LuaTerminal::NumberLiteral, LuaTerminal::Equals, LuaTerminal::Identifier
+        ]);
+    }
+
+    fn token_types_in(dialect: LuaDialect, src: &str) -> Vec<LuaTerminal> {
+        let mut tokeniser = get_lua_tokeniser(dialect).unwrap();
+        let (tokens, _) = tokeniser.tokenise_with_recovery(src);
+
+        tokens.iter().map(|t| t.token_type).filter(|t| *t != LuaTerminal::EndOfFile).collect()
+    }
+
+    #[test]
+    fn keywords_and_operators_follow_the_dialect() {
+        use LuaTerminal::*;
+
+        assert_eq!(token_types_in(LuaDialect::Lua51, "goto x"),  vec![Identifier, Identifier]);
+        assert_eq!(token_types_in(LuaDialect::Lua52, "goto x"),  vec![Goto, Identifier]);
+        assert_eq!(token_types_in(LuaDialect::Lua54, "endx _end End end"), vec![Identifier, Identifier, Identifier, End]);
+        assert_eq!(token_types_in(LuaDialect::Lua52, "a // b"),  vec![Identifier, Divide, Divide, Identifier]);
+        assert_eq!(token_types_in(LuaDialect::Lua53, "a // b"),  vec![Identifier, FloorDivide, Identifier]);
+        assert_eq!(token_types_in(LuaDialect::LuaJit, "a ~ b"),  vec![Identifier, Error, Identifier]);
+        assert_eq!(token_types_in(LuaDialect::Lua54, "a ~ b"),   vec![Identifier, BitwiseNeg, Identifier]);
+        assert_eq!(token_types_in(LuaDialect::Lua51, "a ~= b"),  vec![Identifier, NotEq, Identifier]);
+        assert_eq!(token_types_in(LuaDialect::Lua54, "a ~= b"),  vec![Identifier, NotEq, Identifier]);
+        assert_eq!(token_types_in(LuaDialect::Lua54, "continue"), vec![Identifier]);
+        assert_eq!(token_types_in(LuaDialect::Luau, "continue"),  vec![Identifier]);
+        assert_eq!(token_types_in(LuaDialect::Luau, "s ..= 'x' n //= 2 m -= 1"), vec![
+            Identifier, ConcatEquals, StringLiteral,
+            Identifier, FloorDivideEquals, NumberLiteral,
+            Identifier, MinusEquals, NumberLiteral
+        ]);
+        assert_eq!(token_types_in(LuaDialect::Lua54, "n -= 1"),  vec![Identifier, Minus, Equals, NumberLiteral]);
+
+        let mut luajit = get_lua_tokeniser(LuaDialect::LuaJit).unwrap();
+        let tokens = luajit.tokenise("x = 0x10ULL + 2i").ok().unwrap();
+        match (&tokens[2].token_data, &tokens[4].token_data) {
+            (Some(LuaTokenData::NumberLiteral(long)), Some(LuaTokenData::NumberLiteral(imaginary))) => {
+                assert_eq!(*long,      LuaNumeral::UnsignedLong(16));
+                assert_eq!(*imaginary, LuaNumeral::Imaginary(2.0));
+            },
+            _ => panic!("expected numerals")
+        }
+
+        let mut lua54 = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+        let (_, errors) = lua54.tokenise_with_recovery("x = 0x10ULL");
+        assert_eq!(errors[0].message(), "malformed number near '0x10U'");
+    }
+
+    #[test]
+    fn files_skip_a_byte_order_mark_and_shebang() {
+        let src = "\u{FEFF}#!/usr/bin/env lua\nprint(#arg)\n";
+
+        let mut file_tokeniser = get_lua_file_tokeniser(LuaDialect::Lua54).unwrap();
+        let tokens = file_tokeniser.tokenise(src).ok().unwrap();
+
+        let print = &tokens[0];
+        let leading_kinds: Vec<_> = print.leading_trivia.iter().map(|t| t.kind).collect();
+        assert_eq!(leading_kinds, vec![TriviaKind::ByteOrderMark, TriviaKind::Shebang, TriviaKind::Newline]);
+        assert_eq!(print.location, Location { line: 2, col: 1 });
+        assert_eq!(tokens[2].token_type, LuaTerminal::Length);
+
+        let round_trip: Vec<u8> = tokens.iter().flat_map(|t| t.full_text()).collect();
+        assert_eq!(round_trip, src.as_bytes());
+
+        let mut string_tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+        let (_, errors) = string_tokeniser.tokenise_with_recovery(src);
+        assert_eq!(errors[0].message(), "unexpected symbol near '\u{FEFF}'");
+    }
+
+    #[test]
+    fn every_newline_sequence_is_one_line_break() {
+        let mut tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+        let src = "a\nb\rc\r\nd\n\re\r\rf\n\n\r\rg -- h\ri [[\r\nj\n\rk\r\r\nl]] m";
+
+        let tokens = tokeniser.tokenise(src).ok().unwrap();
+        let lines: Vec<_> = tokens.iter()
+            .filter(|t| t.token_type == LuaTerminal::Identifier)
+            .map(|t| t.location.line)
+            .collect();
+        assert_eq!(lines, vec![1, 2, 3, 4, 5, 7, 10, 11, 15]);
+
+        let newline_texts: Vec<_> = tokens.iter()
+            .flat_map(|t| t.leading_trivia.iter().chain(t.trailing_trivia.iter()))
+            .filter(|trivia| trivia.kind == TriviaKind::Newline)
+            .map(|trivia| trivia.text.clone())
+            .collect();
+        assert_eq!(newline_texts[..6].to_vec(), vec![
+            b"\n".to_vec(), b"\r".to_vec(), b"\r\n".to_vec(), b"\n\r".to_vec(), b"\r".to_vec(), b"\r".to_vec()
+        ]);
+
+        let long_string = tokens.iter().find(|t| t.token_type == LuaTerminal::StringLiteral).unwrap();
+        match &long_string.token_data {
+            Some(LuaTokenData::StringLiteral(value)) => assert_eq!(value.as_bytes(), b"j\nk\n\nl"),
+            _ => panic!("expected a string")
+        }
+
+        let round_trip: Vec<u8> = tokens.iter().flat_map(|t| t.full_text()).collect();
+        assert_eq!(round_trip, src.as_bytes());
+    }
+
+    #[test]
+    fn trivia_reproduces_the_source() {
+        let mut tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+        let src = "-- header\r\n\tlocal x = 1 -- one\n\n--[[ long\ncomment ]] print ( x )  \n  ";
+
+        let tokens = tokeniser.tokenise(String::from(src)).ok().unwrap();
+        let round_trip: Vec<u8> = tokens.iter().flat_map(|t| t.full_text()).collect();
+        assert_eq!(round_trip, src.as_bytes());
+
+        let token_types: Vec<_> = tokens.iter().map(|t| t.token_type).collect();
+        assert!(!token_types.contains(&LuaTerminal::Comment));
+
+        let local = &tokens[0];
+        let leading_kinds: Vec<_> = local.leading_trivia.iter().map(|t| t.kind).collect();
+        assert_eq!(leading_kinds, vec![TriviaKind::LineComment, TriviaKind::Newline, TriviaKind::Whitespace]);
+
+        let one = &tokens[3];
+        let trailing_kinds: Vec<_> = one.trailing_trivia.iter().map(|t| t.kind).collect();
+        assert_eq!(one.text, b"1");
+        assert_eq!(trailing_kinds, vec![TriviaKind::Whitespace, TriviaKind::LineComment, TriviaKind::Newline]);
+
+        let print = &tokens[4];
+        assert_eq!(print.leading_trivia[1].kind, TriviaKind::BlockComment);
+        assert_eq!(print.location, Location { line: 5, col: 12 });
+
+        let eof = tokens.back().unwrap();
+        assert_eq!(eof.token_type, LuaTerminal::EndOfFile);
+        assert_eq!(eof.leading_trivia.len(), 1);
+    }
+
+    fn assert_same_tokens(actual: &VecDeque<Token<LuaTerminal>>, expected: &VecDeque<Token<LuaTerminal>>) {
+        let describe = |tokens: &VecDeque<Token<LuaTerminal>>| -> Vec<String> {
+            tokens.iter()
+                .map(|t| format!("{} {:?} {:?} {:?}", t, t.full_text(), t.start_location(), t.lexer_modes))
+                .collect()
+        };
+
+        assert_eq!(describe(actual), describe(expected));
+    }
+
+    #[test]
+    fn retokenising_matches_tokenising_from_scratch() {
+        let mut tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+        let src = "local a = 1\nlocal b = a + 2 -- two]]\nprint(a, b)\n-- ]==]\nreturn b\n";
+
+        let edits = vec![
+            (14..19, "local function"),
+            (10..11, "100"),
+            (26..27, "--"),
+            (12..12, "x = [["),
+            (12..12, "--[==[ "),
+            (0..0,   "--[[ ]]"),
+            (44..53, "")
+        ];
+
+        for (range, replacement) in edits {
+            let mut tokens = tokeniser.tokenise(String::from(src)).ok().unwrap();
+            let edit = TextEdit { range: range.clone(), replacement: String::from(replacement) };
+
+            let mut expected_src = String::from(src);
+            expected_src.replace_range(range, replacement);
+            let expected = tokeniser.tokenise(expected_src).ok().unwrap();
+
+            tokeniser.retokenise(&mut tokens, &edit).ok().unwrap();
+            assert_same_tokens(&tokens, &expected);
+        }
+    }
+
+    #[test]
+    fn retokenising_stops_once_resynchronised() {
+        let mut tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+        let src = "a = 1\nb = 2\nc = 3\nd = 4\n";
+        let mut tokens = tokeniser.tokenise(String::from(src)).ok().unwrap();
+
+        let edit = TextEdit { range: 10..11, replacement: String::from("20") };
+        let retokenised = tokeniser.retokenise(&mut tokens, &edit).ok().unwrap();
+
+        assert_eq!(retokenised, 4..6);
+        assert_eq!(tokens[9].location, Location { line: 4, col: 1 });
+        assert_eq!(tokens[10].location, Location { line: 4, col: 3 });
+    }
+}
@@ -0,0 +1,67 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::Deref;
+use std::rc::Rc;
+
+/**
+ * A Lua string, which is an immutable sequence of arbitrary bytes rather than
+ * text in any particular encoding. The bytes are shared, so cloning is cheap.
+ */
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LuaBytes(Rc<[u8]>);
+
+impl LuaBytes {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /** The string as text, with any invalid UTF-8 replaced. */
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.0).into_owned()
+    }
+}
+
+impl Deref for LuaBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<&[u8]> for LuaBytes {
+    fn from(bytes: &[u8]) -> LuaBytes {
+        LuaBytes(Rc::from(bytes))
+    }
+}
+
+impl From<Vec<u8>> for LuaBytes {
+    fn from(bytes: Vec<u8>) -> LuaBytes {
+        LuaBytes(Rc::from(bytes))
+    }
+}
+
+impl From<&str> for LuaBytes {
+    fn from(text: &str) -> LuaBytes {
+        LuaBytes::from(text.as_bytes())
+    }
+}
+
+impl Display for LuaBytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+/** Shows the bytes the way a byte string literal would, so that bytes which
+ *  aren't printable can still be told apart. */
+impl Debug for LuaBytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "b\"")?;
+
+        for byte in self.0.iter() {
+            write!(f, "{}", std::ascii::escape_default(*byte))?;
+        }
+
+        write!(f, "\"")
+    }
+}
@@ -1,6 +1,6 @@
-use super::super::syntax_tree::LuaFunctionDef;
+use super::super::syntax_tree::LuaFuncBody;
 
 pub enum LuaFunction {
     Native(),
-    Embedded(LuaFunctionDef)
+    Embedded(LuaFuncBody)
 }
@@ -1,7 +1,9 @@
-mod types;
+mod bytes;
+mod value;
 mod function;
 mod table;
 
-pub use types::*;
+pub use bytes::*;
+pub use value::*;
 pub use function::*;
 pub use table::*;
@@ -1,11 +1,12 @@
 use super::table::LuaTable;
 use super::function::LuaFunction;
+use super::bytes::LuaBytes;
 
 pub enum LuaValue {
     Nil,
     Boolean(bool),
     Number(LuaNumber),
-    LuaString(String),
+    LuaString(LuaBytes),
     Function(Box<LuaFunction>),
     Userdata,
     Thread,
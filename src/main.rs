@@ -1,25 +1,379 @@
+use std::env;
 use std::fs;
-use phobos::{get_lua_tokeniser, get_lua_parser};
+use std::io::{self, IsTerminal, Read, Write};
+use std::process;
+use phobos::{get_lua_file_tokeniser, DiagnosticRenderer, LuaDialect, LuaFormatter, LuaQuoteStyle, LuaCallParens};
+use phobos::{LuaLinter, LuaLintRule, read_lua_chunk, print_lua_chunk, fold_lua_constants, eliminate_lua_dead_code};
+
+const USAGE: &str = "\
+usage: phobos <command> [options] [files...]
+
+commands:
+    fmt       format files in place, or standard input to standard output
+    lint      check files, or standard input, for common mistakes
+    optimise  print a file, or standard input, with constants folded and dead
+              code removed
+    tokens    print the tokens of a file
+
+fmt options:
+    --check                 don't write anything, fail if a file isn't formatted
+    --range <first>:<last>  only format the statements on these lines
+    --indent <width>        indent with this many spaces (4)
+    --tabs                  indent with tabs
+    --line-width <width>    break lines longer than this (100)
+    --quote <style>         double, single or keep (double)
+    --call-parens <style>   keep, always or omit (keep)
+
+lint options:
+    --allow-global <name>   a global the host program defines
+    --disable <rule>        don't check for this
+    --enable <rule>         check for this after all
+    --rules                 list the rules
+
+optimise options:
+    --report                print what was changed to standard error
+";
 
 fn main() {
-    let contents = fs::read_to_string("input/test.lua")
-        .expect("Something bad happened");
-
-    let tokeniser = get_lua_tokeniser();
-    if tokeniser.is_some() {
-        let mut t = tokeniser.unwrap();
-        
-        let tokens = t.tokenise(contents);
-
-        if tokens.is_err() {
-            panic!("{}", tokens.err().unwrap());
-        } else {
-            for token in tokens.ok().unwrap() {
-                println!("{}", token);
-            };
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let code = match args.first().map(String::as_str) {
+        Some("fmt")      => fmt(&args[1..]),
+        Some("lint")     => lint(&args[1..]),
+        Some("optimise") => optimise(&args[1..]),
+        Some("tokens")   => tokens(&args[1..]),
+        _                => usage()
+    };
+
+    process::exit(code);
+}
+
+fn usage() -> i32 {
+    eprint!("{}", USAGE);
+    2
+}
+
+fn fail(message: &str) -> i32 {
+    eprintln!("phobos: {}", message);
+    2
+}
+
+struct FmtOptions {
+    formatter: LuaFormatter,
+    check:     bool,
+    range:     Option<(usize, usize)>,
+    files:     Vec<String>
+}
+
+fn parse_fmt_options(args: &[String]) -> Result<FmtOptions, String> {
+    let mut formatter = LuaFormatter::new(LuaDialect::Lua54);
+    let mut check = false;
+    let mut range = None;
+    let mut files = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().cloned().ok_or(format!("{} needs a value", name));
+
+        match arg.as_str() {
+            "--check" => check = true,
+            "--tabs"  => formatter = formatter.with_tabs(true),
+            "--indent" => {
+                let width = value("--indent")?;
+                formatter = formatter.with_indent_width(parse_number(&width)?);
+            },
+            "--line-width" => {
+                let width = value("--line-width")?;
+                formatter = formatter.with_line_width(parse_number(&width)?);
+            },
+            "--range" => {
+                let lines = value("--range")?;
+                let mut parts = lines.splitn(2, ':');
+                let first = parse_number(parts.next().unwrap_or(""))?;
+                let last = parse_number(parts.next().unwrap_or(""))?;
+
+                if first == 0 || last < first {
+                    return Err(format!("bad range '{}'", lines));
+                }
+
+                range = Some((first, last));
+            },
+            "--quote" => {
+                formatter = formatter.with_quote_style(match value("--quote")?.as_str() {
+                    "double" => LuaQuoteStyle::Double,
+                    "single" => LuaQuoteStyle::Single,
+                    "keep"   => LuaQuoteStyle::Keep,
+                    other    => return Err(format!("unknown quote style '{}'", other))
+                });
+            },
+            "--call-parens" => {
+                formatter = formatter.with_call_parens(match value("--call-parens")?.as_str() {
+                    "keep"   => LuaCallParens::Keep,
+                    "always" => LuaCallParens::Always,
+                    "omit"   => LuaCallParens::Omit,
+                    other    => return Err(format!("unknown call parens style '{}'", other))
+                });
+            },
+            option if option.starts_with("--") => return Err(format!("unknown option '{}'", option)),
+            file => files.push(String::from(file))
+        }
+    }
+
+    Ok(FmtOptions {
+        formatter,
+        check,
+        range,
+        files
+    })
+}
+
+fn parse_number(text: &str) -> Result<usize, String> {
+    text.parse().map_err(|_| format!("'{}' isn't a number", text))
+}
+
+/** Formats some source, reporting why if it doesn't read. */
+fn format_source(options: &FmtOptions, source: &[u8], chunk_name: &str) -> Option<String> {
+    let result = match options.range {
+        Some((first, last)) => options.formatter.format_range(source, first, last),
+        None                => options.formatter.format(source)
+    };
+
+    match result {
+        Ok(formatted) => Some(formatted),
+        Err(error)    => {
+            let renderer = DiagnosticRenderer::new()
+                .with_chunk_name(chunk_name);
+
+            eprint!("{}", renderer.render(&error, source));
+            None
+        }
+    }
+}
+
+fn fmt(args: &[String]) -> i32 {
+    let options = match parse_fmt_options(args) {
+        Ok(options)  => options,
+        Err(message) => return fail(&message)
+    };
+
+    if options.files.is_empty() {
+        let mut source = Vec::new();
+
+        if let Err(error) = io::stdin().read_to_end(&mut source) {
+            return fail(&error.to_string());
+        }
+
+        let formatted = match format_source(&options, &source, "stdin") {
+            Some(formatted) => formatted,
+            None            => return 1
+        };
+
+        if options.check {
+            return if formatted.as_bytes() == source.as_slice() { 0 } else { 1 };
+        }
+
+        return match io::stdout().write_all(formatted.as_bytes()) {
+            Ok(())     => 0,
+            Err(error) => fail(&error.to_string())
+        };
+    }
+
+    let mut code = 0;
+
+    for file in &options.files {
+        let source = match fs::read(file) {
+            Ok(source) => source,
+            Err(error) => {
+                code = code.max(fail(&format!("{}: {}", file, error)));
+                continue;
+            }
+        };
+
+        let formatted = match format_source(&options, &source, file) {
+            Some(formatted) => formatted,
+            None            => {
+                code = code.max(1);
+                continue;
+            }
+        };
+
+        if formatted.as_bytes() == source.as_slice() {
+            continue;
+        }
+
+        if options.check {
+            println!("would reformat {}", file);
+            code = code.max(1);
+        } else if let Err(error) = fs::write(file, formatted) {
+            code = code.max(fail(&format!("{}: {}", file, error)));
+        }
+    }
+
+    code
+}
+
+fn lint(args: &[String]) -> i32 {
+    let mut linter = LuaLinter::new(LuaDialect::Lua54);
+    let mut files = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().cloned().ok_or(format!("{} needs a value", name));
+
+        let result = match arg.as_str() {
+            "--rules" => {
+                LuaLintRule::ALL.iter().for_each(|rule| println!("{}", rule.name()));
+                return 0;
+            },
+            "--allow-global" => value("--allow-global").map(|name| linter.with_allowed_global(&name)),
+            "--disable" | "--enable" => value(arg).and_then(|name| match LuaLintRule::from_name(&name) {
+                Some(rule) => Ok(linter.with_rule(rule, arg == "--enable")),
+                None       => Err(format!("unknown rule '{}'", name))
+            }),
+            option if option.starts_with("--") => Err(format!("unknown option '{}'", option)),
+            file => {
+                files.push(String::from(file));
+                Ok(linter)
+            }
+        };
+
+        linter = match result {
+            Ok(linter)   => linter,
+            Err(message) => return fail(&message)
+        };
+    }
+
+    let sources = match files.is_empty() {
+        true => {
+            let mut source = Vec::new();
+
+            if let Err(error) = io::stdin().read_to_end(&mut source) {
+                return fail(&error.to_string());
+            }
+
+            vec![(String::from("stdin"), Ok(source))]
+        },
+        false => files.into_iter().map(|file| {
+            let source = fs::read(&file);
+            (file, source)
+        }).collect()
+    };
+
+    let mut code = 0;
+
+    for (name, source) in sources {
+        let source = match source {
+            Ok(source) => source,
+            Err(error) => {
+                code = code.max(fail(&format!("{}: {}", name, error)));
+                continue;
+            }
+        };
+
+        match linter.lint(&source) {
+            Ok(lints) => {
+                for lint in &lints {
+                    println!("{}:{}:{}: {} [{}]", name, lint.span.start.line, lint.span.start.col, lint.message, lint.rule.name());
+                }
+
+                if !lints.is_empty() {
+                    code = code.max(1);
+                }
+            },
+            Err(error) => {
+                let renderer = DiagnosticRenderer::new()
+                    .with_chunk_name(&name);
+
+                eprint!("{}", renderer.render(&error, &source));
+                code = code.max(1);
+            }
+        }
+    }
+
+    code
+}
+
+fn optimise(args: &[String]) -> i32 {
+    let (report, file) = match args {
+        [flag, file] if flag == "--report" => (true, Some(file)),
+        [flag] if flag == "--report"       => (true, None),
+        [file] if !file.starts_with("--")  => (false, Some(file)),
+        []                                 => (false, None),
+        _                                  => return usage()
+    };
+
+    let (name, source) = match file {
+        Some(file) => match fs::read(file) {
+            Ok(source) => (file.as_str(), source),
+            Err(error) => return fail(&format!("{}: {}", file, error))
+        },
+        None => {
+            let mut source = Vec::new();
+
+            if let Err(error) = io::stdin().read_to_end(&mut source) {
+                return fail(&error.to_string());
+            }
+
+            ("stdin", source)
+        }
+    };
+
+    let mut chunk = match read_lua_chunk(source.clone(), LuaDialect::Lua54, true) {
+        Ok(chunk)  => chunk,
+        Err(error) => {
+            let renderer = DiagnosticRenderer::new()
+                .with_chunk_name(name);
+
+            eprint!("{}", renderer.render(&error, &source));
+            return 1;
+        }
+    };
+
+    let mut changes = fold_lua_constants(&mut chunk, LuaDialect::Lua54);
+    changes.extend(eliminate_lua_dead_code(&mut chunk));
+
+    if report {
+        for change in &changes {
+            eprintln!("{}:{}:{}: {}", name, change.span.start.line, change.span.start.col, change.message);
         }
     }
 
-    get_lua_parser();
+    match io::stdout().write_all(print_lua_chunk(&chunk).as_bytes()) {
+        Ok(())     => 0,
+        Err(error) => fail(&error.to_string())
+    }
 }
 
+fn tokens(args: &[String]) -> i32 {
+    let file = match args {
+        [file] => file,
+        _      => return usage()
+    };
+
+    let contents = match fs::read(file) {
+        Ok(contents) => contents,
+        Err(error)   => return fail(&format!("{}: {}", file, error))
+    };
+
+    let mut tokeniser = get_lua_file_tokeniser(LuaDialect::Lua54).unwrap();
+
+    match tokeniser.tokenise(contents.clone()) {
+        Ok(tokens) => {
+            for token in tokens {
+                println!("{}", token);
+            }
+
+            0
+        },
+        Err(error) => {
+            // Colour only helps someone reading the error, not a file or a
+            // pipe it's been sent to.
+            let renderer = DiagnosticRenderer::new()
+                .with_chunk_name(file)
+                .with_colour(io::stderr().is_terminal());
+
+            eprint!("{}", renderer.render(&error, &contents));
+            1
+        }
+    }
+}
@@ -1,3 +1,9 @@
+/*!
+ * The LR parser generator. Nothing drives a parser built from it yet, so the
+ * Lua syntax tree reader does the parsing in the meantime.
+ */
+#![allow(dead_code)]
+
 mod symbol;
 mod symbol_sequence;
 mod production;
@@ -8,7 +14,7 @@ mod parser_builder;
 
 pub use symbol::{Symbol, PossiblyEmptyTerminalSymbol, PossiblyEndOfFileTerminalSymbol};
 pub use symbol_sequence::SymbolSequence;
-pub use production::Production;
+pub use production::{Production, ReduceHandler};
 pub use production_builder::ProductionBuilder;
 pub use terminal_symbol::TerminalSymbol;
 pub use nonterminal_symbol::NonterminalSymbol;
@@ -10,7 +10,7 @@ use crate::parsing::{ParserBuilder, TerminalSymbol, NonterminalSymbol, SymbolSeq
  * 
  * First Sets are used in the derivation of Follow Sets.
  */
-impl<'t, T, N> ParserBuilder<'t, T, N> where T: TerminalSymbol, N: NonterminalSymbol {
+impl<T, N> ParserBuilder<T, N> where T: TerminalSymbol, N: NonterminalSymbol {
     
     /**
      * Derives the First Sets for all nonterminal symbols and stores them in the
@@ -29,11 +29,11 @@ impl<'t, T, N> ParserBuilder<'t, T, N> where T: TerminalSymbol, N: NonterminalSy
     /**
      * Calculates the First Set for any given sequence of symbols.
      */
-    pub(super) fn first_set(&self, seq: SymbolSequence<T, N>) -> HashSet<PossiblyEmptyTerminalSymbol<T>> {
+    pub(super) fn first_set(&self, seq: &SymbolSequence<T, N>) -> HashSet<PossiblyEmptyTerminalSymbol<T>> {
         let mut first_set = HashSet::new();
-        self._first_set(&seq, &mut first_set);
+        self._first_set(seq, &mut first_set);
 
-        return first_set;
+        first_set
     }
 
     fn _first_set(&self,
@@ -55,7 +55,7 @@ impl<'t, T, N> ParserBuilder<'t, T, N> where T: TerminalSymbol, N: NonterminalSy
     ) {
         match sym {
             Symbol::Terminal(t)    => { first_set.insert(PossiblyEmptyTerminalSymbol::Terminal(*t)); },
-            Symbol::Nonterminal(n) => { first_set.extend(self.first_sets[&n]) },
+            Symbol::Nonterminal(n) => { first_set.extend(self.first_sets[n].iter().cloned()) },
             Symbol::Empty          => { first_set.insert(PossiblyEmptyTerminalSymbol::Empty); }
         }
     }
@@ -77,21 +77,21 @@ impl<'t, T, N> ParserBuilder<'t, T, N> where T: TerminalSymbol, N: NonterminalSy
         }
     }
 
-    fn get_first_set_for_optional(&mut self,
+    fn get_first_set_for_optional(&self,
         seq:       &SymbolSequence<T, N>,
         first_set: &mut HashSet<PossiblyEmptyTerminalSymbol<T>>
     ) {
         self.get_repeated_optional_thunk(seq, first_set);
     }
 
-    fn get_first_set_for_repeated(&mut self,
+    fn get_first_set_for_repeated(&self,
         seq:       &SymbolSequence<T, N>,
         first_set: &mut HashSet<PossiblyEmptyTerminalSymbol<T>>
     ) {
         self.get_repeated_optional_thunk(seq, first_set);
     }
 
-    fn get_first_set_for_alternatives(&mut self,
+    fn get_first_set_for_alternatives(&self,
         alts:      &Vec<SymbolSequence<T, N>>,
         first_set: &mut HashSet<PossiblyEmptyTerminalSymbol<T>>
     ) {
@@ -100,7 +100,7 @@ impl<'t, T, N> ParserBuilder<'t, T, N> where T: TerminalSymbol, N: NonterminalSy
         }
     }
 
-    fn get_repeated_optional_thunk(&mut self,
+    fn get_repeated_optional_thunk(&self,
         seq:       &SymbolSequence<T, N>,
         first_set: &mut HashSet<PossiblyEmptyTerminalSymbol<T>>
     ) {
@@ -128,7 +128,7 @@ impl<'t, T, N> ParserBuilder<'t, T, N> where T: TerminalSymbol, N: NonterminalSy
             self.derive_first_set_for_symbol_sequence(&p, &mut first_set, break_set);
         }
 
-        return first_set;
+        first_set
     }
 
     /**
@@ -163,7 +163,7 @@ impl<'t, T, N> ParserBuilder<'t, T, N> where T: TerminalSymbol, N: NonterminalSy
     ) {
         for s in seq {
             let mut first_set_of_s = HashSet::new();
-            self.derive_first_set_for_symbol_sequence(&s, &mut first_set_of_s, break_set);
+            self.derive_first_set_for_symbol_sequence(s, &mut first_set_of_s, break_set);
 
             let reduces_to_empty = first_set_of_s.contains(&PossiblyEmptyTerminalSymbol::Empty);
             first_set.extend(first_set_of_s);
@@ -261,10 +261,10 @@ impl<'t, T, N> ParserBuilder<'t, T, N> where T: TerminalSymbol, N: NonterminalSy
      * to the top, used to derive or get First Sets for nonterminals that derive another nonterminal.
      */
     fn get_first_set_for(&mut self, non_terminal: &N, break_set: &mut Vec<N>) -> HashSet<PossiblyEmptyTerminalSymbol<T>> {
-        if self.first_sets.contains_key(&non_terminal) {
-            return self.first_sets.get(&non_terminal).unwrap().clone();
+        if self.first_sets.contains_key(non_terminal) {
+            self.first_sets.get(non_terminal).unwrap().clone()
         } else {
-            return self._derive_first_set(*non_terminal, break_set);
+            self._derive_first_set(*non_terminal, break_set)
         }
     }
 }
@@ -5,15 +5,15 @@ use crate::parsing::{PossiblyEmptyTerminalSymbol, PossiblyEndOfFileTerminalSymbo
 mod first_set;
 mod follow_set;
 
-pub struct ParserBuilder<'t, T, N> where T: TerminalSymbol, N: NonterminalSymbol {
-    productions:  Vec<Production<'t, T, N>>,
+pub struct ParserBuilder<T, N> where T: TerminalSymbol, N: NonterminalSymbol {
+    productions:  Vec<Production<T, N>>,
     first_sets:   HashMap<N, HashSet<PossiblyEmptyTerminalSymbol<T>>>,
     follow_sets:  HashMap<N, HashSet<PossiblyEndOfFileTerminalSymbol<T>>>,
     start_symbol: Option<N>,
 }
 
-impl<'t, T, U> ParserBuilder<'t, T, U> where T: TerminalSymbol, U: NonterminalSymbol {
-    pub fn new() -> ParserBuilder<'t, T, U> {
+impl<T, U> ParserBuilder<T, U> where T: TerminalSymbol, U: NonterminalSymbol {
+    pub fn new() -> ParserBuilder<T, U> {
         ParserBuilder {
             productions:  Vec::new(),
             first_sets:   HashMap::new(),
@@ -23,30 +23,33 @@ impl<'t, T, U> ParserBuilder<'t, T, U> where T: TerminalSymbol, U: NonterminalSy
     }
 
     pub fn with_production(mut self,
-        production: Production<'t, T, U>
-    ) -> ParserBuilder<'t, T, U> {
+        production: Production<T, U>
+    ) -> ParserBuilder<T, U> {
 
         self.productions.push(production);
-        return self;
+        self
     }
 
     pub fn with_productions(mut self,
-        productions: &mut Vec<Production<'t, T, U>>
-    ) -> ParserBuilder<'t, T, U> {
+        productions: &mut Vec<Production<T, U>>
+    ) -> ParserBuilder<T, U> {
         self.productions.append(productions);
-        return self;
+        self
     }
 
     pub fn with_start_symbol(mut self,
         start_symbol: U
-    ) -> ParserBuilder<'t, T, U> {
+    ) -> ParserBuilder<T, U> {
         self.start_symbol = Some(start_symbol);
-        return self;
+        self
     }
 
     pub fn build(mut self) {
         self.derive_first_sets();
-        self.derive_follow_sets();
+
+        if let Some(start_symbol) = self.start_symbol {
+            self.derive_follow_sets(start_symbol);
+        }
 
         for (k, v) in self.first_sets {
             println!("First set of {}", k);
@@ -65,6 +68,6 @@ impl<'t, T, U> ParserBuilder<'t, T, U> where T: TerminalSymbol, U: NonterminalSy
             }
         }
 
-        return false;
+        false
     }
 }
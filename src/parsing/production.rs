@@ -3,22 +3,26 @@ use std::fmt::Display;
 
 use crate::parsing::{Symbol, SymbolSequence, ProductionBuilder, TerminalSymbol, NonterminalSymbol};
 
-pub struct Production<'t, T, U> where T: TerminalSymbol, U: NonterminalSymbol {
+/** Builds the nonterminal a production produces from the symbols it
+ *  consumed. */
+pub type ReduceHandler<T, U> = Box<dyn FnMut(Vec<Symbol<T, U>>) -> U>;
+
+pub struct Production<T, U> where T: TerminalSymbol, U: NonterminalSymbol {
     pub produced_symbol:  U,
     pub consumed_symbols: SymbolSequence<T, U>,
-    pub reduce_handler:   &'t dyn FnMut(Vec<Symbol<T, U>>) -> U
+    pub reduce_handler:   ReduceHandler<T, U>
 }
 
-impl<'t, T, U> Production<'t, T, U> where T: TerminalSymbol, U: NonterminalSymbol {
-    pub fn builder() -> ProductionBuilder<'t, T, U> {
+impl<T, U> Production<T, U> where T: TerminalSymbol, U: NonterminalSymbol {
+    pub fn builder() -> ProductionBuilder<T, U> {
         ProductionBuilder::new()
     }
 
     pub fn new(
         produced_symbol:  U,
         consumed_symbols: SymbolSequence<T, U>,
-        reduce_handler:   &'t dyn FnMut(Vec<Symbol<T, U>>) -> U
-    ) -> Production<'t, T, U> {
+        reduce_handler:   ReduceHandler<T, U>
+    ) -> Production<T, U> {
         Production {
             produced_symbol,
             consumed_symbols,
@@ -31,7 +35,7 @@ impl<'t, T, U> Production<'t, T, U> where T: TerminalSymbol, U: NonterminalSymbo
     }
 }
 
-impl<'t, T, U> Display for Production<'t, T, U> where T: TerminalSymbol, U: NonterminalSymbol {
+impl<T, U> Display for Production<T, U> where T: TerminalSymbol, U: NonterminalSymbol {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         write!(f, "{} ::= {}", self.produced_symbol, self.consumed_symbols)
     }
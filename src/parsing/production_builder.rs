@@ -1,13 +1,13 @@
-use crate::parsing::{Symbol, SymbolSequence, Production, TerminalSymbol, NonterminalSymbol};
+use crate::parsing::{Symbol, SymbolSequence, Production, ReduceHandler, TerminalSymbol, NonterminalSymbol};
 
-pub struct ProductionBuilder<'t, T, U> where T: TerminalSymbol, U: NonterminalSymbol {
+pub struct ProductionBuilder<T, U> where T: TerminalSymbol, U: NonterminalSymbol {
     produced_symbol:  Option<U>,
     consumed_symbols: Option<SymbolSequence<T, U>>,
-    reduce_handler:   Option<&'t dyn FnMut(Vec<Symbol<T, U>>) -> U>
+    reduce_handler:   Option<ReduceHandler<T, U>>
 }
 
-impl<'t, T, U> ProductionBuilder<'t, T, U> where T: TerminalSymbol, U: NonterminalSymbol {
-    pub fn new() -> ProductionBuilder<'t, T, U> {
+impl<T, U> ProductionBuilder<T, U> where T: TerminalSymbol, U: NonterminalSymbol {
+    pub fn new() -> ProductionBuilder<T, U> {
         ProductionBuilder {
             produced_symbol:  None,
             consumed_symbols: None,
@@ -17,37 +17,33 @@ impl<'t, T, U> ProductionBuilder<'t, T, U> where T: TerminalSymbol, U: Nontermin
     
     pub fn producing(mut self,
         produced_symbol: U
-    ) -> ProductionBuilder<'t, T, U> {
+    ) -> ProductionBuilder<T, U> {
         self.produced_symbol = Some(produced_symbol);
-        return self;
+        self
     }
 
     pub fn from(mut self,
         symbol_seq: SymbolSequence<T, U>
-    ) -> ProductionBuilder<'t, T, U> {
+    ) -> ProductionBuilder<T, U> {
         self.consumed_symbols = Some(symbol_seq);
-        return self;
+        self
     }
 
-    pub fn with_handler(mut self,
-        handler: &'t dyn FnMut(Vec<Symbol<T, U>>) -> U
-    ) -> ProductionBuilder<'t, T, U> {
-        self.reduce_handler = Some(handler);
-        return self;
+    pub fn with_handler<F>(mut self,
+        handler: F
+    ) -> ProductionBuilder<T, U> where F: FnMut(Vec<Symbol<T, U>>) -> U + 'static {
+        self.reduce_handler = Some(Box::new(handler));
+        self
     }
 
-    pub fn build(self) -> Option<Production<'t, T, U>> {
-        if 
-            self.produced_symbol.is_some() &&
-            self.consumed_symbols.is_some() &&
-            self.reduce_handler.is_some() {
-            Some(Production::new(
-                self.produced_symbol.unwrap(),
-                self.consumed_symbols.unwrap(),
-                self.reduce_handler.unwrap()
-            ))
-        } else {
-            None
+    pub fn build(self) -> Option<Production<T, U>> {
+        match (self.produced_symbol, self.consumed_symbols, self.reduce_handler) {
+            (Some(produced_symbol), Some(consumed_symbols), Some(reduce_handler)) => Some(Production::new(
+                produced_symbol,
+                consumed_symbols,
+                reduce_handler
+            )),
+            _ => None
         }
     }
 }
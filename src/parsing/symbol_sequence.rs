@@ -97,7 +97,7 @@ impl<T, U> SymbolSequence<T, U> where T: TerminalSymbol, U: NonterminalSymbol {
             }
         }
 
-        return symbols;
+        symbols
     }
 }
 
@@ -113,14 +113,11 @@ impl<T, U> Display for SymbolSequence<T, U> where T: TerminalSymbol, U: Nontermi
             SymbolSequence::Single(s)   => write!(f, "{}", s),
             SymbolSequence::Sequence(v) => {
                 let mut output = "".to_string();
-                let mut i = 1;
-                for symbol in v {
+                for (i, symbol) in v.iter().enumerate() {
                     write!(output, "{}", symbol).unwrap();
-                    if i != v.len() {
+                    if i + 1 != v.len() {
                         write!(output, " ").unwrap();
                     }
-
-                    i += 1;
                 }
 
                 write!(f, "{}", output)
@@ -129,14 +126,11 @@ impl<T, U> Display for SymbolSequence<T, U> where T: TerminalSymbol, U: Nontermi
             SymbolSequence::Repeated(s) => write!(f, "{{ {} }}", s),
             SymbolSequence::Alternatives(v) => {
                 let mut output = "".to_string();
-                let mut i = 1;
-                for symbol in v {
+                for (i, symbol) in v.iter().enumerate() {
                     write!(output, "{}", symbol).unwrap();
-                    if i != v.len() {
+                    if i + 1 != v.len() {
                         write!(output, " | ").unwrap();
                     }
-
-                    i += 1;
                 }
 
                 write!(f, "{}", output)
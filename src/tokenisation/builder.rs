@@ -1,94 +1,197 @@
 use std::vec::Vec;
-use std::collections::HashMap;
-use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use regex::bytes::Regex;
 
-use crate::tokenisation::tokeniser::{Tokeniser, LexemeTokeniser, LexemeBuilder, LexemeMatcher, TokeniserState};
-use crate::tokenisation::token::{Token, Location};
+use crate::tokenisation::tokeniser::{Tokeniser, LexemeTokeniser, LexemeBuilder, LexemeMatcher, TokeniserState, ModeTransition, INITIAL_MODE};
+use crate::tokenisation::tokeniser::{EofHandler, UnexpectedSymbolHandler};
+use crate::tokenisation::token::{Token, Location, TriviaKind};
 use crate::tokenisation::error::TokenisationError;
 use crate::parsing::TerminalSymbol;
 
-pub struct TokeniserBuilder<'t, T, U> where T: TerminalSymbol {
-    lexemes:                   Vec<LexemeTokeniser<'t, T, U>>,
-    error_handlers:            HashMap<char, &'t dyn Fn(Location, String) -> TokenisationError<T, U>>,
-    eof_handler:               Option<&'t dyn Fn(Location) -> Token<T>>,
-    unexpected_symbol_handler: Option<&'t dyn Fn(Location, char) -> TokenisationError<T, U>>
+/**
+ * Builds a `Tokeniser` from its lexemes and handlers. Handlers are closures
+ * owned by the tokeniser, so they can capture whatever configuration they
+ * need, and can keep state of their own between calls.
+ */
+pub struct TokeniserBuilder<T, U> where T: TerminalSymbol {
+    lexemes:                   Vec<LexemeTokeniser<T, U>>,
+    eof_handler:               Option<EofHandler<T>>,
+    unexpected_symbol_handler: Option<UnexpectedSymbolHandler<T, U>>,
+    current_modes:             Vec<&'static str>,
+    verbatim_modes:            HashSet<&'static str>,
+    prologue:                  Vec<(Regex, TriviaKind)>
 }
 
-impl<'t, T, U> TokeniserBuilder<'t, T, U> where T: TerminalSymbol {
-    pub fn new<'u, V, W>() -> TokeniserBuilder<'u, V, W> where V: TerminalSymbol {
-        TokeniserBuilder::<'u, V, W> {
+impl<T, U> TokeniserBuilder<T, U> where T: TerminalSymbol {
+    pub fn new<V, W>() -> TokeniserBuilder<V, W> where V: TerminalSymbol {
+        TokeniserBuilder::<V, W> {
             lexemes:                   Vec::new(),
-            error_handlers:            HashMap::new(),
             eof_handler:               None,
-            unexpected_symbol_handler: None
+            unexpected_symbol_handler: None,
+            current_modes:             vec![INITIAL_MODE],
+            verbatim_modes:            HashSet::new(),
+            prologue:                  Vec::new()
         }
     }
 
+    /** Lexemes added after this call are only tried while the tokeniser is
+     *  in one of `modes`. Lexemes are registered for `INITIAL_MODE` until
+     *  this is first called. */
+    pub fn in_modes(mut self,
+        modes: &[&'static str]
+    ) -> TokeniserBuilder<T, U> {
+        self.current_modes = modes.to_vec();
+        self
+    }
+
+    /** Whitespace is significant in `mode`, so it is left for the lexemes
+     *  to match rather than being skipped between tokens. */
+    pub fn with_verbatim_mode(mut self,
+        mode: &'static str
+    ) -> TokeniserBuilder<T, U> {
+        self.verbatim_modes.insert(mode);
+        self
+    }
+
+    /** Adds trivia that is only recognised at the very start of the source,
+     *  such as a byte order mark. Prologue trivia is tried in the order it
+     *  was added, each at most once. */
+    pub fn with_prologue_trivia(mut self,
+        matcher: Regex,
+        kind:    TriviaKind
+    ) -> TokeniserBuilder<T, U> {
+        self.prologue.push((matcher, kind));
+        self
+    }
+
+    /** Makes the most recently added lexeme enter `mode` once it matches. */
+    pub fn then_push_mode(self,
+        mode: &'static str
+    ) -> TokeniserBuilder<T, U> {
+        self.with_transition(ModeTransition::Push(mode))
+    }
+
+    /** Makes the most recently added lexeme return to the previous mode once
+     *  it matches. */
+    pub fn then_pop_mode(self) -> TokeniserBuilder<T, U> {
+        self.with_transition(ModeTransition::Pop)
+    }
+
+    /** Makes the most recently added lexeme produce trivia of the given kind
+     *  instead of a token. */
+    pub fn with_trivia(mut self,
+        kind: TriviaKind
+    ) -> TokeniserBuilder<T, U> {
+        if let Some(lexeme) = self.lexemes.last_mut() {
+            lexeme.trivia = Some(kind);
+        }
+        self
+    }
+
+    /** Makes `word` a reserved word of the most recently added lexeme, so
+     *  that when the lexeme matches exactly `word` it gives a token of type
+     *  `token_type` instead. This lets one identifier regex stand in for
+     *  every keyword. Only the text the lexeme's regex matched is looked
+     *  up, so keywords have no effect on complicated tokens. */
+    pub fn with_keyword(mut self,
+        word:       &str,
+        token_type: T
+    ) -> TokeniserBuilder<T, U> {
+        if let Some(lexeme) = self.lexemes.last_mut() {
+            lexeme.keywords.insert(word.as_bytes().to_vec(), token_type);
+        }
+        self
+    }
+
+    /** Stops `word` being a reserved word of the most recently added
+     *  lexeme, so it is read like any other match again. */
+    pub fn without_keyword(mut self,
+        word: &str
+    ) -> TokeniserBuilder<T, U> {
+        if let Some(lexeme) = self.lexemes.last_mut() {
+            lexeme.keywords.remove(word.as_bytes());
+        }
+        self
+    }
+
+    fn with_transition(mut self,
+        transition: ModeTransition
+    ) -> TokeniserBuilder<T, U> {
+        if let Some(lexeme) = self.lexemes.last_mut() {
+            lexeme.transition = transition;
+        }
+        self
+    }
+
     pub fn with_static_token(mut self,
         token_matcher: Regex,
         token_type:    T
-    ) -> TokeniserBuilder<'t, T, U> {
+    ) -> TokeniserBuilder<T, U> {
         self.lexemes.push(LexemeTokeniser {
-            matcher: LexemeMatcher::WholeMatcher(token_matcher),
-            builder: LexemeBuilder::StaticBuilder(token_type)
+            matcher:    LexemeMatcher::WholeMatcher(token_matcher),
+            builder:    LexemeBuilder::StaticBuilder(token_type),
+            modes:      self.current_modes.clone(),
+            transition: ModeTransition::Stay,
+            trivia:     None,
+            keywords:   HashMap::new()
         });
-        return self;
+        self
     }
     
-    pub fn with_dynamic_token(mut self,
+    pub fn with_dynamic_token<F>(mut self,
         token_matcher: Regex,
-        token_parser:  &'t dyn Fn(String, Location) -> Result<Token<T>, TokenisationError<T, U>>
-    ) -> TokeniserBuilder<'t, T, U> {
+        token_parser:  F
+    ) -> TokeniserBuilder<T, U> where F: FnMut(Vec<u8>, Location) -> Result<Token<T>, TokenisationError<T, U>> + 'static {
         self.lexemes.push(LexemeTokeniser {
-            matcher: LexemeMatcher::WholeMatcher(token_matcher),
-            builder: LexemeBuilder::DynamicBuilder(token_parser)
+            matcher:    LexemeMatcher::WholeMatcher(token_matcher),
+            builder:    LexemeBuilder::DynamicBuilder(Box::new(token_parser)),
+            modes:      self.current_modes.clone(),
+            transition: ModeTransition::Stay,
+            trivia:     None,
+            keywords:   HashMap::new()
         });
-        return self;
+        self
     }
 
-    pub fn with_complicated_token(mut self,
+    pub fn with_complicated_token<F>(mut self,
         token_start_matcher: Regex,
-        token_parser:        &'t dyn Fn(&mut TokeniserState<T, U>, Location) -> Result<Token<T>, TokenisationError<T, U>>
-    ) -> TokeniserBuilder<'t, T, U> {
+        token_parser:        F
+    ) -> TokeniserBuilder<T, U> where F: FnMut(&mut TokeniserState<T, U>, Location) -> Result<Token<T>, TokenisationError<T, U>> + 'static {
         self.lexemes.push(LexemeTokeniser {
-            matcher: LexemeMatcher::StartMatcher(token_start_matcher),
-            builder: LexemeBuilder::ComplicatedBuilder(token_parser)
+            matcher:    LexemeMatcher::StartMatcher(token_start_matcher),
+            builder:    LexemeBuilder::ComplicatedBuilder(Box::new(token_parser)),
+            modes:      self.current_modes.clone(),
+            transition: ModeTransition::Stay,
+            trivia:     None,
+            keywords:   HashMap::new()
         });
-        return self;
-    }
-
-    pub fn with_eof_handler(mut self,
-        handler: &'t dyn Fn(Location) -> Token<T>
-    ) -> TokeniserBuilder<'t, T, U> {
-        self.eof_handler = Some(handler);
-        return self;
+        self
     }
 
-    pub fn with_error_handler(mut self,
-        start_char: char,
-        handler:    &'t dyn Fn(Location, String) -> TokenisationError<T, U>
-    ) -> TokeniserBuilder<'t, T, U> {
-        self.error_handlers.insert(start_char, handler);
-        return self;
+    pub fn with_eof_handler<F>(mut self,
+        handler: F
+    ) -> TokeniserBuilder<T, U> where F: FnMut(Location) -> Token<T> + 'static {
+        self.eof_handler = Some(Box::new(handler));
+        self
     }
 
-    pub fn with_unexpected_symbol_handler(mut self,
-        handler: &'t dyn Fn(Location, char) -> TokenisationError<T, U>
-    ) -> TokeniserBuilder<'t, T, U> {
-        self.unexpected_symbol_handler = Some(handler);
-        return self;
+    pub fn with_unexpected_symbol_handler<F>(mut self,
+        handler: F
+    ) -> TokeniserBuilder<T, U> where F: FnMut(Location, &[u8]) -> TokenisationError<T, U> + 'static {
+        self.unexpected_symbol_handler = Some(Box::new(handler));
+        self
     }
     
-    pub fn build(self) -> Option<Tokeniser<'t, T, U>> {
-        if self.eof_handler.is_some() && self.unexpected_symbol_handler.is_some() {
-            Some(Tokeniser::new(
-                self.lexemes, 
-                self.error_handlers,
-                self.eof_handler.unwrap(),
-                self.unexpected_symbol_handler.unwrap()
-            ))
-        } else {
-            None
+    pub fn build(self) -> Option<Tokeniser<T, U>> {
+        match (self.eof_handler, self.unexpected_symbol_handler) {
+            (Some(eof_handler), Some(unexpected_symbol_handler)) => Some(Tokeniser::new(
+                self.lexemes,
+                eof_handler,
+                unexpected_symbol_handler,
+                self.verbatim_modes,
+                self.prologue
+            )),
+            _ => None
         }
     }
 }
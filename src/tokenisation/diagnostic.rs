@@ -0,0 +1,316 @@
+use std::fmt::Write;
+
+use crate::tokenisation::error::{TokenisationError, TokenisationErrorType};
+use crate::tokenisation::token::Location;
+use crate::tokenisation::tokeniser::newline_length;
+use crate::parsing::TerminalSymbol;
+
+const RESET:     &str = "\x1b[0m";
+const BOLD:      &str = "\x1b[1m";
+const BOLD_RED:  &str = "\x1b[1;31m";
+const BOLD_BLUE: &str = "\x1b[1;34m";
+
+/** An error that can be shown along with the source it was found in. */
+pub trait Diagnostic {
+    /** Where the error is, which is marked in the source. */
+    fn location(&self) -> Location;
+
+    /** How many characters to mark from the error's location. */
+    fn length(&self) -> usize;
+
+    /** The message the reference Lua implementation would give, without the
+     *  line number. */
+    fn message(&self) -> String;
+
+    /** The line an unfinished construct began on, and what to label it
+     *  with. */
+    fn start_line(&self) -> Option<(usize, &'static str)> {
+        None
+    }
+}
+
+impl<T> Diagnostic for TokenisationError<T, TokenisationErrorType> where T: TerminalSymbol {
+    fn location(&self) -> Location {
+        self.partial_token.location
+    }
+
+    /** Partial tokens that know their own text mark just that, rather than
+     *  everything the message is reported near. */
+    fn length(&self) -> usize {
+        match self.partial_token.text.is_empty() {
+            true  => self.near().chars().count(),
+            false => String::from_utf8_lossy(&self.partial_token.text).chars().count()
+        }
+    }
+
+    fn message(&self) -> String {
+        TokenisationError::message(self)
+    }
+
+    fn start_line(&self) -> Option<(usize, &'static str)> {
+        match &self.error_type {
+            TokenisationErrorType::UnfinishedLongString { start_line }  => Some((*start_line, "long string starts here")),
+            TokenisationErrorType::UnfinishedLongComment { start_line } => Some((*start_line, "long comment starts here")),
+            _                                                           => None
+        }
+    }
+}
+
+/** A line of source to show in a diagnostic, with part of it marked. */
+struct Snippet {
+    line:    usize,
+    col:     usize,
+    length:  usize,
+    primary: bool,
+    label:   Option<String>
+}
+
+/** Renders errors for people to read: the message the reference
+ *  Lua implementation would give, followed by the offending source with the
+ *  problem underlined. */
+pub struct DiagnosticRenderer {
+    chunk_name: Option<String>,
+    colour:     bool
+}
+
+impl DiagnosticRenderer {
+    pub fn new() -> DiagnosticRenderer {
+        DiagnosticRenderer {
+            chunk_name: None,
+            colour:     false
+        }
+    }
+
+    /** The name messages are prefixed with, usually the file name. */
+    pub fn with_chunk_name(mut self,
+        chunk_name: &str
+    ) -> DiagnosticRenderer {
+        self.chunk_name = Some(String::from(chunk_name));
+        self
+    }
+
+    /** Whether to colour the output with ANSI escape codes. */
+    pub fn with_colour(mut self,
+        colour: bool
+    ) -> DiagnosticRenderer {
+        self.colour = colour;
+        self
+    }
+
+    pub fn render<D>(&self,
+        error:  &D,
+        source: &[u8]
+    ) -> String where D: Diagnostic {
+        let location = error.location();
+        let mut output = String::new();
+
+        let chunk_prefix = self.chunk_name
+            .as_ref()
+            .map(|name| format!("{}:", name))
+            .unwrap_or_default();
+
+        writeln!(output, "{}error{}{}: {}{}: {}{}",
+            self.paint(BOLD_RED), self.paint(RESET), self.paint(BOLD),
+            chunk_prefix, location.line, error.message(), self.paint(RESET)
+        ).unwrap();
+
+        let mut snippets = Vec::new();
+
+        if let Some((start_line, label)) = error.start_line() {
+            snippets.push(self.start_snippet(source, start_line, label));
+        }
+
+        snippets.push(Snippet {
+            line:    location.line,
+            col:     location.col,
+            length:  error.length(),
+            primary: true,
+            label:   None
+        });
+
+        self.render_snippets(&mut output, source, &snippets);
+        output
+    }
+
+    /** Marks the whole of a line where an unfinished construct began. */
+    fn start_snippet(&self, source: &[u8], line: usize, label: &str) -> Snippet {
+        let text = get_line(source, line);
+        let indent = text.len() - text.trim_start().len();
+
+        Snippet {
+            line,
+            col:     indent + 1,
+            length:  text.trim().chars().count(),
+            primary: false,
+            label:   Some(String::from(label))
+        }
+    }
+
+    fn render_snippets(&self, output: &mut String, source: &[u8], snippets: &Vec<Snippet>) {
+        let gutter_width = snippets.iter()
+            .map(|snippet| snippet.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let empty_gutter = format!("{} |", " ".repeat(gutter_width));
+
+        writeln!(output, "{}{}{}", self.paint(BOLD_BLUE), empty_gutter, self.paint(RESET)).unwrap();
+
+        let mut previous_line = None;
+
+        for snippet in snippets {
+            if previous_line.map(|line| snippet.line > line + 1).unwrap_or(false) {
+                writeln!(output, "{}...{}", self.paint(BOLD_BLUE), self.paint(RESET)).unwrap();
+            }
+            previous_line = Some(snippet.line);
+
+            let text = get_line(source, snippet.line);
+
+            writeln!(output, "{}{:>width$} |{} {}",
+                self.paint(BOLD_BLUE), snippet.line, self.paint(RESET), text,
+                width = gutter_width
+            ).unwrap();
+
+            let (marker, colour) = if snippet.primary { ('^', BOLD_RED) } else { ('-', BOLD_BLUE) };
+
+            let padding: String = prefix_before(&text, snippet.col)
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+
+            let available = text.chars().count() - padding.chars().count();
+            let length = snippet.length.min(available).max(1);
+
+            write!(output, "{}{}{} {}{}",
+                self.paint(BOLD_BLUE), empty_gutter, self.paint(colour),
+                padding, marker.to_string().repeat(length)
+            ).unwrap();
+
+            match &snippet.label {
+                Some(label) => writeln!(output, " {}{}", label, self.paint(RESET)).unwrap(),
+                None        => writeln!(output, "{}", self.paint(RESET)).unwrap()
+            }
+        }
+    }
+
+    fn paint(&self, code: &'static str) -> &'static str {
+        if self.colour { code } else { "" }
+    }
+}
+
+impl Default for DiagnosticRenderer {
+    fn default() -> DiagnosticRenderer {
+        DiagnosticRenderer::new()
+    }
+}
+
+/** Gets the text of a line by its 1-based number, without its line ending.
+ *  Lines are counted the same way as locations are. Lines past the end of
+ *  the source are empty, and bytes that aren't valid UTF-8 are replaced. */
+fn get_line(source: &[u8], line: usize) -> String {
+    let mut start = 0;
+
+    for _ in 1..line {
+        match source[start..].iter().position(|byte| *byte == b'\n' || *byte == b'\r') {
+            Some(idx) => start = start + idx + newline_length(&source[start + idx..]),
+            None      => return String::new()
+        }
+    }
+
+    let end = source[start..]
+        .iter()
+        .position(|byte| *byte == b'\n' || *byte == b'\r')
+        .map(|idx| start + idx)
+        .unwrap_or(source.len());
+
+    String::from_utf8_lossy(&source[start..end]).into_owned()
+}
+
+/** The part of `text` before the 1-based byte column `col`. */
+fn prefix_before(text: &str, col: usize) -> &str {
+    let mut end = col.saturating_sub(1).min(text.len());
+
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &text[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::{get_lua_tokeniser, read_lua_chunk, LuaDialect, LuaReadError};
+
+    fn render_first_error(src: &str, renderer: DiagnosticRenderer) -> String {
+        let mut tokeniser = get_lua_tokeniser(LuaDialect::Lua54).unwrap();
+        let (_, errors) = tokeniser.tokenise_with_recovery(src);
+
+        renderer.render(&errors[0], src.as_bytes())
+    }
+
+    #[test]
+    fn renders_reference_messages_with_snippets() {
+        let rendered = render_first_error(
+            "local s = 1\nlocal t = \"abc\nreturn s",
+            DiagnosticRenderer::new().with_chunk_name("input.lua")
+        );
+
+        assert_eq!(rendered, concat!(
+            "error: input.lua:2: unfinished string near '\"abc'\n",
+            "  |\n",
+            "2 | local t = \"abc\n",
+            "  |           ^^^^\n"
+        ));
+    }
+
+    #[test]
+    fn renders_the_start_of_unfinished_long_strings() {
+        let rendered = render_first_error(
+            "x = 1\n\ty = [[\n\n\n\n\n\n\n\nabc",
+            DiagnosticRenderer::new()
+        );
+
+        assert_eq!(rendered, concat!(
+            "error: 10: unfinished long string (starting at line 2) near <eof>\n",
+            "   |\n",
+            " 2 | \ty = [[\n",
+            "   | \t------ long string starts here\n",
+            "...\n",
+            "10 | abc\n",
+            "   |    ^\n"
+        ));
+    }
+
+    #[test]
+    fn renders_syntax_and_semantic_errors() {
+        let src = "local x = 1\nif x then\n\ty = = 2\nend";
+        let error = read_lua_chunk(src, LuaDialect::Lua54, false).err().unwrap();
+
+        assert!(matches!(error, LuaReadError::Syntax { .. }));
+        assert_eq!(DiagnosticRenderer::new().render(&error, src.as_bytes()), concat!(
+            "error: 3: unexpected symbol near '='\n",
+            "  |\n",
+            "3 | \ty = = 2\n",
+            "  | \t    ^\n"
+        ));
+
+        let src = "goto skip\nlocal y = 1\n::skip::\nprint(y)";
+        let error = read_lua_chunk(src, LuaDialect::Lua54, false).err().unwrap();
+
+        assert!(matches!(error, LuaReadError::Semantic { .. }));
+        assert_eq!(DiagnosticRenderer::new().render(&error, src.as_bytes()), concat!(
+            "error: 3: <goto skip> at line 1 jumps into the scope of local 'y'\n",
+            "  |\n",
+            "3 | ::skip::\n",
+            "  | ^^\n"
+        ));
+    }
+
+    #[test]
+    fn colours_output_on_request() {
+        let rendered = render_first_error("a = @", DiagnosticRenderer::new().with_colour(true));
+
+        assert!(rendered.starts_with("\x1b[1;31merror\x1b[0m\x1b[1m: 1: unexpected symbol near '@'\x1b[0m\n"));
+        assert!(rendered.contains("\x1b[1;31m     ^\x1b[0m"));
+    }
+}
@@ -4,50 +4,96 @@ use crate::tokenisation::token::Token;
 use crate::parsing::TerminalSymbol;
 
 pub struct TokenisationError<T, U> where T: TerminalSymbol {
-    pub partial_token: Token<T>,
+    pub partial_token: Box<Token<T>>,
     pub error_type:    U
 }
 
-impl<T, U> Display for TokenisationError<T, U> where T: TerminalSymbol {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        write!(f, "{}", self.partial_token)
-    }
-}
-
 #[derive(Debug)]
 pub enum TokenisationErrorType {
     MalformedNumber,
     UnfinishedString,
-    UnfinishedLongString,
-    UnfinishedLongComment,
-    SyntaxError,
+    InvalidEscapeSequence,
+    HexadecimalDigitExpected,
+    DecimalEscapeTooLarge,
+    Utf8ValueTooLarge,
+    MissingUtf8EscapeBrace { opening: bool },
+    UnfinishedLongString { start_line: usize },
+    InvalidLongStringDelimiter,
+    UnfinishedLongComment { start_line: usize },
     UnexpectedSymbol,
     Unimplemented
 }
 
-// impl<T, U> Display for TokenisationError<T, U> {
-//     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-//         match &self.error_type {
-//             TokenisationErrorType::MalformedNumber  => {
-//                 write!(f, "{}: malformed number near {}",
-//                     self.partial_token.location.line,
-//                     self.partial_token
-//                 )
-//             },
-//             TokenisationErrorType::UnfinishedString => {
-//                 write!(f, "{}: unfinished string near {}",
-//                     self.partial_token.location.line,
-//                     self.partial_token
-//                 )
-//             },
-//             TokenisationErrorType::UnfinishedLongString => {
-//                 write!(f, "{}: unfinished long string (starting at line {}) near {}",
-//                     self.partial_token.location.line,
-//                     self.partial_token.location.line,
-//                     self.partial_token
-//                 )
-//             },
-//             t @ _ => write!(f, "{}: {:?}", self.partial_token.location.line, t)
-//         }
-//     }
-// }
\ No newline at end of file
+impl<T> TokenisationError<T, TokenisationErrorType> where T: TerminalSymbol {
+    /** The text the error is reported near, which is the partial token's data
+     *  if it has any, or the name of its terminal otherwise. A token the
+     *  source ended in the middle of is reported near `<eof>`. */
+    pub fn near(&self) -> String {
+        match (&self.partial_token.token_data, &self.error_type) {
+            (Some(data), _) => data.to_string(),
+            (None, TokenisationErrorType::UnfinishedString)             |
+            (None, TokenisationErrorType::UnfinishedLongString { .. })  |
+            (None, TokenisationErrorType::UnfinishedLongComment { .. }) => String::from("<eof>"),
+            (None, _) => String::from(self.partial_token.token_type.get_name())
+        }
+    }
+
+    /** The text the error is reported near, quoted the way `luaX_token2str`
+     *  quotes it, which leaves placeholders like `<eof>` unquoted. */
+    fn quoted_near(&self) -> String {
+        let near = self.near();
+
+        match self.partial_token.token_data.is_none() && near.starts_with('<') && near.ends_with('>') && near.len() > 2 {
+            true  => near,
+            false => format!("'{}'", near)
+        }
+    }
+
+    /** Describes the error the way the reference Lua lexer does, without the
+     *  line number. */
+    pub fn message(&self) -> String {
+        match &self.error_type {
+            TokenisationErrorType::MalformedNumber => {
+                format!("malformed number near {}", self.quoted_near())
+            },
+            TokenisationErrorType::UnfinishedString => {
+                format!("unfinished string near {}", self.quoted_near())
+            },
+            TokenisationErrorType::InvalidEscapeSequence => {
+                format!("invalid escape sequence near {}", self.quoted_near())
+            },
+            TokenisationErrorType::HexadecimalDigitExpected => {
+                format!("hexadecimal digit expected near {}", self.quoted_near())
+            },
+            TokenisationErrorType::DecimalEscapeTooLarge => {
+                format!("decimal escape too large near {}", self.quoted_near())
+            },
+            TokenisationErrorType::Utf8ValueTooLarge => {
+                format!("UTF-8 value too large near {}", self.quoted_near())
+            },
+            TokenisationErrorType::MissingUtf8EscapeBrace { opening } => {
+                let brace = if *opening { '{' } else { '}' };
+                format!("missing '{}' in \\u{{xxxx}} near {}", brace, self.quoted_near())
+            },
+            TokenisationErrorType::UnfinishedLongString { start_line } => {
+                format!("unfinished long string (starting at line {}) near {}", start_line, self.quoted_near())
+            },
+            TokenisationErrorType::UnfinishedLongComment { start_line } => {
+                format!("unfinished long comment (starting at line {}) near {}", start_line, self.quoted_near())
+            },
+            TokenisationErrorType::InvalidLongStringDelimiter => {
+                format!("invalid long string delimiter near {}", self.quoted_near())
+            },
+            TokenisationErrorType::UnexpectedSymbol => {
+                format!("unexpected symbol near {}", self.quoted_near())
+            },
+            t => format!("{:?} near {}", t, self.quoted_near())
+        }
+    }
+}
+
+impl<T> Display for TokenisationError<T, TokenisationErrorType> where T: TerminalSymbol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}: {}", self.partial_token.location.line, self.message())
+    }
+}
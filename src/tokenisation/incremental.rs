@@ -0,0 +1,129 @@
+//! Incremental tokenisation.
+//!
+//! Rather than tokenising a whole file again after every edit, only the
+//! region around the edit is tokenised. Tokenisation carries on past the edit
+//! until it reaches a token boundary that also existed before the edit, with
+//! the tokeniser in the same modes, at which point everything after it must
+//! tokenise exactly as it did before and the old tokens are reused.
+//!
+//! This depends on the token stream being lossless, since the source before
+//! the edit is rebuilt from the tokens and their trivia.
+
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use crate::tokenisation::tokeniser::{Tokeniser, TokeniserState, INITIAL_MODE};
+use crate::tokenisation::token::{Token, Location};
+use crate::tokenisation::error::TokenisationError;
+use crate::parsing::TerminalSymbol;
+
+/** A change to the source: the bytes in `range` are replaced with
+ *  `replacement`. The range is in terms of the source before the edit. */
+#[derive(Debug, Clone)]
+pub struct TextEdit {
+    pub range:       Range<usize>,
+    pub replacement: String
+}
+
+impl<T, U> Tokeniser<T, U> where T: TerminalSymbol {
+
+    /**
+     * Updates `tokens` to reflect `edit`, returning the range of indices in
+     * `tokens` that were tokenised again. `tokens` is left untouched if the
+     * edited source fails to tokenise.
+     *
+     * Panics if the edit's range is out of bounds.
+     */
+    pub fn retokenise(&mut self,
+        tokens: &mut VecDeque<Token<T>>,
+        edit:   &TextEdit
+    ) -> Result<Range<usize>, TokenisationError<T, U>> {
+        let mut old_src = Vec::new();
+        let mut starts  = Vec::with_capacity(tokens.len());
+
+        for token in tokens.iter() {
+            starts.push(old_src.len());
+            old_src.extend(token.full_text());
+        }
+
+        let mut new_src = old_src[..edit.range.start].to_vec();
+        new_src.extend_from_slice(edit.replacement.as_bytes());
+        new_src.extend_from_slice(&old_src[edit.range.end..]);
+
+        // The token before the edited one is tokenised again as well, since
+        // the edit might extend it or change what its trailing trivia is.
+        let edited_index  = starts.iter().rposition(|start| *start <= edit.range.start).unwrap_or(0);
+        let restart_index = edited_index.saturating_sub(1);
+        let edit_end      = edit.range.start + edit.replacement.len();
+
+        let mut state = match tokens.get(restart_index) {
+            Some(token) => TokeniserState::<T, U>::resume(
+                &new_src,
+                starts[restart_index],
+                token.start_location(),
+                token.lexer_modes.clone()
+            ),
+            None => TokeniserState::<T, U>::resume(&new_src, 0, Location { line: 1, col: 1 }, vec![INITIAL_MODE])
+        };
+
+        let mut new_tokens = Vec::new();
+        let mut resync     = None;
+
+        while !state.is_finished() {
+            new_tokens.push(state.get_token(self)?);
+
+            let position = state.position();
+
+            if position >= edit_end {
+                let old_position = position + edit.range.end - edit_end;
+
+                if let Ok(old_index) = starts.binary_search(&old_position) {
+                    if tokens[old_index].lexer_modes == *state.mode_stack() {
+                        resync = Some((old_index, state.location));
+                        break;
+                    }
+                }
+            }
+        }
+
+        let reused_from = match resync {
+            Some((old_index, new_location)) => {
+                let old_location = tokens[old_index].start_location();
+
+                for token in tokens.range_mut(old_index..) {
+                    shift_token(token, old_location, new_location);
+                }
+
+                old_index
+            },
+            None => tokens.len()
+        };
+
+        let new_count = new_tokens.len();
+        let reused: Vec<Token<T>> = tokens.drain(reused_from..).collect();
+
+        tokens.truncate(restart_index);
+        tokens.extend(new_tokens);
+        tokens.extend(reused);
+
+        Ok(restart_index..restart_index + new_count)
+    }
+}
+
+/** Moves a reused token's locations from where they were before the edit to
+ *  where they are now. Only the line containing the resynchronisation point
+ *  has its columns changed. */
+fn shift_token<T>(token: &mut Token<T>, old: Location, new: Location) where T: TerminalSymbol {
+    let shift = |location: &mut Location| {
+        if location.line == old.line {
+            location.col = location.col - old.col + new.col;
+        }
+        location.line = location.line - old.line + new.line;
+    };
+
+    shift(&mut token.location);
+
+    for trivia in token.leading_trivia.iter_mut().chain(token.trailing_trivia.iter_mut()) {
+        shift(&mut trivia.location);
+    }
+}
@@ -2,8 +2,12 @@ mod token;
 mod error;
 mod tokeniser;
 mod builder;
+mod incremental;
+mod diagnostic;
 
-pub use token::{Token, TokenData, Location};
+pub use token::{Token, TokenData, Location, Trivia, TriviaKind};
 pub use error::{TokenisationError, TokenisationErrorType};
-pub use tokeniser::{Tokeniser, TokeniserState};
+pub use tokeniser::{Tokeniser, TokeniserState, ModeTransition, INITIAL_MODE, newline_length};
 pub use builder::TokeniserBuilder;
+pub use incremental::TextEdit;
+pub use diagnostic::{DiagnosticRenderer, Diagnostic};
@@ -2,29 +2,113 @@ use std::fmt::Display;
 use std::fmt::Formatter;
 
 use crate::parsing::TerminalSymbol;
+use crate::tokenisation::tokeniser::newline_length;
 
-pub trait TokenData: Display {
+pub trait TokenData: Display + Clone {
 
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Token<T> where T: TerminalSymbol {
-    pub token_type: T,
-    pub token_data: Option<T::DataType>,
-    pub location:   Location
+    pub token_type:      T,
+    pub token_data:      Option<T::DataType>,
+    pub location:        Location,
+    pub text:            Vec<u8>,
+    pub leading_trivia:  Vec<Trivia>,
+    pub trailing_trivia: Vec<Trivia>,
+    pub lexer_modes:     Vec<&'static str>
+}
+
+impl<T> Token<T> where T: TerminalSymbol {
+    /** Creates a token with no source text or trivia. The tokeniser fills
+     *  those in once the lexeme has been consumed. */
+    pub fn new(
+        token_type: T,
+        token_data: Option<T::DataType>,
+        location:   Location
+    ) -> Token<T> {
+        Token {
+            token_type,
+            token_data,
+            location,
+            text:            Vec::new(),
+            leading_trivia:  Vec::new(),
+            trailing_trivia: Vec::new(),
+            lexer_modes:     Vec::new()
+        }
+    }
+
+    /** Where the token's leading trivia begins, or the token itself if it
+     *  has none. */
+    pub fn start_location(&self) -> Location {
+        self.leading_trivia
+            .first()
+            .map(|trivia| trivia.location)
+            .unwrap_or(self.location)
+    }
+
+    /** Where the token's text ends, just past its last byte. Line breaks in
+     *  the text are counted the same way as in locations. */
+    pub fn end_location(&self) -> Location {
+        let mut location = self.location;
+        let mut idx = 0;
+
+        while idx < self.text.len() {
+            match newline_length(&self.text[idx..]) {
+                0 => {
+                    location.col += 1;
+                    idx += 1;
+                },
+                length => {
+                    location.line += 1;
+                    location.col = 1;
+                    idx += length;
+                }
+            }
+        }
+
+        location
+    }
+
+    /** The length in bytes of the token's text with all of its trivia. */
+    pub fn full_len(&self) -> usize {
+        let trivia_len: usize = self.leading_trivia.iter()
+            .chain(self.trailing_trivia.iter())
+            .map(|trivia| trivia.text.len())
+            .sum();
+
+        trivia_len + self.text.len()
+    }
+
+    /** The token's text with all of its trivia, exactly as it appeared in
+     *  the source. */
+    pub fn full_text(&self) -> Vec<u8> {
+        let mut full_text = Vec::with_capacity(self.full_len());
+
+        for trivia in &self.leading_trivia {
+            full_text.extend_from_slice(&trivia.text);
+        }
+
+        full_text.extend_from_slice(&self.text);
+
+        for trivia in &self.trailing_trivia {
+            full_text.extend_from_slice(&trivia.text);
+        }
+
+        full_text
+    }
 }
 
 impl<T: Display> Display for Token<T> where T: TerminalSymbol {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        if self.token_data.is_some() {
-            write!(f, "{}({}) at {}", self.token_type, self.token_data.as_ref().unwrap(), self.location)
-        } else {
-            write!(f, "{} at {}", self.token_type, self.location)
+        match &self.token_data {
+            Some(data) => write!(f, "{}({}) at {}", self.token_type, data, self.location),
+            None       => write!(f, "{} at {}", self.token_type, self.location)
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Location {
     pub line: usize,
     pub col:  usize
@@ -35,3 +119,21 @@ impl Display for Location {
         write!(f, "line {}, col {}", self.line, self.col)
     }
 }
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TriviaKind {
+    Whitespace,
+    Newline,
+    LineComment,
+    BlockComment,
+    ByteOrderMark,
+    Shebang
+}
+
+/** Source text between tokens that doesn't affect their meaning. */
+#[derive(Debug, Clone)]
+pub struct Trivia {
+    pub kind:     TriviaKind,
+    pub text:     Vec<u8>,
+    pub location: Location
+}
@@ -1,122 +1,245 @@
 use std::marker::PhantomData;
-use std::str::Lines;
-use std::iter::Peekable;
+use std::mem;
 use std::vec::Vec;
-use std::collections::{VecDeque, HashMap};
+use std::collections::{VecDeque, HashMap, HashSet};
 
-use regex::Regex;
+use regex::bytes::Regex;
 
-use crate::tokenisation::token::{Token, Location};
+use crate::tokenisation::token::{Token, Location, Trivia, TriviaKind};
 use crate::tokenisation::error::TokenisationError;
 use crate::parsing::TerminalSymbol;
 
+/** The mode every tokeniser starts in, and the one lexemes are registered
+ *  for unless the builder is told otherwise. */
+pub const INITIAL_MODE: &str = "initial";
+
+/** What happens to the mode stack after a lexeme has been matched. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModeTransition {
+    Stay,
+    Push(&'static str),
+    Pop
+}
+
 pub enum LexemeMatcher {
     WholeMatcher(Regex),
     StartMatcher(Regex)
 }
 
-pub enum LexemeBuilder<'t, T, U> where T: TerminalSymbol {
+/** Builds a token from the whole of the text a lexeme matched. */
+pub type DynamicHandler<T, U> = Box<dyn FnMut(Vec<u8>, Location) -> Result<Token<T>, TokenisationError<T, U>>>;
+
+/** Reads a token itself, starting from where the lexeme matched. */
+pub type ComplicatedHandler<T, U> = Box<dyn FnMut(&mut TokeniserState<T, U>, Location) -> Result<Token<T>, TokenisationError<T, U>>>;
+
+pub type EofHandler<T>                 = Box<dyn FnMut(Location) -> Token<T>>;
+pub type UnexpectedSymbolHandler<T, U> = Box<dyn FnMut(Location, &[u8]) -> TokenisationError<T, U>>;
+
+#[allow(clippy::enum_variant_names)]
+pub enum LexemeBuilder<T, U> where T: TerminalSymbol {
     StaticBuilder(T),
-    DynamicBuilder(&'t dyn Fn(String, Location) -> Result<Token<T>, TokenisationError<T, U>>),
-    ComplicatedBuilder(&'t dyn Fn(&mut TokeniserState<T, U>, Location) -> Result<Token<T>, TokenisationError<T, U>>)
+    DynamicBuilder(DynamicHandler<T, U>),
+    ComplicatedBuilder(ComplicatedHandler<T, U>)
+}
+
+pub struct LexemeTokeniser<T, U> where T: TerminalSymbol {
+    pub matcher:    LexemeMatcher,
+    pub builder:    LexemeBuilder<T, U>,
+    pub modes:      Vec<&'static str>,
+    pub transition: ModeTransition,
+    pub trivia:     Option<TriviaKind>,
+    /** Reserved words the lexeme's matches are looked up in. A whole match
+     *  found here becomes a token of the word's type instead. */
+    pub keywords:   HashMap<Vec<u8>, T>
 }
 
-pub struct LexemeTokeniser<'t, T, U> where T: TerminalSymbol {
-    pub matcher: LexemeMatcher,
-    pub builder: LexemeBuilder<'t, T, U>
+impl<T, U> LexemeTokeniser<T, U> where T: TerminalSymbol {
+    pub fn is_active_in(&self, mode: &str) -> bool {
+        self.modes.contains(&mode)
+    }
+
+    /** The length of the lexeme's match at the start of `text`, if it
+     *  matches there. */
+    fn match_length(&self, text: &[u8]) -> Option<usize> {
+        match &self.matcher {
+            LexemeMatcher::WholeMatcher(matcher)       => matcher.find(text),
+            LexemeMatcher::StartMatcher(start_matcher) => start_matcher.find(text)
+        }.map(|mat| mat.end())
+    }
 }
 
 pub struct TokeniserState<'t, T, U> where T: TerminalSymbol {
     pub location:                Location,
-    pub line_buffer:             Option<String>,
-    line_stack:                  Peekable<Lines<'t>>,
+    source:                      &'t [u8],
+    position:                    usize,
+    token_start:                 usize,
+    token_start_location:        Location,
+    pending_trivia:              Vec<Trivia>,
+    mode_stack:                  Vec<&'static str>,
+    finished:                    bool,
+    line_break_start:            Option<u8>,
     phantom_token_type:          PhantomData<T>,
     phantom_error_type:          PhantomData<U>
 }
 
-impl<T, U> TokeniserState<'_, T, U> where T: TerminalSymbol {
+impl<'t, T, U> TokeniserState<'t, T, U> where T: TerminalSymbol {
+    fn new(src: &'t [u8]) -> TokeniserState<'t, T, U> {
+        TokeniserState {
+            location:             Location { line: 1, col: 1},
+            source:               src,
+            position:             0,
+            token_start:          0,
+            token_start_location: Location { line: 1, col: 1},
+            pending_trivia:       Vec::new(),
+            mode_stack:           vec![INITIAL_MODE],
+            finished:             false,
+            line_break_start:     None,
+            phantom_token_type:   PhantomData,
+            phantom_error_type:   PhantomData
+        }
+    }
+
+    /** Creates a state that picks up tokenising `src` part of the way
+     *  through, as if it had arrived at `position` in the given modes. */
+    pub(super) fn resume(
+        src:        &'t [u8],
+        position:   usize,
+        location:   Location,
+        mode_stack: Vec<&'static str>
+    ) -> TokeniserState<'t, T, U> {
+        let mut state = TokeniserState::new(src);
+        state.position   = position;
+        state.location   = location;
+        state.mode_stack = mode_stack;
+
+        state
+    }
+
+    pub(super) fn position(&self) -> usize {
+        self.position
+    }
+
+    pub(super) fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub(super) fn mode_stack(&self) -> &Vec<&'static str> {
+        &self.mode_stack
+    }
+
+    /** Reads the next token, along with the trivia around it. Whitespace,
+     *  newlines and trivia lexemes before the token become its leading trivia,
+     *  and anything after it up to and including the end of the line becomes
+     *  its trailing trivia. Whatever is left at the end of the source is
+     *  attached to the end of file token. */
     pub fn get_token(&mut self,
-        tokeniser: &Tokeniser<T, U>
+        tokeniser: &mut Tokeniser<T, U>
     ) -> Result<Token<T>, TokenisationError<T, U>> {
-        self.trim_whitespace();
+        let lexer_modes = self.mode_stack.clone();
 
-        while self.is_end_of_line() {
-            self.pop_line();
-            self.trim_whitespace();
+        if self.position == 0 {
+            self.collect_prologue(tokeniser);
+        }
+
+        loop {
+            if !tokeniser.is_verbatim(self.current_mode()) {
+                self.collect_whitespace();
+            }
 
             if self.is_end_of_file() {
-                return Ok((tokeniser.eof_handler)(self.location));
+                let mut token = (tokeniser.eof_handler)(self.location);
+                token.leading_trivia = mem::take(&mut self.pending_trivia);
+                token.lexer_modes    = lexer_modes;
+                self.finished = true;
+
+                return Ok(token);
             }
-        }
 
-        let start_location = self.location;
-        
-        let current_line = self.line_buffer.as_ref().unwrap();
+            let start_location = self.location;
+            self.token_start          = self.position;
+            self.token_start_location = start_location;
 
-        for lexeme in &tokeniser.lexemes {
-            let matches = match &lexeme.matcher {
-                LexemeMatcher::WholeMatcher(matcher)       => matcher.is_match(current_line),
-                LexemeMatcher::StartMatcher(start_matcher) => start_matcher.is_match(current_line)
+            let (lexeme, length) = match self.find_lexeme(tokeniser) {
+                Some((idx, length)) => (&mut tokeniser.lexemes[idx], length),
+                None                => return Err(self.unmatched_symbol_error(tokeniser, start_location))
             };
 
-            if matches {
-                return self.match_lexeme(&lexeme, start_location);
-            }
-        }
+            let mut token = self.build_token(lexeme, length, start_location)?;
+            self.apply_transition(lexeme.transition);
+
+            let text = self.source[self.token_start..self.position].to_vec();
 
-        let first_char = current_line.chars().next().unwrap();
+            match lexeme.trivia {
+                Some(kind) => {
+                    self.pending_trivia.push(Trivia { kind, text, location: start_location });
+                },
+                None => {
+                    token.text            = text;
+                    token.leading_trivia  = mem::take(&mut self.pending_trivia);
+                    token.trailing_trivia = self.collect_trailing_trivia(tokeniser);
+                    token.lexer_modes     = lexer_modes;
 
-        for (start_char, handler) in &tokeniser.error_handlers {
-            if first_char == *start_char {
-                return Err(handler(start_location, String::from(current_line.clone())));
+                    return Ok(token);
+                }
             }
         }
+    }
 
-        if self.is_end_of_file() {
-            return Ok((tokeniser.eof_handler)(start_location));
-        } else {
-            return Err((tokeniser.unexpected_symbol_handler)(start_location, first_char))
-        }
+    /** Finds the index of the first lexeme that matches here, along with
+     *  the length of its match. */
+    fn find_lexeme(&self,
+        tokeniser: &Tokeniser<T, U>
+    ) -> Option<(usize, usize)> {
+        let current_mode = self.current_mode();
+        let remaining    = self.remaining();
+
+        tokeniser.lexemes
+            .iter()
+            .enumerate()
+            .filter(|(_, lexeme)| lexeme.is_active_in(current_mode))
+            .find_map(|(idx, lexeme)| lexeme.match_length(remaining).map(|length| (idx, length)))
     }
 
-    fn match_lexeme(&mut self,
-        lexeme:         &LexemeTokeniser<T, U>,
+    fn unmatched_symbol_error(&mut self,
+        tokeniser:      &mut Tokeniser<T, U>,
         start_location: Location
-    ) -> Result<Token<T>, TokenisationError<T, U>> {
-        let current_line = self.line_buffer.as_ref().unwrap().clone();
+    ) -> TokenisationError<T, U> {
+        let symbol = self.next_symbol();
+        self.consume_chars(symbol.len());
+        (tokeniser.unexpected_symbol_handler)(start_location, symbol)
+    }
 
-        match &lexeme.matcher {
-            LexemeMatcher::WholeMatcher(matcher) => {
-                let lexeme_match = matcher.find(&current_line[..]).unwrap().as_str().to_string();
-                self.build_token(&lexeme.builder, lexeme_match, start_location)
-            },
-            LexemeMatcher::StartMatcher(start_matcher) => {
-                let start_match = start_matcher.find(&current_line[..]).unwrap().as_str().to_string();
+    /** The bytes of the character at the current position, or just the
+     *  first byte if the source isn't valid UTF-8 there. */
+    fn next_symbol(&self) -> &'t [u8] {
+        let remaining = self.remaining();
+        let length = (1..=remaining.len().min(4))
+            .find(|length| std::str::from_utf8(&remaining[..*length]).is_ok())
+            .unwrap_or(1);
 
-                self.build_token(&lexeme.builder, start_match, start_location)
-            }
-        }
+        &remaining[..length]
     }
 
+    /** Builds a token from a lexeme that matched `length` bytes here. */
     fn build_token(&mut self,
-        builder:        &LexemeBuilder<T, U>,
-        value:          String,
+        lexeme:         &mut LexemeTokeniser<T, U>,
+        length:         usize,
         start_location: Location
     ) -> Result<Token<T>, TokenisationError<T, U>> {
-        match builder {
+        let value = self.remaining()[..length].to_vec();
+
+        if let Some(keyword) = lexeme.keywords.get(&value) {
+            self.consume_chars(length);
+            return Ok(Token::new(*keyword, None, start_location));
+        }
+
+        match &mut lexeme.builder {
             LexemeBuilder::StaticBuilder(token_type) => {
-                let chars_to_consume = value.len();
-                self.consume_chars(chars_to_consume);
-                Ok(Token {
-                    token_type: (*token_type).clone(),
-                    token_data: None,
-                    location:   start_location
-                })
+                self.consume_chars(length);
+                Ok(Token::new(*token_type, None, start_location))
             },
             LexemeBuilder::DynamicBuilder(builder_fn) => {
-                let chars_to_consume = value.len();
-                self.consume_chars(chars_to_consume);
-
+                self.consume_chars(length);
                 builder_fn(value, start_location)
             },
             LexemeBuilder::ComplicatedBuilder(handler_fn) => {
@@ -125,99 +248,463 @@ impl<T, U> TokeniserState<'_, T, U> where T: TerminalSymbol {
         }
     }
 
-    pub fn is_end_of_line(&mut self) -> bool {
-        self.line_buffer
-            .as_mut()
-            .map(|line| {
-                line.is_empty() || line.len() == 0
-            })
-            .unwrap_or(true)
+    /** Moves any prologue trivia at the start of the source into the pending
+     *  trivia. */
+    fn collect_prologue(&mut self, tokeniser: &Tokeniser<T, U>) {
+        for (matcher, kind) in &tokeniser.prologue {
+            let length = matcher.find(self.remaining())
+                .filter(|mat| mat.start() == 0)
+                .map(|mat| mat.end())
+                .unwrap_or(0);
+
+            if let Some(trivia) = self.take_trivia(*kind, length) {
+                self.pending_trivia.push(trivia);
+            }
+        }
     }
 
-    pub fn has_next_line(&mut self) -> bool {
-        self.line_stack.peek().is_some()
-    }
+    /** Moves any whitespace and newlines at the current position into the
+     *  pending trivia. */
+    fn collect_whitespace(&mut self) {
+        loop {
+            let trivia = self.take_whitespace().or_else(|| self.take_newline());
 
-    pub fn is_end_of_file(&mut self) -> bool {
-        self.is_end_of_line() && self.line_stack.peek().is_none()
+            match trivia {
+                Some(trivia) => self.pending_trivia.push(trivia),
+                None         => break
+            }
+        }
     }
 
-    pub fn trim_whitespace(&mut self) {
-        if self.line_buffer.is_some() {
-            let line = self.line_buffer.as_ref().unwrap().clone();
+    /** Collects the trivia following a token, up to and including the first
+     *  newline. Stops early at anything that isn't trivia, and backs out of a
+     *  trivia lexeme that fails so the error is reported by the next call to
+     *  `get_token` instead. */
+    fn collect_trailing_trivia(&mut self, tokeniser: &mut Tokeniser<T, U>) -> Vec<Trivia> {
+        let mut trailing_trivia = Vec::new();
 
-            let trimmed_line = line.trim_start();
-            let trimmed_chars = line.len() - trimmed_line.len();
+        if tokeniser.is_verbatim(self.current_mode()) {
+            return trailing_trivia;
+        }
+
+        loop {
+            if let Some(whitespace) = self.take_whitespace() {
+                trailing_trivia.push(whitespace);
+            }
 
-            if trimmed_chars != 0 {
-                self.line_buffer.replace(String::from(trimmed_line));
-                self.location.col = self.location.col + trimmed_chars;
+            if let Some(newline) = self.take_newline() {
+                trailing_trivia.push(newline);
+                break;
             }
+
+            let (lexeme, length) = match self.find_lexeme(tokeniser) {
+                Some((idx, length)) if tokeniser.lexemes[idx].trivia.is_some() && !self.is_end_of_file() => {
+                    (&mut tokeniser.lexemes[idx], length)
+                },
+                _ => break
+            };
+
+            let start_location   = self.location;
+            let start_position   = self.position;
+            let line_break_start = self.line_break_start;
+
+            if self.build_token(lexeme, length, start_location).is_err() {
+                self.location         = start_location;
+                self.position         = start_position;
+                self.line_break_start = line_break_start;
+                break;
+            }
+
+            self.apply_transition(lexeme.transition);
+            trailing_trivia.push(Trivia {
+                kind:     lexeme.trivia.unwrap(),
+                text:     self.source[start_position..self.position].to_vec(),
+                location: start_location
+            });
         }
+
+        trailing_trivia
     }
 
-    pub fn pop_line(&mut self) {
-        if self.line_stack.peek().is_some() {
-            let mut next_line = String::from(self.line_stack.next().unwrap());
-            next_line.push('\n');
+    fn take_whitespace(&mut self) -> Option<Trivia> {
+        let length = self.remaining()
+            .iter()
+            .position(|byte| !matches!(byte, b' ' | b'\t' | b'\x0b' | b'\x0c'))
+            .unwrap_or(self.remaining().len());
 
-            if self.line_buffer.is_some() {
-                self.line_buffer.as_mut().unwrap().push_str(&next_line[..]);
-            } else {
-                self.line_buffer.replace(next_line);
-            }
-            self.location.line = self.location.line + 1;
-            self.location.col  = 1;
+        self.take_trivia(TriviaKind::Whitespace, length)
+    }
+
+    fn take_newline(&mut self) -> Option<Trivia> {
+        let length = newline_length(self.remaining());
+        self.take_trivia(TriviaKind::Newline, length)
+    }
+
+    fn take_trivia(&mut self, kind: TriviaKind, length: usize) -> Option<Trivia> {
+        if length == 0 {
+            return None;
         }
+
+        let location = self.location;
+        let text     = self.remaining()[..length].to_vec();
+        self.consume_chars(length);
+
+        Some(Trivia { kind, text, location })
+    }
+
+    pub fn current_mode(&self) -> &'static str {
+        self.mode_stack.last().copied().unwrap_or(INITIAL_MODE)
+    }
+
+    pub fn push_mode(&mut self, mode: &'static str) {
+        self.mode_stack.push(mode);
     }
 
+    /** Returns to the previous mode. The initial mode is never popped, so an
+     *  unbalanced closing lexeme can't leave the tokeniser without a mode. */
+    pub fn pop_mode(&mut self) {
+        if self.mode_stack.len() > 1 {
+            self.mode_stack.pop();
+        }
+    }
+
+    fn apply_transition(&mut self, transition: ModeTransition) {
+        match transition {
+            ModeTransition::Stay       => { },
+            ModeTransition::Push(mode) => self.push_mode(mode),
+            ModeTransition::Pop        => self.pop_mode()
+        }
+    }
+
+    /** The part of the source that hasn't been consumed yet. */
+    pub fn remaining(&self) -> &'t [u8] {
+        &self.source[self.position..]
+    }
+
+    pub fn is_end_of_file(&self) -> bool {
+        self.position >= self.source.len()
+    }
+
+    /** Discards the rest of the current line, leaving the state at the
+     *  start of the next one. Used to resynchronise after an error. */
+    pub fn skip_line(&mut self) {
+        let remaining   = self.remaining();
+        let line_length = remaining
+            .iter()
+            .position(|byte| is_newline(*byte))
+            .map(|idx| idx + newline_length(&remaining[idx..]))
+            .unwrap_or(remaining.len());
+
+        self.consume_chars(line_length);
+    }
+
+    /**
+     * Consumes `num_chars` bytes of the source, keeping the location in step
+     * with any line breaks passed over. As in the reference Lua lexer, `\n`,
+     * `\r`, `\r\n` and `\n\r` each count as a single line break.
+     */
     pub fn consume_chars(&mut self, num_chars: usize) {
-        if self.line_buffer.is_some() {
-            let line = self.line_buffer.as_ref().unwrap().clone();
-            self.location.col = self.location.col + num_chars;
-            self.line_buffer.replace(line[num_chars..].to_string());
+        let end = (self.position + num_chars).min(self.source.len());
+
+        for byte in &self.source[self.position..end] {
+            match (*byte, self.line_break_start) {
+                (b'\n', Some(b'\r')) | (b'\r', Some(b'\n')) => {
+                    // The second half of a two character line break.
+                    self.line_break_start = None;
+                },
+                (b'\n', _) | (b'\r', _) => {
+                    self.location.line   += 1;
+                    self.location.col     = 1;
+                    self.line_break_start = Some(*byte);
+                },
+                _ => {
+                    self.location.col    += 1;
+                    self.line_break_start = None;
+                }
+            }
         }
+
+        self.position = end;
     }
 }
 
-pub struct Tokeniser<'t, T, U> where T: TerminalSymbol {
-    lexemes:                   Vec<LexemeTokeniser<'t, T, U>>,
-    error_handlers:            HashMap<char, &'t dyn Fn(Location, String) -> TokenisationError<T, U>>,
-    eof_handler:               &'t dyn Fn(Location) -> Token<T>,
-    unexpected_symbol_handler: &'t dyn Fn(Location, char) -> TokenisationError<T, U>
+fn is_newline(byte: u8) -> bool {
+    byte == b'\n' || byte == b'\r'
 }
 
-impl<'t, T, U> Tokeniser<'t, T, U> where T: TerminalSymbol {
+/** The length of the line break at the start of `bytes`, or 0 if it doesn't
+ *  start with one. */
+pub fn newline_length(bytes: &[u8]) -> usize {
+    match bytes {
+        [first, second, ..] if is_newline(*first) && is_newline(*second) && first != second => 2,
+        [first, ..] if is_newline(*first) => 1,
+        _ => 0
+    }
+}
+
+pub struct Tokeniser<T, U> where T: TerminalSymbol {
+    lexemes:                   Vec<LexemeTokeniser<T, U>>,
+    eof_handler:               EofHandler<T>,
+    unexpected_symbol_handler: UnexpectedSymbolHandler<T, U>,
+    verbatim_modes:            HashSet<&'static str>,
+    prologue:                  Vec<(Regex, TriviaKind)>
+}
+
+impl<T, U> Tokeniser<T, U> where T: TerminalSymbol {
     pub fn new(
-        lexemes:                   Vec<LexemeTokeniser<'t, T, U>>,
-        error_handlers:            HashMap<char, &'t dyn Fn(Location, String) -> TokenisationError<T, U>>,
-        eof_handler:               &'t dyn Fn(Location) -> Token<T>,
-        unexpected_symbol_handler: &'t dyn Fn(Location, char) -> TokenisationError<T, U>
-    ) -> Tokeniser<'t, T, U> {
+        lexemes:                   Vec<LexemeTokeniser<T, U>>,
+        eof_handler:               EofHandler<T>,
+        unexpected_symbol_handler: UnexpectedSymbolHandler<T, U>,
+        verbatim_modes:            HashSet<&'static str>,
+        prologue:                  Vec<(Regex, TriviaKind)>
+    ) -> Tokeniser<T, U> {
         Tokeniser {
-            lexemes:                   lexemes,
-            error_handlers:            error_handlers,
-            eof_handler:               eof_handler,
-            unexpected_symbol_handler: unexpected_symbol_handler
+            lexemes,
+            eof_handler,
+            unexpected_symbol_handler,
+            verbatim_modes,
+            prologue
         }
     }
 
-    pub fn tokenise(&mut self, src: String) -> Result<VecDeque<Token<T>>, TokenisationError<T, U>> {
+    fn is_verbatim(&self, mode: &str) -> bool {
+        self.verbatim_modes.contains(mode)
+    }
+
+    /** Tokenises the whole of `src`, which need not be valid UTF-8. The last
+     *  token is always the end of file token, and concatenating the
+     *  `full_text` of every token gives back `src` exactly. */
+    pub fn tokenise<S>(&mut self, src: S) -> Result<VecDeque<Token<T>>, TokenisationError<T, U>> where S: Into<Vec<u8>> {
+        let src = src.into();
         let mut token_deque: VecDeque<Token<T>> = VecDeque::new();
-        let mut lines = src.lines();
-        
-        let mut state = TokeniserState::<T, U> {
-            location:           Location { line: 1, col: 1},
-            line_buffer:        lines.next().map(|line| line.to_string()),
-            line_stack:         lines.peekable(),
-            phantom_token_type: PhantomData,
-            phantom_error_type: PhantomData
-        };
-
-        while !state.is_end_of_file() {
+        let mut state = TokeniserState::<T, U>::new(&src);
+
+        while !state.finished {
             token_deque.push_back(state.get_token(self)?);
         }
 
         Ok(token_deque)
     }
+
+    /** Tokenises the whole of `src` without stopping at the first error.
+     *  Each error is recorded and replaced in the token stream by its partial
+     *  token, then tokenisation resumes from the next resynchronisation point:
+     *  after the offending symbol, or at the start of the next line. */
+    pub fn tokenise_with_recovery<S>(&mut self,
+        src: S
+    ) -> (VecDeque<Token<T>>, Vec<TokenisationError<T, U>>) where S: Into<Vec<u8>> {
+        let src = src.into();
+        let mut token_deque: VecDeque<Token<T>> = VecDeque::new();
+        let mut errors:      Vec<TokenisationError<T, U>> = Vec::new();
+        let mut state = TokeniserState::<T, U>::new(&src);
+
+        while !state.finished {
+            match state.get_token(self) {
+                Ok(token) => token_deque.push_back(token),
+                Err(error) => {
+                    // A lexeme that failed without consuming anything would
+                    // fail again in the same place, so skip past it.
+                    if state.position == state.token_start {
+                        state.skip_line();
+                    }
+
+                    let mut error_token = (*error.partial_token).clone();
+                    error_token.location       = state.token_start_location;
+                    error_token.text           = src[state.token_start..state.position].to_vec();
+                    error_token.leading_trivia = mem::take(&mut state.pending_trivia);
+                    error_token.lexer_modes    = state.mode_stack.clone();
+
+                    token_deque.push_back(error_token);
+                    errors.push(error);
+                }
+            }
+        }
+
+        (token_deque, errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::{Display, Formatter};
+
+    use enum_iterator::IntoEnumIterator;
+    use regex::bytes::Regex;
+
+    use crate::tokenisation::{Token, TokenData, Location, TriviaKind, TokenisationError, TokeniserBuilder, INITIAL_MODE};
+    use crate::parsing::TerminalSymbol;
+
+    #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, IntoEnumIterator)]
+    enum Terminal {
+        Word,
+        Quote,
+        Text,
+        InterpolationStart,
+        InterpolationEnd,
+        CommentStart,
+        CommentText,
+        CommentEnd,
+        If,
+        Then,
+        EndOfFile
+    }
+
+    #[derive(Debug, Clone)]
+    struct Text(String);
+
+    impl TokenData for Text { }
+
+    impl Display for Text {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl Display for Terminal {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+            write!(f, "{:?}", self)
+        }
+    }
+
+    impl TerminalSymbol for Terminal {
+        type DataType = Text;
+
+        fn get_name(&self) -> &'static str {
+            "terminal"
+        }
+    }
+
+    fn text(value: Vec<u8>, location: Location) -> Result<Token<Terminal>, TokenisationError<Terminal, ()>> {
+        Ok(Token::new(Terminal::Text, Some(Text(String::from_utf8(value).unwrap())), location))
+    }
+
+    fn eof(location: Location) -> Token<Terminal> {
+        Token::new(Terminal::EndOfFile, None, location)
+    }
+
+    fn unexpected(location: Location, _: &[u8]) -> TokenisationError<Terminal, ()> {
+        TokenisationError { partial_token: Box::new(eof(location)), error_type: () }
+    }
+
+    #[test]
+    fn lexemes_only_match_in_their_modes() {
+        let mut tokeniser = TokeniserBuilder::<Terminal, ()>::new()
+            .with_static_token(Regex::new(r"^/\*").unwrap(),      Terminal::CommentStart).then_push_mode("comment")
+            .with_static_token(Regex::new("^\"").unwrap(),        Terminal::Quote).then_push_mode("string")
+            .with_static_token(Regex::new(r"^\}").unwrap(),       Terminal::InterpolationEnd).then_pop_mode()
+            .with_static_token(Regex::new(r"^\w+").unwrap(),      Terminal::Word)
+            .in_modes(&["comment"])
+            .with_static_token(Regex::new(r"^/\*").unwrap(),      Terminal::CommentStart).then_push_mode("comment")
+            .with_static_token(Regex::new(r"^\*/").unwrap(),      Terminal::CommentEnd).then_pop_mode()
+            .with_static_token(Regex::new(r"^([^/*]+|.)").unwrap(), Terminal::CommentText)
+            .in_modes(&["string"])
+            .with_verbatim_mode("string")
+            .with_static_token(Regex::new("^\"").unwrap(),        Terminal::Quote).then_pop_mode()
+            .with_static_token(Regex::new(r"^\$\{").unwrap(),     Terminal::InterpolationStart).then_push_mode(INITIAL_MODE)
+            .with_dynamic_token(Regex::new("^[^\"$]+").unwrap(),  text)
+            .with_eof_handler(eof)
+            .with_unexpected_symbol_handler(unexpected)
+            .build()
+            .unwrap();
+
+        let tokens = tokeniser.tokenise(String::from("a /* x /* y */ z */ \"hi ${name}!\"")).ok().unwrap();
+        let token_types: Vec<_> = tokens.iter().map(|t| t.token_type).collect();
+
+        assert_eq!(token_types, vec![
+            Terminal::Word,
+            Terminal::CommentStart, Terminal::CommentText,
+            Terminal::CommentStart, Terminal::CommentText, Terminal::CommentEnd,
+            Terminal::CommentText, Terminal::CommentEnd,
+            Terminal::Quote, Terminal::Text,
+            Terminal::InterpolationStart, Terminal::Word, Terminal::InterpolationEnd,
+            Terminal::Text, Terminal::Quote,
+            Terminal::EndOfFile
+        ]);
+        assert_eq!(tokens[9].token_data.as_ref().unwrap().0, "hi ");
+    }
+
+    #[test]
+    fn nested_comments_are_trivia() {
+        let mut tokeniser = TokeniserBuilder::<Terminal, ()>::new()
+            .with_static_token(Regex::new(r"^/\*").unwrap(),        Terminal::CommentStart).with_trivia(TriviaKind::BlockComment).then_push_mode("comment")
+            .with_static_token(Regex::new(r"^\w+").unwrap(),        Terminal::Word)
+            .in_modes(&["comment"])
+            .with_static_token(Regex::new(r"^/\*").unwrap(),        Terminal::CommentStart).with_trivia(TriviaKind::BlockComment).then_push_mode("comment")
+            .with_static_token(Regex::new(r"^\*/").unwrap(),        Terminal::CommentEnd).with_trivia(TriviaKind::BlockComment).then_pop_mode()
+            .with_static_token(Regex::new(r"^([^/*]+|.)").unwrap(), Terminal::CommentText).with_trivia(TriviaKind::BlockComment)
+            .with_eof_handler(eof)
+            .with_unexpected_symbol_handler(unexpected)
+            .build()
+            .unwrap();
+
+        let source = "a /* x /* y */ b */\nc";
+        let tokens = tokeniser.tokenise(source).ok().unwrap();
+        let token_types: Vec<_> = tokens.iter().map(|t| t.token_type).collect();
+
+        assert_eq!(token_types, vec![Terminal::Word, Terminal::Word, Terminal::EndOfFile]);
+        assert_eq!(tokens[1].text, b"c");
+        assert_eq!(tokens.iter().flat_map(|t| t.full_text()).collect::<Vec<_>>(), source.as_bytes());
+    }
+
+    fn word_tokeniser() -> TokeniserBuilder<Terminal, ()> {
+        TokeniserBuilder::<Terminal, ()>::new()
+            .with_dynamic_token(Regex::new(r"^\w+").unwrap(), text)
+            .with_keyword("if", Terminal::If)
+            .with_keyword("then", Terminal::Then)
+    }
+
+    #[test]
+    fn keywords_replace_whole_matches() {
+        let mut tokeniser = word_tokeniser()
+            .with_eof_handler(eof)
+            .with_unexpected_symbol_handler(unexpected)
+            .build()
+            .unwrap();
+
+        let tokens = tokeniser.tokenise("if iffy then").ok().unwrap();
+        let token_types: Vec<_> = tokens.iter().map(|t| t.token_type).collect();
+
+        assert_eq!(token_types, vec![Terminal::If, Terminal::Text, Terminal::Then, Terminal::EndOfFile]);
+        assert!(tokens[0].token_data.is_none());
+        assert_eq!(tokens[1].token_data.as_ref().unwrap().0, "iffy");
+    }
+
+    #[test]
+    fn removed_keywords_are_read_like_other_matches() {
+        let mut tokeniser = word_tokeniser()
+            .without_keyword("then")
+            .with_eof_handler(eof)
+            .with_unexpected_symbol_handler(unexpected)
+            .build()
+            .unwrap();
+
+        let tokens = tokeniser.tokenise("if then").ok().unwrap();
+        let token_types: Vec<_> = tokens.iter().map(|t| t.token_type).collect();
+
+        assert_eq!(token_types, vec![Terminal::If, Terminal::Text, Terminal::EndOfFile]);
+        assert_eq!(tokens[1].token_data.as_ref().unwrap().0, "then");
+    }
+
+    #[test]
+    fn handlers_own_their_state() {
+        let separator = String::from(":");
+        let mut count = 0;
+
+        let mut tokeniser = TokeniserBuilder::<Terminal, ()>::new()
+            .with_dynamic_token(Regex::new(r"^\w+").unwrap(), move |value, location| {
+                count += 1;
+                let numbered = format!("{}{}{}", count, separator, String::from_utf8(value).unwrap());
+                Ok(Token::new(Terminal::Text, Some(Text(numbered)), location))
+            })
+            .with_eof_handler(eof)
+            .with_unexpected_symbol_handler(unexpected)
+            .build()
+            .unwrap();
+
+        let tokens = tokeniser.tokenise("a b").ok().unwrap();
+        let more   = tokeniser.tokenise("c").ok().unwrap();
+
+        assert_eq!(tokens[1].token_data.as_ref().unwrap().0, "2:b");
+        assert_eq!(more[0].token_data.as_ref().unwrap().0,   "3:c");
+    }
 }
@@ -0,0 +1,19 @@
+describe("the formatter", function()
+    before_each(function() reset() end)
+
+    it("keeps callbacks readable", function()
+        local results = map({ 1, 2, 3 }, function(x) return x * 2 end)
+        assert.are.same({ 2, 4, 6 }, results)
+    end)
+
+    it("handles chains", function()
+        local value = builder:new():with_name("thing"):with_size(10):with_colour("red"):with_owner("nobody"):build()
+        assert.is_not_nil(value)
+    end)
+end)
+
+timer.after(1.5, function()
+    -- a comment inside a callback
+    print("done")
+end, "label", { repeat_count = 3, -- repeats
+    delay = 0.25 })
@@ -0,0 +1,40 @@
+-- Control flow of every kind.
+local items = { 3, 1, 4, 1, 5, 9, 2, 6 }
+local total = 0
+
+for i = 1, #items do total = total + items[i] end
+for i = #items, 1, -1 do
+    if items[i] > 4 then
+        break
+    elseif items[i] == 1 then goto continue
+    else
+        total = total - 1
+    end
+    ::continue::
+end
+
+for key, value in pairs(items) do   -- iterate
+end
+
+while total > 0 do total = total - 10 end
+
+repeat
+    local done = total < 100
+    total = total + 7
+until done
+
+do
+    local x <const> = 10
+    local handle <close> = nil
+end
+
+local function fib(n)
+    if n < 2 then return n end
+
+    -- not the fast way
+    return fib(n - 1) + fib(n - 2)
+end
+
+print(fib(10), total) --[[ inline ]] print("after")
+
+return
@@ -0,0 +1,24 @@
+local a, b, c, d = 1, 2, 3, 4
+
+local sum = a+b*c-d/2^-c%3
+local grouped = (a + b) * (c - d)
+local power = 2^3^2, -2^2, (-2)^2
+local concat = "a".."b".."c" .. a .. ( b .. c )
+local logic = a and not b or c and d or nil
+local comparison = a < b == (c >= d) ~= (a <= d)
+local bits = a & b | c ~ d << 1 >> 2 ~ ~a
+local floor = a // b
+local length = #"string" + #{1, 2, 3}
+local negation = - -a, not not b, - (-c)
+
+local very_long_condition = sum > 100 and grouped < 200 and power ~= concat and logic == comparison and bits ~= floor
+local very_long_string = "the quick brown fox " .. "jumps over the lazy dog " .. "and then keeps on running " .. tostring(length)
+local long_call = string.format("%s has %d items worth %0.2f in total, which is %s", "the basket", 12, 34.5, "fine")
+
+local strings = { 'single', "double", 'it\'s', "say \"hi\"", 'both \' and "', "\n\t\\", [==[
+long string with ]] inside]==] }
+
+if (a) then print(a) end
+local f = function(...) return ... end;
+(f)(1)
+f{1}.x = 2
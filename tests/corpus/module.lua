@@ -0,0 +1,39 @@
+#!/usr/bin/env lua
+-- A small class library, written the way people write Lua rather than the
+-- way a formatter would.
+
+local setmetatable , getmetatable = setmetatable,getmetatable
+local Class = {}
+Class.__index = Class
+
+
+--- Makes a new class.
+-- @param parent the class to inherit from
+function Class.extend( parent , name )
+  local class = setmetatable({ name = name, super = parent }, { __index = parent })
+  class.__index = class ; return class
+end
+
+function Class:new(...)
+    local instance = setmetatable({}, self)   -- every instance shares the metatable
+    if instance.init then instance:init(...) end
+    return instance
+end
+
+function Class:is(class) local mt = getmetatable(self)
+  while mt do
+    if mt == class then return true end
+    mt = mt.super -- walk up
+  end
+  return false
+end
+
+local Point = Class:extend 'Point'
+
+function Point:init(x, y) self.x, self.y = x or 0, y or 0 end
+
+function Point.__add(a, b) return Point:new(a.x + b.x, a.y + b.y) end
+function Point:__tostring() return ("(%d, %d)"):format(self.x, self.y) end
+
+--[[ Everything below is exported. ]]
+return { Class = Class, Point = Point }
@@ -0,0 +1,27 @@
+local config = {
+    name = 'phobos', version = "0.1.0",
+    authors = {'someone', "someone else", [[a third person]]},
+    -- which checks to run
+    checks = {
+        unused = true, -- unused locals
+        shadowing = false,
+        ["undefined-globals"] = { allow = { "vim", 'love', "describe", "it", "before_each", "after_each" } },
+    };
+    [1] = "first"; [2] = 'second',
+    nested = { { { {} } } },
+    weights = { 0.5, 1e10, 0x7fffffffffffffff, 3.25e-3, .5, 0xA.8p1 }
+}
+
+local empty = {}
+local empty_with_comment = {
+    -- nothing here yet
+}
+
+local matrix = {{1, 0, 0}, {0, 1, 0}, {0, 0, 1}}
+
+local words = { "alpha", "beta", "gamma", "delta", "epsilon", "zeta", "eta", "theta", "iota", "kappa", "lambda" }
+
+print(config.checks["undefined-globals"].allow[1], #words, matrix[2][2], empty, empty_with_comment)
+print{ "a table" }
+print "a string"
+require"module".Class:new()